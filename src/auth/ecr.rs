@@ -0,0 +1,173 @@
+//! Amazon ECR credentials, without docker-credential-ecr-login
+//!
+//! ECR registries take a token from the ECR API's `GetAuthorizationToken`
+//! rather than long-lived passwords. krust calls it itself through the AWS
+//! SDK, so the ambient AWS credentials are found the way the AWS CLI finds
+//! them: environment variables, `AWS_PROFILE` or the default profile
+//! (including SSO, `credential_process` and `role_arn`/`source_profile`),
+//! web identity tokens, and the ECS, EKS and EC2 metadata endpoints.
+
+use crate::registry::ecr::EcrRegistry;
+use crate::registry::RegistryAuth;
+use anyhow::{Context, Result};
+use aws_sdk_ecr::error::DisplayErrorContext;
+use base64::Engine;
+use std::time::SystemTime;
+
+/// Credentials for `registry` if it's an ECR registry, from a new token,
+/// and the time the token expires
+pub async fn resolve(registry: &str) -> Result<Option<(RegistryAuth, Option<SystemTime>)>> {
+    let Some(ecr) = EcrRegistry::parse(registry) else {
+        return Ok(None);
+    };
+    let token = authorization_token(&ecr.client().await)
+        .await
+        .with_context(|| format!("Failed to get an Amazon ECR token for {}", registry))?;
+    Ok(Some(token))
+}
+
+/// Ask the ECR API for a token, which is valid for every registry the
+/// credentials can access
+async fn authorization_token(
+    client: &aws_sdk_ecr::Client,
+) -> Result<(RegistryAuth, Option<SystemTime>)> {
+    let response = client
+        .get_authorization_token()
+        .send()
+        .await
+        .map_err(|e| anyhow::anyhow!("{}", DisplayErrorContext(e)))?;
+    let data = response
+        .authorization_data()
+        .first()
+        .context("The ECR API returned no token")?;
+    let token = data
+        .authorization_token()
+        .context("The ECR API returned no token")?;
+    let expires_at = data
+        .expires_at()
+        .and_then(|at| SystemTime::try_from(*at).ok());
+    Ok((decode_token(token)?, expires_at))
+}
+
+/// Registry credentials from a base64 `AWS:<password>` token
+fn decode_token(token: &str) -> Result<RegistryAuth> {
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(token)
+        .context("Invalid ECR token")?;
+    let decoded = String::from_utf8(decoded).context("Invalid ECR token")?;
+    let (username, password) = decoded.split_once(':').context("Invalid ECR token")?;
+    Ok(RegistryAuth::Basic {
+        username: username.to_string(),
+        password: password.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_config::{BehaviorVersion, Region};
+
+    #[test]
+    fn test_decode_token() {
+        let token = base64::engine::general_purpose::STANDARD.encode("AWS:secret");
+        match decode_token(&token).unwrap() {
+            RegistryAuth::Basic { username, password } => {
+                assert_eq!((username.as_str(), password.as_str()), ("AWS", "secret"))
+            }
+            other => panic!("Expected basic auth, got {:?}", other),
+        }
+        assert!(decode_token("not base64!").is_err());
+        assert!(decode_token(&base64::engine::general_purpose::STANDARD.encode("AWS")).is_err());
+    }
+
+    /// Serve the ECR API's GetAuthorizationToken, recording the requests'
+    /// Authorization and X-Amz-Target headers
+    async fn serve_ecr() -> (
+        String,
+        std::sync::Arc<std::sync::Mutex<Vec<(String, String)>>>,
+    ) {
+        use http_body_util::Full;
+        use std::sync::{Arc, Mutex};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let log = log.clone();
+                let handler = hyper::service::service_fn(move |request: hyper::Request<_>| {
+                    let header = |name: &str| {
+                        request
+                            .headers()
+                            .get(name)
+                            .and_then(|value| value.to_str().ok())
+                            .unwrap_or_default()
+                            .to_string()
+                    };
+                    log.lock()
+                        .unwrap()
+                        .push((header("authorization"), header("x-amz-target")));
+                    let token = base64::engine::general_purpose::STANDARD.encode("AWS:secret");
+                    let body = format!(
+                        r#"{{"authorizationData":[{{"authorizationToken":"{}","expiresAt":1.7E9,"proxyEndpoint":"https://123456789012.dkr.ecr.us-west-2.amazonaws.com"}}]}}"#,
+                        token
+                    );
+                    async move {
+                        hyper::Response::builder()
+                            .header("Content-Type", "application/x-amz-json-1.1")
+                            .body(Full::new(bytes::Bytes::from(body)))
+                    }
+                });
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), handler),
+                );
+            }
+        });
+        (endpoint, requests)
+    }
+
+    #[tokio::test]
+    async fn test_authorization_token() {
+        let (endpoint, requests) = serve_ecr().await;
+        let ecr = EcrRegistry::parse("123456789012.dkr.ecr.us-west-2.amazonaws.com").unwrap();
+        let config = aws_sdk_ecr::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new(ecr.region.clone()))
+            .credentials_provider(aws_sdk_ecr::config::Credentials::new(
+                "AKID",
+                "secret",
+                Some("session".to_string()),
+                None,
+                "test",
+            ))
+            .endpoint_url(endpoint)
+            .build();
+        let (auth, expires_at) = authorization_token(&aws_sdk_ecr::Client::from_conf(config))
+            .await
+            .unwrap();
+        assert_eq!(
+            expires_at,
+            Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000))
+        );
+        match auth {
+            RegistryAuth::Basic { username, password } => {
+                assert_eq!((username.as_str(), password.as_str()), ("AWS", "secret"))
+            }
+            other => panic!("Expected basic auth, got {:?}", other),
+        }
+
+        let requests = requests.lock().unwrap();
+        let (authorization, target) = &requests[0];
+        assert!(
+            authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKID/")
+                && authorization.contains("/us-west-2/ecr/aws4_request"),
+            "{}",
+            authorization
+        );
+        assert_eq!(
+            target,
+            "AmazonEC2ContainerRegistry_V20150921.GetAuthorizationToken"
+        );
+    }
+}
@@ -8,9 +8,10 @@ use base64::Engine;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+mod ecr;
 mod simple;
 
-pub use simple::resolve_auth;
+pub use simple::{resolve_auth, resolve_auth_with, resolve_registry_auth};
 
 /// Authentication configuration containing credentials
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -1,17 +1,66 @@
 //! Simple authentication wrapper for registry authentication
 
+use crate::config::RegistryConfig;
+use crate::registry::hosts::registry_host;
 use crate::registry::{ImageReference, RegistryAuth};
 use anyhow::{Context, Result};
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 use std::process::Command;
-use tracing::debug;
+use std::time::SystemTime;
+use tracing::{debug, warn};
 
 use super::{DockerAuthEntry, DockerConfig};
 
 /// Resolve authentication for a given resource using Docker config and credential helpers
 pub fn resolve_auth(resource: &str) -> Result<RegistryAuth> {
+    resolve_auth_with(resource, &HashMap::new())
+}
+
+/// Resolve authentication for a given resource, preferring credentials set
+/// for its registry under `[registries."<host>"]` in krust's config over
+/// Docker config and credential helpers
+pub fn resolve_auth_with(
+    resource: &str,
+    registries: &HashMap<String, RegistryConfig>,
+) -> Result<RegistryAuth> {
+    let registry = resource_registry(resource);
+    Ok(local_auth(&registry, registries)?.unwrap_or_else(|| {
+        debug!("No auth found, using anonymous for registry: {}", registry);
+        RegistryAuth::Anonymous
+    }))
+}
+
+/// Like [`resolve_auth_with`], also asking the ECR API for a token when an
+/// Amazon ECR registry has no credentials configured. Temporary credentials
+/// come with the time they expire.
+pub async fn resolve_registry_auth(
+    resource: &str,
+    registries: &HashMap<String, RegistryConfig>,
+) -> Result<(RegistryAuth, Option<SystemTime>)> {
+    let registry = resource_registry(resource);
+    if let Some(auth) = local_auth(&registry, registries)? {
+        return Ok((auth, None));
+    }
+
+    // Amazon ECR registries take a token from the ECR API
+    match super::ecr::resolve(&registry).await {
+        Ok(Some((auth, expires_at))) => {
+            debug!("Found auth via the ECR API for registry: {}", registry);
+            return Ok((auth, expires_at));
+        }
+        Ok(None) => {}
+        Err(e) => warn!("{:#}", e),
+    }
+
+    debug!("No auth found, using anonymous for registry: {}", registry);
+    Ok((RegistryAuth::Anonymous, None))
+}
+
+/// The registry hosting `resource`
+fn resource_registry(resource: &str) -> String {
     debug!("Resolving auth for resource: {}", resource);
 
     // Parse the resource to extract registry
@@ -26,61 +75,39 @@ pub fn resolve_auth(resource: &str) -> Result<RegistryAuth> {
     };
 
     debug!("Extracted registry from resource: {}", registry);
+    registry
+}
 
-    // Try to read Docker config
-    if let Ok(auth) = read_docker_config(&registry) {
-        debug!("Found auth in Docker config for registry: {}", registry);
-        return Ok(auth);
-    }
-
-    // Try credential helpers
-    if let Ok(auth) = try_credential_helpers(&registry) {
-        debug!(
-            "Found auth via credential helper for registry: {}",
-            registry
-        );
-        return Ok(auth);
+/// Credentials for `registry` from krust's config, Docker config files or
+/// credential helpers
+fn local_auth(
+    registry: &str,
+    registries: &HashMap<String, RegistryConfig>,
+) -> Result<Option<RegistryAuth>> {
+    let configured = registries
+        .iter()
+        .find(|(host, _)| registry_host(host) == registry);
+    if let Some((host, config)) = configured {
+        if let Some(auth) = config_auth(config)
+            .with_context(|| format!("Invalid credentials for {} in krust config", host))?
+        {
+            debug!("Found auth in krust config for registry: {}", registry);
+            return Ok(Some(auth));
+        }
     }
 
-    debug!("No auth found, using anonymous for registry: {}", registry);
-    Ok(RegistryAuth::Anonymous)
+    // Try Docker config files and the credential helpers they name
+    Ok(read_docker_config(registry).ok())
 }
 
 fn read_docker_config(registry: &str) -> Result<RegistryAuth> {
-    let config_paths = get_docker_config_paths();
-
-    for config_path in config_paths {
+    for config_path in get_docker_config_paths() {
         if let Ok(config_content) = fs::read_to_string(&config_path) {
             debug!("Reading Docker config from: {:?}", config_path);
 
             if let Ok(config) = serde_json::from_str::<DockerConfig>(&config_content) {
-                if let Some(auths) = &config.auths {
-                    // Try exact registry match first
-                    if let Some(auth_entry) = auths.get(registry) {
-                        debug!("Found exact registry match for: {}", registry);
-                        return parse_auth_entry(auth_entry);
-                    }
-
-                    // Try with https:// prefix (common in Docker config)
-                    let https_registry = format!("https://{}", registry);
-                    if let Some(auth_entry) = auths.get(&https_registry) {
-                        debug!("Found https registry match for: {}", https_registry);
-                        return parse_auth_entry(auth_entry);
-                    }
-
-                    // Try registry-1.docker.io for docker.io
-                    if registry == "docker.io" || registry == "registry-1.docker.io" {
-                        for key in &[
-                            "docker.io",
-                            "registry-1.docker.io",
-                            "https://index.docker.io/v1/",
-                        ] {
-                            if let Some(auth_entry) = auths.get(*key) {
-                                debug!("Found Docker Hub match with key: {}", key);
-                                return parse_auth_entry(auth_entry);
-                            }
-                        }
-                    }
+                if let Ok(auth) = docker_config_auth(&config, registry, call_credential_helper) {
+                    return Ok(auth);
                 }
             }
         }
@@ -89,6 +116,106 @@ fn read_docker_config(registry: &str) -> Result<RegistryAuth> {
     anyhow::bail!("No auth found in Docker config")
 }
 
+/// The keys `registry`'s credentials can be stored under, the one docker
+/// itself uses first. Docker Hub's are stored under its v1 index URL.
+fn server_keys(registry: &str) -> Vec<String> {
+    if matches!(
+        registry,
+        "docker.io" | "index.docker.io" | "registry-1.docker.io"
+    ) {
+        [
+            "https://index.docker.io/v1/",
+            "docker.io",
+            "index.docker.io",
+            "registry-1.docker.io",
+        ]
+        .map(String::from)
+        .to_vec()
+    } else {
+        vec![registry.to_string(), format!("https://{}", registry)]
+    }
+}
+
+/// Credentials for `registry` in one Docker config file, looked up in the
+/// order docker uses: the registry's credential helper, then the default
+/// credential store, then `auths`. Helpers are run with `helper`.
+pub(super) fn docker_config_auth(
+    config: &DockerConfig,
+    registry: &str,
+    helper: impl Fn(&str, &str) -> Result<RegistryAuth>,
+) -> Result<RegistryAuth> {
+    let keys = server_keys(registry);
+
+    let cred_helper = config.cred_helpers.as_ref().and_then(|cred_helpers| {
+        keys.iter()
+            .find_map(|key| Some((cred_helpers.get(key)?, key)))
+    });
+    if let Some((name, key)) = cred_helper {
+        debug!("Trying credential helper '{}' for registry: {}", name, key);
+        match helper(name, key) {
+            Ok(auth) => {
+                debug!(
+                    "Found auth via credential helper for registry: {}",
+                    registry
+                );
+                return Ok(auth);
+            }
+            Err(e) => debug!("Credential helper '{}' failed: {:#}", name, e),
+        }
+    }
+
+    if let Some(name) = &config.creds_store {
+        debug!(
+            "Trying default credential helper '{}' for registry: {}",
+            name, keys[0]
+        );
+        match helper(name, &keys[0]) {
+            Ok(auth) => {
+                debug!("Found auth via credential store for registry: {}", registry);
+                return Ok(auth);
+            }
+            Err(e) => debug!("Credential store '{}' failed: {:#}", name, e),
+        }
+    }
+
+    // Entries without credentials are left by logins to a credential store
+    let entry = config
+        .auths
+        .iter()
+        .flatten()
+        .filter(|(key, _)| keys.contains(key))
+        .min_by_key(|(key, _)| keys.iter().position(|k| k == *key))
+        .map(|(_, entry)| entry);
+    if let Some(auth_entry) = entry {
+        if let Ok(auth) = parse_auth_entry(auth_entry) {
+            debug!("Found auth in Docker config for registry: {}", registry);
+            return Ok(auth);
+        }
+    }
+
+    anyhow::bail!("No auth for {} in Docker config", registry)
+}
+
+/// Credentials set in a registry's config block, if any
+fn config_auth(config: &RegistryConfig) -> Result<Option<RegistryAuth>> {
+    if let Some(token) = &config.token {
+        return Ok(Some(RegistryAuth::Bearer {
+            token: token.clone(),
+        }));
+    }
+    if config.username.is_none() && config.password.is_none() && config.auth.is_none() {
+        return Ok(None);
+    }
+    parse_auth_entry(&DockerAuthEntry {
+        auth: config.auth.clone(),
+        username: config.username.clone(),
+        password: config.password.clone(),
+        identity_token: None,
+        registry_token: None,
+    })
+    .map(Some)
+}
+
 fn parse_auth_entry(auth_entry: &DockerAuthEntry) -> Result<RegistryAuth> {
     // Check for bearer token first
     if let Some(token) = &auth_entry.registry_token {
@@ -130,6 +257,11 @@ fn parse_auth_entry(auth_entry: &DockerAuthEntry) -> Result<RegistryAuth> {
 fn get_docker_config_paths() -> Vec<PathBuf> {
     let mut paths = Vec::new();
 
+    // Check REGISTRY_AUTH_FILE, as podman and skopeo do
+    if let Ok(auth_file) = std::env::var("REGISTRY_AUTH_FILE") {
+        paths.push(PathBuf::from(auth_file));
+    }
+
     // Check DOCKER_CONFIG environment variable
     if let Ok(docker_config) = std::env::var("DOCKER_CONFIG") {
         paths.push(PathBuf::from(docker_config).join("config.json"));
@@ -152,42 +284,6 @@ fn get_docker_config_paths() -> Vec<PathBuf> {
     paths
 }
 
-fn try_credential_helpers(registry: &str) -> Result<RegistryAuth> {
-    let config_paths = get_docker_config_paths();
-
-    for config_path in config_paths {
-        if let Ok(config_content) = fs::read_to_string(&config_path) {
-            if let Ok(config) = serde_json::from_str::<DockerConfig>(&config_content) {
-                // Check specific credential helpers first
-                if let Some(cred_helpers) = &config.cred_helpers {
-                    if let Some(helper) = cred_helpers.get(registry) {
-                        debug!(
-                            "Trying credential helper '{}' for registry: {}",
-                            helper, registry
-                        );
-                        if let Ok(auth) = call_credential_helper(helper, registry) {
-                            return Ok(auth);
-                        }
-                    }
-                }
-
-                // Try default credential store
-                if let Some(helper) = &config.creds_store {
-                    debug!(
-                        "Trying default credential helper '{}' for registry: {}",
-                        helper, registry
-                    );
-                    if let Ok(auth) = call_credential_helper(helper, registry) {
-                        return Ok(auth);
-                    }
-                }
-            }
-        }
-    }
-
-    anyhow::bail!("No credential helpers found")
-}
-
 #[derive(Debug, Deserialize)]
 struct CredentialHelperResponse {
     #[serde(rename = "Username")]
@@ -58,3 +58,123 @@ fn test_docker_auth_entry_to_auth_config() {
     assert!(config.username.is_none());
     assert!(config.password.is_none());
 }
+
+#[test]
+fn test_resolve_auth_with_config() {
+    use crate::config::RegistryConfig;
+    use crate::registry::RegistryAuth;
+
+    let registries = HashMap::from([
+        (
+            "docker.io".to_string(),
+            RegistryConfig {
+                auth: Some("dXNlcjpwYXNz".to_string()),
+                ..Default::default()
+            },
+        ),
+        (
+            "ghcr.io".to_string(),
+            RegistryConfig {
+                username: Some("user".to_string()),
+                password: Some("secret".to_string()),
+                ..Default::default()
+            },
+        ),
+        (
+            "https://registry.corp/".to_string(),
+            RegistryConfig {
+                token: Some("token".to_string()),
+                ..Default::default()
+            },
+        ),
+        (
+            "broken.corp".to_string(),
+            RegistryConfig {
+                username: Some("user".to_string()),
+                ..Default::default()
+            },
+        ),
+    ]);
+
+    // Config credentials are found for Docker Hub's aliases too
+    match resolve_auth_with("alpine:3.20", &registries).unwrap() {
+        RegistryAuth::Basic { username, password } => {
+            assert_eq!((username.as_str(), password.as_str()), ("user", "pass"))
+        }
+        other => panic!("expected basic auth, got {:?}", other),
+    }
+    match resolve_auth_with("ghcr.io/org/app", &registries).unwrap() {
+        RegistryAuth::Basic { username, password } => {
+            assert_eq!((username.as_str(), password.as_str()), ("user", "secret"))
+        }
+        other => panic!("expected basic auth, got {:?}", other),
+    }
+    match resolve_auth_with("registry.corp/team/app:v1", &registries).unwrap() {
+        RegistryAuth::Bearer { token } => assert_eq!(token, "token"),
+        other => panic!("expected a bearer token, got {:?}", other),
+    }
+
+    let err = resolve_auth_with("broken.corp/app", &registries).unwrap_err();
+    assert!(err.to_string().contains("broken.corp in krust config"));
+}
+
+#[test]
+fn test_docker_config_auth_order() {
+    use super::simple::docker_config_auth;
+    use crate::registry::RegistryAuth;
+
+    let config: DockerConfig = serde_json::from_str(
+        r#"{
+            "auths": {
+                "https://index.docker.io/v1/": {},
+                "gcr.io": {"auth": "c3RhbGU6c3RhbGU="},
+                "quay.io": {"auth": "dXNlcjpwYXNz"}
+            },
+            "credHelpers": {"gcr.io": "gcloud"},
+            "credsStore": "desktop"
+        }"#,
+    )
+    .unwrap();
+    let calls = std::cell::RefCell::new(Vec::new());
+    let helper = |name: &str, key: &str| {
+        calls.borrow_mut().push(format!("{} {}", name, key));
+        match (name, key) {
+            ("gcloud", "gcr.io") => Ok(RegistryAuth::Bearer {
+                token: "gcloud-token".to_string(),
+            }),
+            ("desktop", "https://index.docker.io/v1/") => Ok(RegistryAuth::Basic {
+                username: "hub".to_string(),
+                password: "secret".to_string(),
+            }),
+            _ => anyhow::bail!("credentials not found in native keychain"),
+        }
+    };
+
+    // A registry's credential helper wins over a stale auths entry
+    match docker_config_auth(&config, "gcr.io", helper).unwrap() {
+        RegistryAuth::Bearer { token } => assert_eq!(token, "gcloud-token"),
+        other => panic!("expected a bearer token, got {:?}", other),
+    }
+    // Docker Hub's credentials are asked for under its index URL
+    match docker_config_auth(&config, "registry-1.docker.io", helper).unwrap() {
+        RegistryAuth::Basic { username, .. } => assert_eq!(username, "hub"),
+        other => panic!("expected basic auth, got {:?}", other),
+    }
+    // auths is used when the store has nothing
+    match docker_config_auth(&config, "quay.io", helper).unwrap() {
+        RegistryAuth::Basic { username, password } => {
+            assert_eq!((username.as_str(), password.as_str()), ("user", "pass"))
+        }
+        other => panic!("expected basic auth, got {:?}", other),
+    }
+    assert!(docker_config_auth(&config, "ghcr.io", helper).is_err());
+    assert_eq!(
+        calls.into_inner(),
+        [
+            "gcloud gcr.io",
+            "desktop https://index.docker.io/v1/",
+            "desktop quay.io",
+            "desktop ghcr.io",
+        ]
+    );
+}
@@ -0,0 +1,168 @@
+//! Build timeouts and cancellation
+//!
+//! cargo and hook commands run in their own process group, so when a build
+//! times out or krust is interrupted the whole tree (cargo, rustc, linkers,
+//! build scripts) is stopped rather than just the direct child. Interrupts
+//! are process-wide, so cancellation is a global flag set from the Ctrl-C
+//! handler and checked by every build while it waits on its child.
+
+use anyhow::{Context, Result};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// How often a waiting build checks for cancellation and its deadline
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long an interrupted process group has to exit before it's killed
+const GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Stop every running build, interrupting its processes
+pub fn cancel() {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+/// Whether builds have been cancelled
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Number of child processes builds are currently waiting on
+pub fn running() -> usize {
+    RUNNING.load(Ordering::SeqCst)
+}
+
+/// Start `cmd` in a process group of its own, so it can be stopped along
+/// with everything it spawns
+pub fn spawn(cmd: &mut Command) -> std::io::Result<Child> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    cmd.spawn()
+}
+
+/// Wait for `child`, stopping its process group if the build is cancelled
+/// or `deadline` passes
+pub fn wait(child: &mut Child, deadline: Option<Instant>, what: &str) -> Result<ExitStatus> {
+    RUNNING.fetch_add(1, Ordering::SeqCst);
+    let result = wait_until(child, deadline, &CANCELLED, what);
+    RUNNING.fetch_sub(1, Ordering::SeqCst);
+    result
+}
+
+fn wait_until(
+    child: &mut Child,
+    deadline: Option<Instant>,
+    cancelled: &AtomicBool,
+    what: &str,
+) -> Result<ExitStatus> {
+    loop {
+        if let Some(status) = child
+            .try_wait()
+            .context("Failed to wait for child process")?
+        {
+            return Ok(status);
+        }
+        if cancelled.load(Ordering::SeqCst) {
+            warn!("Stopping {}", what);
+            interrupt(child);
+            anyhow::bail!("{} was cancelled", what);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            kill(child);
+            anyhow::bail!("{} timed out", what);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Send SIGINT to the child's process group, as a terminal would, and kill
+/// it if it hasn't exited after the grace period
+fn interrupt(child: &mut Child) {
+    #[cfg(unix)]
+    signal_group(child, libc::SIGINT);
+    let deadline = Instant::now() + GRACE_PERIOD;
+    while Instant::now() < deadline {
+        if matches!(child.try_wait(), Ok(Some(_))) {
+            return;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    kill(child);
+}
+
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    signal_group(child, libc::SIGKILL);
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(unix)]
+fn signal_group(child: &Child, signal: libc::c_int) {
+    // The child leads its own group, so the group id is its pid
+    let Ok(pgid) = libc::pid_t::try_from(child.id()) else {
+        return;
+    };
+    // SAFETY: kill has no memory safety requirements; a negative pid
+    // addresses the process group
+    unsafe {
+        libc::kill(-pgid, signal);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_exits() {
+        let mut child = spawn(Command::new("sh").args(["-c", "exit 3"])).unwrap();
+        let status = wait_until(&mut child, None, &AtomicBool::new(false), "test").unwrap();
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[test]
+    fn test_wait_timeout_kills_group() {
+        use std::io::Read;
+        use std::process::Stdio;
+
+        let start = Instant::now();
+        // The shell's child holds stdout open, and would outlive a kill of
+        // just the shell
+        let mut child = spawn(
+            Command::new("sh")
+                .args(["-c", "sleep 30 & wait"])
+                .stdout(Stdio::piped()),
+        )
+        .unwrap();
+        let deadline = Some(Instant::now() + Duration::from_millis(200));
+        let err = wait_until(&mut child, deadline, &AtomicBool::new(false), "build")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "build timed out");
+        let mut output = Vec::new();
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_wait_cancelled() {
+        let mut child = spawn(Command::new("sleep").arg("30")).unwrap();
+        let err = wait_until(&mut child, None, &AtomicBool::new(true), "build")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "build was cancelled");
+        assert!(child.try_wait().unwrap().is_some());
+    }
+}
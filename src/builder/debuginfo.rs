@@ -0,0 +1,96 @@
+//! Split debug info
+//!
+//! With `split_debuginfo`, the binary shipped in the image is stripped but
+//! its debug info is kept in a `<binary>.debug` file linked to it with
+//! `.gnu_debuglink`, so crashes in production can still be symbolicated.
+//! The file can be pushed alongside the image as an OCI artifact whose
+//! subject is the platform manifest.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tracing::info;
+
+/// `artifactType` of the debug info artifact
+pub const ARTIFACT_TYPE: &str = "application/vnd.krust.debuginfo.v1";
+/// Media type of the debug info file in the artifact
+pub const LAYER_MEDIA_TYPE: &str = "application/vnd.krust.debuginfo.v1+elf";
+
+/// objcopy implementations to try, in order. llvm-objcopy handles every
+/// target architecture; GNU objcopy usually only the host's.
+const OBJCOPY: [&str; 3] = ["llvm-objcopy", "rust-objcopy", "objcopy"];
+
+/// Where the debug info for `binary` is written
+pub fn debug_path(binary: &Path) -> PathBuf {
+    let mut name = binary.file_name().unwrap_or_default().to_os_string();
+    name.push(".debug");
+    binary.with_file_name(name)
+}
+
+/// Move the debug info of `binary` into a separate file, strip the binary
+/// and link it to that file. Returns the path of the debug file.
+pub fn split(binary: &Path) -> Result<PathBuf> {
+    let objcopy = find_objcopy().context(
+        "split_debuginfo needs objcopy; install llvm-objcopy (e.g. with \
+         `rustup component add llvm-tools` and cargo-binutils) or GNU binutils",
+    )?;
+    let debug_file = debug_path(binary);
+
+    run(Command::new(objcopy)
+        .arg("--only-keep-debug")
+        .arg(binary)
+        .arg(&debug_file))?;
+    run(Command::new(objcopy)
+        .args(["--strip-debug", "--strip-unneeded"])
+        .arg(binary))?;
+    // debuglink records the file name, so debuggers find it next to the binary
+    run(Command::new(objcopy)
+        .arg(format!("--add-gnu-debuglink={}", debug_file.display()))
+        .arg(binary))?;
+
+    info!("Split debug info into {:?}", debug_file);
+    Ok(debug_file)
+}
+
+fn find_objcopy() -> Option<&'static str> {
+    OBJCOPY.into_iter().find(|tool| {
+        Command::new(tool)
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false)
+    })
+}
+
+fn run(cmd: &mut Command) -> Result<()> {
+    let output = cmd
+        .output()
+        .with_context(|| format!("Failed to execute {:?}", cmd.get_program()))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{:?} failed: {}",
+            cmd,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_path() {
+        assert_eq!(
+            debug_path(Path::new("/target/release/server")),
+            PathBuf::from("/target/release/server.debug")
+        );
+        assert_eq!(
+            debug_path(Path::new("/target/release/my.app")),
+            PathBuf::from("/target/release/my.app.debug")
+        );
+    }
+}
@@ -0,0 +1,224 @@
+//! Build fingerprinting
+//!
+//! A fingerprint covers everything that goes into a platform's binary: the
+//! files in the workspace (sources, manifests, Cargo.lock, `.cargo/config`),
+//! the target and the build settings. After a successful build the binary is
+//! copied into krust's cache with its fingerprint, and the next build with
+//! the same fingerprint reuses it without running hooks or cargo. Only the
+//! latest build of each project, target and set of cargo arguments is kept.
+
+use super::BuildResult;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const FINGERPRINT_FILE: &str = "fingerprint";
+/// Records the entry's key so `krust cache ls` can name its project
+pub const KEY_FILE: &str = "key";
+
+/// Accumulates build inputs into a fingerprint
+#[derive(Debug, Default)]
+pub struct Fingerprint {
+    inputs: String,
+}
+
+impl Fingerprint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a named build setting
+    pub fn setting(&mut self, name: &str, value: impl std::fmt::Debug) -> &mut Self {
+        self.inputs.push_str(&format!("{}={:?}\n", name, value));
+        self
+    }
+
+    /// Add the path and contents of every file under `root`, skipping the
+    /// `exclude` directories (target directories) and `.git`
+    pub fn tree(&mut self, root: &Path, exclude: &[PathBuf]) -> Result<&mut Self> {
+        let mut files = Vec::new();
+        collect_files(root, exclude, &mut files)?;
+        files.sort();
+        for path in files {
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            let metadata = fs::symlink_metadata(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let digest = if metadata.file_type().is_symlink() {
+                let link = fs::read_link(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                sha256::digest(link.to_string_lossy().as_bytes())
+            } else {
+                let data = fs::read(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                sha256::digest(data.as_slice())
+            };
+            self.inputs
+                .push_str(&format!("file {} {}\n", relative.display(), digest));
+        }
+        Ok(self)
+    }
+
+    pub fn digest(&self) -> String {
+        sha256::digest(self.inputs.as_bytes())
+    }
+}
+
+fn collect_files(dir: &Path, exclude: &[PathBuf], files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries =
+        fs::read_dir(dir).with_context(|| format!("Failed to read directory {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if entry.file_name() == ".git" || exclude.contains(&path) {
+                continue;
+            }
+            collect_files(&path, exclude, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The cached binary from the last successful build of one project, target
+/// and set of cargo arguments
+#[derive(Debug, Clone)]
+pub struct BuildCache {
+    dir: PathBuf,
+    key: String,
+}
+
+impl BuildCache {
+    /// The cache entry for `key` under `cache_dir`
+    pub fn new(cache_dir: &Path, key: &str) -> Self {
+        Self {
+            dir: cache_dir.join(sha256::digest(key.as_bytes())),
+            key: key.to_string(),
+        }
+    }
+
+    /// The cached build, if it was built with `fingerprint`. A hit marks
+    /// the entry as recently used, so `krust cache prune` keeps it.
+    pub fn lookup(&self, fingerprint: &str) -> Option<BuildResult> {
+        let fingerprint_path = self.dir.join(FINGERPRINT_FILE);
+        let stored = fs::read_to_string(&fingerprint_path).ok()?;
+        let mut lines = stored.lines();
+        if lines.next()? != fingerprint {
+            return None;
+        }
+        let binary_path = self.dir.join(lines.next()?);
+        if !binary_path.is_file() {
+            return None;
+        }
+        let debug_path = Some(super::debuginfo::debug_path(&binary_path)).filter(|p| p.is_file());
+        crate::cache::touch(&fingerprint_path);
+        Some(BuildResult {
+            binary_path,
+            debug_path,
+        })
+    }
+
+    /// Replace the cached build with `result`, built with `fingerprint`
+    pub fn store(&self, fingerprint: &str, result: &BuildResult) -> Result<()> {
+        // Clear the entry first so a partly written one is never reused
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)
+                .with_context(|| format!("Failed to clear {}", self.dir.display()))?;
+        }
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+
+        let name = result
+            .binary_path
+            .file_name()
+            .context("Built binary has no file name")?;
+        let binary_path = self.dir.join(name);
+        fs::copy(&result.binary_path, &binary_path)
+            .with_context(|| format!("Failed to cache {}", result.binary_path.display()))?;
+        if let Some(debug) = &result.debug_path {
+            fs::copy(debug, super::debuginfo::debug_path(&binary_path))
+                .with_context(|| format!("Failed to cache {}", debug.display()))?;
+        }
+        fs::write(
+            self.dir.join(FINGERPRINT_FILE),
+            format!("{}\n{}\n", fingerprint, name.to_string_lossy()),
+        )
+        .context("Failed to write build fingerprint")?;
+        fs::write(self.dir.join(KEY_FILE), &self.key).context("Failed to write build cache key")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn tree_digest(root: &Path, exclude: &[PathBuf]) -> String {
+        Fingerprint::new().tree(root, exclude).unwrap().digest()
+    }
+
+    #[test]
+    fn test_tree_fingerprint() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::create_dir_all(dir.path().join("target/release")).unwrap();
+        fs::create_dir_all(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+        let exclude = vec![dir.path().join("target")];
+        let before = tree_digest(dir.path(), &exclude);
+
+        // Build output and git metadata don't change the fingerprint
+        fs::write(dir.path().join("target/release/app"), "binary").unwrap();
+        fs::write(dir.path().join(".git/index"), "index").unwrap();
+        assert_eq!(tree_digest(dir.path(), &exclude), before);
+
+        fs::write(dir.path().join("src/main.rs"), "fn main() { todo!() }").unwrap();
+        assert_ne!(tree_digest(dir.path(), &exclude), before);
+    }
+
+    #[test]
+    fn test_settings_fingerprint() {
+        let digest = |target: &str| {
+            Fingerprint::new()
+                .setting("target", target)
+                .setting("features", vec!["tls"])
+                .digest()
+        };
+        assert_eq!(
+            digest("x86_64-unknown-linux-musl"),
+            digest("x86_64-unknown-linux-musl")
+        );
+        assert_ne!(
+            digest("x86_64-unknown-linux-musl"),
+            digest("aarch64-unknown-linux-musl")
+        );
+    }
+
+    #[test]
+    fn test_build_cache() {
+        let dir = tempdir().unwrap();
+        let binary = dir.path().join("app");
+        fs::write(&binary, "binary").unwrap();
+        let result = BuildResult {
+            binary_path: binary,
+            debug_path: None,
+        };
+
+        let cache = BuildCache::new(&dir.path().join("cache"), "/src/app x86_64");
+        assert!(cache.lookup("abc").is_none());
+
+        cache.store("abc", &result).unwrap();
+        let cached = cache.lookup("abc").unwrap();
+        assert_eq!(cached.binary_path.file_name().unwrap(), "app");
+        assert_eq!(fs::read_to_string(&cached.binary_path).unwrap(), "binary");
+        assert!(cached.debug_path.is_none());
+        assert!(cache.lookup("def").is_none());
+        assert_eq!(
+            fs::read_to_string(cache.dir.join(KEY_FILE)).unwrap(),
+            "/src/app x86_64"
+        );
+    }
+}
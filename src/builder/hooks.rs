@@ -0,0 +1,149 @@
+//! Build hooks
+//!
+//! Projects can run commands around cargo with `pre-build` and `post-build`
+//! in `[package.metadata.krust]`, e.g. to generate protobuf code before
+//! compiling or compress assets once the binary exists. Hooks run through the
+//! shell in the project directory, once per platform, with the build's
+//! environment variables plus `KRUST_*` variables describing the build.
+
+use super::{cancel, stream_lines};
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Instant;
+use tracing::info;
+
+/// When a hook runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Before cargo builds the binary
+    PreBuild,
+    /// After the binary is built, with `KRUST_BINARY` set to its path
+    PostBuild,
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stage::PreBuild => write!(f, "pre-build"),
+            Stage::PostBuild => write!(f, "post-build"),
+        }
+    }
+}
+
+/// Run each of `commands` in `dir` in order, stopping at the first failure
+/// or once `deadline` passes
+pub fn run(
+    stage: Stage,
+    commands: &[String],
+    dir: &Path,
+    env: &BTreeMap<String, String>,
+    target: &str,
+    deadline: Option<Instant>,
+) -> Result<()> {
+    for command in commands {
+        info!("Running {} hook for {}: {}", stage, target, command);
+        let mut child = cancel::spawn(
+            shell(command)
+                .current_dir(dir)
+                .envs(env)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )
+        .with_context(|| format!("Failed to execute {} hook `{}`", stage, command))?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .context("Failed to capture hook stdout")?;
+        let stderr = child
+            .stderr
+            .take()
+            .context("Failed to capture hook stderr")?;
+        let prefix = target.to_string();
+        let stdout = std::thread::spawn(move || stream_lines(stdout, &prefix));
+        let prefix = target.to_string();
+        let stderr = std::thread::spawn(move || stream_lines(stderr, &prefix));
+        let status = cancel::wait(
+            &mut child,
+            deadline,
+            &format!("{} hook `{}` for {}", stage, command, target),
+        )?;
+        stdout
+            .join()
+            .map_err(|_| anyhow::anyhow!("Reading hook output panicked"))?;
+        let stderr = stderr
+            .join()
+            .map_err(|_| anyhow::anyhow!("Reading hook output panicked"))?;
+
+        if !status.success() {
+            anyhow::bail!(
+                "{} hook `{}` failed ({}): {}",
+                stage,
+                command,
+                status,
+                stderr.trim_end()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_run_in_dir_with_env() {
+        let dir = tempdir().unwrap();
+        let env = BTreeMap::from([("KRUST_TARGET".to_string(), "x86_64".to_string())]);
+        run(
+            Stage::PreBuild,
+            &["echo \"$KRUST_TARGET\" > out.txt".to_string()],
+            dir.path(),
+            &env,
+            "x86_64",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("out.txt")).unwrap(),
+            "x86_64\n"
+        );
+    }
+
+    #[test]
+    fn test_run_stops_at_failure() {
+        let dir = tempdir().unwrap();
+        let err = run(
+            Stage::PostBuild,
+            &[
+                "echo broken >&2; exit 3".to_string(),
+                "touch ran.txt".to_string(),
+            ],
+            dir.path(),
+            &BTreeMap::new(),
+            "x86_64",
+            None,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("post-build hook `echo broken >&2; exit 3` failed"));
+        assert!(err.contains("broken"));
+        assert!(!dir.path().join("ran.txt").exists());
+    }
+}
@@ -0,0 +1,147 @@
+//! Linker detection for plain `cargo build`
+//!
+//! cargo-zigbuild and cross bring their own linkers, but `cargo build` for
+//! another architecture needs one for the target. krust looks for the cross
+//! linkers that common toolchains install (musl-cross on macOS, gcc cross
+//! packages on Linux, musl.cc builds on Windows) and falls back to the
+//! `rust-lld` that ships with rustup, which can link static musl binaries
+//! without C dependencies.
+
+use std::path::{Path, PathBuf};
+
+/// The `rust-lld` linker bundled with the Rust toolchain
+pub const RUST_LLD: &str = "rust-lld";
+
+/// Linker executables to look for when building `target` on `host_os`, in
+/// order of preference
+pub fn candidates(target: &str, host_os: &str) -> Vec<String> {
+    let prefixes: &[&str] = match target {
+        "x86_64-unknown-linux-musl" => &["x86_64-linux-musl", "x86_64-unknown-linux-musl"],
+        "aarch64-unknown-linux-musl" => &["aarch64-linux-musl", "aarch64-unknown-linux-musl"],
+        "armv7-unknown-linux-musleabihf" => &[
+            "armv7-linux-musleabihf",
+            "armv7-unknown-linux-musleabihf",
+            "arm-linux-musleabihf",
+        ],
+        "arm-unknown-linux-musleabihf" => &["arm-linux-musleabihf", "arm-unknown-linux-musleabihf"],
+        "i686-unknown-linux-musl" => &["i686-linux-musl", "i686-unknown-linux-musl"],
+        "riscv64gc-unknown-linux-musl" => &["riscv64-linux-musl", "riscv64-unknown-linux-musl"],
+        "powerpc64le-unknown-linux-musl" => &["powerpc64le-linux-musl"],
+        "s390x-unknown-linux-musl" => &["s390x-linux-musl"],
+        // glibc targets need a gcc cross compiler with a glibc sysroot, from
+        // distribution packages or macos-cross-toolchains
+        "x86_64-unknown-linux-gnu" => &["x86_64-linux-gnu", "x86_64-unknown-linux-gnu"],
+        "aarch64-unknown-linux-gnu" => &["aarch64-linux-gnu", "aarch64-unknown-linux-gnu"],
+        "armv7-unknown-linux-gnueabihf" => {
+            &["arm-linux-gnueabihf", "armv7-unknown-linux-gnueabihf"]
+        }
+        "arm-unknown-linux-gnueabihf" => &["arm-linux-gnueabihf", "arm-unknown-linux-gnueabihf"],
+        "i686-unknown-linux-gnu" => &["i686-linux-gnu", "i686-unknown-linux-gnu"],
+        "riscv64gc-unknown-linux-gnu" => &["riscv64-linux-gnu", "riscv64-unknown-linux-gnu"],
+        "powerpc64le-unknown-linux-gnu" => &["powerpc64le-linux-gnu"],
+        "s390x-unknown-linux-gnu" => &["s390x-linux-gnu"],
+        _ => &[],
+    };
+    let mut candidates: Vec<String> = prefixes.iter().map(|p| format!("{}-gcc", p)).collect();
+
+    // Distribution gcc cross compilers link musl targets too, since Rust
+    // supplies its own self-contained musl startup objects
+    if host_os == "linux" {
+        let gnu = match target {
+            "aarch64-unknown-linux-musl" => Some("aarch64-linux-gnu-gcc"),
+            "armv7-unknown-linux-musleabihf" | "arm-unknown-linux-musleabihf" => {
+                Some("arm-linux-gnueabihf-gcc")
+            }
+            "riscv64gc-unknown-linux-musl" => Some("riscv64-linux-gnu-gcc"),
+            "powerpc64le-unknown-linux-musl" => Some("powerpc64le-linux-gnu-gcc"),
+            "s390x-unknown-linux-musl" => Some("s390x-linux-gnu-gcc"),
+            "x86_64-unknown-linux-musl" => Some("x86_64-linux-gnu-gcc"),
+            _ => None,
+        };
+        candidates.extend(gnu.map(String::from));
+    }
+    candidates
+}
+
+/// The linker to use for `target`: the first candidate found on `PATH`,
+/// otherwise `rust-lld`
+pub fn detect(target: &str) -> String {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let dirs: Vec<PathBuf> = std::env::split_paths(&path).collect();
+    candidates(target, std::env::consts::OS)
+        .into_iter()
+        .find(|candidate| find_in(&dirs, candidate).is_some())
+        .unwrap_or_else(|| RUST_LLD.to_string())
+}
+
+/// The environment variable cargo reads the linker for `target` from,
+/// e.g. `CARGO_TARGET_AARCH64_UNKNOWN_LINUX_MUSL_LINKER`
+pub fn env_var(target: &str) -> String {
+    format!(
+        "CARGO_TARGET_{}_LINKER",
+        target.to_uppercase().replace(['-', '.'], "_")
+    )
+}
+
+fn find_in(dirs: &[PathBuf], name: &str) -> Option<PathBuf> {
+    let file_name = format!("{}{}", name, std::env::consts::EXE_SUFFIX);
+    dirs.iter()
+        .map(|dir| dir.join(&file_name))
+        .find(|path| is_file(path))
+}
+
+fn is_file(path: &Path) -> bool {
+    path.metadata().map(|m| m.is_file()).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidates() {
+        assert_eq!(
+            candidates("aarch64-unknown-linux-musl", "macos"),
+            vec!["aarch64-linux-musl-gcc", "aarch64-unknown-linux-musl-gcc"]
+        );
+        assert_eq!(
+            candidates("aarch64-unknown-linux-musl", "linux")
+                .last()
+                .unwrap(),
+            "aarch64-linux-gnu-gcc"
+        );
+        assert_eq!(
+            candidates("armv7-unknown-linux-musleabihf", "windows")[0],
+            "armv7-linux-musleabihf-gcc"
+        );
+        assert!(candidates("riscv64gc-unknown-linux-musl", "linux")
+            .contains(&"riscv64-linux-gnu-gcc".to_string()));
+        assert_eq!(
+            candidates("aarch64-unknown-linux-gnu", "macos"),
+            vec!["aarch64-linux-gnu-gcc", "aarch64-unknown-linux-gnu-gcc"]
+        );
+        assert!(candidates("wasm32-unknown-unknown", "linux").is_empty());
+    }
+
+    #[test]
+    fn test_find_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let linker = format!("aarch64-linux-musl-gcc{}", std::env::consts::EXE_SUFFIX);
+        std::fs::write(dir.path().join(&linker), "").unwrap();
+
+        let dirs = vec![dir.path().to_path_buf()];
+        assert_eq!(
+            find_in(&dirs, "aarch64-linux-musl-gcc"),
+            Some(dir.path().join(linker))
+        );
+        assert_eq!(find_in(&dirs, "riscv64-linux-musl-gcc"), None);
+    }
+
+    #[test]
+    fn test_env_var() {
+        assert_eq!(
+            env_var("armv7-unknown-linux-musleabihf"),
+            "CARGO_TARGET_ARMV7_UNKNOWN_LINUX_MUSLEABIHF_LINKER"
+        );
+    }
+}
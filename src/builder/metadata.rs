@@ -0,0 +1,398 @@
+//! Package and target discovery with `cargo metadata`
+//!
+//! Cargo resolves workspace inheritance, `[[bin]]` sections and target
+//! auto-discovery, so asking it is more reliable than reading Cargo.toml.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The parts of `cargo metadata --format-version 1` krust uses
+#[derive(Debug, Clone, Deserialize)]
+pub struct CargoMetadata {
+    pub packages: Vec<Package>,
+    pub workspace_members: Vec<String>,
+    pub workspace_root: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Package {
+    pub name: String,
+    pub id: String,
+    pub manifest_path: PathBuf,
+    /// Where the package comes from; None for local (workspace and path) packages
+    #[serde(default)]
+    pub source: Option<String>,
+    pub targets: Vec<Target>,
+    #[serde(default)]
+    pub default_run: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Target {
+    pub name: String,
+    pub kind: Vec<String>,
+}
+
+impl CargoMetadata {
+    /// Run `cargo metadata` for the project in `project_path`, listing only
+    /// the workspace's members
+    pub fn load(project_path: &Path) -> Result<Self> {
+        Self::run(project_path, &["--no-deps".to_string()])
+    }
+
+    /// Run `cargo metadata` for the project in `project_path`, listing every
+    /// package it depends on. `args` are passed on, e.g. `--offline`.
+    pub fn load_with_deps(project_path: &Path, args: &[String]) -> Result<Self> {
+        Self::run(project_path, args)
+    }
+
+    fn run(project_path: &Path, args: &[String]) -> Result<Self> {
+        let manifest_path = project_path.join("Cargo.toml");
+        if !manifest_path.exists() {
+            anyhow::bail!("No Cargo.toml found at {}", manifest_path.display());
+        }
+
+        let output = Command::new("cargo")
+            .args(["metadata", "--format-version", "1"])
+            .args(args)
+            .arg("--manifest-path")
+            .arg(&manifest_path)
+            .output()
+            .context("Failed to execute cargo metadata")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to parse {} with cargo metadata: {}",
+                manifest_path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        Self::from_json(&output.stdout)
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self> {
+        serde_json::from_slice(data).context("Failed to parse cargo metadata output")
+    }
+
+    /// The package whose manifest is `project_path/Cargo.toml`, or None for
+    /// a virtual workspace manifest
+    pub fn root_package(&self, project_path: &Path) -> Option<&Package> {
+        let manifest_path = project_path.join("Cargo.toml");
+        let manifest_path = manifest_path.canonicalize().unwrap_or(manifest_path);
+        self.packages.iter().find(|p| {
+            p.manifest_path
+                .canonicalize()
+                .unwrap_or(p.manifest_path.clone())
+                == manifest_path
+        })
+    }
+
+    /// `package`'s directory relative to the workspace's parent, e.g.
+    /// `app/crates/api`. `root_name` replaces the workspace directory's name.
+    pub fn package_path(&self, package: &Package, root_name: Option<&str>) -> String {
+        let root_name = root_name.map(str::to_string).unwrap_or_else(|| {
+            self.workspace_root
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+        let relative = package
+            .manifest_path
+            .parent()
+            .and_then(|dir| dir.strip_prefix(&self.workspace_root).ok())
+            .map(|dir| {
+                dir.components()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        std::iter::once(root_name)
+            .chain(relative)
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Directories of local packages, workspace members and path
+    /// dependencies, leaving out any inside another one or the workspace
+    pub fn local_package_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = self
+            .packages
+            .iter()
+            .filter(|p| p.source.is_none())
+            .filter_map(|p| p.manifest_path.parent().map(Path::to_path_buf))
+            .chain(std::iter::once(self.workspace_root.clone()))
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+        let mut roots: Vec<PathBuf> = Vec::new();
+        for dir in dirs {
+            if !roots.iter().any(|root| dir.starts_with(root)) {
+                roots.push(dir);
+            }
+        }
+        roots
+    }
+
+    /// Members of the workspace, in the order cargo lists them
+    pub fn workspace_packages(&self) -> impl Iterator<Item = &Package> {
+        self.packages
+            .iter()
+            .filter(|p| self.workspace_members.contains(&p.id))
+    }
+
+    /// The workspace member named `name`
+    pub fn member(&self, name: &str) -> Result<&Package> {
+        self.workspace_packages()
+            .find(|p| p.name == name)
+            .with_context(|| {
+                format!(
+                    "Package {} is not a member of the workspace at {} (members: {})",
+                    name,
+                    self.workspace_root.display(),
+                    self.member_names()
+                )
+            })
+    }
+
+    fn member_names(&self) -> String {
+        self.workspace_packages()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl Package {
+    /// Names of the package's binary targets
+    pub fn binaries(&self) -> Vec<&str> {
+        self.targets
+            .iter()
+            .filter(|t| t.kind.iter().any(|k| k == "bin"))
+            .map(|t| t.name.as_str())
+            .collect()
+    }
+
+    /// Fail early for library-only packages, which have nothing to put in an image
+    pub fn ensure_binaries(&self) -> Result<()> {
+        if self.binaries().is_empty() {
+            anyhow::bail!(
+                "package {} has no binary targets; did you mean --example or -p <member>?",
+                self.name
+            );
+        }
+        Ok(())
+    }
+
+    /// The image name for one of the package's binaries when each binary
+    /// gets its own image: the package name for a binary of the same name,
+    /// otherwise `<package>-<binary>`
+    pub fn image_name(&self, binary: &str) -> String {
+        if binary == self.name {
+            self.name.clone()
+        } else {
+            format!("{}-{}", self.name, binary)
+        }
+    }
+
+    /// The binary `cargo run` would run: `default-run` if set, otherwise
+    /// the only binary target
+    pub fn default_binary(&self) -> Result<&str> {
+        if let Some(default_run) = &self.default_run {
+            return Ok(default_run);
+        }
+        self.ensure_binaries()?;
+        match self.binaries().as_slice() {
+            [only] => Ok(only),
+            many => anyhow::bail!(
+                "Package {} has several binaries ({}); choose one with `-- --bin <NAME>` \
+                 or set `default-run` in Cargo.toml",
+                self.name,
+                many.join(", ")
+            ),
+        }
+    }
+}
+
+/// The package in `project_path`
+pub fn root_package(project_path: &Path) -> Result<Package> {
+    Ok(root_package_path(project_path, None)?.0)
+}
+
+/// The package in `project_path` and its path in the workspace, as
+/// [`CargoMetadata::package_path`] gives it
+pub fn root_package_path(
+    project_path: &Path,
+    root_name: Option<&str>,
+) -> Result<(Package, String)> {
+    let metadata = CargoMetadata::load(project_path)?;
+    let package = metadata.root_package(project_path).with_context(|| {
+        format!(
+            "{} is a virtual workspace manifest, not a package; choose a member \
+             with -p <PACKAGE> (members: {})",
+            project_path.join("Cargo.toml").display(),
+            metadata.member_names()
+        )
+    })?;
+    Ok((package.clone(), metadata.package_path(package, root_name)))
+}
+
+/// The name of the package in `project_path`
+pub fn package_name(project_path: &Path) -> Result<String> {
+    Ok(root_package(project_path)?.name)
+}
+
+/// The directory of the package to build: the workspace member named
+/// `package` when one is selected, otherwise `project_path` itself
+pub fn package_dir(project_path: &Path, package: Option<&str>) -> Result<PathBuf> {
+    let Some(package) = package else {
+        return Ok(project_path.to_path_buf());
+    };
+    let metadata = CargoMetadata::load(project_path)?;
+    let member = metadata.member(package)?;
+    let dir = member
+        .manifest_path
+        .parent()
+        .context("Package manifest has no parent directory")?;
+    Ok(dir.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METADATA: &str = r#"{
+        "packages": [
+            {
+                "name": "server",
+                "id": "path+file:///ws/server#0.1.0",
+                "manifest_path": "/ws/server/Cargo.toml",
+                "targets": [
+                    {"name": "server", "kind": ["lib"]},
+                    {"name": "serve", "kind": ["bin"]},
+                    {"name": "migrate", "kind": ["bin"]}
+                ],
+                "default_run": null
+            },
+            {
+                "name": "tool",
+                "id": "path+file:///ws/tool#0.1.0",
+                "manifest_path": "/ws/tool/Cargo.toml",
+                "targets": [{"name": "tool-cli", "kind": ["bin"]}]
+            },
+            {
+                "name": "shared",
+                "id": "path+file:///libs/shared#0.1.0",
+                "manifest_path": "/libs/shared/Cargo.toml",
+                "source": null,
+                "targets": [{"name": "shared", "kind": ["lib"]}]
+            },
+            {
+                "name": "serde",
+                "id": "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0",
+                "manifest_path": "/cargo/registry/src/serde-1.0.0/Cargo.toml",
+                "source": "registry+https://github.com/rust-lang/crates.io-index",
+                "targets": [{"name": "serde", "kind": ["lib"]}]
+            }
+        ],
+        "workspace_members": ["path+file:///ws/server#0.1.0", "path+file:///ws/tool#0.1.0"],
+        "workspace_root": "/ws",
+        "target_directory": "/ws/target",
+        "version": 1
+    }"#;
+
+    #[test]
+    fn test_binaries() {
+        let metadata = CargoMetadata::from_json(METADATA.as_bytes()).unwrap();
+        assert_eq!(metadata.packages[0].binaries(), vec!["serve", "migrate"]);
+        assert_eq!(metadata.workspace_packages().count(), 2);
+    }
+
+    #[test]
+    fn test_default_binary() {
+        let metadata = CargoMetadata::from_json(METADATA.as_bytes()).unwrap();
+
+        // A renamed [[bin]] is found by its target name
+        assert_eq!(metadata.packages[1].default_binary().unwrap(), "tool-cli");
+
+        let err = metadata.packages[0].default_binary().unwrap_err();
+        assert!(err.to_string().contains("serve, migrate"));
+
+        let mut server = metadata.packages[0].clone();
+        server.default_run = Some("serve".to_string());
+        assert_eq!(server.default_binary().unwrap(), "serve");
+    }
+
+    #[test]
+    fn test_ensure_binaries() {
+        let metadata = CargoMetadata::from_json(METADATA.as_bytes()).unwrap();
+        assert!(metadata.packages[0].ensure_binaries().is_ok());
+
+        let mut lib = metadata.packages[0].clone();
+        lib.targets.retain(|t| t.kind.iter().any(|k| k == "lib"));
+        assert_eq!(
+            lib.ensure_binaries().unwrap_err().to_string(),
+            "package server has no binary targets; did you mean --example or -p <member>?"
+        );
+        assert!(lib.default_binary().is_err());
+    }
+
+    #[test]
+    fn test_image_name() {
+        let metadata = CargoMetadata::from_json(METADATA.as_bytes()).unwrap();
+        let server = &metadata.packages[0];
+        assert_eq!(server.image_name("serve"), "server-serve");
+
+        let mut app = server.clone();
+        app.name = "serve".to_string();
+        assert_eq!(app.image_name("serve"), "serve");
+    }
+
+    #[test]
+    fn test_root_package() {
+        let metadata = CargoMetadata::from_json(METADATA.as_bytes()).unwrap();
+        assert_eq!(
+            metadata.root_package(Path::new("/ws/tool")).unwrap().name,
+            "tool"
+        );
+        assert!(metadata.root_package(Path::new("/ws")).is_none());
+    }
+
+    #[test]
+    fn test_package_path() {
+        let metadata = CargoMetadata::from_json(METADATA.as_bytes()).unwrap();
+        let tool = &metadata.packages[1];
+        assert_eq!(metadata.package_path(tool, None), "ws/tool");
+        assert_eq!(
+            metadata.package_path(tool, Some("github.com/org/ws")),
+            "github.com/org/ws/tool"
+        );
+
+        let mut root = tool.clone();
+        root.manifest_path = PathBuf::from("/ws/Cargo.toml");
+        assert_eq!(metadata.package_path(&root, None), "ws");
+    }
+
+    #[test]
+    fn test_member() {
+        let metadata = CargoMetadata::from_json(METADATA.as_bytes()).unwrap();
+        assert_eq!(
+            metadata.member("tool").unwrap().manifest_path,
+            PathBuf::from("/ws/tool/Cargo.toml")
+        );
+
+        let err = metadata.member("missing").unwrap_err();
+        assert!(err.to_string().contains("members: server, tool"));
+    }
+
+    #[test]
+    fn test_local_package_dirs() {
+        let metadata = CargoMetadata::from_json(METADATA.as_bytes()).unwrap();
+        assert_eq!(
+            metadata.local_package_dirs(),
+            [PathBuf::from("/libs/shared"), PathBuf::from("/ws")]
+        );
+    }
+}
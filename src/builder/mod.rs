@@ -1,19 +1,61 @@
+use crate::config::{BuildBackend, Libc, SbomFormat};
+use crate::events::{self, Event};
+use crate::manifest::Platform;
 use anyhow::{Context, Result};
+use indicatif::HumanBytes;
+use metadata::{CargoMetadata, Package};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use tracing::{debug, info};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+pub mod cancel;
+pub mod debuginfo;
+pub mod fingerprint;
+pub mod git;
+pub mod hooks;
+pub mod linker;
+pub mod metadata;
+pub mod sbom;
+pub mod toolchain;
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests;
 
+#[derive(Clone)]
 pub struct RustBuilder {
     project_path: PathBuf,
     target: String,
     cargo_args: Vec<String>,
+    backend: BuildBackend,
+    toolchain: Option<String>,
+    profile: Option<String>,
+    features: Vec<String>,
+    no_default_features: bool,
+    env: BTreeMap<String, String>,
+    target_dir: Option<PathBuf>,
+    strip: bool,
+    opt_level: Option<String>,
+    lto: Option<String>,
+    split_debuginfo: bool,
+    auditable: bool,
+    pre_build: Vec<String>,
+    post_build: Vec<String>,
+    locked: bool,
+    offline: bool,
+    timeout: Option<Duration>,
+    cache_dir: Option<PathBuf>,
+    rebuild: bool,
 }
 
 pub struct BuildResult {
     pub binary_path: PathBuf,
+    /// Debug info split from the binary, with `split_debuginfo`
+    pub debug_path: Option<PathBuf>,
 }
 
 impl RustBuilder {
@@ -22,6 +64,25 @@ impl RustBuilder {
             project_path: project_path.as_ref().to_path_buf(),
             target: target.to_string(),
             cargo_args: Vec::new(),
+            backend: BuildBackend::default(),
+            toolchain: None,
+            profile: None,
+            features: Vec::new(),
+            no_default_features: false,
+            env: BTreeMap::new(),
+            target_dir: None,
+            strip: false,
+            opt_level: None,
+            lto: None,
+            split_debuginfo: false,
+            auditable: false,
+            pre_build: Vec::new(),
+            post_build: Vec::new(),
+            locked: false,
+            offline: false,
+            timeout: None,
+            cache_dir: None,
+            rebuild: false,
         }
     }
 
@@ -30,16 +91,233 @@ impl RustBuilder {
         self
     }
 
-    /// Check that cargo-zigbuild is available, or bail with install instructions.
-    fn require_zigbuild() -> Result<()> {
-        let available = Command::new("cargo")
+    pub fn with_backend(mut self, backend: BuildBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Build with this toolchain instead of the one the project's
+    /// rust-toolchain.toml pins
+    pub fn with_toolchain(mut self, toolchain: Option<String>) -> Self {
+        self.toolchain = toolchain;
+        self
+    }
+
+    /// Build with a cargo profile other than `release`
+    pub fn with_profile(mut self, profile: Option<String>) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    pub fn with_features(mut self, features: Vec<String>) -> Self {
+        self.features = features;
+        self
+    }
+
+    pub fn with_no_default_features(mut self, no_default_features: bool) -> Self {
+        self.no_default_features = no_default_features;
+        self
+    }
+
+    /// Environment variables for cargo. `RUSTFLAGS` is appended to the flags
+    /// krust sets for static linking rather than replacing them.
+    pub fn with_env(mut self, env: BTreeMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Build in this target directory instead of `<project>/target/krust`.
+    /// Relative paths are relative to the project.
+    pub fn with_target_dir(mut self, target_dir: Option<PathBuf>) -> Self {
+        self.target_dir = target_dir;
+        self
+    }
+
+    /// Strip symbols from the binary
+    pub fn with_strip(mut self, strip: bool) -> Self {
+        self.strip = strip;
+        self
+    }
+
+    /// Override the profile's `opt-level` and `lto` settings
+    pub fn with_optimization(mut self, opt_level: Option<String>, lto: Option<String>) -> Self {
+        self.opt_level = opt_level;
+        self.lto = lto;
+        self
+    }
+
+    /// Build with debug info, then move it out of the binary into a
+    /// `<binary>.debug` file
+    pub fn with_split_debuginfo(mut self, split_debuginfo: bool) -> Self {
+        self.split_debuginfo = split_debuginfo;
+        self
+    }
+
+    /// Embed the dependency tree in the binary with cargo-auditable, so
+    /// scanners such as `cargo audit` and trivy can check the image
+    pub fn with_auditable(mut self, auditable: bool) -> Self {
+        self.auditable = auditable;
+        self
+    }
+
+    /// Shell commands to run in the project directory before and after cargo
+    pub fn with_hooks(mut self, pre_build: Vec<String>, post_build: Vec<String>) -> Self {
+        self.pre_build = pre_build;
+        self.post_build = post_build;
+        self
+    }
+
+    /// Require Cargo.lock to be up to date (`--locked`)
+    pub fn with_locked(mut self, locked: bool) -> Self {
+        self.locked = locked;
+        self
+    }
+
+    /// Fail instead of accessing the network (`--offline`)
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Stop the build if it takes longer than `timeout`, including hooks
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Directory to cache the last build of each target in, so a build whose
+    /// fingerprint is unchanged can be skipped
+    pub fn with_cache_dir(mut self, cache_dir: Option<PathBuf>) -> Self {
+        self.cache_dir = cache_dir;
+        self
+    }
+
+    /// Build even if the cached build's fingerprint matches, refreshing it
+    pub fn with_rebuild(mut self, rebuild: bool) -> Self {
+        self.rebuild = rebuild;
+        self
+    }
+
+    /// Check that cargo-auditable is installed, or bail with install instructions.
+    fn require_auditable(toolchain: Option<&str>) -> Result<()> {
+        let output = toolchain::cargo(toolchain)
+            .arg("--list")
+            .output()
+            .context("Failed to execute cargo --list")?;
+        let installed = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|line| line.split_whitespace().next() == Some("auditable"));
+        if !installed {
+            anyhow::bail!(
+                "build.auditable requires cargo-auditable.\n\
+                 Install it with: cargo install cargo-auditable"
+            );
+        }
+        Ok(())
+    }
+
+    /// Environment variables overriding settings of the profile being built
+    fn profile_env(&self) -> Vec<(String, String)> {
+        let profile = self
+            .profile
+            .as_deref()
+            .unwrap_or("release")
+            .to_uppercase()
+            .replace('-', "_");
+        let mut env = Vec::new();
+        if let Some(opt_level) = &self.opt_level {
+            env.push((
+                format!("CARGO_PROFILE_{}_OPT_LEVEL", profile),
+                opt_level.clone(),
+            ));
+        }
+        if let Some(lto) = &self.lto {
+            env.push((format!("CARGO_PROFILE_{}_LTO", profile), lto.clone()));
+        }
+        if self.split_debuginfo {
+            env.push((
+                format!("CARGO_PROFILE_{}_DEBUG", profile),
+                "true".to_string(),
+            ));
+        }
+        env
+    }
+
+    /// `--remap-path-prefix` flags that replace the machine-specific paths
+    /// rustc embeds in binaries (in panic messages and debug info) with fixed
+    /// ones, so the same sources build the same binary on any machine
+    fn remap_path_flags(&self, target_dir: &Path) -> Vec<String> {
+        let absolute = |path: &Path| {
+            std::path::absolute(path)
+                .map(|p| p.canonicalize().unwrap_or(p))
+                .unwrap_or_else(|_| path.to_path_buf())
+        };
+        let workspace_root = CargoMetadata::load(&self.project_path)
+            .map(|m| m.workspace_root)
+            .unwrap_or_else(|_| absolute(&self.project_path));
+        let cargo_home = std::env::var_os("CARGO_HOME")
+            .map(PathBuf::from)
+            .or_else(|| dirs::home_dir().map(|home| home.join(".cargo")));
+        remap_path_flags(
+            cargo_home.as_deref(),
+            &workspace_root,
+            &absolute(target_dir),
+        )
+    }
+
+    /// `--locked` and `--offline`, unless the cargo arguments already pass
+    /// them (cargo rejects repeated flags)
+    fn lock_args(&self) -> Vec<String> {
+        let given = |flag: &str| self.cargo_args.iter().any(|arg| arg == flag);
+        if given("--frozen") {
+            return Vec::new();
+        }
+        let mut args = Vec::new();
+        if self.locked && !given("--locked") {
+            args.push("--locked".to_string());
+        }
+        if self.offline && !given("--offline") {
+            args.push("--offline".to_string());
+        }
+        args
+    }
+
+    /// Profile and feature selection arguments for cargo
+    fn profile_args(&self) -> Vec<String> {
+        let mut args = match &self.profile {
+            Some(profile) => vec!["--profile".to_string(), profile.clone()],
+            None => vec!["--release".to_string()],
+        };
+        args.extend(self.feature_args());
+        args
+    }
+
+    /// Feature selection arguments for cargo
+    fn feature_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.no_default_features {
+            args.push("--no-default-features".to_string());
+        }
+        if !self.features.is_empty() {
+            args.push("--features".to_string());
+            args.push(self.features.join(","));
+        }
+        args
+    }
+
+    fn zigbuild_available(toolchain: Option<&str>) -> bool {
+        toolchain::cargo(toolchain)
             .args(["zigbuild", "--help"])
-            .stdout(std::process::Stdio::null())
-            .stderr(std::process::Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
             .status()
             .map(|s| s.success())
-            .unwrap_or(false);
-        if !available {
+            .unwrap_or(false)
+    }
+
+    /// Check that cargo-zigbuild is available, or bail with install instructions.
+    fn require_zigbuild(toolchain: Option<&str>) -> Result<()> {
+        if !Self::zigbuild_available(toolchain) {
             anyhow::bail!(
                 "cargo-zigbuild is required but not found.\n\
                  Install it with: cargo install cargo-zigbuild\n\
@@ -49,10 +327,37 @@ impl RustBuilder {
         Ok(())
     }
 
+    fn cross_available() -> bool {
+        Command::new("cross")
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false)
+    }
+
+    /// Check that cross is available, or bail with install instructions.
+    fn require_cross() -> Result<()> {
+        if !Self::cross_available() {
+            anyhow::bail!(
+                "cross is required but not found.\n\
+                 Install it with: cargo install cross\n\
+                 It also needs Docker or Podman to run its build containers"
+            );
+        }
+        Ok(())
+    }
+
     /// Check if the rustup target is installed, and install it if not.
-    fn ensure_target_installed(target: &str) -> Result<()> {
+    /// Offline builds can't download it, so fail with instructions instead.
+    fn ensure_target_installed(target: &str, toolchain: Option<&str>, offline: bool) -> Result<()> {
+        let toolchain_args = toolchain
+            .map(|t| vec!["--toolchain", t])
+            .unwrap_or_default();
         let output = Command::new("rustup")
             .args(["target", "list", "--installed"])
+            .args(&toolchain_args)
             .output()
             .context("Failed to run rustup. Is rustup installed?")?;
 
@@ -61,9 +366,18 @@ impl RustBuilder {
             return Ok(());
         }
 
+        if offline {
+            anyhow::bail!(
+                "Target '{}' isn't installed and offline builds can't download it. Run: rustup target add {}",
+                target,
+                target
+            );
+        }
+
         info!("Installing rustup target: {}", target);
         let status = Command::new("rustup")
             .args(["target", "add", target])
+            .args(&toolchain_args)
             .status()
             .context("Failed to run rustup target add")?;
 
@@ -79,38 +393,272 @@ impl RustBuilder {
     }
 
     /// Get the persistent target directory for krust builds.
-    /// Uses `<project>/target/krust/` so cargo can reuse build caches.
+    /// Uses `<project>/target/krust/<target>/` so cargo can reuse build
+    /// caches, while builds for different targets don't wait on each other's
+    /// lock of the target directory.
     fn target_dir(&self) -> PathBuf {
-        self.project_path.join("target").join("krust")
+        let base = match &self.target_dir {
+            Some(dir) => self.project_path.join(dir),
+            None => self.project_path.join("target").join("krust"),
+        };
+        base.join(&self.target)
+    }
+
+    /// Environment for hooks: the build's variables plus a description of
+    /// the build
+    fn hook_env(&self, target_dir: &Path) -> BTreeMap<String, String> {
+        let mut env = self.env.clone();
+        env.insert("KRUST_TARGET".to_string(), self.target.clone());
+        env.insert(
+            "KRUST_PROFILE".to_string(),
+            self.profile
+                .clone()
+                .unwrap_or_else(|| "release".to_string()),
+        );
+        env.insert(
+            "KRUST_TARGET_DIR".to_string(),
+            target_dir.display().to_string(),
+        );
+        env
+    }
+
+    /// The fingerprint of this build: the files of the workspace and of path
+    /// dependencies, the settings, the compiler's version and the cargo and
+    /// rustc environment variables krust inherits
+    fn fingerprint(&self, toolchain: Option<&str>) -> Result<String> {
+        let mut metadata_args = self.lock_args();
+        if self.cargo_args.iter().any(|arg| arg == "--frozen") {
+            metadata_args.push("--frozen".to_string());
+        }
+        let metadata = CargoMetadata::load_with_deps(&self.project_path, &metadata_args)?;
+        let workspace_root = metadata.workspace_root.clone();
+        let target_base = self.target_dir();
+        let exclude: Vec<PathBuf> = [
+            workspace_root.join("target"),
+            self.project_path.join("target"),
+            target_base.parent().unwrap_or(&target_base).to_path_buf(),
+        ]
+        .into_iter()
+        .map(|dir| dir.canonicalize().unwrap_or(dir))
+        .collect();
+        let inherited: BTreeMap<String, String> = std::env::vars()
+            .filter(|(name, _)| {
+                name.starts_with("CARGO_")
+                    || name.starts_with("RUSTC")
+                    || name == "RUSTFLAGS"
+                    || name == "RUSTUP_TOOLCHAIN"
+            })
+            .collect();
+        // An unpinned toolchain is whichever one rustup or PATH picks, so the
+        // compiler is identified by its own version output
+        let rustc_program = self
+            .env
+            .get("RUSTC")
+            .or_else(|| inherited.get("RUSTC"))
+            .map(String::as_str);
+        let rustc = toolchain::rustc(toolchain, rustc_program)
+            .arg("-vV")
+            .current_dir(&self.project_path)
+            .envs(&self.env)
+            .output()
+            .context("Failed to run rustc -vV")?;
+        if !rustc.status.success() {
+            anyhow::bail!(
+                "rustc -vV failed: {}",
+                String::from_utf8_lossy(&rustc.stderr).trim()
+            );
+        }
+
+        let mut fingerprint = fingerprint::Fingerprint::new();
+        fingerprint
+            .setting("krust", env!("CARGO_PKG_VERSION"))
+            .setting("target", &self.target)
+            .setting("cargo_args", &self.cargo_args)
+            .setting("backend", self.backend)
+            .setting("toolchain", toolchain)
+            .setting("rustc", String::from_utf8_lossy(&rustc.stdout))
+            .setting("profile", &self.profile)
+            .setting("features", &self.features)
+            .setting("no_default_features", self.no_default_features)
+            .setting("env", &self.env)
+            .setting("inherited_env", &inherited)
+            .setting("strip", self.strip)
+            .setting("opt_level", &self.opt_level)
+            .setting("lto", &self.lto)
+            .setting("split_debuginfo", self.split_debuginfo)
+            .setting("auditable", self.auditable)
+            .setting("pre_build", &self.pre_build)
+            .setting("post_build", &self.post_build);
+        for dir in metadata.local_package_dirs() {
+            fingerprint
+                .setting("package_dir", &dir)
+                .tree(&dir, &exclude)?;
+        }
+        Ok(fingerprint.digest())
+    }
+
+    /// The cache entry for this project, target and cargo arguments
+    fn build_cache(&self) -> Option<fingerprint::BuildCache> {
+        let cache_dir = self.cache_dir.as_ref()?;
+        let project = self
+            .project_path
+            .canonicalize()
+            .unwrap_or_else(|_| self.project_path.clone());
+        let key = format!(
+            "{}\n{}\n{}",
+            project.display(),
+            self.target,
+            self.cargo_args.join(" ")
+        );
+        Some(fingerprint::BuildCache::new(cache_dir, &key))
     }
 
     pub fn build(&self) -> Result<BuildResult> {
         info!("Building Rust project at {:?}", self.project_path);
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
 
-        // Ensure the target is installed via rustup
-        Self::ensure_target_installed(&self.target)?;
+        let toolchain = match &self.toolchain {
+            Some(toolchain) => Some(toolchain.clone()),
+            None => toolchain::find(&self.project_path)?,
+        };
+        let toolchain = toolchain.as_deref();
+        if let Some(toolchain) = toolchain {
+            info!("Using Rust toolchain {}", toolchain);
+        }
+
+        let cache = self.build_cache();
+        let fingerprint = match cache {
+            Some(_) => Some(self.fingerprint(toolchain)?),
+            None => None,
+        };
+        if let (Some(cache), Some(fingerprint), false) = (&cache, &fingerprint, self.rebuild) {
+            if let Some(result) = cache.lookup(fingerprint) {
+                info!(
+                    "Sources unchanged since the last build for {}, reusing {:?}",
+                    self.target, result.binary_path
+                );
+                return Ok(result);
+            }
+        }
+
+        let native = is_native_target(&self.target);
+        let backend = resolve_backend(
+            self.backend,
+            Self::zigbuild_available(toolchain),
+            Self::cross_available(),
+            native,
+        );
+
+        // cross brings the target's standard library in its container
+        if backend != BuildBackend::Cross {
+            Self::ensure_target_installed(&self.target, toolchain, self.offline)?;
+        }
 
         let target_dir = self.target_dir();
-        Self::require_zigbuild()?;
+        let mut hook_env = self.hook_env(&target_dir);
+        hooks::run(
+            hooks::Stage::PreBuild,
+            &self.pre_build,
+            &self.project_path,
+            &hook_env,
+            &self.target,
+            deadline,
+        )?;
+
+        if self.auditable {
+            if backend == BuildBackend::Cross {
+                anyhow::bail!("build.auditable isn't supported with the cross backend");
+            }
+            Self::require_auditable(toolchain)?;
+        }
+        // `cargo auditable <subcommand>` runs the subcommand with rustc wrapped
+        // to embed the dependency tree
+        let cargo = || {
+            let mut cmd = toolchain::cargo(toolchain);
+            if self.auditable {
+                cmd.arg("auditable");
+            }
+            cmd
+        };
 
-        let mut cmd = Command::new("cargo");
-        info!("Using cargo-zigbuild for cross-compilation");
-        cmd.arg("zigbuild");
+        let mut cmd = match backend {
+            BuildBackend::Zig | BuildBackend::Auto => {
+                Self::require_zigbuild(toolchain)?;
+                info!("Using cargo-zigbuild for cross-compilation");
+                let mut cmd = cargo();
+                cmd.arg("zigbuild");
+                cmd
+            }
+            BuildBackend::Cross => {
+                Self::require_cross()?;
+                info!("Using cross to build {} in a container", self.target);
+                let mut cmd = Command::new("cross");
+                if let Some(toolchain) = toolchain {
+                    cmd.arg(format!("+{}", toolchain));
+                }
+                cmd.arg("build");
+                cmd
+            }
+            BuildBackend::Cargo => {
+                let mut cmd = cargo();
+                cmd.arg("build");
+                // Pick a linker for other architectures unless one is configured
+                let linker_var = linker::env_var(&self.target);
+                if !native && std::env::var_os(&linker_var).is_none() {
+                    let linker = linker::detect(&self.target);
+                    info!("Linking {} with {}", self.target, linker);
+                    cmd.env(linker_var, linker);
+                }
+                cmd
+            }
+        };
+        // Without a cross toolchain, building for another architecture only
+        // works if a linker for it is installed
+        let missing_toolchain =
+            self.backend == BuildBackend::Auto && backend == BuildBackend::Cargo && !native;
+        if missing_toolchain {
+            info!(
+                "Neither cargo-zigbuild nor cross found, using cargo build for {}",
+                self.target
+            );
+        }
 
-        cmd.arg("--release")
+        cmd.args(self.profile_args())
+            .args(self.lock_args())
             .arg("--target")
             .arg(&self.target)
             .arg("--target-dir")
             .arg(&target_dir)
             .current_dir(&self.project_path);
 
-        // Set RUSTFLAGS for static linking
-        let rustflags = if self.target.contains("musl") {
-            "-C target-feature=+crt-static"
+        // Set RUSTFLAGS for static linking of musl targets, followed by any
+        // configured flags. gnu targets link glibc dynamically.
+        let mut rustflags = if self.target.contains("musl") {
+            "-C target-feature=+crt-static".to_string()
         } else {
-            "-C target-feature=+crt-static -C link-arg=-static-libgcc"
+            String::new()
         };
-        cmd.env("RUSTFLAGS", rustflags);
+        // Splitting debug info strips the binary itself, after copying it out
+        if self.strip && !self.split_debuginfo {
+            rustflags.push_str(" -C strip=symbols");
+        }
+        // cross builds in a container, where host paths don't appear
+        if backend != BuildBackend::Cross {
+            for flag in self.remap_path_flags(&target_dir) {
+                rustflags.push(' ');
+                rustflags.push_str(&flag);
+            }
+        }
+        if let Some(extra) = self.env.get("RUSTFLAGS") {
+            rustflags = format!("{} {}", rustflags, extra);
+        }
+        cmd.envs(&self.env);
+        cmd.env("RUSTFLAGS", &rustflags);
+        cmd.envs(self.profile_env());
+
+        // Cargo reports each artifact it builds on stdout, including the path
+        // of executables, while diagnostics are still rendered on stderr
+        cmd.arg("--message-format=json-render-diagnostics");
 
         for arg in &self.cargo_args {
             cmd.arg(arg);
@@ -120,36 +668,107 @@ impl RustBuilder {
         debug!("RUSTFLAGS: {}", rustflags);
 
         info!("Running cargo build for target: {}", self.target);
-        let output = cmd.output().context("Failed to execute cargo build")?;
+        let mut child = cancel::spawn(cmd.stdout(Stdio::piped()).stderr(Stdio::piped()))
+            .context("Failed to execute cargo build")?;
+
+        // Stream compiler progress as it happens, keeping it for the error report.
+        // Both pipes are drained on their own threads so neither can fill up
+        // and stall cargo while this one watches for a timeout or cancellation.
+        let stderr = child
+            .stderr
+            .take()
+            .context("Failed to capture cargo stderr")?;
+        let target = self.target.clone();
+        let stderr = std::thread::spawn(move || stream_lines(stderr, &target));
+
+        let mut stdout = child
+            .stdout
+            .take()
+            .context("Failed to capture cargo stdout")?;
+        let stdout = std::thread::spawn(move || {
+            let mut output = String::new();
+            stdout.read_to_string(&mut output).map(|_| output)
+        });
+        let status = cancel::wait(
+            &mut child,
+            deadline,
+            &format!("cargo build for {}", self.target),
+        )?;
+        let stdout = stdout
+            .join()
+            .map_err(|_| anyhow::anyhow!("Reading cargo output panicked"))?
+            .context("Failed to read cargo output")?;
+        let stderr = stderr
+            .join()
+            .map_err(|_| anyhow::anyhow!("Reading cargo output panicked"))?;
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
+        if !status.success() {
+            if missing_toolchain {
+                anyhow::bail!(
+                    "Cargo build failed: {}\n\
+                     No cross-compilation toolchain was found for {}. Install cargo-zigbuild \
+                     (cargo install cargo-zigbuild) or cross (cargo install cross), or install \
+                     a linker for the target and set {}",
+                    stderr.trim_end(),
+                    self.target,
+                    linker::env_var(&self.target)
+                );
+            }
             anyhow::bail!("Cargo build failed: {}", stderr);
         }
 
-        let binary_name = self.get_binary_name()?;
-        let binary_subdir = self.get_binary_subdir();
-        let mut binary_path = target_dir.join(&self.target).join("release");
-        if let Some(subdir) = binary_subdir {
-            binary_path = binary_path.join(subdir);
+        let executables = built_executables(&stdout);
+        let mut binary_path = self.select_executable(executables)?;
+        if backend == BuildBackend::Cross {
+            binary_path = self.host_path(&binary_path);
         }
-        binary_path = binary_path.join(&binary_name);
-
-        // Sometimes cargo build completes but the binary isn't immediately visible
-        // due to filesystem sync issues. Give it a moment.
-        let mut retries = 0;
-        while !binary_path.exists() && retries < 3 {
-            std::thread::sleep(std::time::Duration::from_millis(100));
-            retries += 1;
-        }
-
         if !binary_path.exists() {
             anyhow::bail!("Built binary not found at {:?}", binary_path);
         }
+        let debug_path = if self.split_debuginfo {
+            Some(debuginfo::split(&binary_path)?)
+        } else {
+            None
+        };
 
-        info!("Successfully built binary at {:?}", binary_path);
+        hook_env.insert(
+            "KRUST_BINARY".to_string(),
+            binary_path.display().to_string(),
+        );
+        hooks::run(
+            hooks::Stage::PostBuild,
+            &self.post_build,
+            &self.project_path,
+            &hook_env,
+            &self.target,
+            deadline,
+        )?;
+
+        let size = std::fs::metadata(&binary_path)
+            .map(|m| m.len())
+            .unwrap_or_default();
+        info!(
+            "Successfully built binary at {:?} ({}{})",
+            binary_path,
+            HumanBytes(size),
+            if self.strip || self.split_debuginfo {
+                ", stripped"
+            } else {
+                ""
+            }
+        );
 
-        Ok(BuildResult { binary_path })
+        let result = BuildResult {
+            binary_path,
+            debug_path,
+        };
+        if let (Some(cache), Some(fingerprint)) = (&cache, &fingerprint) {
+            // A build that can't be cached still succeeded
+            if let Err(e) = cache.store(fingerprint, &result) {
+                warn!("Failed to cache build for {}: {:#}", self.target, e);
+            }
+        }
+        Ok(result)
     }
 
     fn get_binary_name(&self) -> Result<String> {
@@ -164,44 +783,182 @@ impl RustBuilder {
             i += 1;
         }
 
-        // Fall back to package name
-        let cargo_toml_path = self.project_path.join("Cargo.toml");
-        let content =
-            std::fs::read_to_string(&cargo_toml_path).context("Failed to read Cargo.toml")?;
-
-        let manifest: toml::Value =
-            toml::from_str(&content).context("Failed to parse Cargo.toml")?;
+        // Fall back to the package's default binary
+        Ok(self.package()?.default_binary()?.to_string())
+    }
 
-        let name = manifest
-            .get("package")
-            .and_then(|p| p.get("name"))
-            .and_then(|n| n.as_str())
-            .context("Failed to get package name from Cargo.toml")?;
+    /// An SBOM of the crates compiled into `binary` for this target, with
+    /// the features being built, in `format`
+    pub fn sbom(&self, format: SbomFormat, binary: &Path) -> Result<Option<sbom::Sbom>> {
+        if format == SbomFormat::None {
+            return Ok(None);
+        }
+        let mut args = vec!["--filter-platform".to_string(), self.target.clone()];
+        args.extend(self.feature_args());
+        args.extend(self.lock_args());
+        if self.cargo_args.iter().any(|arg| arg == "--frozen") {
+            args.push("--frozen".to_string());
+        }
+        let graph = sbom::DependencyGraph::load(&self.project_path, &args)?;
+        let binary_digest = sha256::try_digest(binary)
+            .with_context(|| format!("Failed to read {}", binary.display()))?;
+        sbom::generate(
+            format,
+            &graph,
+            &self.package()?.id,
+            &binary_digest,
+            &sbom::created(),
+        )
+    }
 
-        Ok(name.to_string())
+    fn package(&self) -> Result<Package> {
+        let metadata = CargoMetadata::load(&self.project_path)
+            .map_err(|e| anyhow::anyhow!("Failed to get package name from Cargo.toml: {:#}", e))?;
+        let package = metadata.root_package(&self.project_path).with_context(|| {
+            format!(
+                "{} is a workspace root, not a package",
+                self.project_path.display()
+            )
+        })?;
+        Ok(package.clone())
     }
 
-    fn get_binary_subdir(&self) -> Option<&str> {
-        // Check if --example was specified (examples go in "examples/" subdir)
-        for (i, arg) in self.cargo_args.iter().enumerate() {
-            if arg == "--example" && i + 1 < self.cargo_args.len() {
-                return Some("examples");
+    /// Pick the binary to package from the executables cargo built. When
+    /// several were built, the one `cargo run` would choose is used.
+    fn select_executable(&self, mut executables: Vec<(String, PathBuf)>) -> Result<PathBuf> {
+        let path = match executables.len() {
+            0 => anyhow::bail!("Cargo build did not produce an executable"),
+            1 => executables.remove(0).1,
+            _ => {
+                let binary_name = self.get_binary_name()?;
+                executables
+                    .into_iter()
+                    .find(|(name, _)| *name == binary_name)
+                    .map(|(_, path)| path)
+                    .with_context(|| format!("Cargo build did not produce {}", binary_name))?
             }
+        };
+        Ok(path)
+    }
+
+    /// Map a path cross reported from inside its container to the same file
+    /// in the host's target directory
+    fn host_path(&self, path: &Path) -> PathBuf {
+        let mut components = path.components().map(|c| c.as_os_str());
+        if components.any(|c| c == self.target.as_str()) {
+            return self
+                .target_dir()
+                .join(&self.target)
+                .join(components.collect::<PathBuf>());
         }
-        None
+        path.to_path_buf()
     }
 }
 
-pub fn get_rust_target_triple(platform: &str) -> Result<String> {
-    match platform {
-        "linux/amd64" => Ok("x86_64-unknown-linux-musl".to_string()),
-        "linux/arm64" => Ok("aarch64-unknown-linux-musl".to_string()),
-        "linux/arm/v7" => Ok("armv7-unknown-linux-musleabihf".to_string()),
-        "linux/arm/v6" => Ok("arm-unknown-linux-musleabihf".to_string()),
-        "linux/386" => Ok("i686-unknown-linux-musl".to_string()),
-        "linux/ppc64le" => Ok("powerpc64le-unknown-linux-musl".to_string()),
-        "linux/s390x" => Ok("s390x-unknown-linux-musl".to_string()),
-        "linux/riscv64" => Ok("riscv64gc-unknown-linux-musl".to_string()),
-        _ => anyhow::bail!("Unsupported platform: {}", platform),
+/// Remap the cargo home (registry and git sources), the workspace and the
+/// target directory (build script output). rustc applies the last matching
+/// flag, so the target directory wins when it's inside the workspace. Paths
+/// containing whitespace can't be passed in RUSTFLAGS and are left alone.
+fn remap_path_flags(
+    cargo_home: Option<&Path>,
+    workspace_root: &Path,
+    target_dir: &Path,
+) -> Vec<String> {
+    [
+        (cargo_home, "/cargo"),
+        (Some(workspace_root), "/build"),
+        (Some(target_dir), "/target"),
+    ]
+    .into_iter()
+    .filter_map(|(from, to)| Some((from?.to_str()?, to)))
+    .filter(|(from, _)| !from.contains(char::is_whitespace))
+    .map(|(from, to)| format!("--remap-path-prefix={}={}", from, to))
+    .collect()
+}
+
+/// Decide which backend builds the binary. `Auto` prefers cargo-zigbuild,
+/// then plain cargo for the host's own architecture, then cross.
+fn resolve_backend(
+    configured: BuildBackend,
+    zigbuild_available: bool,
+    cross_available: bool,
+    native: bool,
+) -> BuildBackend {
+    match configured {
+        BuildBackend::Auto if zigbuild_available => BuildBackend::Zig,
+        BuildBackend::Auto if !native && cross_available => BuildBackend::Cross,
+        BuildBackend::Auto => BuildBackend::Cargo,
+        backend => backend,
     }
 }
+
+/// Whether `target` is for the Linux host krust is running on, so the host's
+/// own linker can link it
+fn is_native_target(target: &str) -> bool {
+    cfg!(target_os = "linux") && target.split('-').next() == Some(std::env::consts::ARCH)
+}
+
+/// Log each line cargo writes, prefixed with the target being built since
+/// platforms build concurrently, and return everything that was written
+fn stream_lines(reader: impl Read, target: &str) -> String {
+    let mut output = String::new();
+    for line in BufReader::new(reader).lines() {
+        let Ok(line) = line else { break };
+        info!("{}: {}", target, line);
+        events::emit(Event::CargoProgress {
+            target: target.to_string(),
+            line: line.clone(),
+        });
+        output.push_str(&line);
+        output.push('\n');
+    }
+    output
+}
+
+/// A `compiler-artifact` message from `cargo build --message-format=json`
+#[derive(Deserialize)]
+struct ArtifactMessage {
+    reason: String,
+    target: Option<metadata::Target>,
+    executable: Option<PathBuf>,
+}
+
+/// The (target name, path) of every executable in cargo's JSON messages.
+/// Lines that aren't JSON messages are ignored.
+fn built_executables(stdout: &str) -> Vec<(String, PathBuf)> {
+    stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str::<ArtifactMessage>(line).ok())
+        .filter(|m| m.reason == "compiler-artifact")
+        .filter_map(|m| Some((m.target?.name, m.executable?)))
+        .collect()
+}
+
+/// The Rust target triple for `platform`, linking against musl
+pub fn get_rust_target_triple(platform: &Platform) -> Result<String> {
+    rust_target_triple(platform, Libc::Musl)
+}
+
+/// The Rust target triple for `platform`, linking against `libc`
+pub fn rust_target_triple(platform: &Platform, libc: Libc) -> Result<String> {
+    let (triple, abi) = match (
+        platform.os.as_str(),
+        platform.architecture.as_str(),
+        platform.variant.as_deref(),
+    ) {
+        ("linux", "amd64", None) => ("x86_64-unknown-linux", ""),
+        ("linux", "arm64", None | Some("v8")) => ("aarch64-unknown-linux", ""),
+        ("linux", "arm", Some("v7")) => ("armv7-unknown-linux", "eabihf"),
+        ("linux", "arm", Some("v6")) => ("arm-unknown-linux", "eabihf"),
+        ("linux", "386", None) => ("i686-unknown-linux", ""),
+        ("linux", "ppc64le", None) => ("powerpc64le-unknown-linux", ""),
+        ("linux", "s390x", None) => ("s390x-unknown-linux", ""),
+        ("linux", "riscv64", None) => ("riscv64gc-unknown-linux", ""),
+        _ => anyhow::bail!("Unsupported platform: {}", platform),
+    };
+    let env = match libc {
+        Libc::Musl => "musl",
+        Libc::Gnu => "gnu",
+    };
+    Ok(format!("{}-{}{}", triple, env, abi))
+}
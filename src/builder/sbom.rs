@@ -0,0 +1,874 @@
+//! Software bills of materials for built binaries
+//!
+//! With `--sbom`, krust describes the crates compiled into each platform's
+//! binary, as an SPDX or CycloneDX document. `cargo metadata` resolves the
+//! dependency graph for the target with the features being built, and only
+//! normal dependencies are followed: build scripts and dev-dependencies
+//! don't end up in the binary. Cargo.lock supplies each crate's checksum. The document is pushed as an OCI artifact
+//! whose subject is the platform image, so tools that follow the referrers
+//! API (`oras discover`, `regctl artifact list`) find it.
+
+use crate::config::SbomFormat;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Media type of SPDX JSON documents, also the SBOM artifact's type
+pub const SPDX_MEDIA_TYPE: &str = "application/spdx+json";
+
+/// Media type of CycloneDX JSON documents, also the SBOM artifact's type
+pub const CYCLONEDX_MEDIA_TYPE: &str = "application/vnd.cyclonedx+json";
+
+/// Source of crates published on crates.io, as cargo metadata reports it
+const CRATES_IO_SOURCES: [&str; 2] = [
+    "registry+https://github.com/rust-lang/crates.io-index",
+    "sparse+https://index.crates.io/",
+];
+
+/// An SBOM document
+#[derive(Debug, Clone)]
+pub struct Sbom {
+    /// Media type of the document, and `artifactType` of the artifact
+    pub media_type: &'static str,
+    /// File name the document is given in the artifact
+    pub file_name: &'static str,
+    pub data: Vec<u8>,
+}
+
+/// The parts of `cargo metadata --format-version 1` the SBOM is made from
+#[derive(Debug, Clone, Deserialize)]
+pub struct DependencyGraph {
+    pub packages: Vec<Crate>,
+    pub resolve: Resolve,
+    pub workspace_root: PathBuf,
+    /// Checksums from Cargo.lock, by name and version
+    #[serde(skip)]
+    checksums: HashMap<(String, String), String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Crate {
+    pub name: String,
+    pub version: String,
+    pub id: String,
+    /// Where the crate comes from: a registry or git repository, or None
+    /// for local paths
+    #[serde(default)]
+    pub source: Option<String>,
+    /// SPDX license expression from Cargo.toml
+    #[serde(default)]
+    pub license: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Resolve {
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Node {
+    pub id: String,
+    #[serde(default)]
+    pub deps: Vec<NodeDep>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NodeDep {
+    pub pkg: String,
+    #[serde(default)]
+    pub dep_kinds: Vec<DepKind>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DepKind {
+    /// `dev` or `build`; None for normal dependencies
+    pub kind: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    checksum: Option<String>,
+}
+
+impl DependencyGraph {
+    /// Run `cargo metadata` for the project in `project_path`, passing
+    /// `args` (e.g. `--filter-platform` and feature flags)
+    pub fn load(project_path: &Path, args: &[String]) -> Result<Self> {
+        let manifest_path = project_path.join("Cargo.toml");
+        let output = Command::new("cargo")
+            .args(["metadata", "--format-version", "1"])
+            .args(args)
+            .arg("--manifest-path")
+            .arg(&manifest_path)
+            .output()
+            .context("Failed to execute cargo metadata")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to resolve the dependencies of {}: {}",
+                manifest_path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let mut graph = Self::from_json(&output.stdout)?;
+        let lockfile = graph.workspace_root.join("Cargo.lock");
+        if let Ok(content) = std::fs::read_to_string(&lockfile) {
+            graph.read_checksums(&content)?;
+        }
+        Ok(graph)
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self> {
+        serde_json::from_slice(data).context("Failed to parse cargo metadata output")
+    }
+
+    fn read_checksums(&mut self, lockfile: &str) -> Result<()> {
+        let lockfile: Lockfile = toml::from_str(lockfile).context("Failed to parse Cargo.lock")?;
+        self.checksums = lockfile
+            .package
+            .into_iter()
+            .filter_map(|p| Some(((p.name, p.version), p.checksum?)))
+            .collect();
+        Ok(())
+    }
+
+    /// The crates compiled into `root`'s binaries, `root` first, each with
+    /// its normal dependencies
+    pub fn crates(&self, root: &str) -> Result<Vec<(&Crate, Vec<&Crate>)>> {
+        let packages: HashMap<&str, &Crate> =
+            self.packages.iter().map(|p| (p.id.as_str(), p)).collect();
+        let nodes: HashMap<&str, &Node> = self
+            .resolve
+            .nodes
+            .iter()
+            .map(|n| (n.id.as_str(), n))
+            .collect();
+        let package = |id: &str| {
+            packages
+                .get(id)
+                .copied()
+                .with_context(|| format!("cargo metadata lists no package {}", id))
+        };
+
+        let mut crates = Vec::new();
+        let mut seen = HashSet::from([root]);
+        let mut queue = VecDeque::from([root]);
+        while let Some(id) = queue.pop_front() {
+            let deps: Vec<&str> = nodes
+                .get(id)
+                .map(|node| {
+                    node.deps
+                        .iter()
+                        .filter(|dep| dep.dep_kinds.iter().any(|k| k.kind.is_none()))
+                        .map(|dep| dep.pkg.as_str())
+                        .collect()
+                })
+                .unwrap_or_default();
+            for dep in &deps {
+                if seen.insert(dep) {
+                    queue.push_back(dep);
+                }
+            }
+            crates.push((
+                package(id)?,
+                deps.into_iter().map(package).collect::<Result<_>>()?,
+            ));
+        }
+        Ok(crates)
+    }
+
+    fn checksum(&self, package: &Crate) -> Option<&str> {
+        self.checksums
+            .get(&(package.name.clone(), package.version.clone()))
+            .map(String::as_str)
+    }
+}
+
+/// Describe the crates compiled into `root`'s binaries in `format`.
+/// `binary_digest` names the document uniquely; `created` is its timestamp.
+pub fn generate(
+    format: SbomFormat,
+    graph: &DependencyGraph,
+    root: &str,
+    binary_digest: &str,
+    created: &str,
+) -> Result<Option<Sbom>> {
+    match format {
+        SbomFormat::None => Ok(None),
+        SbomFormat::Spdx => Ok(Some(Sbom {
+            media_type: SPDX_MEDIA_TYPE,
+            file_name: "sbom.spdx.json",
+            data: spdx(graph, root, binary_digest, created)?,
+        })),
+        SbomFormat::Cyclonedx => Ok(Some(Sbom {
+            media_type: CYCLONEDX_MEDIA_TYPE,
+            file_name: "sbom.cdx.json",
+            data: cyclonedx(graph, root, binary_digest, created)?,
+        })),
+    }
+}
+
+/// An SPDX 2.3 JSON document with a package for each crate and a
+/// DEPENDS_ON relationship for each dependency
+fn spdx(
+    graph: &DependencyGraph,
+    root: &str,
+    binary_digest: &str,
+    created: &str,
+) -> Result<Vec<u8>> {
+    let crates = graph.crates(root)?;
+    let root_crate = crates[0].0;
+
+    let packages: Vec<_> = crates
+        .iter()
+        .map(|(package, _)| {
+            let mut spdx_package = serde_json::json!({
+                "SPDXID": spdx_id(package),
+                "name": package.name,
+                "versionInfo": package.version,
+                "downloadLocation": match origin(package) {
+                    Some(Origin::CratesIo) => crates_io_download(package),
+                    Some(Origin::Git { url, revision }) => match revision {
+                        Some(revision) => format!("git+{}@{}", url, revision),
+                        None => format!("git+{}", url),
+                    },
+                    None => "NOASSERTION".to_string(),
+                },
+                "filesAnalyzed": false,
+                "licenseConcluded": "NOASSERTION",
+                "licenseDeclared": package
+                    .license
+                    .as_deref()
+                    .map(license_expression)
+                    .unwrap_or_else(|| "NOASSERTION".to_string()),
+                "copyrightText": "NOASSERTION",
+                "primaryPackagePurpose": if package.id == root_crate.id {
+                    "APPLICATION"
+                } else {
+                    "LIBRARY"
+                },
+                "externalRefs": [{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": purl(package),
+                }],
+            });
+            if let Some(checksum) = graph.checksum(package) {
+                spdx_package["checksums"] = serde_json::json!([{
+                    "algorithm": "SHA256",
+                    "checksumValue": checksum,
+                }]);
+            }
+            spdx_package
+        })
+        .collect();
+
+    let mut relationships = vec![serde_json::json!({
+        "spdxElementId": "SPDXRef-DOCUMENT",
+        "relationshipType": "DESCRIBES",
+        "relatedSpdxElement": spdx_id(root_crate),
+    })];
+    for (package, deps) in &crates {
+        for dep in deps {
+            relationships.push(serde_json::json!({
+                "spdxElementId": spdx_id(package),
+                "relationshipType": "DEPENDS_ON",
+                "relatedSpdxElement": spdx_id(dep),
+            }));
+        }
+    }
+
+    let name = format!("{}-{}", root_crate.name, root_crate.version);
+    let document = serde_json::json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": name,
+        "documentNamespace": format!(
+            "https://spdx.org/spdxdocs/krust/{}-{}",
+            name,
+            binary_digest.trim_start_matches("sha256:")
+        ),
+        "creationInfo": {
+            "created": created,
+            "creators": [format!("Tool: krust-{}", env!("CARGO_PKG_VERSION"))],
+        },
+        "documentDescribes": [spdx_id(root_crate)],
+        "packages": packages,
+        "relationships": relationships,
+    });
+    Ok(serde_json::to_vec_pretty(&document)?)
+}
+
+/// A CycloneDX 1.5 JSON document with the root crate as its subject, a
+/// component for each dependency and the dependency graph between them
+fn cyclonedx(
+    graph: &DependencyGraph,
+    root: &str,
+    binary_digest: &str,
+    created: &str,
+) -> Result<Vec<u8>> {
+    let crates = graph.crates(root)?;
+    let root_crate = crates[0].0;
+    let refs = bom_refs(&crates);
+    let bom_ref = |package: &Crate| refs[package.id.as_str()].clone();
+
+    let component = |package: &Crate, kind: &str| {
+        let mut component = serde_json::json!({
+            "type": kind,
+            "bom-ref": bom_ref(package),
+            "name": package.name,
+            "version": package.version,
+            "purl": purl(package),
+        });
+        if let Some(license) = &package.license {
+            component["licenses"] = serde_json::json!([
+                { "expression": license_expression(license) }
+            ]);
+        }
+        if let Some(checksum) = graph.checksum(package) {
+            component["hashes"] = serde_json::json!([{ "alg": "SHA-256", "content": checksum }]);
+        }
+        match origin(package) {
+            Some(Origin::CratesIo) => {
+                component["externalReferences"] = serde_json::json!([{
+                    "type": "distribution",
+                    "url": crates_io_download(package),
+                }]);
+            }
+            Some(Origin::Git { url, revision }) => {
+                let mut reference = serde_json::json!({ "type": "vcs", "url": url });
+                if let Some(revision) = revision {
+                    reference["comment"] = format!("Revision {}", revision).into();
+                }
+                component["externalReferences"] = serde_json::json!([reference]);
+            }
+            None => {}
+        }
+        component
+    };
+
+    let components: Vec<_> = crates[1..]
+        .iter()
+        .map(|(package, _)| component(package, "library"))
+        .collect();
+    let dependencies: Vec<_> = crates
+        .iter()
+        .map(|(package, deps)| {
+            serde_json::json!({
+                "ref": bom_ref(package),
+                "dependsOn": deps.iter().map(|dep| bom_ref(dep)).collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+
+    let document = serde_json::json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "serialNumber": serial_number(binary_digest),
+        "version": 1,
+        "metadata": {
+            "timestamp": created,
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": "krust",
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            },
+            "component": component(root_crate, "application"),
+        },
+        "components": components,
+        "dependencies": dependencies,
+    });
+    Ok(serde_json::to_vec_pretty(&document)?)
+}
+
+/// Each crate's CycloneDX `bom-ref`, by package ID: its purl, followed by
+/// its position in `crates` when crates of the same name and version from
+/// different sources share the purl
+fn bom_refs<'a>(crates: &[(&'a Crate, Vec<&Crate>)]) -> HashMap<&'a str, String> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for (package, _) in crates {
+        *counts.entry(purl(package)).or_default() += 1;
+    }
+    crates
+        .iter()
+        .enumerate()
+        .map(|(i, (package, _))| {
+            let purl = purl(package);
+            let bom_ref = if counts[&purl] > 1 {
+                format!("{}#{}", purl, i)
+            } else {
+                purl
+            };
+            (package.id.as_str(), bom_ref)
+        })
+        .collect()
+}
+
+/// A CycloneDX serial number, a URN with a UUID, derived from the binary's
+/// digest so it identifies the build
+fn serial_number(binary_digest: &str) -> String {
+    let hex = sha256::digest(binary_digest);
+    format!(
+        "urn:uuid:{}-{}-5{}-8{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[13..16],
+        &hex[17..20],
+        &hex[20..32]
+    )
+}
+
+/// The package's SPDX identifier, which may only contain letters, digits,
+/// `.` and `-`
+fn spdx_id(package: &Crate) -> String {
+    let id: String = format!("{}-{}", package.name, package.version)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("SPDXRef-Package-{}", id)
+}
+
+/// Package URL of the crate
+fn purl(package: &Crate) -> String {
+    format!("pkg:cargo/{}@{}", package.name, package.version)
+}
+
+/// Where a crate comes from, when it's somewhere others can fetch it
+enum Origin<'a> {
+    CratesIo,
+    /// A git repository, at the locked revision
+    Git {
+        url: &'a str,
+        revision: Option<&'a str>,
+    },
+}
+
+/// The crate's origin: crates.io or a git repository. Other registries and
+/// local paths aren't asserted.
+fn origin(package: &Crate) -> Option<Origin<'_>> {
+    let source = package.source.as_deref()?;
+    if CRATES_IO_SOURCES.contains(&source) {
+        return Some(Origin::CratesIo);
+    }
+    // git+https://host/repo?branch=main#<revision>
+    let source = source.strip_prefix("git+")?;
+    let (url, revision) = match source.split_once('#') {
+        Some((url, revision)) => (url, Some(revision)),
+        None => (source, None),
+    };
+    Some(Origin::Git {
+        url: url.split('?').next().unwrap_or(url),
+        revision,
+    })
+}
+
+fn crates_io_download(package: &Crate) -> String {
+    format!(
+        "https://crates.io/api/v1/crates/{}/{}/download",
+        package.name, package.version
+    )
+}
+
+/// Cargo's license field as an SPDX expression; old manifests separate
+/// alternatives with `/`
+fn license_expression(license: &str) -> String {
+    license
+        .split('/')
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" OR ")
+}
+
+/// The document's creation time: SOURCE_DATE_EPOCH when set, so rebuilding
+/// reproduces the document, otherwise now
+pub fn created() -> String {
+    let time = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .and_then(|epoch| chrono::DateTime::from_timestamp(epoch, 0))
+        .unwrap_or_else(chrono::Utc::now);
+    time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METADATA: &str = r#"{
+        "packages": [
+            {
+                "name": "server",
+                "version": "0.1.0",
+                "id": "path+file:///ws/server#0.1.0",
+                "source": null,
+                "license": "MIT/Apache-2.0"
+            },
+            {
+                "name": "serde",
+                "version": "1.0.200",
+                "id": "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.200",
+                "source": "registry+https://github.com/rust-lang/crates.io-index",
+                "license": "MIT OR Apache-2.0"
+            },
+            {
+                "name": "cc",
+                "version": "1.0.90",
+                "id": "registry+https://github.com/rust-lang/crates.io-index#cc@1.0.90",
+                "source": "registry+https://github.com/rust-lang/crates.io-index",
+                "license": "MIT OR Apache-2.0"
+            },
+            {
+                "name": "tempfile",
+                "version": "3.10.0",
+                "id": "registry+https://github.com/rust-lang/crates.io-index#tempfile@3.10.0",
+                "source": "registry+https://github.com/rust-lang/crates.io-index"
+            },
+            {
+                "name": "forked",
+                "version": "0.2.0",
+                "id": "git+https://github.com/org/forked?branch=main#abc123",
+                "source": "git+https://github.com/org/forked?branch=main#abc123"
+            }
+        ],
+        "resolve": {
+            "nodes": [
+                {
+                    "id": "path+file:///ws/server#0.1.0",
+                    "deps": [
+                        {
+                            "pkg": "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.200",
+                            "dep_kinds": [{"kind": null, "target": null}]
+                        },
+                        {
+                            "pkg": "registry+https://github.com/rust-lang/crates.io-index#cc@1.0.90",
+                            "dep_kinds": [{"kind": "build", "target": null}]
+                        },
+                        {
+                            "pkg": "registry+https://github.com/rust-lang/crates.io-index#tempfile@3.10.0",
+                            "dep_kinds": [{"kind": "dev", "target": null}]
+                        },
+                        {
+                            "pkg": "git+https://github.com/org/forked?branch=main#abc123",
+                            "dep_kinds": [{"kind": null, "target": null}]
+                        }
+                    ]
+                },
+                {
+                    "id": "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.200",
+                    "deps": []
+                },
+                {
+                    "id": "git+https://github.com/org/forked?branch=main#abc123",
+                    "deps": [
+                        {
+                            "pkg": "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.200",
+                            "dep_kinds": [{"kind": null, "target": null}]
+                        }
+                    ]
+                }
+            ],
+            "root": null
+        },
+        "workspace_root": "/ws",
+        "version": 1
+    }"#;
+
+    const LOCKFILE: &str = r#"
+version = 3
+
+[[package]]
+name = "serde"
+version = "1.0.200"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "ddc6f9cc94d67c0e21aaf7eda3a010fd3af78ebf6e096aa6e2e13c79749cce4f"
+
+[[package]]
+name = "server"
+version = "0.1.0"
+dependencies = ["serde"]
+"#;
+
+    fn graph() -> DependencyGraph {
+        let mut graph = DependencyGraph::from_json(METADATA.as_bytes()).unwrap();
+        graph.read_checksums(LOCKFILE).unwrap();
+        graph
+    }
+
+    #[test]
+    fn test_crates_follow_normal_dependencies() {
+        let graph = graph();
+        let crates = graph.crates("path+file:///ws/server#0.1.0").unwrap();
+        let names: Vec<(&str, Vec<&str>)> = crates
+            .iter()
+            .map(|(package, deps)| {
+                (
+                    package.name.as_str(),
+                    deps.iter().map(|d| d.name.as_str()).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("server", vec!["serde", "forked"]),
+                ("serde", vec![]),
+                ("forked", vec!["serde"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spdx() {
+        let sbom = generate(
+            SbomFormat::Spdx,
+            &graph(),
+            "path+file:///ws/server#0.1.0",
+            "sha256:abc",
+            "2024-01-01T00:00:00Z",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(sbom.media_type, SPDX_MEDIA_TYPE);
+        let document: serde_json::Value = serde_json::from_slice(&sbom.data).unwrap();
+
+        assert_eq!(document["spdxVersion"], "SPDX-2.3");
+        assert_eq!(
+            document["documentNamespace"],
+            "https://spdx.org/spdxdocs/krust/server-0.1.0-abc"
+        );
+        assert_eq!(document["creationInfo"]["created"], "2024-01-01T00:00:00Z");
+        assert_eq!(
+            document["documentDescribes"],
+            serde_json::json!(["SPDXRef-Package-server-0.1.0"])
+        );
+
+        let packages = document["packages"].as_array().unwrap();
+        assert_eq!(packages.len(), 3);
+        assert_eq!(packages[0]["licenseDeclared"], "MIT OR Apache-2.0");
+        assert_eq!(packages[0]["downloadLocation"], "NOASSERTION");
+        assert_eq!(packages[0]["primaryPackagePurpose"], "APPLICATION");
+        assert_eq!(
+            packages[1]["downloadLocation"],
+            "https://crates.io/api/v1/crates/serde/1.0.200/download"
+        );
+        assert_eq!(
+            packages[1]["externalRefs"][0]["referenceLocator"],
+            "pkg:cargo/serde@1.0.200"
+        );
+        assert_eq!(
+            packages[1]["checksums"][0]["checksumValue"],
+            "ddc6f9cc94d67c0e21aaf7eda3a010fd3af78ebf6e096aa6e2e13c79749cce4f"
+        );
+        assert_eq!(
+            packages[2]["downloadLocation"],
+            "git+https://github.com/org/forked@abc123"
+        );
+        assert_eq!(packages[2]["licenseDeclared"], "NOASSERTION");
+        assert!(packages[2].get("checksums").is_none());
+
+        let relationships: Vec<(&str, &str, &str)> = document["relationships"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| {
+                (
+                    r["spdxElementId"].as_str().unwrap(),
+                    r["relationshipType"].as_str().unwrap(),
+                    r["relatedSpdxElement"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            relationships,
+            vec![
+                (
+                    "SPDXRef-DOCUMENT",
+                    "DESCRIBES",
+                    "SPDXRef-Package-server-0.1.0"
+                ),
+                (
+                    "SPDXRef-Package-server-0.1.0",
+                    "DEPENDS_ON",
+                    "SPDXRef-Package-serde-1.0.200"
+                ),
+                (
+                    "SPDXRef-Package-server-0.1.0",
+                    "DEPENDS_ON",
+                    "SPDXRef-Package-forked-0.2.0"
+                ),
+                (
+                    "SPDXRef-Package-forked-0.2.0",
+                    "DEPENDS_ON",
+                    "SPDXRef-Package-serde-1.0.200"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cyclonedx() {
+        let sbom = generate(
+            SbomFormat::Cyclonedx,
+            &graph(),
+            "path+file:///ws/server#0.1.0",
+            "sha256:abc",
+            "2024-01-01T00:00:00Z",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(sbom.media_type, CYCLONEDX_MEDIA_TYPE);
+        let document: serde_json::Value = serde_json::from_slice(&sbom.data).unwrap();
+
+        assert_eq!(document["bomFormat"], "CycloneDX");
+        assert_eq!(document["specVersion"], "1.5");
+        let serial = document["serialNumber"].as_str().unwrap();
+        assert_eq!(serial, serial_number("sha256:abc"));
+        assert_eq!(serial.len(), "urn:uuid:".len() + 36);
+        assert_eq!(document["metadata"]["timestamp"], "2024-01-01T00:00:00Z");
+
+        let root = &document["metadata"]["component"];
+        assert_eq!(root["type"], "application");
+        assert_eq!(root["purl"], "pkg:cargo/server@0.1.0");
+        assert_eq!(root["licenses"][0]["expression"], "MIT OR Apache-2.0");
+
+        let components = document["components"].as_array().unwrap();
+        assert_eq!(components.len(), 2);
+        assert_eq!(components[0]["bom-ref"], "pkg:cargo/serde@1.0.200");
+        assert_eq!(components[0]["type"], "library");
+        assert_eq!(
+            components[0]["hashes"][0]["content"],
+            "ddc6f9cc94d67c0e21aaf7eda3a010fd3af78ebf6e096aa6e2e13c79749cce4f"
+        );
+        assert_eq!(
+            components[0]["externalReferences"][0]["url"],
+            "https://crates.io/api/v1/crates/serde/1.0.200/download"
+        );
+        assert_eq!(components[1]["externalReferences"][0]["type"], "vcs");
+        assert_eq!(
+            components[1]["externalReferences"][0]["url"],
+            "https://github.com/org/forked"
+        );
+
+        assert_eq!(
+            document["dependencies"],
+            serde_json::json!([
+                {
+                    "ref": "pkg:cargo/server@0.1.0",
+                    "dependsOn": ["pkg:cargo/serde@1.0.200", "pkg:cargo/forked@0.2.0"],
+                },
+                { "ref": "pkg:cargo/serde@1.0.200", "dependsOn": [] },
+                { "ref": "pkg:cargo/forked@0.2.0", "dependsOn": ["pkg:cargo/serde@1.0.200"] },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_cyclonedx_duplicate_purls() {
+        // The same version of a crate from crates.io and from a fork
+        let metadata = r#"{
+            "packages": [
+                {"name": "app", "version": "0.1.0", "id": "path+file:///app#0.1.0"},
+                {
+                    "name": "log",
+                    "version": "0.4.21",
+                    "id": "registry+https://github.com/rust-lang/crates.io-index#log@0.4.21",
+                    "source": "registry+https://github.com/rust-lang/crates.io-index"
+                },
+                {
+                    "name": "log",
+                    "version": "0.4.21",
+                    "id": "git+https://github.com/org/log#def456",
+                    "source": "git+https://github.com/org/log#def456"
+                }
+            ],
+            "resolve": {
+                "nodes": [
+                    {
+                        "id": "path+file:///app#0.1.0",
+                        "deps": [
+                            {
+                                "pkg": "registry+https://github.com/rust-lang/crates.io-index#log@0.4.21",
+                                "dep_kinds": [{"kind": null}]
+                            },
+                            {
+                                "pkg": "git+https://github.com/org/log#def456",
+                                "dep_kinds": [{"kind": null}]
+                            }
+                        ]
+                    }
+                ]
+            },
+            "workspace_root": "/app"
+        }"#;
+        let graph = DependencyGraph::from_json(metadata.as_bytes()).unwrap();
+        let sbom = generate(
+            SbomFormat::Cyclonedx,
+            &graph,
+            "path+file:///app#0.1.0",
+            "sha256:abc",
+            "2024-01-01T00:00:00Z",
+        )
+        .unwrap()
+        .unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&sbom.data).unwrap();
+
+        let refs: Vec<&str> = document["components"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c["bom-ref"].as_str().unwrap())
+            .collect();
+        assert_eq!(refs, ["pkg:cargo/log@0.4.21#1", "pkg:cargo/log@0.4.21#2"]);
+        assert_eq!(
+            document["dependencies"][0],
+            serde_json::json!({
+                "ref": "pkg:cargo/app@0.1.0",
+                "dependsOn": refs,
+            })
+        );
+    }
+
+    #[test]
+    fn test_generate_none() {
+        assert!(generate(
+            SbomFormat::None,
+            &graph(),
+            "path+file:///ws/server#0.1.0",
+            "",
+            ""
+        )
+        .unwrap()
+        .is_none());
+    }
+
+    #[test]
+    fn test_spdx_id() {
+        let package = Crate {
+            name: "my_crate".to_string(),
+            version: "1.0.0+build.1".to_string(),
+            id: String::new(),
+            source: None,
+            license: None,
+        };
+        assert_eq!(spdx_id(&package), "SPDXRef-Package-my-crate-1.0.0-build.1");
+    }
+}
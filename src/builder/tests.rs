@@ -7,38 +7,55 @@ mod tests {
     #[test]
     fn test_get_rust_target_triple() {
         assert_eq!(
-            get_rust_target_triple("linux/amd64").unwrap(),
+            get_rust_target_triple(&"linux/amd64".parse().unwrap()).unwrap(),
             "x86_64-unknown-linux-musl"
         );
         assert_eq!(
-            get_rust_target_triple("linux/arm64").unwrap(),
+            get_rust_target_triple(&"linux/arm64".parse().unwrap()).unwrap(),
             "aarch64-unknown-linux-musl"
         );
         assert_eq!(
-            get_rust_target_triple("linux/arm/v7").unwrap(),
+            get_rust_target_triple(&"linux/arm/v7".parse().unwrap()).unwrap(),
             "armv7-unknown-linux-musleabihf"
         );
         assert_eq!(
-            get_rust_target_triple("linux/arm/v6").unwrap(),
+            get_rust_target_triple(&"linux/arm/v6".parse().unwrap()).unwrap(),
             "arm-unknown-linux-musleabihf"
         );
         assert_eq!(
-            get_rust_target_triple("linux/386").unwrap(),
+            get_rust_target_triple(&"linux/386".parse().unwrap()).unwrap(),
             "i686-unknown-linux-musl"
         );
         assert_eq!(
-            get_rust_target_triple("linux/ppc64le").unwrap(),
+            get_rust_target_triple(&"linux/ppc64le".parse().unwrap()).unwrap(),
             "powerpc64le-unknown-linux-musl"
         );
         assert_eq!(
-            get_rust_target_triple("linux/s390x").unwrap(),
+            get_rust_target_triple(&"linux/s390x".parse().unwrap()).unwrap(),
             "s390x-unknown-linux-musl"
         );
         assert_eq!(
-            get_rust_target_triple("linux/riscv64").unwrap(),
+            get_rust_target_triple(&"linux/riscv64".parse().unwrap()).unwrap(),
             "riscv64gc-unknown-linux-musl"
         );
-        assert!(get_rust_target_triple("windows/amd64").is_err());
+        assert_eq!(
+            get_rust_target_triple(&"linux/arm64/v8".parse().unwrap()).unwrap(),
+            "aarch64-unknown-linux-musl"
+        );
+        assert!(get_rust_target_triple(&"windows/amd64".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_rust_target_triple_gnu() {
+        let gnu = |platform: &str| rust_target_triple(&platform.parse().unwrap(), Libc::Gnu);
+        assert_eq!(gnu("linux/amd64").unwrap(), "x86_64-unknown-linux-gnu");
+        assert_eq!(gnu("linux/arm64").unwrap(), "aarch64-unknown-linux-gnu");
+        assert_eq!(
+            gnu("linux/arm/v7").unwrap(),
+            "armv7-unknown-linux-gnueabihf"
+        );
+        assert_eq!(gnu("linux/riscv64").unwrap(), "riscv64gc-unknown-linux-gnu");
+        assert!(gnu("windows/amd64").is_err());
     }
 
     #[test]
@@ -54,6 +71,8 @@ version = "0.1.0"
 "#,
         )
         .unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
 
         let builder = RustBuilder::new(dir.path(), "x86_64-unknown-linux-musl");
         let name = builder.get_binary_name().unwrap();
@@ -100,6 +119,89 @@ version = "0.1.0"
         assert!(result.unwrap_err().to_string().contains("package name"));
     }
 
+    #[test]
+    fn test_get_binary_name_renamed_bin() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            r#"
+[package]
+name = "my-package"
+version = "0.1.0"
+
+[[bin]]
+name = "server"
+path = "src/main.rs"
+"#,
+        )
+        .unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+
+        let builder = RustBuilder::new(dir.path(), "x86_64-unknown-linux-musl");
+        assert_eq!(builder.get_binary_name().unwrap(), "server");
+    }
+
+    #[test]
+    fn test_binaries() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            r#"
+[package]
+name = "tools"
+version = "0.1.0"
+"#,
+        )
+        .unwrap();
+        fs::create_dir_all(dir.path().join("src/bin")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(dir.path().join("src/bin/migrate.rs"), "fn main() {}").unwrap();
+
+        let builder = RustBuilder::new(dir.path(), "x86_64-unknown-linux-musl");
+        let package = builder.package().unwrap();
+        let mut binaries = package.binaries();
+        binaries.sort();
+        assert_eq!(binaries, vec!["migrate", "tools"]);
+    }
+
+    #[test]
+    fn test_get_binary_name_inherited_from_workspace() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            r#"
+[workspace]
+members = ["app"]
+
+[workspace.package]
+version = "1.2.3"
+"#,
+        )
+        .unwrap();
+        fs::create_dir_all(dir.path().join("app/src")).unwrap();
+        fs::write(
+            dir.path().join("app/Cargo.toml"),
+            r#"
+[package]
+name = "member-app"
+version.workspace = true
+"#,
+        )
+        .unwrap();
+        fs::write(dir.path().join("app/src/main.rs"), "fn main() {}").unwrap();
+
+        let builder = RustBuilder::new(dir.path().join("app"), "x86_64-unknown-linux-musl");
+        assert_eq!(builder.get_binary_name().unwrap(), "member-app");
+
+        let root = RustBuilder::new(dir.path(), "x86_64-unknown-linux-musl");
+        assert!(root
+            .get_binary_name()
+            .unwrap_err()
+            .to_string()
+            .contains("workspace root"));
+    }
+
     #[test]
     fn test_rust_builder_with_cargo_args() {
         let dir = tempdir().unwrap();
@@ -141,6 +243,8 @@ version = "0.1.0"
 "#,
         )
         .unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
 
         let builder = RustBuilder::new(dir.path(), "x86_64-unknown-linux-musl")
             .with_cargo_args(vec!["--bin".to_string()]);
@@ -149,34 +253,205 @@ version = "0.1.0"
     }
 
     #[test]
-    fn test_get_binary_subdir_with_example() {
-        let dir = tempdir().unwrap();
-        let builder = RustBuilder::new(dir.path(), "x86_64-unknown-linux-musl")
-            .with_cargo_args(vec!["--example".to_string(), "my-example".to_string()]);
-        assert_eq!(builder.get_binary_subdir(), Some("examples"));
+    fn test_profile_args() {
+        let builder = RustBuilder::new("/src/app", "x86_64-unknown-linux-musl");
+        assert_eq!(builder.profile_args(), vec!["--release"]);
+
+        let builder = builder
+            .with_profile(Some("dist".to_string()))
+            .with_no_default_features(true)
+            .with_features(vec!["jemalloc".to_string(), "tls".to_string()]);
+        assert_eq!(
+            builder.profile_args(),
+            vec![
+                "--profile",
+                "dist",
+                "--no-default-features",
+                "--features",
+                "jemalloc,tls"
+            ]
+        );
     }
 
     #[test]
-    fn test_get_binary_subdir_without_example() {
-        let dir = tempdir().unwrap();
-        let builder = RustBuilder::new(dir.path(), "x86_64-unknown-linux-musl");
-        assert_eq!(builder.get_binary_subdir(), None);
+    fn test_lock_args() {
+        let builder = RustBuilder::new("/src/app", "x86_64-unknown-linux-musl");
+        assert!(builder.lock_args().is_empty());
+
+        let builder = builder.with_locked(true).with_offline(true);
+        assert_eq!(builder.lock_args(), vec!["--locked", "--offline"]);
+
+        // Flags already in the cargo arguments aren't repeated
+        let builder = builder.with_cargo_args(vec!["--locked".to_string()]);
+        assert_eq!(builder.lock_args(), vec!["--offline"]);
+        let builder = builder.with_cargo_args(vec!["--frozen".to_string()]);
+        assert!(builder.lock_args().is_empty());
     }
 
     #[test]
-    fn test_get_binary_subdir_with_bin() {
-        let dir = tempdir().unwrap();
-        let builder = RustBuilder::new(dir.path(), "x86_64-unknown-linux-musl")
-            .with_cargo_args(vec!["--bin".to_string(), "my-bin".to_string()]);
-        assert_eq!(builder.get_binary_subdir(), None);
+    fn test_profile_env() {
+        let builder = RustBuilder::new("/src/app", "x86_64-unknown-linux-musl");
+        assert!(builder.profile_env().is_empty());
+
+        let builder = builder.with_optimization(Some("z".to_string()), Some("fat".to_string()));
+        assert_eq!(
+            builder.profile_env(),
+            vec![
+                (
+                    "CARGO_PROFILE_RELEASE_OPT_LEVEL".to_string(),
+                    "z".to_string()
+                ),
+                ("CARGO_PROFILE_RELEASE_LTO".to_string(), "fat".to_string()),
+            ]
+        );
+
+        let builder = builder
+            .with_profile(Some("release-small".to_string()))
+            .with_split_debuginfo(true);
+        assert_eq!(
+            builder.profile_env()[0].0,
+            "CARGO_PROFILE_RELEASE_SMALL_OPT_LEVEL"
+        );
+        assert_eq!(
+            builder.profile_env()[2],
+            (
+                "CARGO_PROFILE_RELEASE_SMALL_DEBUG".to_string(),
+                "true".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_remap_path_flags() {
+        assert_eq!(
+            remap_path_flags(
+                Some(Path::new("/home/me/.cargo")),
+                Path::new("/home/me/src/app"),
+                Path::new("/home/me/src/app/target/krust/x86_64-unknown-linux-musl"),
+            ),
+            vec![
+            "--remap-path-prefix=/home/me/.cargo=/cargo",
+            "--remap-path-prefix=/home/me/src/app=/build",
+            "--remap-path-prefix=/home/me/src/app/target/krust/x86_64-unknown-linux-musl=/target",
+        ]
+        );
+
+        // RUSTFLAGS is split on whitespace
+        assert_eq!(
+            remap_path_flags(
+                None,
+                Path::new("/Users/Me/My Projects/app"),
+                Path::new("/tmp/t")
+            ),
+            vec!["--remap-path-prefix=/tmp/t=/target"]
+        );
     }
 
     #[test]
-    fn test_get_binary_subdir_example_at_end_without_value() {
+    fn test_target_dir() {
+        let builder = RustBuilder::new("/src/app", "x86_64-unknown-linux-musl");
+        assert_eq!(
+            builder.target_dir(),
+            PathBuf::from("/src/app/target/krust/x86_64-unknown-linux-musl")
+        );
+
+        let builder = builder.with_target_dir(Some(PathBuf::from("../target")));
+        assert_eq!(
+            builder.target_dir(),
+            PathBuf::from("/src/app/../target/x86_64-unknown-linux-musl")
+        );
+
+        let builder = builder.with_target_dir(Some(PathBuf::from("/cache/target")));
+        assert_eq!(
+            builder.target_dir(),
+            PathBuf::from("/cache/target/x86_64-unknown-linux-musl")
+        );
+    }
+
+    #[test]
+    fn test_resolve_backend() {
+        use BuildBackend::*;
+        assert_eq!(resolve_backend(Auto, true, true, false), Zig);
+        assert_eq!(resolve_backend(Auto, false, true, false), Cross);
+        assert_eq!(resolve_backend(Auto, false, true, true), Cargo);
+        assert_eq!(resolve_backend(Auto, false, false, false), Cargo);
+        assert_eq!(resolve_backend(Cross, true, false, true), Cross);
+    }
+
+    #[test]
+    fn test_cross_host_path() {
+        let builder = RustBuilder::new("/src/app", "s390x-unknown-linux-musl");
+        assert_eq!(
+        builder.host_path(Path::new(
+            "/target/s390x-unknown-linux-musl/release/examples/demo"
+        )),
+        PathBuf::from(
+            "/src/app/target/krust/s390x-unknown-linux-musl/s390x-unknown-linux-musl/release/examples/demo"
+        )
+    );
+    }
+
+    #[test]
+    fn test_stream_lines_keeps_output() {
+        let output = stream_lines(
+            "   Compiling app v0.1.0\nerror: could not compile `app`\n".as_bytes(),
+            "x86_64-unknown-linux-musl",
+        );
+        assert_eq!(
+            output,
+            "   Compiling app v0.1.0\nerror: could not compile `app`\n"
+        );
+    }
+
+    #[test]
+    fn test_built_executables() {
+        let stdout = r#"{"reason":"compiler-artifact","target":{"name":"serde","kind":["lib"]},"executable":null}
+{"reason":"build-script-executed","package_id":"app 0.1.0"}
+{"reason":"compiler-artifact","target":{"name":"server","kind":["bin"]},"executable":"/p/target/release/server"}
+{"reason":"compiler-artifact","target":{"name":"demo","kind":["example"]},"executable":"/p/target/release/examples/demo"}
+{"reason":"build-finished","success":true}"#;
+        assert_eq!(
+            built_executables(stdout),
+            vec![
+                (
+                    "server".to_string(),
+                    PathBuf::from("/p/target/release/server")
+                ),
+                (
+                    "demo".to_string(),
+                    PathBuf::from("/p/target/release/examples/demo")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_select_executable_by_binary_name() {
         let dir = tempdir().unwrap();
-        // --example at end with no value should not match (needs i+1 < len)
+        let bin_dir = dir.path().join("release");
+        fs::create_dir(&bin_dir).unwrap();
+        for name in ["serve", "migrate"] {
+            fs::write(bin_dir.join(name), "").unwrap();
+        }
+        let executables = vec![
+            ("serve".to_string(), bin_dir.join("serve")),
+            ("migrate".to_string(), bin_dir.join("migrate")),
+        ];
+
         let builder = RustBuilder::new(dir.path(), "x86_64-unknown-linux-musl")
-            .with_cargo_args(vec!["--example".to_string()]);
-        assert_eq!(builder.get_binary_subdir(), None);
+            .with_cargo_args(vec!["--bin".to_string(), "migrate".to_string()]);
+        assert_eq!(
+            builder.select_executable(executables.clone()).unwrap(),
+            bin_dir.join("migrate")
+        );
+
+        let builder = RustBuilder::new(dir.path(), "x86_64-unknown-linux-musl");
+        assert_eq!(
+            builder
+                .select_executable(executables[..1].to_vec())
+                .unwrap(),
+            bin_dir.join("serve")
+        );
+        assert!(builder.select_executable(Vec::new()).is_err());
     }
 }
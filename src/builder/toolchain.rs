@@ -0,0 +1,134 @@
+//! Toolchain pinning with `rust-toolchain.toml`
+//!
+//! rustup's cargo proxy already honors these files, but a cargo installed
+//! some other way (or first on `PATH` in CI) doesn't. krust reads the file
+//! itself and runs cargo through `rustup run <toolchain>` so a pinned project
+//! is always built with its toolchain.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Deserialize)]
+struct ToolchainFile {
+    toolchain: ToolchainSection,
+}
+
+#[derive(Deserialize)]
+struct ToolchainSection {
+    channel: Option<String>,
+}
+
+/// The toolchain pinned by the nearest `rust-toolchain.toml` or legacy
+/// `rust-toolchain` file in `project_path` or its parents, like rustup
+pub fn find(project_path: &Path) -> Result<Option<String>> {
+    let start = project_path
+        .canonicalize()
+        .unwrap_or_else(|_| project_path.to_path_buf());
+    for dir in start.ancestors() {
+        for name in ["rust-toolchain", "rust-toolchain.toml"] {
+            let path = dir.join(name);
+            if path.is_file() {
+                let content = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                return parse(&content)
+                    .with_context(|| format!("Failed to parse {}", path.display()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Parse a toolchain file: TOML with a `[toolchain]` section, or the legacy
+/// format holding just the channel name
+pub fn parse(content: &str) -> Result<Option<String>> {
+    let trimmed = content.trim();
+    if !trimmed.contains('[') && !trimmed.contains('=') {
+        return Ok(Some(trimmed.to_string()).filter(|c| !c.is_empty()));
+    }
+    let file: ToolchainFile = toml::from_str(content)?;
+    Ok(file.toolchain.channel)
+}
+
+/// A `cargo` command, run with `toolchain` when one is pinned
+pub fn cargo(toolchain: Option<&str>) -> Command {
+    run(toolchain, "cargo")
+}
+
+/// The compiler cargo runs: `rustc`, or `program` when `RUSTC` names
+/// another, with `toolchain` when one is pinned
+pub fn rustc(toolchain: Option<&str>, program: Option<&str>) -> Command {
+    run(toolchain, program.unwrap_or("rustc"))
+}
+
+fn run(toolchain: Option<&str>, program: &str) -> Command {
+    match toolchain {
+        Some(toolchain) => {
+            let mut cmd = Command::new("rustup");
+            cmd.args(["run", toolchain, program]);
+            cmd
+        }
+        None => Command::new(program),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("[toolchain]\nchannel = \"1.79.0\"\ncomponents = [\"clippy\"]\n").unwrap(),
+            Some("1.79.0".to_string())
+        );
+        assert_eq!(
+            parse("nightly-2024-06-01\n").unwrap(),
+            Some("nightly-2024-06-01".to_string())
+        );
+        assert_eq!(parse("[toolchain]\nprofile = \"minimal\"\n").unwrap(), None);
+        assert!(parse("[toolchain\n").is_err());
+    }
+
+    #[test]
+    fn test_find_in_parent() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("rust-toolchain.toml"),
+            "[toolchain]\nchannel = \"stable\"\n",
+        )
+        .unwrap();
+        let member = dir.path().join("crates/app");
+        fs::create_dir_all(&member).unwrap();
+
+        assert_eq!(find(&member).unwrap(), Some("stable".to_string()));
+    }
+
+    #[test]
+    fn test_cargo_command() {
+        let cmd = cargo(Some("1.79.0"));
+        assert_eq!(cmd.get_program(), "rustup");
+        assert_eq!(
+            cmd.get_args().collect::<Vec<_>>(),
+            vec!["run", "1.79.0", "cargo"]
+        );
+        assert_eq!(cargo(None).get_program(), "cargo");
+    }
+
+    #[test]
+    fn test_rustc_command() {
+        let cmd = rustc(Some("1.79.0"), None);
+        assert_eq!(
+            cmd.get_args().collect::<Vec<_>>(),
+            vec!["run", "1.79.0", "rustc"]
+        );
+        assert_eq!(rustc(None, None).get_program(), "rustc");
+        assert_eq!(
+            rustc(None, Some("/opt/rust/bin/rustc")).get_program(),
+            "/opt/rust/bin/rustc"
+        );
+    }
+}
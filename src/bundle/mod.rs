@@ -0,0 +1,238 @@
+//! Deployable bundles of resolved manifests and the images they reference
+//!
+//! A bundle is a gzipped tarball containing `bundle.json` (the images by
+//! digest) and the resolved YAML under `manifests/`. It can be written to
+//! disk or pushed to a registry as an OCI artifact, so a release can be
+//! promoted across environments as a single unit.
+
+use crate::registry::encoding::EncodedManifest;
+use crate::registry::{media_types, OciDescriptor, OciImageManifest, RegistryAuth, RegistryClient};
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use tracing::info;
+
+/// Config media type identifying a krust bundle artifact
+pub const BUNDLE_CONFIG_MEDIA_TYPE: &str = "application/vnd.krust.bundle.config.v1+json";
+
+/// Media type of the bundle tarball layer
+pub const BUNDLE_LAYER_MEDIA_TYPE: &str = "application/vnd.krust.bundle.v1.tar+gzip";
+
+/// Name of the bundle index inside the tarball
+pub const BUNDLE_INDEX_FILE: &str = "bundle.json";
+
+/// Contents of `bundle.json`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct BundleIndex {
+    /// krust:// path to the image reference (by digest) it resolved to
+    pub images: BTreeMap<String, String>,
+    /// Paths of the resolved manifests within the bundle
+    pub manifests: Vec<String>,
+}
+
+/// A set of resolved manifests and the images they reference
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    index: BundleIndex,
+    manifests: Vec<(String, String)>,
+}
+
+impl Bundle {
+    /// Create a bundle from resolved images and `(source filename, resolved YAML)` pairs.
+    /// Manifests are numbered in input order so names stay unique and apply order is kept.
+    pub fn new(images: BTreeMap<String, String>, documents: Vec<(String, String)>) -> Self {
+        let manifests: Vec<(String, String)> = documents
+            .into_iter()
+            .enumerate()
+            .map(|(i, (filename, content))| {
+                let base = Path::new(&filename)
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or("manifest.yaml")
+                    .to_string();
+                (format!("manifests/{:03}-{}", i, base), content)
+            })
+            .collect();
+
+        Self {
+            index: BundleIndex {
+                images,
+                manifests: manifests.iter().map(|(path, _)| path.clone()).collect(),
+            },
+            manifests,
+        }
+    }
+
+    pub fn index(&self) -> &BundleIndex {
+        &self.index
+    }
+
+    /// Serialize the bundle as a gzipped tarball. Entries have fixed
+    /// timestamps and ownership, so the same inputs give the same digest.
+    pub fn to_tar_gz(&self) -> Result<Vec<u8>> {
+        let mut tar_data = Vec::new();
+        {
+            let mut tar = tar::Builder::new(&mut tar_data);
+
+            let index_json = serde_json::to_vec_pretty(&self.index)?;
+            append_file(&mut tar, BUNDLE_INDEX_FILE, &index_json)?;
+            for (path, content) in &self.manifests {
+                append_file(&mut tar, path, content.as_bytes())?;
+            }
+
+            tar.finish()?;
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&tar_data)?;
+        Ok(encoder.finish()?)
+    }
+
+    /// Write the bundle tarball to a file.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_tar_gz()?)
+            .with_context(|| format!("Failed to write bundle to {}", path.display()))
+    }
+
+    /// Push the bundle to a registry as an OCI artifact. The bundle index is
+    /// stored as the artifact config so it can be inspected without pulling
+    /// the tarball. Returns the artifact reference by digest.
+    pub async fn push(
+        &self,
+        registry_client: &mut RegistryClient,
+        target: &str,
+        auth: &RegistryAuth,
+    ) -> Result<String> {
+        let config_data = serde_json::to_vec(&self.index)?;
+        let config_digest = format!("sha256:{}", sha256::digest(config_data.as_slice()));
+        registry_client
+            .push_blob(target, &config_data, &config_digest, auth)
+            .await?;
+
+        let layer_data = self.to_tar_gz()?;
+        let layer_digest = format!("sha256:{}", sha256::digest(layer_data.as_slice()));
+        registry_client
+            .push_blob(target, &layer_data, &layer_digest, auth)
+            .await?;
+
+        let manifest = OciImageManifest {
+            schema_version: 2,
+            media_type: media_types::OCI_MANIFEST.to_string(),
+            artifact_type: None,
+            config: Some(OciDescriptor {
+                media_type: BUNDLE_CONFIG_MEDIA_TYPE.to_string(),
+                digest: config_digest,
+                size: config_data.len() as i64,
+                urls: None,
+                annotations: None,
+            }),
+            layers: vec![OciDescriptor {
+                media_type: BUNDLE_LAYER_MEDIA_TYPE.to_string(),
+                digest: layer_digest,
+                size: layer_data.len() as i64,
+                urls: None,
+                annotations: None,
+            }],
+            subject: None,
+            annotations: None,
+        };
+        let encoded = EncodedManifest::new(
+            &manifest,
+            &manifest.media_type,
+            registry_client.json_format(),
+        )?;
+        let manifest_digest = registry_client
+            .push_encoded_manifest(target, &encoded, auth)
+            .await?;
+
+        let reference = crate::registry::ImageReference::parse(target)?;
+
+        let bundle_ref = format!("{}@{}", reference.repository_url(), manifest_digest);
+        info!("Pushed bundle {}", bundle_ref);
+        Ok(bundle_ref)
+    }
+}
+
+fn append_file<W: Write>(tar: &mut tar::Builder<W>, path: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_path(path)?;
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_cksum();
+    tar.append(&header, data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn sample_bundle() -> Bundle {
+        let mut images = BTreeMap::new();
+        images.insert(
+            "./app".to_string(),
+            "ttl.sh/test/app@sha256:abc".to_string(),
+        );
+        Bundle::new(
+            images,
+            vec![
+                (
+                    "k8s/deploy.yaml".to_string(),
+                    "image: ttl.sh/test/app@sha256:abc\n".to_string(),
+                ),
+                (
+                    "k8s/service.yaml".to_string(),
+                    "kind: Service\n".to_string(),
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_bundle_manifest_names() {
+        let bundle = sample_bundle();
+        assert_eq!(
+            bundle.index().manifests,
+            vec!["manifests/000-deploy.yaml", "manifests/001-service.yaml"]
+        );
+    }
+
+    #[test]
+    fn test_bundle_tarball_contents() {
+        let data = sample_bundle().to_tar_gz().unwrap();
+        let mut archive = tar::Archive::new(GzDecoder::new(data.as_slice()));
+
+        let mut files = BTreeMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().to_string();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            files.insert(path, content);
+        }
+
+        let index: BundleIndex = serde_json::from_str(&files[BUNDLE_INDEX_FILE]).unwrap();
+        assert_eq!(index.images["./app"], "ttl.sh/test/app@sha256:abc");
+        assert_eq!(
+            files["manifests/000-deploy.yaml"],
+            "image: ttl.sh/test/app@sha256:abc\n"
+        );
+    }
+
+    #[test]
+    fn test_bundle_tarball_is_reproducible() {
+        assert_eq!(
+            sample_bundle().to_tar_gz().unwrap(),
+            sample_bundle().to_tar_gz().unwrap()
+        );
+    }
+}
@@ -0,0 +1,416 @@
+//! Inspecting and garbage collecting krust's cache directory
+//!
+//! The cache holds cached builds (`builds/`), base image manifests, configs,
+//! layers and tag resolutions (`manifests/`) and git clones (`git/`).
+//! Everything in it can be recreated, so `krust cache prune` removes the
+//! least recently used entries by age or to fit a size budget, and
+//! `krust cache clear` removes it all. Reading a cached build or blob
+//! updates its modification time, which is what "recently used" means here.
+
+use crate::builder::fingerprint::KEY_FILE;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// What a cache entry holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    Build,
+    Blob,
+    Tag,
+    Git,
+}
+
+impl std::fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            EntryKind::Build => "build",
+            EntryKind::Blob => "blob",
+            EntryKind::Tag => "tag",
+            EntryKind::Git => "git",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One removable unit of the cache
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CacheEntry {
+    pub kind: EntryKind,
+    pub path: PathBuf,
+    /// The project, digest, reference or repository the entry is for
+    pub description: String,
+    /// Bytes on disk
+    pub size: u64,
+    /// When the entry was last written or used
+    #[serde(skip)]
+    pub modified: SystemTime,
+}
+
+impl CacheEntry {
+    pub fn age(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(self.modified)
+            .unwrap_or_default()
+    }
+
+    /// Delete the entry from disk
+    pub fn remove(&self) -> Result<()> {
+        let result = if self.path.is_dir() {
+            fs::remove_dir_all(&self.path)
+        } else {
+            fs::remove_file(&self.path)
+        };
+        result.with_context(|| format!("Failed to remove {}", self.path.display()))
+    }
+}
+
+/// Every entry in the cache at `cache_dir`, most recently used first.
+/// A missing cache directory has no entries.
+pub fn entries(cache_dir: &Path) -> Result<Vec<CacheEntry>> {
+    let mut entries = Vec::new();
+
+    for path in children(&cache_dir.join("builds"))? {
+        let fingerprint = path.join("fingerprint");
+        let description = fs::read_to_string(path.join(KEY_FILE))
+            .map(|key| describe_build_key(&key))
+            .unwrap_or_else(|_| file_name(&path));
+        entries.push(CacheEntry {
+            kind: EntryKind::Build,
+            description,
+            size: disk_usage(&path)?,
+            modified: modified(&fingerprint).or_else(|_| modified(&path))?,
+            path,
+        });
+    }
+
+    let manifests = cache_dir.join("manifests");
+    for path in children(&manifests.join("sha256"))? {
+        entries.push(CacheEntry {
+            kind: EntryKind::Blob,
+            description: format!("sha256:{}", file_name(&path)),
+            size: disk_usage(&path)?,
+            modified: modified(&path)?,
+            path,
+        });
+    }
+    for path in children(&manifests.join("tags"))? {
+        let description = fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<serde_json::Value>(&data).ok())
+            .and_then(|tag| {
+                Some(format!(
+                    "{} -> {}",
+                    tag["reference"].as_str()?,
+                    tag["digest"].as_str()?
+                ))
+            })
+            .unwrap_or_else(|| file_name(&path));
+        entries.push(CacheEntry {
+            kind: EntryKind::Tag,
+            description,
+            size: disk_usage(&path)?,
+            modified: modified(&path)?,
+            path,
+        });
+    }
+
+    for path in children(&cache_dir.join("git"))? {
+        let description = fs::read_to_string(path.join(".git").join("config"))
+            .ok()
+            .and_then(|config| origin_url(&config))
+            .unwrap_or_else(|| file_name(&path));
+        entries.push(CacheEntry {
+            kind: EntryKind::Git,
+            description,
+            size: disk_usage(&path)?,
+            modified: modified(&path.join(".git").join("FETCH_HEAD"))
+                .or_else(|_| modified(&path))?,
+            path,
+        });
+    }
+
+    entries.sort_by_key(|e| std::cmp::Reverse(e.modified));
+    Ok(entries)
+}
+
+/// The entries to remove so that none is older than `older_than` and the
+/// rest fit in `max_size` bytes, least recently used first
+pub fn prunable(
+    entries: &[CacheEntry],
+    older_than: Option<Duration>,
+    max_size: Option<u64>,
+) -> Vec<&CacheEntry> {
+    let mut by_age: Vec<&CacheEntry> = entries.iter().collect();
+    by_age.sort_by_key(|e| e.modified);
+
+    let mut remaining: u64 = entries.iter().map(|e| e.size).sum();
+    let mut prune = Vec::new();
+    for entry in by_age {
+        let too_old = older_than.is_some_and(|age| entry.age() > age);
+        let too_big = max_size.is_some_and(|max| remaining > max);
+        if too_old || too_big {
+            remaining -= entry.size;
+            prune.push(entry);
+        }
+    }
+    prune
+}
+
+/// Remove everything in the cache at `cache_dir`, returning the bytes freed
+pub fn clear(cache_dir: &Path) -> Result<u64> {
+    let mut freed = 0;
+    for path in children(cache_dir)? {
+        freed += disk_usage(&path)?;
+        let result = if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        result.with_context(|| format!("Failed to remove {}", path.display()))?;
+    }
+    Ok(freed)
+}
+
+/// Mark a cache file as just used. Failures are ignored; at worst the entry
+/// is pruned sooner than it should be.
+pub fn touch(path: &Path) {
+    if let Ok(file) = fs::File::options().append(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+/// Parse a size like `500MB`, `2GiB` or `1024`. Decimal units are powers of
+/// 1000 and binary units (`KiB`, `MiB`, ...) powers of 1024.
+pub fn parse_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let amount: f64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid size '{}': expected e.g. 500MB, 2GiB", value))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => anyhow::bail!(
+            "Invalid size unit '{}' in '{}': use B, KB, MB, GB, TB or KiB, MiB, GiB, TiB",
+            unit,
+            value
+        ),
+    };
+    Ok((amount * multiplier as f64) as u64)
+}
+
+/// `project (target) cargo args` from a build cache key
+fn describe_build_key(key: &str) -> String {
+    let mut lines = key.lines();
+    let project = lines.next().unwrap_or_default();
+    let target = lines.next().unwrap_or_default();
+    let args = lines.next().unwrap_or_default();
+    let mut description = format!("{} ({})", project, target);
+    if !args.is_empty() {
+        description.push(' ');
+        description.push_str(args);
+    }
+    description
+}
+
+/// The `origin` remote's URL from a `.git/config`
+fn origin_url(config: &str) -> Option<String> {
+    let mut in_origin = false;
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_origin = line == r#"[remote "origin"]"#;
+        } else if let Some(url) = line.strip_prefix("url") {
+            if in_origin {
+                return Some(url.trim_start().trim_start_matches('=').trim().to_string());
+            }
+        }
+    }
+    None
+}
+
+fn children(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read directory {}", dir.display()))
+        }
+    };
+    let mut paths = entries
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()
+        .with_context(|| format!("Failed to read directory {}", dir.display()))?;
+    paths.sort();
+    Ok(paths)
+}
+
+/// Total size of the files at or under `path`, not following symlinks
+fn disk_usage(path: &Path) -> Result<u64> {
+    let metadata =
+        fs::symlink_metadata(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for child in children(path)? {
+        size += disk_usage(&child)?;
+    }
+    Ok(size)
+}
+
+fn modified(path: &Path) -> Result<SystemTime> {
+    fs::symlink_metadata(path)
+        .and_then(|m| m.modified())
+        .with_context(|| format!("Failed to read {}", path.display()))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn entry(name: &str, size: u64, age_secs: u64) -> CacheEntry {
+        CacheEntry {
+            kind: EntryKind::Blob,
+            path: PathBuf::from(name),
+            description: name.to_string(),
+            size,
+            modified: SystemTime::now() - Duration::from_secs(age_secs),
+        }
+    }
+
+    fn names(entries: Vec<&CacheEntry>) -> Vec<&str> {
+        entries.iter().map(|e| e.description.as_str()).collect()
+    }
+
+    #[test]
+    fn test_entries() {
+        let dir = tempdir().unwrap();
+        let cache_dir = dir.path();
+        assert!(entries(&cache_dir.join("missing")).unwrap().is_empty());
+
+        let build = cache_dir.join("builds").join("abc");
+        fs::create_dir_all(&build).unwrap();
+        fs::write(build.join("fingerprint"), "123\napp\n").unwrap();
+        fs::write(build.join("app"), "binary").unwrap();
+        fs::write(
+            build.join(KEY_FILE),
+            "/src/app\nx86_64-unknown-linux-musl\n--features tls",
+        )
+        .unwrap();
+
+        let manifests = crate::registry::cache::ManifestCache::new(cache_dir.join("manifests"));
+        let data = br#"{"schemaVersion":2}"#;
+        let digest = format!("sha256:{}", sha256::digest(data.as_slice()));
+        manifests.put(&digest, data).unwrap();
+        manifests
+            .put_tag("cgr.dev/chainguard/static:latest", &digest)
+            .unwrap();
+
+        let clone = cache_dir.join("git").join("def");
+        fs::create_dir_all(clone.join(".git")).unwrap();
+        fs::write(
+            clone.join(".git").join("config"),
+            "[core]\n\tbare = false\n[remote \"origin\"]\n\turl = https://github.com/user/app\n",
+        )
+        .unwrap();
+
+        let mut found = entries(cache_dir).unwrap();
+        found.sort_by_key(|e| e.kind);
+        let summary: Vec<(EntryKind, &str)> = found
+            .iter()
+            .map(|e| (e.kind, e.description.as_str()))
+            .collect();
+        let tag = format!("cgr.dev/chainguard/static:latest -> {}", digest);
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    EntryKind::Build,
+                    "/src/app (x86_64-unknown-linux-musl) --features tls"
+                ),
+                (EntryKind::Blob, digest.as_str()),
+                (EntryKind::Tag, tag.as_str()),
+                (EntryKind::Git, "https://github.com/user/app"),
+            ]
+        );
+        assert_eq!(found[1].size, data.len() as u64);
+
+        assert!(clear(cache_dir).unwrap() > 0);
+        assert!(entries(cache_dir).unwrap().is_empty());
+        assert!(cache_dir.exists());
+    }
+
+    #[test]
+    fn test_prunable() {
+        let entries = vec![
+            entry("new", 100, 60),
+            entry("old", 100, 10 * 86400),
+            entry("middle", 300, 3600),
+        ];
+        let day = Duration::from_secs(86400);
+
+        assert_eq!(names(prunable(&entries, Some(day), None)), vec!["old"]);
+        assert_eq!(
+            names(prunable(&entries, None, Some(150))),
+            vec!["old", "middle"]
+        );
+        assert_eq!(names(prunable(&entries, None, Some(400))), vec!["old"]);
+        assert!(prunable(&entries, None, Some(500)).is_empty());
+        assert_eq!(
+            names(prunable(&entries, Some(day), Some(0))),
+            vec!["old", "middle", "new"]
+        );
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("500MB").unwrap(), 500_000_000);
+        assert_eq!(parse_size("2GiB").unwrap(), 2 << 30);
+        assert_eq!(parse_size("1.5gb").unwrap(), 1_500_000_000);
+        assert_eq!(parse_size("10 KiB").unwrap(), 10240);
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("5PB").is_err());
+    }
+
+    #[test]
+    fn test_touch() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("entry");
+        fs::write(&path, "data").unwrap();
+        let old = SystemTime::now() - Duration::from_secs(86400);
+        fs::File::options()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        touch(&path);
+        assert!(modified(&path).unwrap() > old);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "data");
+        touch(&dir.path().join("missing"));
+    }
+}
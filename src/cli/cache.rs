@@ -0,0 +1,65 @@
+//! Human-readable output for `krust cache`
+
+use crate::cache::CacheEntry;
+use indicatif::{HumanBytes, HumanDuration};
+use std::fmt::Write;
+
+/// Render cache entries as a table followed by their total size
+pub fn format_table(entries: &[CacheEntry]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{:<8} {:>10}  {:<16} ENTRY",
+        "KIND", "SIZE", "LAST USED"
+    );
+    for entry in entries {
+        let _ = writeln!(
+            out,
+            "{:<8} {:>10}  {:<16} {}",
+            entry.kind,
+            HumanBytes(entry.size).to_string(),
+            format!("{} ago", HumanDuration(entry.age())),
+            entry.description
+        );
+    }
+    let total: u64 = entries.iter().map(|e| e.size).sum();
+    let _ = writeln!(
+        out,
+        "{} {}, {} total",
+        entries.len(),
+        if entries.len() == 1 {
+            "entry"
+        } else {
+            "entries"
+        },
+        HumanBytes(total)
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::EntryKind;
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_format_table() {
+        let entries = vec![CacheEntry {
+            kind: EntryKind::Build,
+            path: PathBuf::from("/cache/builds/abc"),
+            description: "/src/app (x86_64-unknown-linux-musl)".to_string(),
+            size: 2048,
+            modified: SystemTime::now() - Duration::from_secs(7200),
+        }];
+        let table = format_table(&entries);
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].starts_with("KIND"));
+        assert!(lines[1].starts_with("build"));
+        assert!(lines[1].contains("2.00 KiB"));
+        assert!(lines[1].contains("2 hours ago"));
+        assert!(lines[1].ends_with("/src/app (x86_64-unknown-linux-musl)"));
+        assert_eq!(lines[2], "1 entry, 2.00 KiB total");
+    }
+}
@@ -0,0 +1,154 @@
+//! GitHub Actions step outputs and job summaries for `krust build`
+//!
+//! Actions hands each step the paths of files to append to in
+//! `GITHUB_OUTPUT` and `GITHUB_STEP_SUMMARY`. With `--github-outputs`, krust
+//! writes the image it built there, so later steps can use
+//! `steps.<id>.outputs.image` and the run's summary page lists what was built.
+
+use crate::publish::BuildMetadata;
+use anyhow::{Context, Result};
+use indicatif::HumanBytes;
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+/// Environment variable naming the file step outputs are appended to
+pub const OUTPUT_ENV: &str = "GITHUB_OUTPUT";
+/// Environment variable naming the file the job summary is appended to
+pub const STEP_SUMMARY_ENV: &str = "GITHUB_STEP_SUMMARY";
+
+/// Write outputs and a summary for `metadata` to the files Actions named.
+/// Outside of Actions neither variable is set and nothing is written.
+pub fn write(metadata: &BuildMetadata) -> Result<()> {
+    if let Some(path) = std::env::var_os(OUTPUT_ENV).filter(|p| !p.is_empty()) {
+        append(Path::new(&path), &format_outputs(metadata))?;
+    }
+    if let Some(path) = std::env::var_os(STEP_SUMMARY_ENV).filter(|p| !p.is_empty()) {
+        append(Path::new(&path), &format_summary(metadata))?;
+    }
+    Ok(())
+}
+
+fn append(path: &Path, contents: &str) -> Result<()> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .with_context(|| format!("Failed to write to {}", path.display()))
+}
+
+/// Step outputs: `digest` and `image` (by digest) of the first image built,
+/// and `images`, every image built, one per line
+fn format_outputs(metadata: &BuildMetadata) -> String {
+    let mut out = String::new();
+    if let Some(image) = metadata.images.first() {
+        let _ = writeln!(out, "digest={}", image.digest);
+        let _ = writeln!(out, "image={}@{}", image.name, image.digest);
+    }
+    // Values spanning lines are written between delimiters, which must not
+    // appear in the value; image references can't contain spaces
+    let _ = writeln!(out, "images<<KRUST IMAGES");
+    for image in &metadata.images {
+        let _ = writeln!(out, "{}@{}", image.name, image.digest);
+    }
+    let _ = writeln!(out, "KRUST IMAGES");
+    out
+}
+
+/// A Markdown section per image with a table of its platforms
+fn format_summary(metadata: &BuildMetadata) -> String {
+    let mut out = String::new();
+    for image in &metadata.images {
+        let _ = writeln!(out, "### `{}`\n", image.name);
+        let _ = write!(out, "Index `{}`", image.digest);
+        if !image.tags.is_empty() {
+            let tags: Vec<String> = image.tags.iter().map(|t| format!("`{}`", t)).collect();
+            let _ = write!(out, ", tagged {}", tags.join(", "));
+        }
+        let _ = writeln!(out, "\n");
+        let _ = writeln!(out, "| Platform | Digest | Size | Added by krust |");
+        let _ = writeln!(out, "|----------|--------|-----:|--------------:|");
+        for platform in &image.platforms {
+            let _ = writeln!(
+                out,
+                "| {} | `{}` | {} | {} |",
+                platform.platform,
+                platform.digest,
+                HumanBytes(platform.size.max(0) as u64),
+                HumanBytes(platform.added_size.max(0) as u64)
+            );
+        }
+        let _ = writeln!(out);
+    }
+    let base = match &metadata.base_digest {
+        Some(digest) => format!("`{}` at `{}`", metadata.base_image, digest),
+        None => format!("`{}`", metadata.base_image),
+    };
+    let _ = writeln!(out, "Built on {} in {:.1}s\n", base, metadata.duration_secs);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::publish::{ImageMetadata, PlatformMetadata};
+    use std::time::Duration;
+
+    fn metadata() -> BuildMetadata {
+        let mut metadata = BuildMetadata::new("cgr.dev/chainguard/static:latest", Some("sha256:b"))
+            .with_duration(Duration::from_millis(41_372));
+        for name in ["ghcr.io/org/api", "ghcr.io/org/worker"] {
+            metadata.images.push(ImageMetadata {
+                name: name.to_string(),
+                digest: format!("sha256:{}", name.len()),
+                tags: vec!["v1".to_string()],
+                references: Vec::new(),
+                platforms: vec![PlatformMetadata {
+                    platform: "linux/amd64".to_string(),
+                    digest: "sha256:m".to_string(),
+                    manifest_size: 500,
+                    size: 3 * 1024 * 1024,
+                    added_size: 1024,
+                }],
+            });
+        }
+        metadata
+    }
+
+    #[test]
+    fn test_format_outputs() {
+        assert_eq!(
+            format_outputs(&metadata()),
+            "digest=sha256:15\n\
+             image=ghcr.io/org/api@sha256:15\n\
+             images<<KRUST IMAGES\n\
+             ghcr.io/org/api@sha256:15\n\
+             ghcr.io/org/worker@sha256:18\n\
+             KRUST IMAGES\n"
+        );
+    }
+
+    #[test]
+    fn test_format_summary() {
+        let summary = format_summary(&metadata());
+        assert!(summary.starts_with("### `ghcr.io/org/api`\n\nIndex `sha256:15`, tagged `v1`\n\n"));
+        assert!(summary.contains("| linux/amd64 | `sha256:m` | 3.00 MiB | 1.00 KiB |\n"));
+        assert!(summary.contains("### `ghcr.io/org/worker`"));
+        assert!(summary
+            .ends_with("Built on `cgr.dev/chainguard/static:latest` at `sha256:b` in 41.4s\n\n"));
+    }
+
+    #[test]
+    fn test_append() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output");
+        std::fs::write(&path, "earlier=1\n").unwrap();
+        append(&path, "digest=sha256:a\n").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "earlier=1\ndigest=sha256:a\n"
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Human-readable output for `krust inspect`
+
+use crate::registry::inspect::ImageDetails;
+use indicatif::HumanBytes;
+use std::fmt::Write;
+
+/// Render image details as a summary per platform followed by a layer table
+pub fn format_table(details: &ImageDetails) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{}", details.reference);
+    let _ = writeln!(out, "Media type: {}", details.media_type);
+
+    for image in &details.images {
+        let platform = image
+            .platform
+            .as_ref()
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let _ = writeln!(out);
+        let _ = writeln!(
+            out,
+            "{}  {}  ({} layers, {})",
+            platform,
+            image.digest,
+            image.layers.len(),
+            HumanBytes(image.total_size().max(0) as u64)
+        );
+
+        let container = &image.config["config"];
+        for key in ["Entrypoint", "Cmd", "User", "WorkingDir"] {
+            match &container[key] {
+                serde_json::Value::Null => {}
+                serde_json::Value::String(s) if s.is_empty() => {}
+                serde_json::Value::String(s) => {
+                    let _ = writeln!(out, "  {}: {}", key, s);
+                }
+                value => {
+                    let _ = writeln!(out, "  {}: {}", key, value);
+                }
+            }
+        }
+        if let Some(created) = image.config["created"].as_str() {
+            let _ = writeln!(out, "  Created: {}", created);
+        }
+
+        let _ = writeln!(out, "  {:<73} {:>10}  MEDIA TYPE", "LAYER", "SIZE");
+        for layer in &image.layers {
+            let _ = writeln!(
+                out,
+                "  {:<73} {:>10}  {}",
+                layer.digest,
+                HumanBytes(layer.size.max(0) as u64).to_string(),
+                layer.media_type
+            );
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Platform;
+    use crate::registry::inspect::{LayerDetails, PlatformDetails};
+
+    #[test]
+    fn test_format_table() {
+        let details = ImageDetails {
+            reference: "ghcr.io/user/app@sha256:index".to_string(),
+            media_type: "application/vnd.oci.image.index.v1+json".to_string(),
+            digest: "sha256:index".to_string(),
+            index: None,
+            images: vec![PlatformDetails {
+                platform: Some(Platform::new("linux", "arm64", None)),
+                digest: "sha256:arm".to_string(),
+                manifest: serde_json::Value::Null,
+                config: serde_json::json!({
+                    "config": {"Cmd": ["/app/hello"], "User": "65532"}
+                }),
+                layers: vec![
+                    LayerDetails {
+                        media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
+                        digest: "sha256:base".to_string(),
+                        size: 2 * 1024 * 1024,
+                    },
+                    LayerDetails {
+                        media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
+                        digest: "sha256:app".to_string(),
+                        size: 1024,
+                    },
+                ],
+            }],
+        };
+
+        let table = format_table(&details);
+        assert!(table.contains("linux/arm64  sha256:arm  (2 layers, 2.00 MiB)"));
+        assert!(table.contains(r#"Cmd: ["/app/hello"]"#));
+        assert!(table.contains("User: 65532"));
+        assert!(table.contains("sha256:app"));
+    }
+}
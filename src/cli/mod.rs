@@ -1,4 +1,14 @@
+pub mod cache;
+pub mod github;
+pub mod inspect;
+pub mod progress;
+
+use crate::config::{Include, Libc, SbomFormat, Severity};
+use crate::events::EventFormat;
+use crate::manifest::Platform;
+use crate::publish::{Cluster, ImageStore, TarballFormat};
 use clap::{Parser, Subcommand};
+use std::net::SocketAddr;
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -11,31 +21,282 @@ pub struct Cli {
     /// Enable verbose logging
     #[arg(short, long, global = true)]
     pub verbose: bool,
+
+    /// Only log warnings and errors, hiding build progress
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Write structured build events (build-started, cargo-progress,
+    /// layer-pushed, index-pushed) to stderr, one per line
+    #[arg(long, global = true, value_name = "FORMAT")]
+    pub events: Option<EventFormat>,
+
+    /// Write events to this file instead of stderr
+    #[arg(long, global = true, requires = "events")]
+    pub events_file: Option<PathBuf>,
+
+    /// Profile in the global config, `[profile.NAME]`, whose settings
+    /// replace the rest of the file's
+    #[arg(long, global = true, env = "KRUST_CONFIG_PROFILE", value_name = "NAME")]
+    pub config_profile: Option<String>,
 }
 
+// Commands is parsed once per run, so Build's size doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 pub enum Commands {
     /// Build a container image from a Rust application
     Build {
-        /// Path to the Rust project directory
+        /// Path to the Rust project directory, or a git URL with an optional
+        /// #revision (e.g. https://github.com/org/tool#v1.0)
         #[arg(value_name = "DIRECTORY")]
         path: Option<PathBuf>,
 
+        /// Workspace member to build when DIRECTORY is a workspace root
+        #[arg(short = 'p', long, value_name = "PACKAGE")]
+        package: Option<String>,
+
+        /// Build one image per binary target in the package, named
+        /// <package>-<binary> (or <package> for the binary of the same name)
+        #[arg(long, conflicts_with_all = ["artifact_dir", "oci_layout", "tarball"])]
+        all_bins: bool,
+
+        /// Binary targets to put in one image under /app/. Can be specified
+        /// multiple times or as a comma-separated list
+        #[arg(
+            long,
+            value_name = "BINS",
+            value_delimiter = ',',
+            conflicts_with = "all_bins"
+        )]
+        bins: Vec<String>,
+
+        /// Binary from --bins that's the image's entrypoint (defaults to the first)
+        #[arg(long, value_name = "BIN", requires = "bins")]
+        entrypoint: Option<String>,
+
         /// Target platforms (e.g., linux/amd64, linux/arm64)
         /// Can be specified multiple times or as a comma-separated list
         #[arg(long, value_delimiter = ',')]
-        platform: Option<Vec<String>>,
+        platform: Option<Vec<Platform>>,
+
+        /// Cargo profile to build with (defaults to release)
+        #[arg(long, value_name = "PROFILE")]
+        profile: Option<String>,
+
+        /// Maximum number of platforms to compile at once (defaults to the
+        /// number of CPUs)
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        build_concurrency: Option<u32>,
+
+        /// C library to link against; gnu builds dynamically linked binaries
+        /// that need a base image with glibc (defaults to build.libc, or musl)
+        #[arg(long, value_name = "LIBC")]
+        libc: Option<Libc>,
+
+        /// Write images with OCI media types (the default, unless
+        /// build.media_types is docker)
+        #[arg(long, conflicts_with = "docker")]
+        oci: bool,
+
+        /// Write images with Docker schema2 media types, for registries and
+        /// tools that reject OCI images
+        #[arg(long)]
+        docker: bool,
+
+        /// Require Cargo.lock to be up to date
+        #[arg(long)]
+        locked: bool,
+
+        /// Build without accessing the network
+        #[arg(long)]
+        offline: bool,
+
+        /// Same as --locked --offline
+        #[arg(long)]
+        frozen: bool,
+
+        /// Never contact a registry: use only cached or local base images,
+        /// and write images to --tarball, --oci-layout or --artifact-dir
+        #[arg(long)]
+        offline_images: bool,
+
+        /// Stop a platform's build if it takes longer than this many seconds
+        /// (defaults to build.timeout_secs, or no limit)
+        #[arg(long, value_name = "SECS")]
+        build_timeout: Option<u64>,
+
+        /// Build even if the sources and settings are unchanged since the
+        /// last build, instead of reusing its binary
+        #[arg(long)]
+        rebuild: bool,
+
+        /// Resolve the base image's tag again and pin it to the digest in
+        /// krust.lock, which later builds then use
+        #[arg(long)]
+        pin_base: bool,
+
+        /// Check the base image's cosign signatures against [base_policy] in
+        /// the config before building on it (defaults to build.verify_base)
+        #[arg(long)]
+        verify_base: bool,
 
         /// Skip pushing the image to the registry after building
         #[arg(long)]
         no_push: bool,
 
-        /// Tag to apply to the image (e.g., latest, v1.0.0)
+        /// Whether to push the image after building (--push=false is the same as --no-push)
+        #[arg(
+            long,
+            value_name = "BOOL",
+            num_args = 0..=1,
+            default_missing_value = "true",
+            conflicts_with = "no_push"
+        )]
+        push: Option<bool>,
+
+        /// Create the repository before pushing if the registry requires it
+        /// (Amazon ECR), through the ECR API
+        #[arg(long)]
+        create_repo: bool,
+
+        /// After pushing, read the image back from the registry to check it
+        /// matches what was uploaded, and smoke-test it with Docker if available
+        #[arg(long)]
+        verify: bool,
+
+        /// Sign the pushed image with Sigstore keyless signing, storing the
+        /// signature where `cosign verify` finds it
+        #[arg(long)]
+        sign: bool,
+
+        /// Sign the pushed image with this cosign key file, or a KMS key such
+        /// as awskms:///alias/NAME. Encrypted keys use the COSIGN_PASSWORD password.
+        #[arg(long, value_name = "KEY", conflicts_with = "sign")]
+        sign_key: Option<String>,
+
+        /// Scan each platform image for vulnerabilities with trivy, grype or
+        /// scan.url before publishing it, failing on findings at or above
+        /// scan.severity (defaults to build.scan)
+        #[arg(long)]
+        scan: bool,
+
+        /// Fail --scan on findings at or above this severity (defaults to scan.severity)
+        #[arg(long, value_name = "SEVERITY")]
+        scan_severity: Option<Severity>,
+
+        /// Generate an SBOM of the crates in each platform's binary and push
+        /// it as an artifact referring to the image (defaults to build.sbom)
+        #[arg(long, value_name = "FORMAT")]
+        sbom: Option<SbomFormat>,
+
+        /// Write the built images as an OCI layout to this directory, along with
+        /// a krust-push.json describing where they should be pushed by `krust push`
+        #[arg(long, value_name = "DIR")]
+        artifact_dir: Option<PathBuf>,
+
+        /// Write the image to an OCI image layout in this directory instead
+        /// of pushing it, for tools such as cosign, crane and syft
+        #[arg(long, value_name = "DIR")]
+        oci_layout: Option<PathBuf>,
+
+        /// Load the image into a local image store instead of pushing it
+        /// (builds for the host platform unless --platform is given). auto
+        /// picks Docker if it's running, otherwise nerdctl, k3s or ctr
+        #[arg(
+            long,
+            value_name = "STORE",
+            num_args = 0..=1,
+            default_missing_value = "docker"
+        )]
+        load: Option<ImageStore>,
+
+        /// containerd namespace to load the image into (defaults to k8s.io,
+        /// where Kubernetes looks for images, or nerdctl's own default)
+        #[arg(long, value_name = "NAMESPACE", requires = "load")]
+        containerd_namespace: Option<String>,
+
+        /// Load the image into a kind or minikube cluster instead of pushing
+        /// it (builds for the cluster's architecture unless --platform is
+        /// given). KRUST_REPO defaults to kind.local or minikube.local
+        #[arg(long, value_name = "CLUSTER")]
+        load_into: Option<Cluster>,
+
+        /// Write the image to a tarball instead of pushing it (builds for the
+        /// host platform unless --platform is given)
+        #[arg(long, value_name = "FILE")]
+        tarball: Option<PathBuf>,
+
+        /// Format of --tarball: a docker-archive for `docker load`, or an
+        /// oci-archive, which can hold several platforms
+        #[arg(
+            long,
+            value_name = "FORMAT",
+            default_value = "docker",
+            requires = "tarball"
+        )]
+        tarball_format: TarballFormat,
+
+        /// Write a JSON summary of the build (image names, index and
+        /// per-platform digests, sizes, base image digest and duration)
+        /// to this file
+        #[arg(long, value_name = "FILE")]
+        metadata_file: Option<PathBuf>,
+
+        /// In GitHub Actions, write the image's digest and reference as step
+        /// outputs (digest, image and images) and a table of its platforms to
+        /// the job summary
+        #[arg(long)]
+        github_outputs: bool,
+
+        /// Tag to apply to the image (e.g., latest, v1.0.0). Can be repeated
         /// If not specified, only pushes by digest
         #[arg(long)]
-        tag: Option<String>,
+        tag: Vec<String>,
+
+        /// Annotation to set on the image index (e.g.,
+        /// org.opencontainers.image.revision=abc123). Can be repeated
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        annotation: Vec<(String, String)>,
+
+        /// Annotation to set on each platform entry in the image index. Can be repeated
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        manifest_annotation: Vec<(String, String)>,
+
+        /// Label to set on the image config and manifest (e.g.,
+        /// org.opencontainers.image.title=app). Can be repeated
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        image_label: Vec<(String, String)>,
+
+        /// Environment variable to set in the image (e.g., RUST_LOG=info),
+        /// overriding the base image and project config. Can be repeated
+        #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        image_env: Vec<(String, String)>,
+
+        /// Local file or directory to add to the image, at DEST or else under
+        /// /app/ (e.g., config/app.toml:/etc/app/). Can be repeated
+        #[arg(long, value_name = "PATH[:DEST]")]
+        include: Vec<Include>,
+
+        /// Put included files in a layer of their own beneath the binary's
+        #[arg(long)]
+        include_layer: bool,
+
+        /// Push images to KRUST_REPO itself rather than a repository of their own
+        #[arg(long, conflicts_with_all = ["base_import_paths", "preserve_import_paths"])]
+        bare: bool,
+
+        /// Name images after their package's directory
+        #[arg(long, conflicts_with = "preserve_import_paths")]
+        base_import_paths: bool,
+
+        /// Name images after their package's path in its workspace or git
+        /// repository (e.g., app/crates/api)
+        #[arg(long)]
+        preserve_import_paths: bool,
 
-        /// Repository prefix (e.g., ghcr.io/username)
+        /// Repository prefix (e.g., ghcr.io/username), defaulting to
+        /// default_registry in the global config
         #[arg(env = "KRUST_REPO")]
         repo: Option<String>,
 
@@ -52,15 +313,35 @@ pub enum Commands {
 
         /// Target platforms (e.g., linux/amd64, linux/arm64)
         #[arg(long, value_delimiter = ',')]
-        platform: Option<Vec<String>>,
+        platform: Option<Vec<Platform>>,
+
+        /// Push images to KRUST_REPO itself rather than a repository of their own
+        #[arg(long, conflicts_with_all = ["base_import_paths", "preserve_import_paths"])]
+        bare: bool,
+
+        /// Name images after their package's directory
+        #[arg(long, conflicts_with = "preserve_import_paths")]
+        base_import_paths: bool,
 
-        /// Repository prefix (e.g., ghcr.io/username)
+        /// Name images after their package's path in its workspace or git
+        /// repository (e.g., app/crates/api)
+        #[arg(long)]
+        preserve_import_paths: bool,
+
+        /// Repository prefix (e.g., ghcr.io/username), defaulting to
+        /// default_registry in the global config
         #[arg(env = "KRUST_REPO")]
         repo: Option<String>,
 
-        /// Tag to apply to the images (e.g., latest, v1.0.0)
+        /// Tag to apply to the images (e.g., latest, v1.0.0). Can be repeated
         #[arg(long)]
-        tag: Option<String>,
+        tag: Vec<String>,
+
+        /// Load the images into a kind[:CLUSTER] or minikube[:PROFILE]
+        /// cluster instead of pushing them, and resolve references to the
+        /// loaded names
+        #[arg(long, value_name = "CLUSTER")]
+        load_into: Option<Cluster>,
     },
 
     /// Build images and apply resolved YAML with kubectl
@@ -71,17 +352,266 @@ pub enum Commands {
 
         /// Target platforms (e.g., linux/amd64, linux/arm64)
         #[arg(long, value_delimiter = ',')]
-        platform: Option<Vec<String>>,
+        platform: Option<Vec<Platform>>,
+
+        /// Push images to KRUST_REPO itself rather than a repository of their own
+        #[arg(long, conflicts_with_all = ["base_import_paths", "preserve_import_paths"])]
+        bare: bool,
+
+        /// Name images after their package's directory
+        #[arg(long, conflicts_with = "preserve_import_paths")]
+        base_import_paths: bool,
+
+        /// Name images after their package's path in its workspace or git
+        /// repository (e.g., app/crates/api)
+        #[arg(long)]
+        preserve_import_paths: bool,
+
+        /// Repository prefix (e.g., ghcr.io/username), defaulting to
+        /// default_registry in the global config
+        #[arg(env = "KRUST_REPO")]
+        repo: Option<String>,
+
+        /// Tag to apply to the images (e.g., latest, v1.0.0). Can be repeated
+        #[arg(long)]
+        tag: Vec<String>,
+
+        /// Load the images into a kind[:CLUSTER] or minikube[:PROFILE]
+        /// cluster instead of pushing them, and resolve references to the
+        /// loaded names
+        #[arg(long, value_name = "CLUSTER")]
+        load_into: Option<Cluster>,
+    },
+
+    /// Build images, resolve YAML and package both into a single deployable bundle
+    #[command(group(clap::ArgGroup::new("destination").required(true).multiple(true)))]
+    Bundle {
+        /// Path to YAML file or directory containing YAML files
+        #[arg(short = 'f', long = "filename", required = true)]
+        filenames: Vec<PathBuf>,
+
+        /// Target platforms (e.g., linux/amd64, linux/arm64)
+        #[arg(long, value_delimiter = ',')]
+        platform: Option<Vec<Platform>>,
+
+        /// Push images to KRUST_REPO itself rather than a repository of their own
+        #[arg(long, conflicts_with_all = ["base_import_paths", "preserve_import_paths"])]
+        bare: bool,
+
+        /// Name images after their package's directory
+        #[arg(long, conflicts_with = "preserve_import_paths")]
+        base_import_paths: bool,
+
+        /// Name images after their package's path in its workspace or git
+        /// repository (e.g., app/crates/api)
+        #[arg(long)]
+        preserve_import_paths: bool,
 
-        /// Repository prefix (e.g., ghcr.io/username)
+        /// Repository prefix (e.g., ghcr.io/username), defaulting to
+        /// default_registry in the global config
         #[arg(env = "KRUST_REPO")]
         repo: Option<String>,
 
-        /// Tag to apply to the images (e.g., latest, v1.0.0)
+        /// Tag to apply to the images (e.g., latest, v1.0.0). Can be repeated
+        #[arg(long)]
+        tag: Vec<String>,
+
+        /// Write the bundle as a .tar.gz to this path
+        #[arg(short = 'o', long, group = "destination")]
+        output: Option<PathBuf>,
+
+        /// Push the bundle as an OCI artifact to this reference (e.g., ghcr.io/user/bundle:v1)
+        #[arg(long, value_name = "REF", group = "destination")]
+        artifact: Option<String>,
+    },
+
+    /// Copy an image or image index between repositories
+    Copy {
+        /// Source image reference (e.g., ghcr.io/user/app:v1)
+        src: String,
+
+        /// Destination image reference (e.g., registry.example.com/app:v1)
+        dst: String,
+    },
+
+    /// Show an image's manifests, configs, layer sizes and platforms
+    Inspect {
+        /// Image reference to inspect (e.g., ghcr.io/user/app:v1)
+        image: String,
+
+        /// Only show this platform of a multi-platform image
+        #[arg(long)]
+        platform: Option<Platform>,
+
+        /// Print the full manifests and configs as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print the digest of an image's manifest or index
+    Digest {
+        /// Image reference to resolve (e.g., ghcr.io/user/app:v1)
+        image: String,
+    },
+
+    /// Point one or more new tags at an existing image without rebuilding it
+    Tag {
+        /// Image to tag, ideally by digest (e.g., ghcr.io/user/app@sha256:...)
+        image: String,
+
+        /// Tags to apply (e.g., prod)
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+
+    /// List the tags in a repository
+    Tags {
+        /// Repository to list (e.g., ghcr.io/username/app)
+        repo: String,
+
+        /// Print the tags as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Delete an image or image index (and its platform images) from a registry
+    DeleteImage {
+        /// Image reference to delete, by tag or digest
+        image: String,
+    },
+
+    /// Delete remote images outside a retention policy
+    PruneRemote {
+        /// Repository to prune (e.g., ghcr.io/username/app)
+        #[arg(long)]
+        repo: String,
+
+        /// Number of most recently created images to always keep
+        #[arg(long, required_unless_present = "older_than")]
+        keep: Option<usize>,
+
+        /// Only delete images older than this age (e.g., 30d, 12h, 2w)
+        #[arg(long)]
+        older_than: Option<String>,
+
+        /// Show what would be deleted without deleting anything
         #[arg(long)]
-        tag: Option<String>,
+        dry_run: bool,
+    },
+
+    /// Push images from a directory written by `krust build --artifact-dir`
+    Push {
+        /// Artifact directory containing an OCI layout and krust-push.json
+        #[arg(value_name = "DIR")]
+        dir: PathBuf,
+    },
+
+    /// Serve build and resolve requests over HTTP, keeping registry
+    /// sessions and caches warm between them
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+
+        /// Serve a non-loopback address without KRUST_SERVE_TOKEN, letting
+        /// anyone who can reach it build (running the project's code) and push
+        #[arg(long)]
+        insecure_no_auth: bool,
+
+        /// Repository prefix for requests that don't give one (e.g.,
+        /// ghcr.io/username), defaulting to default_registry in the global config
+        #[arg(env = "KRUST_REPO")]
+        repo: Option<String>,
+    },
+
+    /// Show, prune or clear krust's local cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+
+    /// Show, check or change krust's configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
     },
 
     /// Show version information
     Version,
 }
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// List cached builds, manifests, tags and git clones with their sizes
+    Ls {
+        /// Print the entries as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Remove the least recently used entries by age or total size
+    Prune {
+        /// Remove entries not used for this long (e.g., 30d, 12h, 2w)
+        #[arg(long, required_unless_present = "max_size")]
+        older_than: Option<String>,
+
+        /// Remove entries until the cache fits in this size (e.g., 5GB, 2GiB)
+        #[arg(long)]
+        max_size: Option<String>,
+
+        /// Show what would be removed without removing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Remove everything in the cache
+    Clear,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print the path of the global config file
+    Path,
+
+    /// Print the global config file
+    View {
+        /// Print the settings in effect instead: the file with defaults, the
+        /// chosen profile and KRUST_* variables applied
+        #[arg(long)]
+        resolved: bool,
+    },
+
+    /// Print a setting in effect, e.g. `base_image` or `build.locked`
+    Get {
+        /// Dotted path of the setting
+        key: String,
+    },
+
+    /// Change a setting in the global config file, or in the chosen
+    /// profile's table in it, keeping the rest of the file as it is
+    Set {
+        /// Dotted path of the setting, e.g. `registries."ghcr.io".insecure`
+        key: String,
+
+        /// New value, read like a KRUST_* variable's: as TOML, a string or
+        /// a comma-separated list
+        value: String,
+    },
+
+    /// Check the global config, and the project config file and Cargo.toml
+    /// of a project, for unknown keys and invalid values, reporting the line
+    /// each problem is on
+    Validate {
+        /// Project directory
+        #[arg(default_value = ".")]
+        path: PathBuf,
+    },
+}
+
+/// Parse a `KEY=VALUE` argument
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", s)),
+    }
+}
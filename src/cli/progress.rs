@@ -0,0 +1,142 @@
+//! Terminal rendering of blob transfer progress
+//!
+//! Progress bars are drawn when stderr is a terminal. Otherwise (CI logs,
+//! redirected output) progress is logged as plain lines at a fixed interval.
+
+use crate::registry::progress::{Direction, ProgressEvent, ProgressReporter};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Blobs smaller than this (configs, small layers) aren't worth reporting
+const MIN_REPORTED_SIZE: u64 = 1024 * 1024;
+
+/// How often plain-line progress is logged per transfer
+const LINE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct CliProgress {
+    mode: Mode,
+}
+
+#[derive(Debug)]
+enum Mode {
+    Bars {
+        multi: MultiProgress,
+        bars: Mutex<HashMap<(Direction, String), ProgressBar>>,
+    },
+    Lines {
+        last_logged: Mutex<HashMap<(Direction, String), Instant>>,
+    },
+}
+
+impl CliProgress {
+    /// Draw bars if stderr is a terminal, log lines otherwise
+    pub fn new() -> Self {
+        if std::io::stderr().is_terminal() {
+            Self {
+                mode: Mode::Bars {
+                    multi: MultiProgress::new(),
+                    bars: Mutex::new(HashMap::new()),
+                },
+            }
+        } else {
+            Self {
+                mode: Mode::Lines {
+                    last_logged: Mutex::new(HashMap::new()),
+                },
+            }
+        }
+    }
+}
+
+impl Default for CliProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressReporter for CliProgress {
+    fn report(&self, event: &ProgressEvent) {
+        if event.total.is_some_and(|total| total < MIN_REPORTED_SIZE) {
+            return;
+        }
+        let key = (event.direction, event.digest.clone());
+
+        match &self.mode {
+            Mode::Bars { multi, bars } => {
+                let mut bars = bars.lock().unwrap();
+                let bar = bars
+                    .entry(key.clone())
+                    .or_insert_with(|| {
+                        let bar = multi.add(ProgressBar::new(event.total.unwrap_or(0)));
+                        bar.set_style(
+                            ProgressStyle::with_template(
+                                "{msg} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec})",
+                            )
+                            .unwrap()
+                            .progress_chars("=> "),
+                        );
+                        bar.set_message(describe(event));
+                        bar
+                    })
+                    .clone();
+                bar.set_position(event.transferred);
+                if event.done {
+                    bar.finish_and_clear();
+                    multi.remove(&bar);
+                    bars.remove(&key);
+                }
+            }
+            Mode::Lines { last_logged } => {
+                let mut last_logged = last_logged.lock().unwrap();
+                let now = Instant::now();
+                let due = match last_logged.get(&key) {
+                    Some(last) => now.duration_since(*last) >= LINE_INTERVAL,
+                    // Wait for the first interval so quick transfers stay quiet
+                    None => {
+                        last_logged.insert(key.clone(), now);
+                        false
+                    }
+                };
+
+                if event.done {
+                    if last_logged.remove(&key).is_some() {
+                        info!(
+                            "{}: done, {} ({}/s)",
+                            describe(event),
+                            HumanBytes(event.transferred),
+                            HumanBytes(event.rate as u64)
+                        );
+                    }
+                } else if due {
+                    last_logged.insert(key, now);
+                    let total = event
+                        .total
+                        .map(|t| format!(" of {}", HumanBytes(t)))
+                        .unwrap_or_default();
+                    info!(
+                        "{}: {}{} ({}/s)",
+                        describe(event),
+                        HumanBytes(event.transferred),
+                        total,
+                        HumanBytes(event.rate as u64)
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// e.g. "Pulling sha256:0a1b2c3d4e5f"
+fn describe(event: &ProgressEvent) -> String {
+    let verb = match event.direction {
+        Direction::Download => "Pulling",
+        Direction::Upload => "Pushing",
+    };
+    let short = event.digest.get(..19).unwrap_or(event.digest.as_str());
+    format!("{} {}", verb, short)
+}
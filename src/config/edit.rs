@@ -0,0 +1,205 @@
+//! Reading and changing single settings, for `krust config get` and `set`
+//!
+//! Keys are dotted paths as in TOML, e.g. `build.locked` or
+//! `registries."ghcr.io".insecure`. Values are read the way `KRUST_*`
+//! variables are, and changes are made in place, so the rest of the file
+//! keeps its comments and layout.
+
+use super::{env, validate, Config};
+use anyhow::{Context, Result};
+use std::path::Path;
+use toml_edit::{DocumentMut, Item, Key, TableLike};
+
+fn parse_key(key: &str) -> Result<Vec<Key>> {
+    Key::parse(key).with_context(|| format!("Invalid key {}", key))
+}
+
+/// The setting at `key` in `config`, as `krust config get` prints it:
+/// strings as they are, tables as TOML documents and anything else as a
+/// TOML value
+pub fn get(config: &Config, key: &str) -> Result<String> {
+    let keys = parse_key(key)?;
+    let mut value = toml::Value::try_from(config)?;
+    for name in &keys {
+        match value.get(name.get()) {
+            Some(inner) => value = inner.clone(),
+            None => return Err(unset(&keys, key)),
+        }
+    }
+    Ok(match value {
+        toml::Value::String(s) => format!("{}\n", s),
+        toml::Value::Table(table) => toml::to_string_pretty(&table)?,
+        value => format!("{}\n", value),
+    })
+}
+
+/// The error for a key with no value: it's unset, or not a setting at all
+fn unset(keys: &[Key], key: &str) -> anyhow::Error {
+    let path: Vec<String> = keys.iter().map(|name| name.get().to_string()).collect();
+    match validate::nested(&path, toml::Value::String(String::new())).try_into::<Config>() {
+        Err(e) if e.message().starts_with("unknown field") => anyhow::anyhow!(
+            "{} isn't a setting: {}",
+            key,
+            validate::suggest(e.message())
+        ),
+        _ => anyhow::anyhow!("{} isn't set", key),
+    }
+}
+
+/// Set `key` to `value` in the config file at `path`, or in `profile`'s
+/// table in it, creating the file if there isn't one
+pub fn set(path: &Path, profile: Option<&str>, key: &str, value: &str) -> Result<()> {
+    let content = if path.exists() {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+    } else {
+        String::new()
+    };
+    let document: DocumentMut = content
+        .parse()
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    // Problems already in the file are its own, not the new value's
+    toml::from_str::<Config>(&content)
+        .with_context(|| format!("Invalid settings in {}", path.display()))?;
+
+    let mut keys = parse_key(key)?;
+    if let Some(profile) = profile.filter(|profile| !profile.is_empty()) {
+        keys.splice(0..0, [Key::new("profile"), Key::new(profile)]);
+    }
+    let mut string_error = None;
+    for candidate in env::candidates(value) {
+        let is_string = candidate.is_str();
+        let mut edited = document.clone();
+        insert(&mut edited, &keys, candidate.to_string().parse()?)?;
+        let edited = edited.to_string();
+        match toml::from_str::<Config>(&edited) {
+            Ok(_) => {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                return std::fs::write(path, edited)
+                    .with_context(|| format!("Failed to write {}", path.display()));
+            }
+            // Reading the value as a string fails in the clearest way
+            Err(e) if is_string => string_error = Some(e.message().to_string()),
+            Err(_) => {}
+        }
+    }
+    Err(anyhow::anyhow!(
+        string_error.expect("every value is tried as a string")
+    ))
+    .with_context(|| format!("Invalid value {:?} for {}", value, key))
+}
+
+/// Put `value` at `keys` in `document`, creating tables on the way and
+/// keeping the comments around a value it replaces
+fn insert(document: &mut DocumentMut, keys: &[Key], mut value: toml_edit::Value) -> Result<()> {
+    let (last, tables) = keys.split_last().context("Expected a key")?;
+    let mut table: &mut dyn TableLike = document.as_table_mut();
+    for name in tables {
+        let mut implicit = toml_edit::Table::new();
+        implicit.set_implicit(true);
+        table = table
+            .entry_format(name)
+            .or_insert(Item::Table(implicit))
+            .as_table_like_mut()
+            .with_context(|| format!("{} isn't a table", name.get()))?;
+    }
+    match table.get_mut(last.get()) {
+        Some(Item::Value(existing)) => {
+            *value.decor_mut() = existing.decor().clone();
+            *existing = value;
+        }
+        _ => {
+            value.decor_mut().clear();
+            table.insert(last.get(), Item::Value(value));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get() {
+        let config: Config = toml::from_str(
+            r#"
+base_image = "scratch"
+platforms = ["linux/amd64"]
+[build]
+locked = true
+[registries."ghcr.io"]
+insecure = true
+"#,
+        )
+        .unwrap();
+        assert_eq!(get(&config, "base_image").unwrap(), "scratch\n");
+        assert_eq!(get(&config, "build.locked").unwrap(), "true\n");
+        assert_eq!(get(&config, "platforms").unwrap(), "[\"linux/amd64\"]\n");
+        assert_eq!(
+            get(&config, "registries.\"ghcr.io\".insecure").unwrap(),
+            "true\n"
+        );
+        assert!(get(&config, "registry")
+            .unwrap()
+            .contains("timeout_secs = 300\n"));
+
+        let err = get(&config, "default_registry").unwrap_err();
+        assert_eq!(err.to_string(), "default_registry isn't set");
+        let err = get(&config, "build.lockd").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "build.lockd isn't a setting: unknown field `lockd`, did you mean `locked`?"
+        );
+    }
+
+    #[test]
+    fn test_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config").join("config.toml");
+        set(&path, None, "base_image", "scratch").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "base_image = \"scratch\"\n"
+        );
+
+        std::fs::write(
+            &path,
+            "# Where images go\ndefault_registry = \"ttl.sh/me\" # for now\n\n[build]\nlocked = false\n",
+        )
+        .unwrap();
+        set(&path, None, "default_registry", "ghcr.io/org").unwrap();
+        set(&path, None, "build.locked", "true").unwrap();
+        set(&path, None, "build.cargo_args", "--features=tls,--offline").unwrap();
+        set(&path, None, "registries.\"ghcr.io\".insecure", "true").unwrap();
+        set(&path, Some("prod"), "sign", "true").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            r#"# Where images go
+default_registry = "ghcr.io/org" # for now
+
+[build]
+locked = true
+cargo_args = ["--features=tls", "--offline"]
+
+[registries."ghcr.io"]
+insecure = true
+
+[profile.prod]
+sign = true
+"#
+        );
+
+        let err = set(&path, None, "build.libc", "uclibc").unwrap_err();
+        assert!(
+            format!("{:#}", err)
+                .starts_with("Invalid value \"uclibc\" for build.libc: unknown variant"),
+            "{:#}",
+            err
+        );
+        assert!(set(&path, None, "build.lockd", "true").is_err());
+        assert!(set(&path, None, "default_registry.host", "x").is_err());
+    }
+}
@@ -0,0 +1,162 @@
+//! Settings from `KRUST_*` environment variables
+//!
+//! Every setting in the global config can be set with an environment
+//! variable named after its key: `KRUST_BASE_IMAGE` for `base_image`, and
+//! `KRUST_<TABLE>_<KEY>` for keys in a table, e.g. `KRUST_BUILD_LOCKED` for
+//! `build.locked`. They override config files and are overridden by flags.
+//!
+//! Values are read as TOML where that gives a valid setting (`true`, `600`,
+//! `["a", "b"]`), then as a string, then as a comma-separated list.
+
+use super::{validate, Config};
+use anyhow::Result;
+use tracing::debug;
+
+const PREFIX: &str = "KRUST_";
+
+/// Top-level keys that can be set from the environment. The per-host
+/// `registries` table can't, since hosts don't fit in variable names.
+const KEYS: [&str; 6] = [
+    "base_image",
+    "default_registry",
+    "default_tag",
+    "naming",
+    "platforms",
+    "sign",
+];
+
+/// Tables whose keys can be set from the environment, longest first so
+/// `base_policy` isn't mistaken for a key starting with `base`
+const TABLES: [&str; 5] = ["base_policy", "registry", "signing", "build", "scan"];
+
+/// Variables krust reads for other purposes, or sets for build hooks, even
+/// where their names look like settings
+const RESERVED: [&str; 11] = [
+    "KRUST_REPO",
+    "KRUST_HOME",
+    "KRUST_CONFIG_DIR",
+    "KRUST_CACHE_DIR",
+    "KRUST_CONFIG_PROFILE",
+    "KRUST_PROFILE",
+    "KRUST_SCAN_TOKEN",
+    "KRUST_SERVE_TOKEN",
+    "KRUST_TARGET",
+    "KRUST_TARGET_DIR",
+    "KRUST_BINARY",
+];
+
+/// The config key path a `KRUST_*` variable sets, if it sets one
+fn key_path(name: &str) -> Option<Vec<String>> {
+    if RESERVED.contains(&name) {
+        return None;
+    }
+    let key = name.strip_prefix(PREFIX)?.to_lowercase();
+    for table in TABLES {
+        if let Some(rest) = key.strip_prefix(table).and_then(|k| k.strip_prefix('_')) {
+            if !rest.is_empty() {
+                return Some(vec![table.to_string(), rest.to_string()]);
+            }
+        }
+    }
+    KEYS.contains(&key.as_str()).then(|| vec![key])
+}
+
+/// Ways to read `value`, in the order they're tried
+pub fn candidates(value: &str) -> Vec<toml::Value> {
+    let mut candidates = Vec::new();
+    if let Ok(mut table) = toml::from_str::<toml::Table>(&format!("value = {}", value)) {
+        if let Some(value) = table.remove("value") {
+            candidates.push(value);
+        }
+    }
+    candidates.push(toml::Value::String(value.to_string()));
+    candidates.push(toml::Value::Array(
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| toml::Value::String(item.to_string()))
+            .collect(),
+    ));
+    candidates
+}
+
+/// Whether `path` is a key in the config, whatever its value
+fn is_setting(path: &[String]) -> bool {
+    match validate::nested(path, toml::Value::String(String::new())).try_into::<Config>() {
+        Err(e) => !e.message().starts_with("unknown field"),
+        Ok(_) => true,
+    }
+}
+
+/// What a setting expects, from the error reading a value for it. Values
+/// can be secrets, so they're left out.
+fn expected(message: &str) -> String {
+    match message.rfind("expected ") {
+        Some(start) => message[start..].to_string(),
+        None => "not a valid value for this setting".to_string(),
+    }
+}
+
+fn insert(settings: &mut toml::Table, path: &[String], value: toml::Value) {
+    let (key, tables) = path.split_last().expect("key paths aren't empty");
+    let mut table = settings;
+    for name in tables {
+        let entry = table
+            .entry(name.clone())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        if !entry.is_table() {
+            *entry = toml::Value::Table(toml::Table::new());
+        }
+        table = entry.as_table_mut().expect("just made a table");
+    }
+    table.insert(key.clone(), value);
+}
+
+/// `settings` with `vars` layered over them, as a config
+pub fn apply(
+    mut settings: toml::Table,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<Config> {
+    let mut vars: Vec<(String, String)> = vars
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .filter(|(name, _)| name.starts_with(PREFIX))
+        .collect();
+    vars.sort();
+    // Problems in the files are theirs to report, not the variables'
+    toml::Value::Table(settings.clone()).try_into::<Config>()?;
+
+    for (name, value) in vars {
+        let Some(path) = key_path(&name) else {
+            continue;
+        };
+        if !is_setting(&path) {
+            debug!("Ignoring {}, which isn't a setting", name);
+            continue;
+        }
+        let mut string_error = None;
+        let mut applied = false;
+        for candidate in candidates(&value) {
+            let is_string = candidate.is_str();
+            let mut layered = settings.clone();
+            insert(&mut layered, &path, candidate);
+            match toml::Value::Table(layered.clone()).try_into::<Config>() {
+                Ok(_) => {
+                    settings = layered;
+                    applied = true;
+                    break;
+                }
+                // Reading the value as a string fails in the clearest way
+                Err(e) if is_string => string_error = Some(e.message().to_string()),
+                Err(_) => {}
+            }
+        }
+        if !applied {
+            let message = string_error.expect("every value is tried as a string");
+            anyhow::bail!("Invalid value for {}: {}", name, expected(&message));
+        }
+    }
+
+    Ok(toml::Value::Table(settings).try_into()?)
+}
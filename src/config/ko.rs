@@ -0,0 +1,173 @@
+//! ko's `.ko.yaml`, read when `build.ko_yaml` is set
+//!
+//! Repositories with both Go and Rust services can keep their base images
+//! and platforms in the file ko already reads. `defaultBaseImage`,
+//! `baseImageOverrides` and `defaultPlatforms` become krust's `base-image`,
+//! `base-image-overrides` and `platforms`; ko's Go-specific settings are
+//! ignored. The project config file and Cargo.toml layer over them.
+
+use super::{project_file, ProjectConfig};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Names ko's config file can have, in the order they're looked for
+const FILE_NAMES: [&str; 2] = [".ko.yaml", ".ko.yml"];
+
+/// The `.ko.yaml` settings krust reads, and the keys they become
+const KEYS: [(&str, &str); 3] = [
+    ("defaultBaseImage", "base-image"),
+    ("baseImageOverrides", "base-image-overrides"),
+    ("defaultPlatforms", "platforms"),
+];
+
+/// A `.ko.yaml` file, as krust's project settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct KoFile {
+    pub path: PathBuf,
+    settings: toml::Table,
+}
+
+impl KoFile {
+    /// The `.ko.yaml` nearest to `dir`, found the way project config files are
+    pub fn find(dir: &Path) -> Result<Option<Self>> {
+        match project_file::nearest(dir, &FILE_NAMES)? {
+            Some(path) => Self::load(&path).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the `.ko.yaml` at `path`, keeping the settings krust uses
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut ko = project_file::parse_yaml(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        let settings: toml::Table = KEYS
+            .iter()
+            .filter_map(|(ko_key, key)| Some((key.to_string(), ko.remove(*ko_key)?)))
+            .collect();
+        toml::Value::Table(settings.clone())
+            .try_into::<ProjectConfig>()
+            .with_context(|| format!("Invalid settings in {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            settings,
+        })
+    }
+
+    /// The default base image and platforms, without `baseImageOverrides`,
+    /// which [`KoFile::base_image_override`] applies
+    pub fn project_settings(&self) -> toml::Table {
+        let mut settings = self.settings.clone();
+        settings.remove("base-image-overrides");
+        settings
+    }
+
+    /// The base image `baseImageOverrides` gives the package named `package`
+    /// in `project_path`, if any
+    pub fn base_image_override(
+        &self,
+        project_path: &Path,
+        package: Option<&str>,
+    ) -> Option<toml::Value> {
+        let overrides = self.settings.get("base-image-overrides")?.as_table()?;
+        self.matching_override(overrides, project_path, package)
+            .cloned()
+    }
+
+    /// The entry in `overrides` for the package: ko keys them by Go import
+    /// path, so a key matches a package whose directory, relative to the
+    /// `.ko.yaml`, it ends with, or one with its name
+    fn matching_override<'a>(
+        &self,
+        overrides: &'a toml::Table,
+        project_path: &Path,
+        package: Option<&str>,
+    ) -> Option<&'a toml::Value> {
+        let dir = self.path.parent()?.canonicalize().ok()?;
+        let project = project_path.canonicalize().ok()?;
+        let relative = project
+            .strip_prefix(&dir)
+            .ok()
+            .filter(|relative| !relative.as_os_str().is_empty());
+        let by_path = relative.and_then(|relative| {
+            overrides
+                .iter()
+                .find(|(key, _)| Path::new(key).ends_with(relative))
+        });
+        let by_name = || {
+            overrides
+                .iter()
+                .find(|(key, _)| Some(key.as_str()) == package)
+        };
+        by_path.or_else(by_name).map(|(_, image)| image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_project_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::write(
+            dir.path().join(".ko.yaml"),
+            r#"
+defaultBaseImage: cgr.dev/chainguard/static
+defaultPlatforms: [linux/amd64, linux/arm64]
+baseImageOverrides:
+  github.com/example/repo/services/edge: cgr.dev/chainguard/glibc-dynamic
+  worker: alpine:3.20
+builds:
+  - id: api
+    main: ./cmd/api
+"#,
+        )
+        .unwrap();
+        for path in ["services/edge", "services/api", "jobs/worker"] {
+            fs::create_dir_all(dir.path().join(path)).unwrap();
+        }
+
+        let ko = KoFile::find(&dir.path().join("services/edge"))
+            .unwrap()
+            .unwrap();
+        let base_image = |path: &str, package: &str| {
+            ko.base_image_override(&dir.path().join(path), Some(package))
+                .and_then(|image| image.as_str().map(String::from))
+        };
+        assert_eq!(
+            base_image("services/edge", "edge").as_deref(),
+            Some("cgr.dev/chainguard/glibc-dynamic")
+        );
+        assert_eq!(base_image("services/api", "api"), None);
+        assert_eq!(
+            base_image("jobs/worker", "worker").as_deref(),
+            Some("alpine:3.20")
+        );
+
+        let settings = ko.project_settings();
+        assert!(!settings.contains_key("base-image-overrides"));
+        assert_eq!(
+            settings["base-image"].as_str(),
+            Some("cgr.dev/chainguard/static")
+        );
+        assert_eq!(
+            settings["platforms"],
+            toml::Value::Array(vec!["linux/amd64".into(), "linux/arm64".into()])
+        );
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".ko.yaml");
+        fs::write(&path, "defaultPlatforms: [linux/amd64/v9/x]\n").unwrap();
+        let err = KoFile::load(&path).unwrap_err();
+        assert!(format!("{:#}", err).starts_with("Invalid settings in"));
+
+        assert!(KoFile::find(dir.path()).is_err());
+    }
+}
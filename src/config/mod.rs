@@ -1,33 +1,95 @@
+use crate::manifest::Platform;
+use crate::paths::Paths;
+use crate::registry::media_types;
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+mod edit;
+mod env;
+mod ko;
+mod project_file;
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests;
+mod validate;
+
+pub use project_file::ProjectFile;
+pub use validate::{Problem, Report};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     /// Default base image for containers
-    #[serde(default = "default_base_image")]
+    #[serde(
+        default = "default_base_image",
+        deserialize_with = "image_reference::deserialize"
+    )]
     pub base_image: String,
 
-    /// Default registry to push images to
+    /// Repository prefix to push images under when KRUST_REPO isn't set
+    #[serde(default, deserialize_with = "image_reference::deserialize_option")]
     pub default_registry: Option<String>,
 
+    /// How image repositories are named under KRUST_REPO
+    #[serde(default)]
+    pub naming: Naming,
+
+    /// Tag applied to built images when no --tag is given
+    pub default_tag: Option<String>,
+
+    /// Platforms to build for when neither --platform nor the project
+    /// chooses any, instead of the base image's
+    #[serde(default, with = "platform_strings")]
+    pub platforms: Vec<Platform>,
+
+    /// Sign images krust pushes with a keyless signature, as with --sign.
+    /// Builds that don't push aren't signed.
+    #[serde(default)]
+    pub sign: bool,
+
+    /// Named sets of settings, `[profile.NAME]`, layered over the rest of
+    /// the file when chosen with --config-profile or KRUST_CONFIG_PROFILE
+    #[serde(
+        default,
+        deserialize_with = "profiles",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub profile: BTreeMap<String, toml::Table>,
+
     /// Build configuration
     #[serde(default)]
     pub build: BuildConfig,
 
-    /// Registry authentication configuration
+    /// Settings for individual registries, keyed by host
+    #[serde(default)]
+    pub registries: HashMap<String, RegistryConfig>,
+
+    /// Registry client settings
+    #[serde(default)]
+    pub registry: RegistrySettings,
+
+    /// Image signing settings
+    #[serde(default)]
+    pub signing: SigningConfig,
+
+    /// Signers trusted for base images, with `build.verify_base`
     #[serde(default)]
-    pub registries: HashMap<String, RegistryCredential>,
+    pub base_policy: BasePolicy,
+
+    /// Vulnerability scanning of built images, with `build.scan`
+    #[serde(default)]
+    pub scan: ScanConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct BuildConfig {
     /// Additional environment variables for cargo build
     #[serde(default)]
-    pub env: HashMap<String, String>,
+    pub env: BTreeMap<String, String>,
 
     /// Default cargo build arguments
     #[serde(default)]
@@ -35,71 +97,1325 @@ pub struct BuildConfig {
 
     /// Target directory for build artifacts
     pub target_dir: Option<PathBuf>,
+
+    /// Require Cargo.lock to be up to date, failing instead of updating it
+    #[serde(default)]
+    pub locked: bool,
+
+    /// Fail the build if cargo would need the network, using only
+    /// dependencies already downloaded (e.g. by `cargo fetch`)
+    #[serde(default)]
+    pub offline: bool,
+
+    /// Never contact a registry: base images must be cached or local, and
+    /// images can only be written to tarballs and OCI layouts
+    #[serde(default)]
+    pub offline_images: bool,
+
+    /// Seconds a platform's build (cargo and hooks) may take before it's
+    /// stopped. Unset means no limit.
+    pub timeout_secs: Option<u64>,
+
+    /// Rust toolchain to build with, overriding the project's rust-toolchain.toml
+    pub toolchain: Option<String>,
+
+    /// Strip symbols from binaries (`-C strip=symbols`)
+    #[serde(default)]
+    pub strip: bool,
+
+    /// Override the profile's `opt-level`, e.g. `"s"` or `"z"` for size
+    pub opt_level: Option<String>,
+
+    /// Override the profile's `lto`, e.g. `"fat"`, `"thin"` or `"off"`
+    pub lto: Option<String>,
+
+    /// Build with debug info and move it into a `<binary>.debug` file,
+    /// shipping a stripped binary
+    #[serde(default, alias = "split-debuginfo")]
+    pub split_debuginfo: bool,
+
+    /// Push split debug info to the registry as an artifact referring to
+    /// each platform image
+    #[serde(default, alias = "push-debuginfo")]
+    pub push_debuginfo: bool,
+
+    /// Embed the dependency tree in binaries with cargo-auditable
+    #[serde(default)]
+    pub auditable: bool,
+
+    /// How binaries are compiled for the target platform
+    #[serde(default)]
+    pub backend: BuildBackend,
+
+    /// C library to link against; `gnu` builds dynamically linked binaries
+    /// that need a base image with glibc
+    #[serde(default)]
+    pub libc: Libc,
+
+    /// Media types of built images: OCI, or Docker for registries and tools
+    /// that only accept Docker schema2 images
+    #[serde(default, alias = "media-types")]
+    pub media_types: MediaTypeFamily,
+
+    /// How the application layer is compressed, e.g. `gzip:9` or `zstd:3`
+    #[serde(default)]
+    pub compression: LayerCompression,
+
+    /// Format of the SBOM pushed with each platform image, if any
+    #[serde(default)]
+    pub sbom: SbomFormat,
+
+    /// Check the base image's cosign signatures against `base_policy`
+    /// before building on it
+    #[serde(default)]
+    pub verify_base: bool,
+
+    /// Scan each platform image for vulnerabilities before publishing it,
+    /// failing the build on findings at or above `scan.severity`
+    #[serde(default)]
+    pub scan: bool,
+
+    /// Backends for specific platforms, e.g. `"linux/s390x" = "cross"`,
+    /// overriding `backend`
+    #[serde(default, deserialize_with = "platform_keys")]
+    pub platform_backends: BTreeMap<String, BuildBackend>,
+
+    /// Read `defaultBaseImage`, `baseImageOverrides` and `defaultPlatforms`
+    /// from ko's `.ko.yaml`, under the project's own settings
+    #[serde(default, alias = "ko-yaml")]
+    pub ko_yaml: bool,
+
+    /// Always run cargo, neither reusing nor storing builds in krust's cache
+    #[serde(default, alias = "no-cache")]
+    pub no_cache: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RegistryCredential {
+impl BuildConfig {
+    /// This configuration with a project's build settings applied: its
+    /// environment variables override these, and its cargo arguments follow them
+    pub fn with_project(&self, project: &ProjectConfig) -> Self {
+        let mut config = self.clone();
+        config.env.extend(project.env.clone());
+        config.cargo_args.extend(project.cargo_args.iter().cloned());
+        config
+    }
+
+    /// The backend to build `platform` with. A platform key without a
+    /// variant applies to every variant.
+    pub fn backend_for(&self, platform: &Platform) -> BuildBackend {
+        self.platform_backends
+            .iter()
+            .filter_map(|(key, backend)| Some((key.parse::<Platform>().ok()?, *backend)))
+            .filter(|(key, _)| key.matches(platform))
+            // An exact match wins over one without a variant
+            .max_by_key(|(key, _)| key.variant.is_some())
+            .map(|(_, backend)| backend)
+            .unwrap_or(self.backend)
+    }
+}
+
+/// The tool used to cross-compile binaries
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildBackend {
+    /// `cargo zigbuild` when it's installed, otherwise `cargo build` for the
+    /// host architecture and `cross build` for others
+    #[default]
+    Auto,
+    /// `cargo zigbuild`, which links with zig and needs no target toolchain
+    Zig,
+    /// `cargo build`, relying on linkers configured for the target
+    Cargo,
+    /// `cross build`, which builds inside a container with the target's
+    /// toolchain, for targets without a local one
+    Cross,
+}
+
+/// Format of the software bill of materials attached to pushed images
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SbomFormat {
+    /// No SBOM
+    #[default]
+    None,
+    /// SPDX 2.3 JSON
+    Spdx,
+    /// CycloneDX 1.5 JSON
+    Cyclonedx,
+}
+
+/// The C library binaries are linked against
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Libc {
+    /// musl, linked statically so the binary runs on any base image
+    #[default]
+    Musl,
+    /// glibc, linked dynamically, for libraries that don't work with musl
+    Gnu,
+}
+
+impl Libc {
+    /// A warning when `base_image` likely lacks the C library binaries
+    /// linked against this one need
+    pub fn base_image_warning(&self, base_image: &str) -> Option<String> {
+        if *self == Libc::Musl {
+            return None;
+        }
+        let name = base_image
+            .split('@')
+            .next()
+            .unwrap_or(base_image)
+            .rsplit('/')
+            .next()
+            .unwrap_or(base_image);
+        let name = name.split(':').next().unwrap_or(name);
+        let without_glibc = name == "scratch" || name.starts_with("static") || name == "alpine";
+        without_glibc.then(|| {
+            format!(
+                "Base image {} probably doesn't include glibc, which binaries built with --libc gnu load at runtime; \
+                 use a base image such as cgr.dev/chainguard/glibc-dynamic or gcr.io/distroless/cc",
+                base_image
+            )
+        })
+    }
+}
+
+/// The family of media types images are written with. Base image layers
+/// are converted to match, so every image is entirely one or the other.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaTypeFamily {
+    /// OCI image manifests, configs, layers and indexes
+    #[default]
+    Oci,
+    /// Docker schema2 manifests, configs, layers and manifest lists
+    Docker,
+}
+
+impl MediaTypeFamily {
+    pub fn manifest(&self) -> &'static str {
+        match self {
+            MediaTypeFamily::Oci => media_types::OCI_MANIFEST,
+            MediaTypeFamily::Docker => media_types::DOCKER_MANIFEST,
+        }
+    }
+
+    pub fn config(&self) -> &'static str {
+        match self {
+            MediaTypeFamily::Oci => media_types::OCI_CONFIG,
+            MediaTypeFamily::Docker => media_types::DOCKER_CONFIG,
+        }
+    }
+
+    pub fn layer_gzip(&self) -> &'static str {
+        match self {
+            MediaTypeFamily::Oci => media_types::OCI_LAYER_GZIP,
+            MediaTypeFamily::Docker => media_types::DOCKER_LAYER_GZIP,
+        }
+    }
+}
+
+/// Compression of the application layer, written `ALGORITHM[:LEVEL]`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum LayerCompression {
+    /// gzip, levels 0 (none) to 9 (smallest)
+    Gzip(u32),
+    /// zstd, levels 1 to 22; zstd layers need OCI media types
+    Zstd(i32),
+    /// eStargz, gzip levels 0 to 9: gzip laid out for lazy pulling
+    Estargz(u32),
+}
+
+impl Default for LayerCompression {
+    fn default() -> Self {
+        LayerCompression::Gzip(6)
+    }
+}
+
+impl std::str::FromStr for LayerCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (algorithm, level) = match s.split_once(':') {
+            Some((algorithm, level)) => (algorithm, Some(level)),
+            None => (s, None),
+        };
+        let invalid_level =
+            |level: &str| format!("Invalid {} compression level {}", algorithm, level);
+        match algorithm {
+            "gzip" => match level {
+                None => Ok(LayerCompression::Gzip(6)),
+                Some(level) => level
+                    .parse()
+                    .ok()
+                    .filter(|level| *level <= 9)
+                    .map(LayerCompression::Gzip)
+                    .ok_or_else(|| invalid_level(level)),
+            },
+            "zstd" => match level {
+                None => Ok(LayerCompression::Zstd(3)),
+                Some(level) => level
+                    .parse()
+                    .ok()
+                    .filter(|level| (1..=22).contains(level))
+                    .map(LayerCompression::Zstd)
+                    .ok_or_else(|| invalid_level(level)),
+            },
+            "estargz" => match level {
+                None => Ok(LayerCompression::Estargz(6)),
+                Some(level) => level
+                    .parse()
+                    .ok()
+                    .filter(|level| *level <= 9)
+                    .map(LayerCompression::Estargz)
+                    .ok_or_else(|| invalid_level(level)),
+            },
+            _ => Err(format!(
+                "Unknown compression {}, expected gzip[:0-9], zstd[:1-22] or estargz[:0-9]",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for LayerCompression {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<LayerCompression> for String {
+    fn from(compression: LayerCompression) -> Self {
+        compression.to_string()
+    }
+}
+
+impl std::fmt::Display for LayerCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayerCompression::Gzip(level) => write!(f, "gzip:{}", level),
+            LayerCompression::Zstd(level) => write!(f, "zstd:{}", level),
+            LayerCompression::Estargz(level) => write!(f, "estargz:{}", level),
+        }
+    }
+}
+
+/// How an image's repository is named under KRUST_REPO: one of ko's
+/// naming modes, or a template such as `{repo}/{package}-{target}`
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Naming {
+    /// `<repo>/<package>`, or `<repo>/<package>-<binary>` with --all-bins
+    #[default]
+    Package,
+    /// `<repo>` itself
+    Bare,
+    /// `<repo>/<directory>`, the name of the package's directory
+    BaseImportPaths,
+    /// `<repo>/<path>`, the package's path in its workspace, starting with
+    /// the workspace's directory or git repository
+    PreserveImportPaths,
+    /// A template with `{repo}`, `{package}`, `{target}` (the binary),
+    /// `{dir}` and `{path}` placeholders
+    Template(String),
+}
+
+/// What an image's repository can be named after
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageName<'a> {
+    /// Repository prefix, KRUST_REPO
+    pub repo: &'a str,
+    pub package: &'a str,
+    /// The binary target in the image
+    pub target: &'a str,
+    /// The image's name in the default mode
+    pub image: &'a str,
+    /// The package's path in its workspace, starting with the workspace's
+    /// directory or git repository, e.g. `app/crates/api`
+    pub path: &'a str,
+}
+
+const NAMING_PLACEHOLDERS: [&str; 5] = ["repo", "package", "target", "dir", "path"];
+
+impl Naming {
+    /// The mode chosen by --bare, --base-import-paths or
+    /// --preserve-import-paths, if any
+    pub fn from_flags(
+        bare: bool,
+        base_import_paths: bool,
+        preserve_import_paths: bool,
+    ) -> Option<Self> {
+        if bare {
+            Some(Naming::Bare)
+        } else if base_import_paths {
+            Some(Naming::BaseImportPaths)
+        } else if preserve_import_paths {
+            Some(Naming::PreserveImportPaths)
+        } else {
+            None
+        }
+    }
+
+    /// The repository `name` is pushed to. Names taken from paths are
+    /// lowercased, since repositories can't have capitals.
+    pub fn repository(&self, name: &ImageName) -> String {
+        let path = name.path.trim_matches('/').to_lowercase();
+        let dir = path.rsplit('/').next().unwrap_or(&path).to_string();
+        match self {
+            Naming::Package => format!("{}/{}", name.repo, name.image),
+            Naming::Bare => name.repo.to_string(),
+            Naming::BaseImportPaths => format!("{}/{}", name.repo, dir),
+            Naming::PreserveImportPaths => format!("{}/{}", name.repo, path),
+            Naming::Template(template) => template
+                .replace("{repo}", name.repo)
+                .replace("{package}", name.package)
+                .replace("{target}", name.target)
+                .replace("{dir}", &dir)
+                .replace("{path}", &path),
+        }
+    }
+}
+
+impl std::str::FromStr for Naming {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "package" => Ok(Naming::Package),
+            "bare" => Ok(Naming::Bare),
+            "base-import-paths" => Ok(Naming::BaseImportPaths),
+            "preserve-import-paths" => Ok(Naming::PreserveImportPaths),
+            template if template.contains('{') => {
+                let mut rest = template;
+                while let Some(start) = rest.find('{') {
+                    let end = rest[start..].find('}').ok_or_else(|| {
+                        format!("Unclosed placeholder in naming template {}", template)
+                    })?;
+                    let placeholder = &rest[start + 1..start + end];
+                    if !NAMING_PLACEHOLDERS.contains(&placeholder) {
+                        return Err(format!(
+                            "Unknown placeholder {{{}}} in naming template {}, expected one of {{{}}}",
+                            placeholder,
+                            template,
+                            NAMING_PLACEHOLDERS.join("}, {")
+                        ));
+                    }
+                    rest = &rest[start + end + 1..];
+                }
+                Ok(Naming::Template(template.to_string()))
+            }
+            _ => Err(format!(
+                "Unknown naming {}, expected package, bare, base-import-paths, \
+                 preserve-import-paths or a template such as {{repo}}/{{package}}",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Naming {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Naming> for String {
+    fn from(naming: Naming) -> Self {
+        naming.to_string()
+    }
+}
+
+impl std::fmt::Display for Naming {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Naming::Package => f.write_str("package"),
+            Naming::Bare => f.write_str("bare"),
+            Naming::BaseImportPaths => f.write_str("base-import-paths"),
+            Naming::PreserveImportPaths => f.write_str("preserve-import-paths"),
+            Naming::Template(template) => f.write_str(template),
+        }
+    }
+}
+
+/// Timeouts applied to registry traffic
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RegistrySettings {
+    /// Seconds allowed to establish a connection to a registry
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+
+    /// Seconds allowed for a single registry request, including the body
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+
+    /// Seconds allowed for a whole operation such as pushing an image.
+    /// Unset means operations are only bounded by the per-request timeouts.
+    pub deadline_secs: Option<u64>,
+
+    /// Appended to the User-Agent header, e.g. to identify the CI system running krust
+    pub user_agent_suffix: Option<String>,
+
+    /// Serialize manifests, indexes and configs as canonical JSON (compact,
+    /// sorted keys) instead of indented JSON
+    #[serde(default)]
+    pub canonical_json: bool,
+
+    /// Create missing repositories before pushing, on registries such as
+    /// Amazon ECR that don't create them on first push
+    #[serde(default)]
+    pub create_repositories: bool,
+
+    /// Seconds a base image tag's resolved digest is reused before asking
+    /// the registry again. 0 always asks, but still falls back to the last
+    /// digest when the registry can't be reached.
+    #[serde(default = "default_manifest_cache_ttl_secs")]
+    pub manifest_cache_ttl_secs: u64,
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    30
+}
+
+fn default_timeout_secs() -> u64 {
+    300
+}
+
+fn default_manifest_cache_ttl_secs() -> u64 {
+    300
+}
+
+impl Default for RegistrySettings {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: default_connect_timeout_secs(),
+            timeout_secs: default_timeout_secs(),
+            deadline_secs: None,
+            user_agent_suffix: None,
+            canonical_json: false,
+            create_repositories: false,
+            manifest_cache_ttl_secs: default_manifest_cache_ttl_secs(),
+        }
+    }
+}
+
+impl RegistrySettings {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline_secs.map(Duration::from_secs)
+    }
+
+    pub fn manifest_cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.manifest_cache_ttl_secs)
+    }
+
+    /// User-Agent sent with every registry request: `krust/<version> (<os>; <arch>)`
+    /// followed by the configured suffix, if any.
+    pub fn user_agent(&self) -> String {
+        let base = format!(
+            "krust/{} ({}; {})",
+            env!("CARGO_PKG_VERSION"),
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        );
+        match self.user_agent_suffix.as_deref().map(str::trim) {
+            Some(suffix) if !suffix.is_empty() => format!("{} {}", base, suffix),
+            _ => base,
+        }
+    }
+}
+
+/// Settings for one registry, under `[registries."<host>"]`. Credentials
+/// here take precedence over Docker config files and credential helpers.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RegistryConfig {
     pub username: Option<String>,
     pub password: Option<String>,
+    /// Base64-encoded `username:password`, as in Docker config files
     pub auth: Option<String>,
+    /// Bearer token sent with every request
+    pub token: Option<String>,
+
+    /// Talk to the registry over plain HTTP, e.g. a local development registry
+    #[serde(default)]
+    pub insecure: bool,
+
+    /// Pull-through mirror to read images from, as `host[/path]`. Pushes
+    /// still go to the registry, and reads fall back to it if the mirror
+    /// fails.
+    pub mirror: Option<String>,
+
+    /// PEM file of CA certificates to trust for this registry, in addition
+    /// to the system's
+    pub ca_file: Option<PathBuf>,
+
+    /// Seconds allowed for a single request, instead of `registry.timeout_secs`
+    pub timeout_secs: Option<u64>,
 }
 
 fn default_base_image() -> String {
     "cgr.dev/chainguard/static:latest".to_string()
 }
 
+/// Sigstore settings used by `--sign` and `--sign-key`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SigningConfig {
+    /// Fulcio instance issuing keyless signing certificates
+    #[serde(default = "default_fulcio_url")]
+    pub fulcio_url: String,
+
+    /// Rekor transparency log recording signatures
+    #[serde(default = "default_rekor_url")]
+    pub rekor_url: String,
+
+    /// Record `--sign-key` signatures in Rekor. Keyless signatures always are.
+    #[serde(default = "default_tlog_upload")]
+    pub tlog_upload: bool,
+}
+
+fn default_tlog_upload() -> bool {
+    true
+}
+
+fn default_fulcio_url() -> String {
+    crate::sign::keyless::DEFAULT_FULCIO_URL.to_string()
+}
+
+fn default_rekor_url() -> String {
+    crate::sign::rekor::DEFAULT_REKOR_URL.to_string()
+}
+
+impl Default for SigningConfig {
+    fn default() -> Self {
+        Self {
+            fulcio_url: default_fulcio_url(),
+            rekor_url: default_rekor_url(),
+            tlog_upload: default_tlog_upload(),
+        }
+    }
+}
+
+/// Who may sign base images. A base image passes if any of its signatures
+/// verifies with one of the keys or was made by one of the identities.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BasePolicy {
+    /// PEM public keys, e.g. a `cosign.pub`
+    #[serde(default)]
+    pub keys: Vec<PathBuf>,
+
+    /// Keyless signers, by the identity in their Fulcio certificate
+    #[serde(default)]
+    pub identities: Vec<SignerIdentity>,
+
+    /// Sigstore `trusted_root.json` that keyless signatures are checked
+    /// against. Defaults to the one `cosign initialize` fetches.
+    pub trusted_root: Option<PathBuf>,
+}
+
+/// A keyless signer: the OIDC issuer and the subject it vouched for
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SignerIdentity {
+    /// OIDC issuer, e.g. `https://token.actions.githubusercontent.com`
+    pub issuer: String,
+
+    /// Email address or URI in the certificate. A trailing `*` matches any
+    /// suffix, e.g. a workflow at any ref.
+    pub subject: String,
+}
+
+impl SignerIdentity {
+    pub fn matches(&self, issuer: &str, subject: &str) -> bool {
+        if issuer != self.issuer {
+            return false;
+        }
+        match self.subject.strip_suffix('*') {
+            Some(prefix) => subject.starts_with(prefix),
+            None => subject == self.subject,
+        }
+    }
+}
+
+/// How `--scan` scans images and which findings fail the build
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ScanConfig {
+    /// Scanner to run. `auto` uses `url` if it's set, then trivy, then grype.
+    #[serde(default)]
+    pub scanner: ScannerKind,
+
+    /// Scanning service images are uploaded to, for the `http` scanner
+    pub url: Option<String>,
+
+    /// Findings at or above this severity fail the build
+    #[serde(default)]
+    pub severity: Severity,
+
+    /// Vulnerability IDs that never fail the build, e.g. accepted risks
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
+/// The vulnerability scanner `--scan` runs
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScannerKind {
+    /// The `http` scanner if `scan.url` is set, else trivy or grype,
+    /// whichever is installed
+    #[default]
+    Auto,
+    /// `trivy image --input`
+    Trivy,
+    /// `grype oci-dir:`
+    Grype,
+    /// Upload the image to the service at `scan.url`
+    Http,
+}
+
+/// Severity of a vulnerability, least severe first
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Serialize,
+    Deserialize,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Not rated by the scanner
+    Unknown,
+    Low,
+    Medium,
+    #[default]
+    High,
+    Critical,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Unknown => "unknown",
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        })
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             base_image: default_base_image(),
             default_registry: None,
+            naming: Naming::default(),
+            default_tag: None,
+            platforms: Vec::new(),
+            sign: false,
+            profile: BTreeMap::new(),
             build: BuildConfig::default(),
             registries: HashMap::new(),
+            registry: RegistrySettings::default(),
+            signing: SigningConfig::default(),
+            base_policy: BasePolicy::default(),
+            scan: ScanConfig::default(),
         }
     }
 }
 
-/// Project-specific configuration from Cargo.toml
+/// Project-specific configuration from Cargo.toml and the project config file
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct ProjectConfig {
     /// Base image for this project
-    #[serde(rename = "base-image")]
+    #[serde(
+        rename = "base-image",
+        default,
+        deserialize_with = "image_reference::deserialize_option"
+    )]
     pub base_image: Option<String>,
+
+    /// Base images for particular packages, keyed by their directory
+    /// relative to the project config file (e.g. `./services/edge`) or by
+    /// package name. Read from the project config file, where an entry for
+    /// a package replaces the file's `base-image`.
+    #[serde(
+        default,
+        rename = "base-image-overrides",
+        deserialize_with = "image_reference::deserialize_values"
+    )]
+    pub base_image_overrides: BTreeMap<String, String>,
+
+    /// Platforms to build for when none are given on the command line,
+    /// instead of those of the base image
+    #[serde(default, with = "platform_strings")]
+    pub platforms: Vec<Platform>,
+
+    /// Ownership of files krust adds to the image
+    #[serde(default)]
+    pub ownership: FileOwnership,
+
+    /// Annotations on the pushed image index
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+
+    /// Annotations on each platform entry in the image index
+    #[serde(default, rename = "manifest-annotations")]
+    pub manifest_annotations: BTreeMap<String, String>,
+
+    /// Cargo profile to build with instead of `release`
+    pub profile: Option<String>,
+
+    /// Cargo features to enable
+    #[serde(default)]
+    pub features: Vec<String>,
+
+    /// Disable the package's default features
+    #[serde(default, rename = "no-default-features")]
+    pub no_default_features: bool,
+
+    /// Additional features for particular platforms, e.g.
+    /// `"linux/amd64" = ["jemalloc"]`. A platform without a variant applies
+    /// to every variant.
+    #[serde(
+        default,
+        rename = "platform-features",
+        deserialize_with = "platform_keys"
+    )]
+    pub platform_features: BTreeMap<String, Vec<String>>,
+
+    /// Environment variables for cargo build, e.g. `RUSTFLAGS`
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// Arguments passed to every cargo build of this project, e.g. `--locked`
+    #[serde(default, rename = "cargo-args")]
+    pub cargo_args: Vec<String>,
+
+    /// Labels on the image config, also written as manifest annotations.
+    /// Override the `org.opencontainers.image.source` and `.revision` labels
+    /// krust derives from git.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+
+    /// Environment variables set in the image, e.g. `RUST_LOG = "info"`,
+    /// overriding the base image's. (`env` sets them for cargo build.)
+    #[serde(default, rename = "image-env")]
+    pub image_env: BTreeMap<String, String>,
+
+    /// Image ENTRYPOINT, e.g. `["/usr/bin/tini", "--", "/app/server"]`.
+    /// Defaults to the binary, `/app/<binary>`.
+    pub entrypoint: Option<Vec<String>>,
+
+    /// Default arguments (the image CMD), which `docker run` arguments and
+    /// Kubernetes `args:` replace
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Working directory of the image, instead of the base image's
+    #[serde(rename = "working-dir")]
+    pub working_dir: Option<String>,
+
+    /// Ports the image exposes, e.g. `["8080", "53/udp"]`, in addition to
+    /// the base image's
+    #[serde(default)]
+    pub ports: Vec<String>,
+
+    /// Absolute paths the image declares as volumes, in addition to the base
+    /// image's
+    #[serde(default)]
+    pub volumes: Vec<String>,
+
+    /// Signal that stops the container, e.g. `SIGQUIT`, instead of the base
+    /// image's
+    #[serde(rename = "stop-signal")]
+    pub stop_signal: Option<String>,
+
+    /// Local files and directories to add to the image, as `PATH[:DEST]`
+    /// strings or `{ src, dest, mode }` tables. Paths are relative to the
+    /// project.
+    #[serde(default)]
+    pub include: Vec<Include>,
+
+    /// Put included files in a layer of their own beneath the binary's, so
+    /// it's reused while only the binary changes
+    #[serde(default, rename = "include-layer")]
+    pub include_layer: bool,
+
+    /// Add a layer with Mozilla's CA certificate bundle at
+    /// `/etc/ssl/certs/ca-certificates.crt` and set `SSL_CERT_FILE` to it,
+    /// for TLS from scratch or other bases without certificates
+    #[serde(default, rename = "include-ca-certs")]
+    pub include_ca_certs: bool,
+
+    /// Shell commands run in the project directory before cargo builds each
+    /// platform, e.g. to generate code
+    #[serde(default, rename = "pre-build")]
+    pub pre_build: Vec<String>,
+
+    /// Shell commands run after each platform's binary is built, with its
+    /// path in `KRUST_BINARY`
+    #[serde(default, rename = "post-build")]
+    pub post_build: Vec<String>,
 }
 
-impl Config {
-    pub fn load() -> anyhow::Result<Self> {
-        if let Some(config_dir) = dirs::config_dir() {
-            let config_path = config_dir.join("krust").join("config.toml");
-            if config_path.exists() {
-                let content = std::fs::read_to_string(config_path)?;
-                let config: Config = toml::from_str(&content)?;
-                return Ok(config);
+/// Platforms written as strings, e.g. `["linux/amd64", "linux/arm/v7"]`
+mod platform_strings {
+    use crate::manifest::Platform;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        platforms: &[Platform],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(platforms.iter().map(Platform::to_string))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Platform>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|platform| platform.parse().map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+/// `settings` with those of the profile named `name` layered over them
+fn with_profile(mut settings: toml::Table, name: &str) -> anyhow::Result<toml::Table> {
+    let profiles = settings.get("profile").and_then(toml::Value::as_table);
+    let Some(overlay) = profiles
+        .and_then(|profiles| profiles.get(name))
+        .and_then(toml::Value::as_table)
+        .cloned()
+    else {
+        let defined: Vec<&str> = profiles
+            .map(|profiles| profiles.keys().map(String::as_str).collect())
+            .unwrap_or_default();
+        anyhow::bail!(
+            "Profile {} isn't defined; add a [profile.{}] table{}",
+            name,
+            name,
+            match defined.as_slice() {
+                [] => String::new(),
+                defined => format!(" or choose one of {}", defined.join(", ")),
+            }
+        );
+    };
+    project_file::merge(&mut settings, overlay);
+    Ok(settings)
+}
+
+/// Profiles, each checked as a config of its own
+fn profiles<'de, D>(deserializer: D) -> Result<BTreeMap<String, toml::Table>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+    let profiles = BTreeMap::<String, toml::Table>::deserialize(deserializer)?;
+    for (name, settings) in &profiles {
+        if settings.contains_key("profile") {
+            return Err(D::Error::custom(format!(
+                "profile {} can't define profiles of its own",
+                name
+            )));
+        }
+        toml::Value::Table(settings.clone())
+            .try_into::<Config>()
+            .map_err(|e| D::Error::custom(e.message()))?;
+    }
+    Ok(profiles)
+}
+
+/// A map keyed by platform strings, checked when it's read
+fn platform_keys<'de, D, T>(deserializer: D) -> Result<BTreeMap<String, T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    let map = BTreeMap::<String, T>::deserialize(deserializer)?;
+    for key in map.keys() {
+        key.parse::<Platform>().map_err(serde::de::Error::custom)?;
+    }
+    Ok(map)
+}
+
+/// Image references checked when they're read, so a typo fails when the
+/// config is loaded rather than when the image is pulled. Base images may
+/// also be `scratch` or a local image.
+mod image_reference {
+    use crate::image::{is_scratch, local::LocalImage};
+    use crate::registry::ImageReference;
+    use serde::{de::Error, Deserialize, Deserializer};
+    use std::collections::BTreeMap;
+
+    fn check<E: Error>(reference: &str) -> Result<(), E> {
+        if is_scratch(reference) || LocalImage::parse(reference).is_some() {
+            return Ok(());
+        }
+        ImageReference::parse(reference)
+            .map(drop)
+            .map_err(|e| E::custom(e.to_string().lines().next().unwrap_or_default()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        let reference = String::deserialize(deserializer)?;
+        check(&reference)?;
+        Ok(reference)
+    }
+
+    pub fn deserialize_values<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<String, String>, D::Error> {
+        let references = BTreeMap::<String, String>::deserialize(deserializer)?;
+        for reference in references.values() {
+            check(reference)?;
+        }
+        Ok(references)
+    }
+
+    pub fn deserialize_option<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<String>, D::Error> {
+        let reference = Option::<String>::deserialize(deserializer)?;
+        if let Some(reference) = &reference {
+            check(reference)?;
+        }
+        Ok(reference)
+    }
+}
+
+impl ProjectConfig {
+    /// The features to enable when building for `platform`
+    pub fn features_for(&self, platform: &Platform) -> Vec<String> {
+        let mut features = self.features.clone();
+        for (key, extra) in &self.platform_features {
+            if key
+                .parse::<Platform>()
+                .is_ok_and(|key| key.matches(platform))
+            {
+                for feature in extra {
+                    if !features.contains(feature) {
+                        features.push(feature.clone());
+                    }
+                }
             }
         }
-        Ok(Config::default())
+        features
     }
+}
+
+/// A local file or directory added to the image, written `PATH[:DEST]`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "IncludeSpec")]
+pub struct Include {
+    /// File or directory to add
+    pub src: PathBuf,
+    /// Absolute path in the image. A directory's contents are added under
+    /// it; a file is added as it, or inside it when it ends with `/`.
+    /// Defaults to `/app/<name>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dest: Option<String>,
+    /// Permission bits of added files, e.g. `0o644`. Defaults to 0755 for
+    /// executable files and 0644 for the rest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IncludeSpec {
+    Short(String),
+    Full {
+        src: PathBuf,
+        dest: Option<String>,
+        mode: Option<u32>,
+    },
+}
+
+impl TryFrom<IncludeSpec> for Include {
+    type Error = String;
+
+    fn try_from(spec: IncludeSpec) -> Result<Self, Self::Error> {
+        let include = match spec {
+            IncludeSpec::Short(s) => return s.parse(),
+            IncludeSpec::Full { src, dest, mode } => Include { src, dest, mode },
+        };
+        include.validate()?;
+        Ok(include)
+    }
+}
+
+impl std::str::FromStr for Include {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (src, dest) = match s.split_once(':') {
+            Some((src, dest)) => (src, Some(dest.to_string())),
+            None => (s, None),
+        };
+        if src.is_empty() {
+            return Err(format!("expected PATH[:DEST], got '{}'", s));
+        }
+        let include = Include {
+            src: PathBuf::from(src),
+            dest,
+            mode: None,
+        };
+        include.validate()?;
+        Ok(include)
+    }
+}
+
+impl Include {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(dest) = &self.dest {
+            if !dest.starts_with('/') || dest.split('/').any(|part| part == "..") {
+                return Err(format!(
+                    "include destination must be an absolute path without '..', got {}",
+                    dest
+                ));
+            }
+        }
+        if let Some(mode) = self.mode {
+            if mode > 0o7777 {
+                return Err(format!("Invalid include mode {:o}", mode));
+            }
+        }
+        Ok(())
+    }
+
+    /// This include with a relative `src` resolved against `dir`
+    pub fn relative_to(&self, dir: &Path) -> Include {
+        Include {
+            src: dir.join(&self.src),
+            ..self.clone()
+        }
+    }
+}
 
-    /// Load project-specific configuration from Cargo.toml
+/// Owner recorded in the tar headers of files added to image layers.
+/// Defaults to the conventional `nonroot` user (65532) so images work
+/// with `runAsNonRoot` without fixing permissions at startup.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FileOwnership {
+    #[serde(default = "default_nonroot_id")]
+    pub uid: u64,
+    #[serde(default = "default_nonroot_id")]
+    pub gid: u64,
+    #[serde(default = "default_nonroot_name")]
+    pub uname: String,
+    #[serde(default = "default_nonroot_name")]
+    pub gname: String,
+}
+
+fn default_nonroot_id() -> u64 {
+    65532
+}
+
+fn default_nonroot_name() -> String {
+    "nonroot".to_string()
+}
+
+impl Default for FileOwnership {
+    fn default() -> Self {
+        Self {
+            uid: default_nonroot_id(),
+            gid: default_nonroot_id(),
+            uname: default_nonroot_name(),
+            gname: default_nonroot_name(),
+        }
+    }
+}
+
+impl Config {
+    /// The global config, with `profile`'s settings and then `KRUST_*`
+    /// environment variables layered over it
+    pub fn load(profile: Option<&str>) -> anyhow::Result<Self> {
+        env::apply(Self::global_settings(profile)?, std::env::vars())
+    }
+
+    /// The global config with `profile`'s settings, `naming` and `[build]`
+    /// from the project config file nearest to `dir`, then `KRUST_*`
+    /// environment variables, layered over it
+    pub fn load_for_project(dir: &Path, profile: Option<&str>) -> anyhow::Result<Self> {
+        let Some(file) = ProjectFile::find(dir)? else {
+            return Self::load(profile);
+        };
+        let mut settings = Self::global_settings(profile)?;
+        project_file::merge(&mut settings, file.global_settings());
+        toml::Value::Table(settings.clone())
+            .try_into::<Config>()
+            .with_context(|| format!("Invalid settings in {}", file.path.display()))?;
+        env::apply(settings, std::env::vars())
+    }
+
+    /// The settings in the global config file, if there is one, with
+    /// `profile`'s layered over them
+    fn global_settings(profile: Option<&str>) -> anyhow::Result<toml::Table> {
+        let config_path = Paths::new().config_file();
+        let mut settings = toml::Table::new();
+        if config_path.exists() {
+            let content = std::fs::read_to_string(&config_path)?;
+            settings = toml::from_str(&content)
+                .with_context(|| format!("Failed to parse {}", config_path.display()))?;
+            // Deserializing the text rather than the table reports the line
+            toml::from_str::<Config>(&content).with_context(|| {
+                format!(
+                    "Invalid settings in {}; run `krust config validate` to list every problem",
+                    config_path.display()
+                )
+            })?;
+        }
+        match profile.filter(|profile| !profile.is_empty()) {
+            Some(profile) => with_profile(settings, profile)
+                .with_context(|| format!("Failed to load {}", config_path.display())),
+            None => Ok(settings),
+        }
+    }
+
+    /// Check every config file that applies to the project in `dir`,
+    /// reporting each problem with the line it's on
+    pub fn validate(dir: &Path) -> anyhow::Result<Report> {
+        validate::check(dir)
+    }
+
+    /// The setting at dotted `key`, e.g. `build.locked`, as `krust config
+    /// get` prints it
+    pub fn get(&self, key: &str) -> anyhow::Result<String> {
+        edit::get(self, key)
+    }
+
+    /// Set `key` to `value` in the global config file, or in `profile`'s
+    /// table in it, keeping the rest of the file as it is. Returns the
+    /// file's path.
+    pub fn set(key: &str, value: &str, profile: Option<&str>) -> anyhow::Result<PathBuf> {
+        let path = Paths::new().config_file();
+        edit::set(&path, profile, key, value)?;
+        Ok(path)
+    }
+
+    /// `tags` given on the command line, or else `default_tag`
+    pub fn tags(&self, tags: Vec<String>) -> Vec<String> {
+        if tags.is_empty() {
+            self.default_tag.iter().cloned().collect()
+        } else {
+            tags
+        }
+    }
+
+    /// The repository prefix to push images under: `repo`, given on the
+    /// command line or as KRUST_REPO, otherwise `default_registry`
+    pub fn repo(&self, repo: Option<String>) -> anyhow::Result<String> {
+        repo.filter(|repo| !repo.is_empty())
+            .or_else(|| self.default_registry.clone())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "KRUST_REPO must be set to choose where images are pushed: pass a \
+                     repository prefix such as ghcr.io/user as the REPO argument, set the \
+                     KRUST_REPO environment variable, or set default_registry in {}",
+                    Paths::new().config_file().display()
+                )
+            })
+    }
+
+    /// Load project-specific configuration: the project config file
+    /// nearest to `project_path`, with Cargo.toml's
+    /// `[package.metadata.krust]` layered over it
     pub fn load_project_config(project_path: &Path) -> anyhow::Result<ProjectConfig> {
+        Self::read_project_config(project_path, false)
+    }
+
+    /// [`Config::load_project_config`], over the settings in ko's
+    /// `.ko.yaml` when `build.ko_yaml` is set
+    pub fn project_config(&self, project_path: &Path) -> anyhow::Result<ProjectConfig> {
+        Self::read_project_config(project_path, self.build.ko_yaml)
+    }
+
+    fn read_project_config(project_path: &Path, ko_yaml: bool) -> anyhow::Result<ProjectConfig> {
         let cargo_toml_path = project_path.join("Cargo.toml");
-        if !cargo_toml_path.exists() {
-            return Ok(ProjectConfig::default());
-        }
+        let cargo_toml: Option<toml::Value> = if cargo_toml_path.exists() {
+            let content = std::fs::read_to_string(&cargo_toml_path)?;
+            Some(toml::from_str(&content)?)
+        } else {
+            None
+        };
+        let package = cargo_toml
+            .as_ref()
+            .and_then(|value| value.get("package"))
+            .and_then(|p| p.get("name"))
+            .and_then(toml::Value::as_str);
 
-        let content = std::fs::read_to_string(&cargo_toml_path)?;
-        let value: toml::Value = toml::from_str(&content)?;
+        let ko_file = if ko_yaml {
+            ko::KoFile::find(project_path)?
+        } else {
+            None
+        };
+        let mut settings = ko_file
+            .as_ref()
+            .map(ko::KoFile::project_settings)
+            .unwrap_or_default();
+        let file = ProjectFile::find(project_path)?;
+        if let Some(file) = &file {
+            project_file::merge(&mut settings, file.project_settings());
+        }
+        // A package's own base image, from ko's overrides or more
+        // specifically the project file's, beats any default base image
+        if let Some(image) = ko_file
+            .as_ref()
+            .and_then(|ko_file| ko_file.base_image_override(project_path, package))
+        {
+            settings.insert("base-image".to_string(), image);
+        }
+        if let Some(image) = file
+            .as_ref()
+            .and_then(|file| file.base_image_override(project_path, package))
+        {
+            settings.insert("base-image".to_string(), toml::Value::String(image));
+        }
 
         // Look for [package.metadata.krust] section
-        if let Some(metadata) = value
-            .get("package")
+        if let Some(toml::Value::Table(metadata)) = cargo_toml
+            .as_ref()
+            .and_then(|value| value.get("package"))
             .and_then(|p| p.get("metadata"))
             .and_then(|m| m.get("krust"))
         {
-            let project_config: ProjectConfig = metadata.clone().try_into()?;
-            return Ok(project_config);
+            project_file::merge(&mut settings, metadata.clone());
         }
 
-        Ok(ProjectConfig::default())
+        toml::Value::Table(settings)
+            .try_into()
+            .with_context(|| match &file {
+                Some(file) => format!(
+                    "Invalid project settings in {} or {}",
+                    file.path.display(),
+                    cargo_toml_path.display()
+                ),
+                None => format!("Invalid project settings in {}", cargo_toml_path.display()),
+            })
     }
 }
@@ -0,0 +1,197 @@
+//! Project config files, `.krust.toml` or `krust.yaml`
+//!
+//! Teams check one in next to their code to share defaults without editing
+//! Cargo.toml. It holds the same keys as `[package.metadata.krust]`, plus
+//! `naming` and a `[build]` table like the global config's. The nearest one
+//! to a project, looking up to the root of its git repository, applies.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use yaml_rust2::{Yaml, YamlLoader};
+
+/// Names a project config file can have, in the order they're looked for
+pub const FILE_NAMES: [&str; 3] = [".krust.toml", "krust.yaml", "krust.yml"];
+
+/// Keys that layer over the global config rather than under the project's
+/// Cargo.toml metadata
+const GLOBAL_KEYS: [&str; 2] = ["naming", "build"];
+
+/// A project config file and its settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectFile {
+    pub path: PathBuf,
+    settings: toml::Table,
+}
+
+impl ProjectFile {
+    /// The project config file nearest to `dir`: in it or in one of its
+    /// parents, stopping at the root of the git repository it's in
+    pub fn find(dir: &Path) -> Result<Option<Self>> {
+        match Self::locate(dir)? {
+            Some(path) => Self::load(&path).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The path of the file [`ProjectFile::find`] would read
+    pub fn locate(dir: &Path) -> Result<Option<PathBuf>> {
+        nearest(dir, &FILE_NAMES)
+    }
+
+    /// Read the file at `path`, as YAML if it's named `.yaml` or `.yml`
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let settings = if is_yaml(path) {
+            parse_yaml(&content)
+        } else {
+            toml::from_str(&content).map_err(anyhow::Error::from)
+        }
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            settings,
+        })
+    }
+
+    /// Settings that layer over the global config: `naming` and `[build]`
+    pub fn global_settings(&self) -> toml::Table {
+        self.settings
+            .iter()
+            .filter(|(key, _)| is_global_key(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// The base image `base-image-overrides` gives the package in
+    /// `project_path` named `package`: the entry for its directory, or
+    /// else for its name
+    pub fn base_image_override(
+        &self,
+        project_path: &Path,
+        package: Option<&str>,
+    ) -> Option<String> {
+        let overrides = self.settings.get("base-image-overrides")?.as_table()?;
+        let dir = self.path.parent()?;
+        let project = project_path.canonicalize().ok()?;
+        let by_path = overrides
+            .iter()
+            .find(|(key, _)| dir.join(key).canonicalize().ok().as_ref() == Some(&project));
+        let by_name = || {
+            overrides
+                .iter()
+                .find(|(key, _)| Some(key.as_str()) == package)
+        };
+        by_path
+            .or_else(by_name)
+            .and_then(|(_, image)| image.as_str())
+            .map(String::from)
+    }
+
+    /// Settings for the project, which Cargo.toml metadata layers over
+    pub fn project_settings(&self) -> toml::Table {
+        self.settings
+            .iter()
+            .filter(|(key, _)| !is_global_key(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
+/// The file named one of `names` nearest to `dir`: in it or in one of its
+/// parents, stopping at the root of the git repository it's in
+pub fn nearest(dir: &Path, names: &[&str]) -> Result<Option<PathBuf>> {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    for dir in dir.ancestors() {
+        let found: Vec<PathBuf> = names
+            .iter()
+            .map(|name| dir.join(name))
+            .filter(|path| path.is_file())
+            .collect();
+        match found.as_slice() {
+            [] => {}
+            [path] => return Ok(Some(path.clone())),
+            [first, second, ..] => anyhow::bail!(
+                "Both {} and {} configure the project; keep one of them",
+                first.display(),
+                second.display()
+            ),
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+/// Whether the project config file at `path` is YAML rather than TOML
+pub fn is_yaml(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "yaml" || ext == "yml")
+}
+
+/// Whether `key` is one of the settings layered over the global config
+pub fn is_global_key(key: &str) -> bool {
+    GLOBAL_KEYS.contains(&key)
+}
+
+/// Layer `overlay` over `base`: tables are merged key by key, and any
+/// other value replaces the one beneath it
+pub fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+pub fn parse_yaml(content: &str) -> Result<toml::Table> {
+    let docs = YamlLoader::load_from_str(content)?;
+    match docs.into_iter().next() {
+        None | Some(Yaml::Null) => Ok(toml::Table::new()),
+        Some(doc) => match yaml_to_toml(doc)? {
+            Some(toml::Value::Table(table)) => Ok(table),
+            _ => anyhow::bail!("Expected a mapping of settings"),
+        },
+    }
+}
+
+/// `yaml` as a TOML value. Nulls, which TOML can't express, are None and
+/// left out of mappings and lists.
+fn yaml_to_toml(yaml: Yaml) -> Result<Option<toml::Value>> {
+    Ok(Some(match yaml {
+        Yaml::Null => return Ok(None),
+        Yaml::String(s) => toml::Value::String(s),
+        Yaml::Integer(i) => toml::Value::Integer(i),
+        Yaml::Real(real) => toml::Value::Float(
+            real.parse()
+                .with_context(|| format!("Invalid number {}", real))?,
+        ),
+        Yaml::Boolean(b) => toml::Value::Boolean(b),
+        Yaml::Array(items) => toml::Value::Array(
+            items
+                .into_iter()
+                .map(yaml_to_toml)
+                .filter_map(Result::transpose)
+                .collect::<Result<_>>()?,
+        ),
+        Yaml::Hash(map) => {
+            let mut table = toml::Table::new();
+            for (key, value) in map {
+                let key = match key {
+                    Yaml::String(s) => s,
+                    Yaml::Integer(i) => i.to_string(),
+                    other => anyhow::bail!("Unsupported key {:?}", other),
+                };
+                if let Some(value) = yaml_to_toml(value)? {
+                    table.insert(key, value);
+                }
+            }
+            toml::Value::Table(table)
+        }
+        other => anyhow::bail!("Unsupported value {:?}", other),
+    }))
+}
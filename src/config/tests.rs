@@ -12,12 +12,507 @@ mod tests {
         assert!(config.registries.is_empty());
     }
 
+    #[test]
+    fn test_registries() {
+        let config: Config = toml::from_str(
+            r#"
+[registries."localhost:5000"]
+insecure = true
+
+[registries."docker.io"]
+mirror = "mirror.gcr.io"
+
+[registries."registry.corp"]
+username = "ci"
+password = "secret"
+ca_file = "/etc/ssl/corp-ca.pem"
+timeout_secs = 900
+"#,
+        )
+        .unwrap();
+        assert!(config.registries["localhost:5000"].insecure);
+        assert_eq!(
+            config.registries["docker.io"].mirror.as_deref(),
+            Some("mirror.gcr.io")
+        );
+        let corp = &config.registries["registry.corp"];
+        assert_eq!(corp.username.as_deref(), Some("ci"));
+        assert_eq!(corp.ca_file, Some(PathBuf::from("/etc/ssl/corp-ca.pem")));
+        assert_eq!(corp.timeout_secs, Some(900));
+        assert!(!corp.insecure);
+        assert!(toml::from_str::<Config>("[registries.\"ghcr.io\"]\ninsecure = \"yes\"").is_err());
+    }
+
     #[test]
     fn test_build_config_default() {
         let build_config = BuildConfig::default();
         assert!(build_config.env.is_empty());
         assert!(build_config.cargo_args.is_empty());
         assert!(build_config.target_dir.is_none());
+        assert!(build_config.toolchain.is_none());
+        assert_eq!(build_config.backend, BuildBackend::Auto);
+    }
+
+    #[test]
+    fn test_build_backend() {
+        let config: Config = toml::from_str(
+            r#"
+[build]
+backend = "zig"
+toolchain = "1.79.0"
+strip = true
+opt_level = "z"
+lto = "fat"
+auditable = true
+"#,
+        )
+        .unwrap();
+        assert!(config.build.strip);
+        assert_eq!(config.build.opt_level.as_deref(), Some("z"));
+        assert_eq!(config.build.lto.as_deref(), Some("fat"));
+        assert!(config.build.auditable);
+        assert_eq!(config.build.backend, BuildBackend::Zig);
+        assert_eq!(config.build.toolchain.as_deref(), Some("1.79.0"));
+
+        assert!(toml::from_str::<Config>("[build]\nbackend = \"bazel\"").is_err());
+    }
+
+    #[test]
+    fn test_platform_backends() {
+        let config: Config = toml::from_str(
+            r#"
+[build]
+backend = "zig"
+
+[build.platform_backends]
+"linux/s390x" = "cross"
+"linux/arm" = "cargo"
+"linux/arm/v6" = "cross"
+"#,
+        )
+        .unwrap();
+        let backend_for = |platform: &str| config.build.backend_for(&platform.parse().unwrap());
+        assert_eq!(backend_for("linux/amd64"), BuildBackend::Zig);
+        assert_eq!(backend_for("linux/s390x"), BuildBackend::Cross);
+        assert_eq!(backend_for("linux/arm/v7"), BuildBackend::Cargo);
+        assert_eq!(backend_for("linux/arm/v6"), BuildBackend::Cross);
+    }
+
+    #[test]
+    fn test_locked_offline() {
+        let build_config = BuildConfig::default();
+        assert!(!build_config.locked);
+        assert!(!build_config.offline);
+
+        let config: Config = toml::from_str("[build]\nlocked = true\noffline = true").unwrap();
+        assert!(config.build.locked);
+        assert!(config.build.offline);
+        assert!(!config.build.offline_images);
+
+        let config: Config = toml::from_str("[build]\noffline_images = true").unwrap();
+        assert!(config.build.offline_images);
+    }
+
+    #[test]
+    fn test_build_timeout() {
+        assert_eq!(BuildConfig::default().timeout_secs, None);
+        let config: Config = toml::from_str("[build]\ntimeout_secs = 900").unwrap();
+        assert_eq!(config.build.timeout_secs, Some(900));
+    }
+
+    #[test]
+    fn test_libc() {
+        assert_eq!(BuildConfig::default().libc, Libc::Musl);
+        let config: Config = toml::from_str("[build]\nlibc = \"gnu\"").unwrap();
+        assert_eq!(config.build.libc, Libc::Gnu);
+        assert!(toml::from_str::<Config>("[build]\nlibc = \"uclibc\"").is_err());
+    }
+
+    #[test]
+    fn test_media_types() {
+        assert_eq!(BuildConfig::default().media_types, MediaTypeFamily::Oci);
+        let config: Config = toml::from_str("[build]\nmedia_types = \"docker\"").unwrap();
+        assert_eq!(config.build.media_types, MediaTypeFamily::Docker);
+        assert_eq!(
+            config.build.media_types.manifest(),
+            "application/vnd.docker.distribution.manifest.v2+json"
+        );
+    }
+
+    #[test]
+    fn test_layer_compression() {
+        assert_eq!(
+            BuildConfig::default().compression,
+            LayerCompression::Gzip(6)
+        );
+        let config: Config = toml::from_str("[build]\ncompression = \"zstd:3\"").unwrap();
+        assert_eq!(config.build.compression, LayerCompression::Zstd(3));
+
+        assert_eq!("gzip:9".parse(), Ok(LayerCompression::Gzip(9)));
+        assert_eq!("gzip".parse(), Ok(LayerCompression::Gzip(6)));
+        assert_eq!("zstd".parse(), Ok(LayerCompression::Zstd(3)));
+        assert_eq!("estargz".parse(), Ok(LayerCompression::Estargz(6)));
+        assert_eq!("estargz:9".parse(), Ok(LayerCompression::Estargz(9)));
+        assert_eq!(LayerCompression::Gzip(1).to_string(), "gzip:1");
+        for invalid in ["gzip:10", "zstd:0", "zstd:fast", "brotli:5"] {
+            assert!(invalid.parse::<LayerCompression>().is_err(), "{}", invalid);
+        }
+        assert!(toml::from_str::<Config>("[build]\ncompression = \"lz4\"").is_err());
+    }
+
+    #[test]
+    fn test_repo() {
+        let mut config = Config::default();
+        assert_eq!(
+            config.repo(Some("ghcr.io/org".to_string())).unwrap(),
+            "ghcr.io/org"
+        );
+        let err = config.repo(None).unwrap_err().to_string();
+        assert!(err.contains("KRUST_REPO must be set"));
+        assert!(err.contains("default_registry"));
+
+        config.default_registry = Some("registry.corp/team".to_string());
+        assert_eq!(config.repo(None).unwrap(), "registry.corp/team");
+        assert_eq!(
+            config.repo(Some(String::new())).unwrap(),
+            "registry.corp/team"
+        );
+        assert_eq!(
+            config.repo(Some("ghcr.io/org".to_string())).unwrap(),
+            "ghcr.io/org"
+        );
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let settings: toml::Table = toml::from_str(
+            r#"
+base_image = "cgr.dev/chainguard/static:latest"
+[build]
+locked = false
+cargo_args = ["--verbose"]
+"#,
+        )
+        .unwrap();
+        let config = env::apply(
+            settings,
+            vars(&[
+                ("KRUST_BASE_IMAGE", "cgr.dev/chainguard/glibc-dynamic"),
+                ("KRUST_DEFAULT_TAG", "dev"),
+                ("KRUST_BUILD_LOCKED", "true"),
+                ("KRUST_BUILD_TIMEOUT_SECS", "600"),
+                ("KRUST_BUILD_OPT_LEVEL", "3"),
+                ("KRUST_BUILD_CARGO_ARGS", "--features=tls,--offline"),
+                ("KRUST_BUILD_LIBC", "gnu"),
+                ("KRUST_REGISTRY_USER_AGENT_SUFFIX", "ci"),
+                ("KRUST_BASE_POLICY_KEYS", "cosign.pub"),
+                ("KRUST_NAMING", "{repo}/{package}"),
+                ("KRUST_PLATFORMS", "linux/amd64, linux/arm64"),
+                // Not settings
+                ("KRUST_REPO", "ghcr.io/org"),
+                ("KRUST_HOME", "/ci/krust"),
+                ("KRUST_SIGNING", "true"),
+                ("KRUST_SCAN_TOKEN", "secret"),
+                ("KRUST_SERVE_TOKEN", "secret"),
+                ("KRUST_BUILD_ID", "1234"),
+                ("KRUST_REGISTRY_MIRROR_HOST", "mirror.local"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.base_image, "cgr.dev/chainguard/glibc-dynamic");
+        assert_eq!(config.default_tag.as_deref(), Some("dev"));
+        assert!(config.build.locked);
+        assert_eq!(config.build.timeout_secs, Some(600));
+        assert_eq!(config.build.opt_level.as_deref(), Some("3"));
+        assert_eq!(config.build.cargo_args, vec!["--features=tls", "--offline"]);
+        assert_eq!(config.build.libc, Libc::Gnu);
+        assert_eq!(config.registry.user_agent_suffix.as_deref(), Some("ci"));
+        assert_eq!(config.base_policy.keys, vec![PathBuf::from("cosign.pub")]);
+        assert_eq!(
+            config.naming,
+            Naming::Template("{repo}/{package}".to_string())
+        );
+        assert_eq!(
+            config.platforms,
+            vec![
+                Platform::new("linux", "amd64", None),
+                Platform::new("linux", "arm64", None),
+            ]
+        );
+        assert_eq!(config.tags(Vec::new()), vec!["dev"]);
+        assert_eq!(config.tags(vec!["v1".to_string()]), vec!["v1"]);
+
+        let err =
+            env::apply(toml::Table::new(), vars(&[("KRUST_BUILD_LIBC", "uclibc")])).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "Invalid value for KRUST_BUILD_LIBC: expected `musl` or `gnu`"
+        );
+        // Values aren't repeated, since they can be secrets
+        let err = env::apply(toml::Table::new(), vars(&[("KRUST_SIGN", "hunter2")])).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "Invalid value for KRUST_SIGN: expected a boolean"
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys_and_invalid_values() {
+        let err = toml::from_str::<Config>("[build]\nlockd = true\n").unwrap_err();
+        assert!(err.to_string().contains("unknown field `lockd`"), "{}", err);
+        assert!(toml::from_str::<ProjectConfig>("base_image = \"scratch\"").is_err());
+        assert!(toml::from_str::<Config>("base_image = \"cgr.dev/Chainguard/static\"").is_err());
+        assert!(toml::from_str::<Config>("[build.platform_backends]\nlinux = \"zig\"").is_err());
+
+        for base_image in [
+        "scratch",
+        "oci-layout:./bases",
+        "docker-archive:base.tar",
+        "alpine:3.20",
+        "cgr.dev/chainguard/static@sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+    ] {
+        let config = format!("base-image = {:?}", base_image);
+        assert!(toml::from_str::<ProjectConfig>(&config).is_ok(), "{}", base_image);
+    }
+    }
+
+    #[test]
+    fn test_profiles() {
+        let settings: toml::Table = toml::from_str(
+            r#"
+base_image = "cgr.dev/chainguard/static:latest"
+default_registry = "ttl.sh/me"
+
+[build]
+locked = true
+
+[profile.prod]
+default_registry = "ghcr.io/org"
+sign = true
+
+[profile.prod.build]
+strip = true
+"#,
+        )
+        .unwrap();
+        let config: Config = toml::Value::Table(settings.clone()).try_into().unwrap();
+        assert_eq!(config.default_registry.as_deref(), Some("ttl.sh/me"));
+        assert!(!config.sign);
+        assert_eq!(config.profile.keys().collect::<Vec<_>>(), vec!["prod"]);
+
+        let config: Config = toml::Value::Table(with_profile(settings.clone(), "prod").unwrap())
+            .try_into()
+            .unwrap();
+        assert_eq!(config.default_registry.as_deref(), Some("ghcr.io/org"));
+        assert_eq!(config.base_image, "cgr.dev/chainguard/static:latest");
+        assert!(config.sign);
+        assert!(config.build.locked);
+        assert!(config.build.strip);
+
+        let err = with_profile(settings, "staging").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Profile staging isn't defined; add a [profile.staging] table or choose one of prod"
+        );
+
+        assert!(toml::from_str::<Config>("[profile.dev]\nbase_imag = \"scratch\"").is_err());
+        assert!(toml::from_str::<Config>("[profile.dev.profile.inner]\nsign = true").is_err());
+    }
+
+    #[test]
+    fn test_platforms() {
+        assert!(Config::default().platforms.is_empty());
+        let config: Config = toml::from_str(r#"platforms = ["linux/arm/v7"]"#).unwrap();
+        assert_eq!(
+            config.platforms,
+            vec![Platform::new("linux", "arm", Some("v7"))]
+        );
+        assert!(toml::from_str::<Config>(r#"platforms = ["linux"]"#).is_err());
+    }
+
+    #[test]
+    fn test_naming() {
+        assert_eq!(Config::default().naming, Naming::Package);
+        let config: Config = toml::from_str("naming = \"{repo}/{package}-{target}\"").unwrap();
+        assert_eq!(
+            config.naming,
+            Naming::Template("{repo}/{package}-{target}".to_string())
+        );
+        let config: Config = toml::from_str("naming = \"preserve-import-paths\"").unwrap();
+        assert_eq!(config.naming, Naming::PreserveImportPaths);
+
+        let name = ImageName {
+            repo: "ghcr.io/org",
+            package: "api",
+            target: "api-server",
+            image: "api",
+            path: "App/crates/api-dir",
+        };
+        assert_eq!(Naming::Package.repository(&name), "ghcr.io/org/api");
+        assert_eq!(Naming::Bare.repository(&name), "ghcr.io/org");
+        assert_eq!(
+            Naming::BaseImportPaths.repository(&name),
+            "ghcr.io/org/api-dir"
+        );
+        assert_eq!(
+            Naming::PreserveImportPaths.repository(&name),
+            "ghcr.io/org/app/crates/api-dir"
+        );
+        assert_eq!(
+            Naming::Template("{repo}/{package}-{target}".to_string()).repository(&name),
+            "ghcr.io/org/api-api-server"
+        );
+
+        for invalid in ["flat", "{repo}/{binary}", "{repo}/{package"] {
+            assert!(invalid.parse::<Naming>().is_err(), "{}", invalid);
+        }
+        assert_eq!(
+            Naming::from_flags(false, true, false),
+            Some(Naming::BaseImportPaths)
+        );
+        assert_eq!(Naming::from_flags(false, false, false), None);
+    }
+
+    #[test]
+    fn test_libc_base_image_warning() {
+        assert!(Libc::Gnu
+            .base_image_warning("cgr.dev/chainguard/static:latest")
+            .is_some());
+        assert!(Libc::Gnu
+            .base_image_warning("gcr.io/distroless/static-debian12@sha256:abc")
+            .is_some());
+        assert!(Libc::Gnu.base_image_warning("alpine:3.20").is_some());
+        assert!(Libc::Gnu
+            .base_image_warning("cgr.dev/chainguard/glibc-dynamic:latest")
+            .is_none());
+        assert!(Libc::Gnu
+            .base_image_warning("gcr.io/distroless/cc-debian12")
+            .is_none());
+        assert!(Libc::Musl
+            .base_image_warning("cgr.dev/chainguard/static:latest")
+            .is_none());
+    }
+
+    #[test]
+    fn test_registry_settings() {
+        let config: Config = toml::from_str(
+            r#"
+[registry]
+timeout_secs = 60
+deadline_secs = 600
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.registry.connect_timeout_secs, 30);
+        assert_eq!(config.registry.timeout_secs, 60);
+        assert_eq!(
+            config.registry.manifest_cache_ttl(),
+            std::time::Duration::from_secs(300)
+        );
+        assert_eq!(
+            config.registry.deadline(),
+            Some(std::time::Duration::from_secs(600))
+        );
+
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.registry, RegistrySettings::default());
+        assert!(config.registry.deadline().is_none());
+    }
+
+    #[test]
+    fn test_signing_config() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.signing, SigningConfig::default());
+        assert_eq!(config.signing.fulcio_url, "https://fulcio.sigstore.dev");
+
+        let config: Config = toml::from_str(
+            r#"
+[signing]
+rekor_url = "https://rekor.example.com"
+tlog_upload = false
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.signing.fulcio_url, "https://fulcio.sigstore.dev");
+        assert_eq!(config.signing.rekor_url, "https://rekor.example.com");
+        assert!(!config.signing.tlog_upload);
+        assert!(SigningConfig::default().tlog_upload);
+    }
+
+    #[test]
+    fn test_base_policy() {
+        let config: Config = toml::from_str(
+        r#"
+[build]
+verify_base = true
+
+[base_policy]
+keys = ["cosign.pub"]
+identities = [
+    { issuer = "https://token.actions.githubusercontent.com", subject = "https://github.com/example/base/.github/workflows/release.yaml@*" },
+]
+"#,
+    )
+    .unwrap();
+        assert!(config.build.verify_base);
+        assert_eq!(config.base_policy.keys, vec![PathBuf::from("cosign.pub")]);
+        assert!(config.base_policy.trusted_root.is_none());
+
+        let identity = &config.base_policy.identities[0];
+        let issuer = "https://token.actions.githubusercontent.com";
+        assert!(identity.matches(
+            issuer,
+            "https://github.com/example/base/.github/workflows/release.yaml@refs/heads/main"
+        ));
+        assert!(!identity.matches(
+            issuer,
+            "https://github.com/example/other/.github/workflows/release.yaml@refs/heads/main"
+        ));
+        assert!(!identity.matches(
+            "https://accounts.google.com",
+            "https://github.com/example/base/.github/workflows/release.yaml@refs/heads/main"
+        ));
+
+        let exact = SignerIdentity {
+            issuer: "https://accounts.google.com".to_string(),
+            subject: "dev@example.com".to_string(),
+        };
+        assert!(exact.matches("https://accounts.google.com", "dev@example.com"));
+        assert!(!exact.matches("https://accounts.google.com", "dev@example.com.evil"));
+
+        let config: Config = toml::from_str("").unwrap();
+        assert!(!config.build.verify_base);
+        assert_eq!(config.base_policy, BasePolicy::default());
+    }
+
+    #[test]
+    fn test_user_agent() {
+        let mut settings = RegistrySettings::default();
+        let user_agent = settings.user_agent();
+        assert_eq!(
+            user_agent,
+            format!(
+                "krust/{} ({}; {})",
+                env!("CARGO_PKG_VERSION"),
+                std::env::consts::OS,
+                std::env::consts::ARCH
+            )
+        );
+
+        settings.user_agent_suffix = Some("github-actions/1234".to_string());
+        assert_eq!(
+            settings.user_agent(),
+            format!("{} github-actions/1234", user_agent)
+        );
+
+        settings.user_agent_suffix = Some("  ".to_string());
+        assert_eq!(settings.user_agent(), user_agent);
     }
 
     #[test]
@@ -48,6 +543,458 @@ base-image = "custom:latest"
         assert_eq!(config.base_image, Some("custom:latest".to_string()));
     }
 
+    #[test]
+    fn test_base_image_overrides() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::write(
+            dir.path().join("krust.yaml"),
+            r#"
+base-image: cgr.dev/chainguard/static
+base-image-overrides:
+  ./services/edge: cgr.dev/chainguard/glibc-dynamic
+  worker: alpine:3.20
+"#,
+        )
+        .unwrap();
+        for (path, name, metadata) in [
+            ("services/edge", "edge", ""),
+            ("services/api", "api", ""),
+            ("jobs/worker", "worker", ""),
+            (
+                "jobs/pinned",
+                "pinned",
+                "[package.metadata.krust]\nbase-image = \"custom:latest\"\n",
+            ),
+        ] {
+            let member = dir.path().join(path);
+            fs::create_dir_all(&member).unwrap();
+            fs::write(
+                member.join("Cargo.toml"),
+                format!(
+                    "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n{}",
+                    name, metadata
+                ),
+            )
+            .unwrap();
+        }
+        let base_image = |path: &str| {
+            Config::load_project_config(&dir.path().join(path))
+                .unwrap()
+                .base_image
+                .unwrap()
+        };
+        assert_eq!(
+            base_image("services/edge"),
+            "cgr.dev/chainguard/glibc-dynamic"
+        );
+        assert_eq!(base_image("services/api"), "cgr.dev/chainguard/static");
+        assert_eq!(base_image("jobs/worker"), "alpine:3.20");
+        // The package's own Cargo.toml still wins
+        assert_eq!(base_image("jobs/pinned"), "custom:latest");
+
+        assert!(toml::from_str::<ProjectConfig>(
+            "[base-image-overrides]\n\"./edge\" = \"cgr.dev/Chainguard/static\""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_ko_yaml() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::write(
+            dir.path().join(".ko.yaml"),
+            r#"
+defaultBaseImage: cgr.dev/chainguard/static
+defaultPlatforms: [linux/arm64]
+baseImageOverrides:
+  github.com/example/repo/services/edge: cgr.dev/chainguard/glibc-dynamic
+"#,
+        )
+        .unwrap();
+        for (path, name) in [("services/edge", "edge"), ("services/api", "api")] {
+            let member = dir.path().join(path);
+            fs::create_dir_all(&member).unwrap();
+            fs::write(
+                member.join("Cargo.toml"),
+                format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n", name),
+            )
+            .unwrap();
+        }
+        let edge = dir.path().join("services/edge");
+        let api = dir.path().join("services/api");
+
+        // Only read when opted into
+        let project = Config::load_project_config(&edge).unwrap();
+        assert_eq!(project.base_image, None);
+        let mut config = Config::default();
+        config.build.ko_yaml = true;
+        let project = config.project_config(&edge).unwrap();
+        assert_eq!(
+            project.base_image.as_deref(),
+            Some("cgr.dev/chainguard/glibc-dynamic")
+        );
+        assert_eq!(project.platforms, vec!["linux/arm64".parse().unwrap()]);
+        let project = config.project_config(&api).unwrap();
+        assert_eq!(
+            project.base_image.as_deref(),
+            Some("cgr.dev/chainguard/static")
+        );
+
+        // The project config file layers over it
+        fs::write(
+            dir.path().join(".krust.toml"),
+            "base-image = \"alpine:3.20\"\n",
+        )
+        .unwrap();
+        let project = config.project_config(&api).unwrap();
+        assert_eq!(project.base_image.as_deref(), Some("alpine:3.20"));
+        assert_eq!(project.platforms, vec!["linux/arm64".parse().unwrap()]);
+    }
+
+    #[test]
+    fn test_load_project_config_with_project_file() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::write(
+            dir.path().join(".krust.toml"),
+            r#"
+base-image = "cgr.dev/chainguard/glibc-dynamic"
+platforms = ["linux/amd64", "linux/arm/v7"]
+labels = { team = "payments", tier = "backend" }
+naming = "base-import-paths"
+
+[build]
+locked = true
+"#,
+        )
+        .unwrap();
+        let member = dir.path().join("crates/api");
+        fs::create_dir_all(&member).unwrap();
+        fs::write(
+            member.join("Cargo.toml"),
+            r#"
+[package]
+name = "api"
+version = "0.1.0"
+
+[package.metadata.krust]
+base-image = "custom:latest"
+labels = { tier = "edge" }
+"#,
+        )
+        .unwrap();
+
+        // Cargo.toml metadata layers over the file, table by table
+        let config = Config::load_project_config(&member).unwrap();
+        assert_eq!(config.base_image, Some("custom:latest".to_string()));
+        assert_eq!(
+            config.platforms,
+            vec![
+                Platform::new("linux", "amd64", None),
+                Platform::new("linux", "arm", Some("v7"))
+            ]
+        );
+        assert_eq!(config.labels["team"], "payments");
+        assert_eq!(config.labels["tier"], "edge");
+
+        let file = ProjectFile::find(&member).unwrap().unwrap();
+        assert_eq!(file.path.file_name().unwrap(), ".krust.toml");
+        let global = file.global_settings();
+        assert_eq!(global["naming"].as_str(), Some("base-import-paths"));
+        assert_eq!(global["build"]["locked"].as_bool(), Some(true));
+        assert!(!file.project_settings().contains_key("build"));
+
+        // The search stops at the repository root
+        let nested = member.join("nested");
+        fs::create_dir_all(nested.join(".git")).unwrap();
+        assert!(ProjectFile::find(&nested).unwrap().is_none());
+
+        // Only one file may configure a directory
+        fs::write(dir.path().join("krust.yaml"), "platforms: [linux/amd64]\n").unwrap();
+        let err = ProjectFile::find(&member).unwrap_err().to_string();
+        assert!(err.contains("keep one of them"), "{}", err);
+    }
+
+    #[test]
+    fn test_project_file_yaml() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("krust.yaml");
+        fs::write(
+            &path,
+            r#"
+base-image: cgr.dev/chainguard/static
+platforms:
+  - linux/amd64
+  - linux/arm64
+env:
+  RUSTFLAGS: "-C target-cpu=native"
+include-layer: true
+stop-signal: ~
+build:
+  timeout_secs: 600
+"#,
+        )
+        .unwrap();
+        let file = ProjectFile::load(&path).unwrap();
+        let project: ProjectConfig = toml::Value::Table(file.project_settings())
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            project.base_image,
+            Some("cgr.dev/chainguard/static".to_string())
+        );
+        assert_eq!(project.platforms.len(), 2);
+        assert_eq!(project.env["RUSTFLAGS"], "-C target-cpu=native");
+        assert!(project.include_layer);
+        assert!(project.stop_signal.is_none());
+        assert_eq!(
+            file.global_settings()["build"]["timeout_secs"].as_integer(),
+            Some(600)
+        );
+
+        fs::write(&path, "platforms: [linux]\n").unwrap();
+        let file = ProjectFile::load(&path).unwrap();
+        assert!(toml::Value::Table(file.project_settings())
+            .try_into::<ProjectConfig>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_load_project_config_ownership() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            r#"
+[package]
+name = "test"
+version = "0.1.0"
+
+[package.metadata.krust.ownership]
+uid = 1000
+gid = 1000
+uname = "app"
+"#,
+        )
+        .unwrap();
+
+        let config = Config::load_project_config(dir.path()).unwrap();
+        assert_eq!(config.ownership.uid, 1000);
+        assert_eq!(config.ownership.gid, 1000);
+        assert_eq!(config.ownership.uname, "app");
+        assert_eq!(config.ownership.gname, "nonroot");
+    }
+
+    #[test]
+    fn test_load_project_config_annotations() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            r#"
+[package]
+name = "test"
+version = "0.1.0"
+
+[package.metadata.krust.annotations]
+"org.opencontainers.image.source" = "https://github.com/example/app"
+
+[package.metadata.krust.manifest-annotations]
+"com.example.team" = "infra"
+"#,
+        )
+        .unwrap();
+
+        let config = Config::load_project_config(dir.path()).unwrap();
+        assert_eq!(
+            config.annotations["org.opencontainers.image.source"],
+            "https://github.com/example/app"
+        );
+        assert_eq!(config.manifest_annotations["com.example.team"], "infra");
+    }
+
+    #[test]
+    fn test_load_project_config_features() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            r#"
+[package]
+name = "test"
+version = "0.1.0"
+
+[package.metadata.krust]
+profile = "dist"
+features = ["tls"]
+no-default-features = true
+
+[package.metadata.krust.platform-features]
+"linux/amd64" = ["jemalloc"]
+"linux/arm" = ["small"]
+"#,
+        )
+        .unwrap();
+
+        let config = Config::load_project_config(dir.path()).unwrap();
+        assert_eq!(config.profile.as_deref(), Some("dist"));
+        assert!(config.no_default_features);
+        assert_eq!(
+            config.features_for(&"linux/amd64".parse().unwrap()),
+            vec!["tls", "jemalloc"]
+        );
+        assert_eq!(
+            config.features_for(&"linux/arm/v7".parse().unwrap()),
+            vec!["tls", "small"]
+        );
+        assert_eq!(
+            config.features_for(&"linux/arm64".parse().unwrap()),
+            vec!["tls"]
+        );
+    }
+
+    #[test]
+    fn test_load_project_config_hooks() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            r#"
+[package]
+name = "test"
+version = "0.1.0"
+
+[package.metadata.krust]
+pre-build = ["npm run build-assets", "buf generate"]
+post-build = ["upx --best $KRUST_BINARY"]
+"#,
+        )
+        .unwrap();
+
+        let config = Config::load_project_config(dir.path()).unwrap();
+        assert_eq!(
+            config.pre_build,
+            vec!["npm run build-assets", "buf generate"]
+        );
+        assert_eq!(config.post_build, vec!["upx --best $KRUST_BINARY"]);
+    }
+
+    #[test]
+    fn test_load_project_config_entrypoint() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            r#"
+[package]
+name = "test"
+version = "0.1.0"
+
+[package.metadata.krust]
+entrypoint = ["/usr/bin/tini", "--", "/app/test"]
+args = ["serve", "--port", "8080"]
+working-dir = "/data"
+image-env = { RUST_LOG = "info", PORT = "8080" }
+ports = ["8080", "53/udp"]
+volumes = ["/data"]
+stop-signal = "SIGQUIT"
+
+[package.metadata.krust.labels]
+"org.opencontainers.image.title" = "test"
+"#,
+        )
+        .unwrap();
+
+        let config = Config::load_project_config(dir.path()).unwrap();
+        assert_eq!(
+            config.entrypoint,
+            Some(vec![
+                "/usr/bin/tini".to_string(),
+                "--".to_string(),
+                "/app/test".to_string()
+            ])
+        );
+        assert_eq!(config.args, vec!["serve", "--port", "8080"]);
+        assert_eq!(config.working_dir.as_deref(), Some("/data"));
+        assert_eq!(config.labels["org.opencontainers.image.title"], "test");
+        assert_eq!(config.image_env["RUST_LOG"], "info");
+        assert_eq!(config.image_env["PORT"], "8080");
+        assert_eq!(config.ports, vec!["8080", "53/udp"]);
+        assert_eq!(config.volumes, vec!["/data"]);
+        assert_eq!(config.stop_signal.as_deref(), Some("SIGQUIT"));
+    }
+
+    #[test]
+    fn test_load_project_config_include() {
+        let project: ProjectConfig = toml::from_str(
+            r#"
+include = [
+    "LICENSE",
+    "config/app.toml:/etc/app/",
+    { src = "templates", dest = "/srv/templates", mode = 0o640 },
+]
+include-layer = true
+include-ca-certs = true
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            project.include,
+            vec![
+                Include {
+                    src: PathBuf::from("LICENSE"),
+                    dest: None,
+                    mode: None,
+                },
+                Include {
+                    src: PathBuf::from("config/app.toml"),
+                    dest: Some("/etc/app/".to_string()),
+                    mode: None,
+                },
+                Include {
+                    src: PathBuf::from("templates"),
+                    dest: Some("/srv/templates".to_string()),
+                    mode: Some(0o640),
+                },
+            ]
+        );
+        assert!(project.include_layer);
+        assert!(project.include_ca_certs);
+
+        assert!("config:etc/app".parse::<Include>().is_err());
+        assert!("config:/etc/../root".parse::<Include>().is_err());
+        assert!(":/etc/app".parse::<Include>().is_err());
+    }
+
+    #[test]
+    fn test_build_config_with_project() {
+        let config: Config = toml::from_str(
+            r#"
+[build]
+cargo_args = ["--locked"]
+env = { RUSTFLAGS = "-C opt-level=s", CARGO_INCREMENTAL = "0" }
+"#,
+        )
+        .unwrap();
+        let project: ProjectConfig = toml::from_str(
+            r#"
+cargo-args = ["--bin", "server"]
+env = { RUSTFLAGS = "-C debuginfo=1" }
+"#,
+        )
+        .unwrap();
+
+        let build = config.build.with_project(&project);
+        assert_eq!(build.cargo_args, vec!["--locked", "--bin", "server"]);
+        assert_eq!(build.env["RUSTFLAGS"], "-C debuginfo=1");
+        assert_eq!(build.env["CARGO_INCREMENTAL"], "0");
+    }
+
+    #[test]
+    fn test_default_ownership_is_nonroot() {
+        let dir = tempdir().unwrap();
+        let config = Config::load_project_config(dir.path()).unwrap();
+        assert_eq!(config.ownership, FileOwnership::default());
+        assert_eq!(config.ownership.uid, 65532);
+    }
+
     #[test]
     fn test_load_project_config_without_metadata() {
         let dir = tempdir().unwrap();
@@ -75,4 +1022,41 @@ version = "0.1.0"
         let result = Config::load_project_config(dir.path());
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_sbom_format() {
+        assert_eq!(BuildConfig::default().sbom, SbomFormat::None);
+        let config: Config = toml::from_str("[build]\nsbom = \"spdx\"").unwrap();
+        assert_eq!(config.build.sbom, SbomFormat::Spdx);
+        let config: Config = toml::from_str("[build]\nsbom = \"cyclonedx\"").unwrap();
+        assert_eq!(config.build.sbom, SbomFormat::Cyclonedx);
+        assert!(toml::from_str::<Config>("[build]\nsbom = \"swid\"").is_err());
+    }
+
+    #[test]
+    fn test_scan_config() {
+        let config: Config = toml::from_str(
+            r#"
+[build]
+scan = true
+
+[scan]
+scanner = "grype"
+severity = "critical"
+ignore = ["CVE-2023-1234"]
+"#,
+        )
+        .unwrap();
+        assert!(config.build.scan);
+        assert_eq!(config.scan.scanner, ScannerKind::Grype);
+        assert_eq!(config.scan.severity, Severity::Critical);
+        assert_eq!(config.scan.ignore, vec!["CVE-2023-1234".to_string()]);
+
+        let config: Config = toml::from_str("").unwrap();
+        assert!(!config.build.scan);
+        assert_eq!(config.scan.scanner, ScannerKind::Auto);
+        assert_eq!(config.scan.severity, Severity::High);
+        assert!(config.scan.url.is_none());
+        assert!(Severity::Critical > Severity::High && Severity::Low > Severity::Unknown);
+    }
 }
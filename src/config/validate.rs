@@ -0,0 +1,486 @@
+//! Checking config files for `krust config validate`
+//!
+//! Loading a config stops at its first problem and can't say where in the
+//! file it is. Validation instead deserializes the settings one key at a
+//! time, narrowing each problem down to the key it's in, and reports every
+//! one with the line that key is on.
+
+use super::{project_file, Config, ProjectConfig, ProjectFile};
+use crate::paths::Paths;
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use toml::de::{DeTable, DeValue};
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::Marker;
+
+/// Where `[package.metadata.krust]` is in Cargo.toml
+const CARGO_METADATA: [&str; 3] = ["package", "metadata", "krust"];
+
+/// A problem with a setting in a config file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub path: PathBuf,
+    /// Line the setting is on, counting from 1
+    pub line: Option<usize>,
+    /// Dotted path of the setting, empty for the file as a whole
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if !self.key.is_empty() {
+            write!(f, ": {}", self.key)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// The config files that apply to the project in `dir`, and the problems
+/// found in them
+#[derive(Debug, Default)]
+pub struct Report {
+    pub files: Vec<PathBuf>,
+    pub problems: Vec<Problem>,
+}
+
+/// Check the global config file, and the project config file and
+/// Cargo.toml that apply to the project in `dir`
+pub fn check(dir: &Path) -> Result<Report> {
+    let mut report = Report::default();
+
+    let global = Paths::new().config_file();
+    if global.exists() {
+        report.problems.extend(global_config(&global)?);
+        report.files.push(global);
+    }
+    if let Some(path) = ProjectFile::locate(dir)? {
+        report.problems.extend(project_config_file(&path)?);
+        report.files.push(path);
+    }
+    let cargo_toml = dir.join("Cargo.toml");
+    if cargo_toml.exists() {
+        report.problems.extend(cargo_metadata(&cargo_toml)?);
+        report.files.push(cargo_toml);
+    }
+    Ok(report)
+}
+
+/// Problems in the global config file at `path`
+pub fn global_config(path: &Path) -> Result<Vec<Problem>> {
+    let document = match Document::read(path)? {
+        Ok(document) => document,
+        Err(problem) => return Ok(vec![problem]),
+    };
+    Ok(document.problems::<Config>(&[], &document.settings))
+}
+
+/// Problems in the project config file at `path`
+pub fn project_config_file(path: &Path) -> Result<Vec<Problem>> {
+    let document = match Document::read(path)? {
+        Ok(document) => document,
+        Err(problem) => return Ok(vec![problem]),
+    };
+    let (global, project) = document
+        .settings
+        .clone()
+        .into_iter()
+        .partition(|(key, _)| project_file::is_global_key(key));
+    let mut problems = document.problems::<Config>(&[], &global);
+    problems.extend(document.problems::<ProjectConfig>(&[], &project));
+    problems.sort_by_key(|problem| problem.line);
+    Ok(problems)
+}
+
+/// Problems in `[package.metadata.krust]` of the Cargo.toml at `path`
+pub fn cargo_metadata(path: &Path) -> Result<Vec<Problem>> {
+    let document = match Document::read(path)? {
+        Ok(document) => document,
+        Err(problem) => return Ok(vec![problem]),
+    };
+    let prefix = CARGO_METADATA.map(String::from);
+    let mut value = &toml::Value::Table(document.settings.clone());
+    for key in &prefix {
+        match value.get(key) {
+            Some(inner) => value = inner,
+            None => return Ok(Vec::new()),
+        }
+    }
+    let Some(metadata) = value.as_table() else {
+        return Ok(vec![
+            document.problem(&prefix, "expected a table".to_string())
+        ]);
+    };
+    Ok(document.problems::<ProjectConfig>(&prefix, metadata))
+}
+
+/// A config file's settings and the line each key is on
+struct Document {
+    path: PathBuf,
+    settings: toml::Table,
+    lines: HashMap<Vec<String>, usize>,
+}
+
+impl Document {
+    /// Read the file at `path`, or the problem that keeps it from being read
+    /// as TOML, or YAML for a `.yaml` project config file
+    fn read(path: &Path) -> Result<std::result::Result<Self, Problem>> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let syntax_error = |line, message| Problem {
+            path: path.to_path_buf(),
+            line,
+            key: String::new(),
+            message,
+        };
+        let mut lines = HashMap::new();
+        let settings = if project_file::is_yaml(path) {
+            let mut keys = YamlKeys::default();
+            if let Err(e) = Parser::new_from_str(&content).load(&mut keys, false) {
+                return Ok(Err(syntax_error(
+                    Some(e.marker().line()),
+                    e.info().to_string(),
+                )));
+            }
+            lines = keys.lines;
+            match project_file::parse_yaml(&content) {
+                Ok(settings) => settings,
+                Err(e) => return Ok(Err(syntax_error(None, format!("{:#}", e)))),
+            }
+        } else {
+            match DeTable::parse(&content) {
+                Ok(table) => toml_lines(&content, &[], table.get_ref(), &mut lines),
+                Err(e) => {
+                    let line = e.span().map(|span| line_at(&content, span.start));
+                    return Ok(Err(syntax_error(line, e.message().to_string())));
+                }
+            }
+            toml::from_str(&content)?
+        };
+        Ok(Ok(Self {
+            path: path.to_path_buf(),
+            settings,
+            lines,
+        }))
+    }
+
+    /// Problems with `settings`, found at `prefix` in the file, as a `T`
+    fn problems<T: DeserializeOwned>(
+        &self,
+        prefix: &[String],
+        settings: &toml::Table,
+    ) -> Vec<Problem> {
+        let mut found = Vec::new();
+        narrow::<T>(&[], &toml::Value::Table(settings.clone()), &mut found);
+        let mut problems: Vec<Problem> = found
+            .into_iter()
+            .map(|(key, message)| self.problem(&[prefix, &key].concat(), message))
+            .collect();
+        problems.sort_by_key(|problem| problem.line);
+        problems
+    }
+
+    /// A problem with the setting at `key`, on the line of the nearest key
+    /// to it the file has
+    fn problem(&self, key: &[String], message: String) -> Problem {
+        let line = (1..=key.len())
+            .rev()
+            .find_map(|len| self.lines.get(&key[..len]).copied());
+        Problem {
+            path: self.path.clone(),
+            line,
+            key: key.join("."),
+            message,
+        }
+    }
+}
+
+/// Narrow down why `value` at `key` isn't a valid `T`: to the keys in it
+/// that are invalid on their own, or else to `key` itself
+fn narrow<T: DeserializeOwned>(
+    key: &[String],
+    value: &toml::Value,
+    problems: &mut Vec<(Vec<String>, String)>,
+) {
+    let Err(e) = nested(key, value.clone()).try_into::<T>() else {
+        return;
+    };
+    if let toml::Value::Table(table) = value {
+        let found = problems.len();
+        for (name, value) in table {
+            narrow::<T>(&[key, std::slice::from_ref(name)].concat(), value, problems);
+        }
+        if problems.len() > found {
+            return;
+        }
+    }
+    problems.push((key.to_vec(), suggest(e.message())));
+}
+
+/// `message` with serde's list of every expected field replaced by the
+/// one an unknown field was probably meant to be, if there's one close
+/// enough to it
+pub(super) fn suggest(message: &str) -> String {
+    if !message.starts_with("unknown field") {
+        return message.to_string();
+    }
+    let mut names = message.split('`').skip(1).step_by(2);
+    let Some(unknown) = names.next() else {
+        return message.to_string();
+    };
+    let closest = names
+        .map(|name| (edit_distance(unknown, name), name))
+        .filter(|(distance, _)| *distance <= (unknown.len() / 3).max(1))
+        .min();
+    match closest {
+        Some((_, name)) => format!("unknown field `{}`, did you mean `{}`?", unknown, name),
+        None => message.to_string(),
+    }
+}
+
+/// Levenshtein distance between `a` and `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// `value` inside tables named by `key`, so it's where `key` says
+pub(super) fn nested(key: &[String], value: toml::Value) -> toml::Value {
+    key.iter().rev().fold(value, |value, name| {
+        toml::Value::Table(toml::Table::from_iter([(name.clone(), value)]))
+    })
+}
+
+/// The line of the byte at `offset` in `content`
+fn line_at(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+/// Record the line of every key in `table`, and in the tables under it
+fn toml_lines(
+    content: &str,
+    prefix: &[String],
+    table: &DeTable,
+    lines: &mut HashMap<Vec<String>, usize>,
+) {
+    for (name, value) in table {
+        let key = [prefix, &[name.get_ref().to_string()]].concat();
+        lines.insert(key.clone(), line_at(content, name.span().start));
+        if let DeValue::Table(table) = value.get_ref() {
+            toml_lines(content, &key, table, lines);
+        }
+    }
+}
+
+/// Collects the line of every mapping key in a YAML document, outside of
+/// sequences
+#[derive(Default)]
+struct YamlKeys {
+    /// Open mappings, with the key whose value is being read, and sequences
+    stack: Vec<Option<Option<String>>>,
+    lines: HashMap<Vec<String>, usize>,
+}
+
+impl YamlKeys {
+    /// A scalar or alias: a mapping's key, or a value
+    fn node(&mut self, scalar: Option<String>, line: usize) {
+        let path: Option<Vec<String>> = self.stack[..self.stack.len().saturating_sub(1)]
+            .iter()
+            .map(|frame| frame.clone().flatten())
+            .collect();
+        match self.stack.last_mut() {
+            Some(Some(pending @ None)) => {
+                if let (Some(mut path), Some(key)) = (path, scalar.clone()) {
+                    path.push(key);
+                    self.lines.insert(path, line);
+                }
+                *pending = Some(scalar.unwrap_or_default());
+            }
+            Some(Some(pending)) => *pending = None,
+            _ => {}
+        }
+    }
+
+    /// The end of a mapping or sequence, the value of its parent's key
+    fn end(&mut self) {
+        self.stack.pop();
+        if let Some(Some(pending)) = self.stack.last_mut() {
+            *pending = None;
+        }
+    }
+}
+
+impl MarkedEventReceiver for YamlKeys {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(value, ..) => self.node(Some(value), mark.line()),
+            Event::Alias(_) => self.node(None, mark.line()),
+            Event::MappingStart(..) => self.stack.push(Some(None)),
+            Event::SequenceStart(..) => self.stack.push(None),
+            Event::MappingEnd | Event::SequenceEnd => self.end(),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn summary(problems: &[Problem]) -> Vec<(Option<usize>, &str)> {
+        problems
+            .iter()
+            .map(|problem| (problem.line, problem.key.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_global_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(
+            dir.path(),
+            "config.toml",
+            r#"base_image = "cgr.dev/chainguard/static:latest"
+platforms = ["linux/amd64", "linux"]
+
+[build]
+lockd = true
+libc = "gnu"
+timeout_secs = "ten"
+
+[registries."ghcr.io"]
+insecure = "yes"
+
+[profile.prod.build]
+stirp = true
+"#,
+        );
+        let problems = global_config(&path).unwrap();
+        assert_eq!(
+            summary(&problems),
+            vec![
+                (Some(2), "platforms"),
+                (Some(5), "build.lockd"),
+                (Some(7), "build.timeout_secs"),
+                (Some(10), "registries.ghcr.io.insecure"),
+                (Some(13), "profile.prod.build.stirp"),
+            ],
+            "{:#?}",
+            problems
+        );
+        assert!(problems[0].message.contains("linux"), "{}", problems[0]);
+        assert_eq!(
+            problems[1].message,
+            "unknown field `lockd`, did you mean `locked`?"
+        );
+        assert_eq!(
+            problems[1].to_string(),
+            format!("{}:5: build.lockd: {}", path.display(), problems[1].message)
+        );
+
+        let path = write(dir.path(), "valid.toml", "base_image = \"scratch\"\n");
+        assert!(global_config(&path).unwrap().is_empty());
+
+        let path = write(dir.path(), "syntax.toml", "base_image = \n");
+        assert_eq!(summary(&global_config(&path).unwrap()), vec![(Some(1), "")]);
+    }
+
+    #[test]
+    fn test_suggest() {
+        assert_eq!(
+            suggest("unknown field `base_imag`, expected one of `base_image`, `naming`"),
+            "unknown field `base_imag`, did you mean `base_image`?"
+        );
+        assert_eq!(
+            suggest("unknown field `colour`, expected `libc` or `lto`"),
+            "unknown field `colour`, expected `libc` or `lto`"
+        );
+        assert_eq!(
+            suggest("invalid type: string \"yes\", expected a boolean"),
+            "invalid type: string \"yes\", expected a boolean"
+        );
+        assert_eq!(edit_distance("lockd", "locked"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_image_references() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(
+            dir.path(),
+            "config.toml",
+            "base_image = \"cgr.dev/Chainguard/static\"\ndefault_registry = \"ghcr.io/user\"\n",
+        );
+        let problems = global_config(&path).unwrap();
+        assert_eq!(summary(&problems), vec![(Some(1), "base_image")]);
+        assert!(problems[0].message.contains("lowercase"), "{}", problems[0]);
+    }
+
+    #[test]
+    fn test_project_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(
+            dir.path(),
+            "krust.yaml",
+            "naming: bare\nbuild:\n  locked: maybe\nplatforms:\n  - linux/amd64\nlabels:\n  team: a\nbase-imag: scratch\n",
+        );
+        assert_eq!(
+            summary(&project_config_file(&path).unwrap()),
+            vec![(Some(3), "build.locked"), (Some(8), "base-imag")]
+        );
+
+        let path = write(dir.path(), ".krust.toml", "default_tag = \"dev\"\n");
+        assert_eq!(
+            summary(&project_config_file(&path).unwrap()),
+            vec![(Some(1), "default_tag")]
+        );
+    }
+
+    #[test]
+    fn test_cargo_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(
+            dir.path(),
+            "Cargo.toml",
+            r#"[package]
+name = "app"
+version = "0.1.0"
+
+[package.metadata.krust]
+base-image = "cgr.dev/chainguard/static"
+features = ["tls"]
+platform-features = { "linux" = ["jemalloc"] }
+"#,
+        );
+        assert_eq!(
+            summary(&cargo_metadata(&path).unwrap()),
+            vec![(Some(8), "package.metadata.krust.platform-features.linux")]
+        );
+
+        let path = write(dir.path(), "Other.toml", "[package]\nname = \"app\"\n");
+        assert!(cargo_metadata(&path).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+//! Structured build events
+//!
+//! With `--events json`, krust writes one JSON object per line as a build
+//! progresses, so CI systems and TUIs can follow it and collect digests
+//! without scraping logs. Builds run on many threads and tasks at once, so
+//! the sink is process-wide: it's set once at startup and everything that
+//! has something to report writes to it. Without a sink, events are dropped.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use tracing::debug;
+
+static SINK: OnceLock<Mutex<Box<dyn Write + Send>>> = OnceLock::new();
+
+/// Formats events can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EventFormat {
+    /// Newline-delimited JSON, one event per line
+    Json,
+}
+
+/// Something that happened during a build
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// Compiling started for one platform
+    BuildStarted { platform: String, target: String },
+    /// A line cargo wrote while compiling for `target`
+    CargoProgress { target: String, line: String },
+    /// A layer is in the registry, whether it was uploaded, mounted from
+    /// another repository or already there
+    LayerPushed {
+        repository: String,
+        digest: String,
+        size: i64,
+    },
+    /// An image index was pushed, along with the tags pointing at it
+    IndexPushed {
+        image: String,
+        digest: String,
+        tags: Vec<String>,
+    },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    time: String,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// Write events to stderr
+pub fn to_stderr() -> Result<()> {
+    init(Box::new(std::io::stderr()))
+}
+
+/// Write events to the file at `path`, replacing it
+pub fn to_file(path: &Path) -> Result<()> {
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create events file {}", path.display()))?;
+    init(Box::new(LineWriter::new(file)))
+}
+
+fn init(writer: Box<dyn Write + Send>) -> Result<()> {
+    SINK.set(Mutex::new(writer))
+        .map_err(|_| anyhow::anyhow!("Events are already being written"))
+}
+
+/// Report `event`, if events are being written
+pub fn emit(event: Event) {
+    let Some(sink) = SINK.get() else { return };
+    let line = encode(&event, chrono::Utc::now());
+    let mut writer = sink.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(e) = writer
+        .write_all(line.as_bytes())
+        .and_then(|_| writer.flush())
+    {
+        debug!("Failed to write event: {}", e);
+    }
+}
+
+/// `event` as a line of JSON, stamped with `time`
+fn encode(event: &Event, time: chrono::DateTime<chrono::Utc>) -> String {
+    let record = Record {
+        time: time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        event,
+    };
+    let mut line = serde_json::to_string(&record).expect("events serialize to JSON");
+    line.push('\n');
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let time = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let event = Event::LayerPushed {
+            repository: "ghcr.io/org/app".to_string(),
+            digest: "sha256:abc".to_string(),
+            size: 42,
+        };
+        assert_eq!(
+            encode(&event, time),
+            "{\"time\":\"2023-11-14T22:13:20.000Z\",\"event\":\"layer-pushed\",\
+             \"repository\":\"ghcr.io/org/app\",\"digest\":\"sha256:abc\",\"size\":42}\n"
+        );
+
+        let event = Event::CargoProgress {
+            target: "x86_64-unknown-linux-musl".to_string(),
+            line: "   Compiling app v0.1.0\n\"quoted\"".to_string(),
+        };
+        let line = encode(&event, time);
+        assert_eq!(line.matches('\n').count(), 1);
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["event"], "cargo-progress");
+        assert_eq!(value["line"], "   Compiling app v0.1.0\n\"quoted\"");
+    }
+}
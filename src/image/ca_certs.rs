@@ -0,0 +1,30 @@
+//! The CA certificate bundle added to images with `include-ca-certs`
+//!
+//! `ca-certificates.crt` is Mozilla's root store as packaged by certifi
+//! 2026.05.20. To update it, replace the file and [`BUNDLE_SHA256`]
+//! together; the tests fail if they disagree.
+
+/// PEM bundle of trusted root certificates
+pub const BUNDLE: &[u8] = include_bytes!("ca-certificates.crt");
+
+/// SHA-256 of [`BUNDLE`], pinning the vendored file
+pub const BUNDLE_SHA256: &str = "21328ad9d446bda7159faf1b8ab76530b3e6dbc4b035de91ac6966d1b8d28e93";
+
+/// Where the bundle is written in the image, the path Debian-based images
+/// and most TLS libraries use
+pub const BUNDLE_PATH: &str = "/etc/ssl/certs/ca-certificates.crt";
+
+/// Environment variable pointing OpenSSL and rustls-native-certs at the bundle
+pub const SSL_CERT_FILE: &str = "SSL_CERT_FILE";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle_matches_pinned_digest() {
+        assert_eq!(sha256::digest(BUNDLE), BUNDLE_SHA256);
+        let pem = String::from_utf8_lossy(BUNDLE);
+        assert!(pem.matches("-----BEGIN CERTIFICATE-----").count() > 100);
+    }
+}
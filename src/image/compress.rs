@@ -0,0 +1,141 @@
+//! Layer compression
+//!
+//! Large layers are gzipped in parallel: the tarball is split into fixed-size
+//! chunks, each compressed as its own gzip member, and the members are
+//! concatenated, which gzip readers decompress as one stream (as pigz does).
+//! The chunk size doesn't depend on the number of CPUs, so the compressed
+//! layer and its digest are the same on every machine. zstd uses its own
+//! worker threads, whose output is likewise independent of their number.
+//! eStargz layers rewrite the tarball as well as compressing it, so they're
+//! built by [`super::estargz`] instead.
+
+use crate::config::LayerCompression;
+use anyhow::{bail, Context, Result};
+use flate2::write::GzEncoder;
+use std::io::Write;
+
+/// Size of each independently compressed gzip member
+const GZIP_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Compress a layer tarball
+pub fn compress(data: &[u8], compression: LayerCompression) -> Result<Vec<u8>> {
+    match compression {
+        LayerCompression::Gzip(level) => gzip(data, level),
+        LayerCompression::Zstd(level) => zstd(data, level),
+        LayerCompression::Estargz(_) => {
+            bail!("eStargz layers are built with estargz::build, not compressed")
+        }
+    }
+}
+
+fn gzip(data: &[u8], level: u32) -> Result<Vec<u8>> {
+    if data.len() <= GZIP_CHUNK_SIZE {
+        return gzip_member(data, level);
+    }
+
+    let chunks: Vec<&[u8]> = data.chunks(GZIP_CHUNK_SIZE).collect();
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(chunks.len());
+    let per_thread = chunks.len().div_ceil(threads);
+
+    let members = std::thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .chunks(per_thread)
+            .map(|group| {
+                scope.spawn(move || {
+                    group
+                        .iter()
+                        .map(|chunk| gzip_member(chunk, level))
+                        .collect::<Result<Vec<_>>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("Compressing layer panicked"))?
+            })
+            .collect::<Result<Vec<_>>>()
+    })?;
+    Ok(members.concat().concat())
+}
+
+fn gzip_member(data: &[u8], level: u32) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn zstd(data: &[u8], level: i32) -> Result<Vec<u8>> {
+    let workers = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let mut encoder =
+        zstd::Encoder::new(Vec::new(), level).context("Failed to create zstd encoder")?;
+    // At least one worker, so the output matches on machines with any
+    // number of CPUs
+    encoder
+        .multithread(u32::try_from(workers).unwrap_or(1))
+        .context("Failed to enable multithreaded zstd compression")?;
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i % 251) as u8 ^ (i / 4096) as u8)
+            .collect()
+    }
+
+    fn gunzip(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        flate2::read::MultiGzDecoder::new(data)
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_gzip_round_trip() {
+        for len in [0, 1024, GZIP_CHUNK_SIZE * 3 + 17] {
+            let data = sample(len);
+            let compressed = compress(&data, LayerCompression::Gzip(1)).unwrap();
+            assert_eq!(gunzip(&compressed), data, "{}", len);
+        }
+    }
+
+    #[test]
+    fn test_gzip_small_layer_is_one_member() {
+        let data = sample(1024);
+        let compressed = compress(&data, LayerCompression::Gzip(6)).unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::new(6));
+        encoder.write_all(&data).unwrap();
+        assert_eq!(compressed, encoder.finish().unwrap());
+    }
+
+    #[test]
+    fn test_gzip_level() {
+        let data = sample(64 * 1024);
+        let fast = compress(&data, LayerCompression::Gzip(1)).unwrap();
+        let best = compress(&data, LayerCompression::Gzip(9)).unwrap();
+        assert_ne!(fast, best);
+        assert_eq!(gunzip(&best), data);
+    }
+
+    #[test]
+    fn test_zstd_round_trip() {
+        let data = sample(GZIP_CHUNK_SIZE + 17);
+        let compressed = compress(&data, LayerCompression::Zstd(3)).unwrap();
+        assert_eq!(&compressed[..4], &[0x28, 0xb5, 0x2f, 0xfd]);
+        assert_eq!(zstd::decode_all(compressed.as_slice()).unwrap(), data);
+    }
+}
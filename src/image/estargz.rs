@@ -0,0 +1,476 @@
+//! eStargz layers, which lazy-pulling snapshotters can run before they're
+//! downloaded
+//!
+//! An eStargz layer is an ordinary gzipped tarball, so any runtime can pull
+//! it, laid out so the stargz snapshotter can fetch files on demand: each
+//! file's contents start a new gzip member, large files are split into
+//! chunks, and a table of contents (`stargz.index.json`) at the end of the
+//! tarball records the compressed offset of every file and chunk. A
+//! fixed-size footer points at the TOC. The layer's diff ID covers the
+//! tarball as rewritten, TOC included.
+
+use anyhow::{bail, Context, Result};
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use sha256::digest;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Layer annotation with the digest of the TOC, which the snapshotter
+/// verifies the TOC against
+pub const TOC_DIGEST_ANNOTATION: &str = "containerd.io/snapshot/stargz/toc.digest";
+
+/// Layer annotation with the size of the uncompressed tarball
+pub const UNCOMPRESSED_SIZE_ANNOTATION: &str = "io.containers.estargz.uncompressed-size";
+
+/// Name of the TOC entry, the last in the tarball
+const TOC_NAME: &str = "stargz.index.json";
+
+/// First entry, telling the snapshotter not to prefetch any files
+const NO_PREFETCH_LANDMARK: &str = ".no.prefetch.landmark";
+const LANDMARK_CONTENTS: &[u8] = &[0xf];
+
+/// Files larger than this are split into chunks fetched separately
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Size of the footer: an empty gzip member whose extra field holds the
+/// TOC's offset
+const FOOTER_SIZE: usize = 51;
+
+/// A layer rewritten as eStargz
+pub struct Estargz {
+    /// The compressed layer
+    pub data: Vec<u8>,
+    /// Digest of the uncompressed tarball
+    pub diff_id: String,
+    /// Annotations the layer's descriptor needs
+    pub annotations: BTreeMap<String, String>,
+}
+
+/// The table of contents
+#[derive(Debug, Serialize, Deserialize)]
+struct Toc {
+    version: u32,
+    entries: Vec<TocEntry>,
+}
+
+/// An entry in the TOC: a tar entry, or a chunk of a regular file after its
+/// first
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TocEntry {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    size: u64,
+    #[serde(default, rename = "modtime", skip_serializing_if = "Option::is_none")]
+    mod_time: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    link_name: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    mode: u32,
+    #[serde(default, skip_serializing_if = "is_default")]
+    uid: u64,
+    #[serde(default, skip_serializing_if = "is_default")]
+    gid: u64,
+    #[serde(default, skip_serializing_if = "is_default")]
+    user_name: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    group_name: String,
+    /// Compressed offset of the gzip member holding the contents
+    #[serde(default, skip_serializing_if = "is_default")]
+    offset: u64,
+    /// Digest of the whole file, on its first entry
+    #[serde(default, skip_serializing_if = "is_default")]
+    digest: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    chunk_offset: u64,
+    /// Size of the chunk; zero for the file's last chunk when it's short
+    #[serde(default, skip_serializing_if = "is_default")]
+    chunk_size: u64,
+    #[serde(default, skip_serializing_if = "is_default")]
+    chunk_digest: String,
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// Gzip output split into members where entries need them
+struct Members {
+    level: flate2::Compression,
+    compressed: Vec<u8>,
+    uncompressed: Vec<u8>,
+    member: Option<GzEncoder<Vec<u8>>>,
+}
+
+impl Members {
+    fn new(level: u32) -> Self {
+        Self {
+            level: flate2::Compression::new(level),
+            compressed: Vec::new(),
+            uncompressed: Vec::new(),
+            member: None,
+        }
+    }
+
+    /// Write to the current member, starting one if the last was closed
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let level = self.level;
+        self.member
+            .get_or_insert_with(|| GzEncoder::new(Vec::new(), level))
+            .write_all(data)?;
+        self.uncompressed.extend_from_slice(data);
+        Ok(())
+    }
+
+    /// Close the current member. Returns the offset the next one starts at.
+    fn close(&mut self) -> Result<u64> {
+        if let Some(member) = self.member.take() {
+            self.compressed.extend(member.finish()?);
+        }
+        Ok(self.compressed.len() as u64)
+    }
+}
+
+/// Rewrite a layer tarball as eStargz, gzipped at `level`
+pub fn build(tar_data: &[u8], level: u32) -> Result<Estargz> {
+    let mut members = Members::new(level);
+    let mut toc = Vec::new();
+
+    let landmark = regular_header(NO_PREFETCH_LANDMARK, LANDMARK_CONTENTS.len())?;
+    add_entry(
+        &mut members,
+        &mut toc,
+        landmark.as_bytes(),
+        TocEntry {
+            name: NO_PREFETCH_LANDMARK.to_string(),
+            kind: "reg".to_string(),
+            mode: 0o644,
+            ..Default::default()
+        },
+        LANDMARK_CONTENTS,
+        &vec![0; padding(LANDMARK_CONTENTS.len())],
+    )?;
+
+    // Copy each entry's header and contents as they are, dropping the
+    // end-of-archive blocks, which go after the TOC
+    let mut archive = tar::Archive::new(tar_data);
+    let mut header_start = 0;
+    for entry in archive
+        .entries()
+        .context("Failed to read the layer tarball")?
+    {
+        let entry = entry.context("Failed to read the layer tarball")?;
+        let contents_start = entry.raw_file_position() as usize;
+        let contents_end = contents_start + entry.size() as usize;
+        let next = contents_end + padding(entry.size() as usize);
+        let header = entry.header();
+        let toc_entry = TocEntry {
+            name: clean_name(&entry.path()?.to_string_lossy()),
+            kind: entry_kind(header.entry_type())?.to_string(),
+            mod_time: modtime(header.mtime()?),
+            link_name: entry
+                .link_name()?
+                .map(|link| link.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            mode: header.mode()?,
+            uid: header.uid()?,
+            gid: header.gid()?,
+            user_name: header.username().ok().flatten().unwrap_or("").to_string(),
+            group_name: header.groupname().ok().flatten().unwrap_or("").to_string(),
+            ..Default::default()
+        };
+        add_entry(
+            &mut members,
+            &mut toc,
+            &tar_data[header_start..contents_start],
+            toc_entry,
+            &tar_data[contents_start..contents_end],
+            &tar_data[contents_end..next],
+        )?;
+        header_start = next;
+    }
+
+    // The TOC and the end of the archive, in a member of their own
+    let toc_offset = members.close()?;
+    let toc_json = serde_json::to_vec(&Toc {
+        version: 1,
+        entries: toc,
+    })?;
+    members.write(regular_header(TOC_NAME, toc_json.len())?.as_bytes())?;
+    members.write(&toc_json)?;
+    members.write(&vec![0; padding(toc_json.len()) + 1024])?;
+    members.close()?;
+    members.compressed.extend(footer(toc_offset));
+
+    let annotations = BTreeMap::from([
+        (
+            TOC_DIGEST_ANNOTATION.to_string(),
+            format!("sha256:{}", digest(toc_json.as_slice())),
+        ),
+        (
+            UNCOMPRESSED_SIZE_ANNOTATION.to_string(),
+            members.uncompressed.len().to_string(),
+        ),
+    ]);
+    Ok(Estargz {
+        diff_id: format!("sha256:{}", digest(members.uncompressed.as_slice())),
+        data: members.compressed,
+        annotations,
+    })
+}
+
+/// Write a tar entry and record it in the TOC. A regular file's contents
+/// start a new member, and each chunk after the first starts another, so
+/// they can be decompressed alone.
+fn add_entry(
+    members: &mut Members,
+    toc: &mut Vec<TocEntry>,
+    header: &[u8],
+    mut entry: TocEntry,
+    contents: &[u8],
+    padding: &[u8],
+) -> Result<()> {
+    members.write(header)?;
+    if entry.kind != "reg" || contents.is_empty() {
+        toc.push(entry);
+        members.write(contents)?;
+        return members.write(padding);
+    }
+
+    entry.size = contents.len() as u64;
+    entry.digest = format!("sha256:{}", digest(contents));
+    let name = entry.name.clone();
+    let mut first = Some(entry);
+    for (i, chunk) in contents.chunks(CHUNK_SIZE).enumerate() {
+        let mut chunk_entry = first.take().unwrap_or_else(|| TocEntry {
+            name: name.clone(),
+            kind: "chunk".to_string(),
+            ..Default::default()
+        });
+        chunk_entry.offset = members.close()?;
+        chunk_entry.chunk_offset = (i * CHUNK_SIZE) as u64;
+        if chunk.len() == CHUNK_SIZE {
+            chunk_entry.chunk_size = CHUNK_SIZE as u64;
+        }
+        chunk_entry.chunk_digest = format!("sha256:{}", digest(chunk));
+        toc.push(chunk_entry);
+        members.write(chunk)?;
+    }
+    members.write(padding)
+}
+
+/// Header of a regular file krust adds to the tarball
+fn regular_header(name: &str, size: usize) -> Result<tar::Header> {
+    let mut header = tar::Header::new_gnu();
+    header.set_path(name)?;
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(size as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_cksum();
+    Ok(header)
+}
+
+/// The footer: a gzip member with no data whose extra field holds the TOC
+/// offset, in hex, followed by `STARGZ`
+fn footer(toc_offset: u64) -> Vec<u8> {
+    let payload = format!("{:016x}STARGZ", toc_offset);
+    let mut footer = Vec::with_capacity(FOOTER_SIZE);
+    // Magic, deflate, FEXTRA, no mtime, no extra flags, unknown OS
+    footer.extend([0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff]);
+    footer.extend(((payload.len() + 4) as u16).to_le_bytes());
+    footer.extend(b"SG");
+    footer.extend((payload.len() as u16).to_le_bytes());
+    footer.extend(payload.as_bytes());
+    // An empty final stored block, then the CRC and size of no data
+    footer.extend([0x01, 0x00, 0x00, 0xff, 0xff]);
+    footer.extend([0; 8]);
+    footer
+}
+
+/// Bytes of padding after `len` bytes of contents, up to a tar block
+fn padding(len: usize) -> usize {
+    len.div_ceil(512) * 512 - len
+}
+
+/// The entry's path as the TOC records it, without `./` or slashes around it
+fn clean_name(path: &str) -> String {
+    path.trim_start_matches("./").trim_matches('/').to_string()
+}
+
+fn entry_kind(entry_type: tar::EntryType) -> Result<&'static str> {
+    Ok(match entry_type {
+        tar::EntryType::Regular | tar::EntryType::Continuous => "reg",
+        tar::EntryType::Directory => "dir",
+        tar::EntryType::Symlink => "symlink",
+        tar::EntryType::Link => "hardlink",
+        tar::EntryType::Char => "char",
+        tar::EntryType::Block => "block",
+        tar::EntryType::Fifo => "fifo",
+        other => bail!("Unsupported tar entry type for eStargz: {:?}", other),
+    })
+}
+
+/// The TOC's modification time, omitted for the epoch
+fn modtime(mtime: u64) -> Option<String> {
+    if mtime == 0 {
+        return None;
+    }
+    chrono::DateTime::from_timestamp(mtime as i64, 0)
+        .map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn sample_tar(large: &[u8]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut dir = tar::Header::new_gnu();
+        dir.set_path("app/").unwrap();
+        dir.set_entry_type(tar::EntryType::Directory);
+        dir.set_mode(0o755);
+        dir.set_mtime(1609459200);
+        dir.set_uid(0);
+        dir.set_gid(0);
+        dir.set_size(0);
+        dir.set_cksum();
+        builder.append(&dir, std::io::empty()).unwrap();
+        for (path, data) in [("app/small", b"hello".as_slice()), ("app/large", large)] {
+            let mut file = tar::Header::new_gnu();
+            file.set_path(path).unwrap();
+            file.set_mode(0o755);
+            file.set_uid(1000);
+            file.set_gid(1000);
+            file.set_mtime(0);
+            file.set_size(data.len() as u64);
+            file.set_cksum();
+            builder.append(&file, data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// Decompress the single gzip member at `offset`
+    fn member_at(data: &[u8], offset: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        flate2::read::GzDecoder::new(&data[offset as usize..])
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    fn toc(layer: &Estargz) -> (Toc, Vec<u8>) {
+        let footer = &layer.data[layer.data.len() - FOOTER_SIZE..];
+        assert_eq!(&footer[12..14], b"SG");
+        assert_eq!(&footer[32..38], b"STARGZ");
+        let offset = std::str::from_utf8(&footer[16..32]).unwrap();
+        let offset = u64::from_str_radix(offset, 16).unwrap();
+
+        let mut archive = tar::Archive::new(std::io::Cursor::new(member_at(&layer.data, offset)));
+        let mut entry = archive.entries().unwrap().next().unwrap().unwrap();
+        assert_eq!(entry.path().unwrap().to_str(), Some(TOC_NAME));
+        let mut json = Vec::new();
+        entry.read_to_end(&mut json).unwrap();
+        (serde_json::from_slice(&json).unwrap(), json)
+    }
+
+    #[test]
+    fn test_build_is_a_gzipped_tarball() {
+        let large: Vec<u8> = (0..CHUNK_SIZE * 2 + 5).map(|i| (i % 251) as u8).collect();
+        let layer = build(&sample_tar(&large), 6).unwrap();
+
+        let mut uncompressed = Vec::new();
+        flate2::read::MultiGzDecoder::new(layer.data.as_slice())
+            .read_to_end(&mut uncompressed)
+            .unwrap();
+        assert_eq!(layer.diff_id, format!("sha256:{}", digest(&uncompressed)));
+        assert_eq!(
+            layer.annotations[UNCOMPRESSED_SIZE_ANNOTATION],
+            uncompressed.len().to_string()
+        );
+
+        let mut archive = tar::Archive::new(uncompressed.as_slice());
+        let paths: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                NO_PREFETCH_LANDMARK,
+                "app/",
+                "app/small",
+                "app/large",
+                TOC_NAME
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_toc() {
+        let large: Vec<u8> = (0..CHUNK_SIZE * 2 + 5).map(|i| (i % 251) as u8).collect();
+        let layer = build(&sample_tar(&large), 6).unwrap();
+        let (toc, json) = toc(&layer);
+        assert_eq!(
+            layer.annotations[TOC_DIGEST_ANNOTATION],
+            format!("sha256:{}", digest(json.as_slice()))
+        );
+
+        let kinds: Vec<(&str, &str)> = toc
+            .entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.kind.as_str()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (NO_PREFETCH_LANDMARK, "reg"),
+                ("app", "dir"),
+                ("app/small", "reg"),
+                ("app/large", "reg"),
+                ("app/large", "chunk"),
+                ("app/large", "chunk"),
+            ]
+        );
+        assert_eq!(
+            toc.entries[1].mod_time.as_deref(),
+            Some("2021-01-01T00:00:00Z")
+        );
+        assert_eq!(toc.entries[3].uid, 1000);
+        assert_eq!(toc.entries[3].size, large.len() as u64);
+        assert_eq!(
+            toc.entries[3].digest,
+            format!("sha256:{}", digest(large.as_slice()))
+        );
+
+        // Each chunk decompresses alone from its offset
+        let small = &toc.entries[2];
+        assert_eq!(&member_at(&layer.data, small.offset)[..5], b"hello");
+        for (i, (entry, chunk)) in toc.entries[3..]
+            .iter()
+            .zip(large.chunks(CHUNK_SIZE))
+            .enumerate()
+        {
+            let data = member_at(&layer.data, entry.offset);
+            assert_eq!(&data[..chunk.len()], chunk);
+            assert_eq!(entry.chunk_offset as usize, i * CHUNK_SIZE);
+            assert_eq!(entry.chunk_digest, format!("sha256:{}", digest(chunk)));
+        }
+        assert_eq!(toc.entries[3].chunk_size, CHUNK_SIZE as u64);
+        assert_eq!(toc.entries[5].chunk_size, 0);
+    }
+
+    #[test]
+    fn test_build_is_reproducible() {
+        let tar = sample_tar(b"data");
+        let first = build(&tar, 6).unwrap();
+        let second = build(&tar, 6).unwrap();
+        assert_eq!(first.data, second.data);
+        assert_eq!(first.diff_id, second.diff_id);
+    }
+}
@@ -0,0 +1,357 @@
+//! Base images read from local files instead of a registry
+//!
+//! `base-image = "oci-layout:DIR"` builds on an image in an OCI image layout
+//! and `base-image = "docker-archive:FILE"` on one in a `docker save`
+//! tarball, so layered images can be built without network access.
+
+use super::ImageConfig;
+use crate::layout::OciLayout;
+use crate::manifest::Platform;
+use crate::registry::{is_index_media_type, media_types, OciDescriptor, OciImageManifest};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const LAYOUT_PREFIX: &str = "oci-layout:";
+const DOCKER_ARCHIVE_PREFIX: &str = "docker-archive:";
+
+/// A base image stored on disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocalImage {
+    /// Every image in an OCI image layout's `index.json`
+    Layout(PathBuf),
+    /// Every image in a docker-archive's `manifest.json`
+    DockerArchive(PathBuf),
+}
+
+impl fmt::Display for LocalImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocalImage::Layout(path) => write!(f, "{}{}", LAYOUT_PREFIX, path.display()),
+            LocalImage::DockerArchive(path) => {
+                write!(f, "{}{}", DOCKER_ARCHIVE_PREFIX, path.display())
+            }
+        }
+    }
+}
+
+/// An entry of a docker-archive's `manifest.json`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ArchiveEntry {
+    config: String,
+    layers: Vec<String>,
+}
+
+impl LocalImage {
+    /// The local image a base image reference names, if it's one
+    pub fn parse(reference: &str) -> Option<Self> {
+        if let Some(path) = reference.strip_prefix(LAYOUT_PREFIX) {
+            Some(LocalImage::Layout(PathBuf::from(path)))
+        } else {
+            reference
+                .strip_prefix(DOCKER_ARCHIVE_PREFIX)
+                .map(|path| LocalImage::DockerArchive(PathBuf::from(path)))
+        }
+    }
+
+    /// The same image with a relative path resolved against `dir`
+    pub fn relative_to(&self, dir: &Path) -> Self {
+        match self {
+            LocalImage::Layout(path) => LocalImage::Layout(dir.join(path)),
+            LocalImage::DockerArchive(path) => LocalImage::DockerArchive(dir.join(path)),
+        }
+    }
+
+    /// The platforms of the images stored here
+    pub fn platforms(&self) -> Result<Vec<Platform>> {
+        Ok(self
+            .images()?
+            .iter()
+            .map(|(_, config)| config_platform(config))
+            .collect())
+    }
+
+    /// The manifest and config of the image for `platform`
+    pub fn image_data(&self, platform: &Platform) -> Result<(OciImageManifest, ImageConfig)> {
+        self.images()?
+            .into_iter()
+            .find(|(_, config)| platform.matches(&config_platform(config)))
+            .with_context(|| format!("No image for platform {} in {}", platform, self))
+    }
+
+    /// Read a config or layer blob by digest
+    pub fn read_blob(&self, digest: &str) -> Result<Vec<u8>> {
+        match self {
+            LocalImage::Layout(path) => OciLayout::open(path)?.read_blob(digest),
+            LocalImage::DockerArchive(path) => {
+                // Paths in a docker-archive aren't always named by digest
+                let mut archive = tar::Archive::new(open(path)?);
+                for entry in archive.entries()? {
+                    let mut entry = entry?;
+                    if !entry.header().entry_type().is_file() {
+                        continue;
+                    }
+                    let mut data = Vec::new();
+                    entry.read_to_end(&mut data)?;
+                    if format!("sha256:{}", sha256::digest(data.as_slice())) == digest {
+                        return Ok(data);
+                    }
+                }
+                bail!("Blob {} not found in {}", digest, self)
+            }
+        }
+    }
+
+    /// Every image stored here, with its manifest and config
+    fn images(&self) -> Result<Vec<(OciImageManifest, ImageConfig)>> {
+        let images = match self {
+            LocalImage::Layout(path) => layout_images(&OciLayout::open(path)?)?,
+            LocalImage::DockerArchive(path) => archive_images(path)?,
+        };
+        if images.is_empty() {
+            bail!("No images found in {}", self);
+        }
+        Ok(images)
+    }
+}
+
+fn config_platform(config: &ImageConfig) -> Platform {
+    Platform::new(&config.os, &config.architecture, config.variant.as_deref())
+}
+
+fn open(path: &Path) -> Result<std::fs::File> {
+    std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))
+}
+
+/// The image manifests in a layout's `index.json`, including those of the
+/// image indexes it lists
+fn layout_images(layout: &OciLayout) -> Result<Vec<(OciImageManifest, ImageConfig)>> {
+    let mut entries = std::collections::VecDeque::from(layout.read_index()?.manifests);
+    let mut images = Vec::new();
+    while let Some(entry) = entries.pop_front() {
+        let data = layout.read_blob(&entry.digest)?;
+        if is_index_media_type(&entry.media_type) {
+            let index: crate::registry::OciImageIndex =
+                serde_json::from_slice(&data).context("Failed to parse image index")?;
+            entries.extend(index.manifests);
+            continue;
+        }
+        let manifest: OciImageManifest =
+            serde_json::from_slice(&data).context("Failed to parse image manifest")?;
+        let config = manifest
+            .config
+            .as_ref()
+            .context("Manifest has no config descriptor")?;
+        let config = serde_json::from_slice(&layout.read_blob(&config.digest)?)
+            .context("Failed to parse image config")?;
+        images.push((manifest, config));
+    }
+    Ok(images)
+}
+
+/// The images in a docker-archive, described by Docker manifests over the
+/// archive's files
+fn archive_images(path: &Path) -> Result<Vec<(OciImageManifest, ImageConfig)>> {
+    // Contents of every file, by path
+    let mut files = std::collections::HashMap::new();
+    let mut archive = tar::Archive::new(open(path)?);
+    let mut entries = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry
+            .path()?
+            .to_string_lossy()
+            .trim_start_matches("./")
+            .to_string();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        if name == "manifest.json" {
+            let parsed: Vec<ArchiveEntry> = serde_json::from_slice(&data)
+                .with_context(|| format!("Failed to parse manifest.json in {}", path.display()))?;
+            entries = Some(parsed);
+        }
+        files.insert(name, data);
+    }
+    let entries = entries.with_context(|| {
+        format!(
+            "{} is not a docker-archive (no manifest.json)",
+            path.display()
+        )
+    })?;
+
+    let descriptor = |name: &str, media_type: &str| -> Result<OciDescriptor> {
+        let data = files
+            .get(name)
+            .with_context(|| format!("{} not found in {}", name, path.display()))?;
+        Ok(OciDescriptor {
+            media_type: media_type.to_string(),
+            digest: format!("sha256:{}", sha256::digest(data.as_slice())),
+            size: data.len() as i64,
+            urls: None,
+            annotations: None,
+        })
+    };
+
+    let mut images = Vec::new();
+    for entry in entries {
+        let config_descriptor = descriptor(&entry.config, media_types::DOCKER_CONFIG)?;
+        let config: ImageConfig = serde_json::from_slice(&files[&entry.config])
+            .context("Failed to parse image config")?;
+        let layers = entry
+            .layers
+            .iter()
+            .map(|layer| {
+                let data = files.get(layer).map(Vec::as_slice).unwrap_or_default();
+                descriptor(layer, layer_media_type(data))
+            })
+            .collect::<Result<_>>()?;
+        images.push((
+            OciImageManifest {
+                schema_version: 2,
+                media_type: media_types::DOCKER_MANIFEST.to_string(),
+                artifact_type: None,
+                config: Some(config_descriptor),
+                layers,
+                subject: None,
+                annotations: None,
+            },
+            config,
+        ));
+    }
+    Ok(images)
+}
+
+/// Media type of a docker-archive layer, which `docker save` leaves
+/// uncompressed but other tools may compress
+fn layer_media_type(data: &[u8]) -> &'static str {
+    if data.starts_with(&[0x1f, 0x8b]) {
+        media_types::DOCKER_LAYER_GZIP
+    } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        media_types::OCI_LAYER_ZSTD
+    } else {
+        media_types::DOCKER_LAYER
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::publish::archive::ArchiveImage;
+
+    fn config(architecture: &str) -> Vec<u8> {
+        let mut config = ImageConfig::scratch(&Platform::new("linux", architecture, None));
+        config.rootfs.diff_ids.push("sha256:abc".to_string());
+        serde_json::to_vec(&config).unwrap()
+    }
+
+    #[test]
+    fn test_parse_local_image() {
+        assert_eq!(
+            LocalImage::parse("oci-layout:./bases/static"),
+            Some(LocalImage::Layout(PathBuf::from("./bases/static")))
+        );
+        assert_eq!(
+            LocalImage::parse("docker-archive:base.tar"),
+            Some(LocalImage::DockerArchive(PathBuf::from("base.tar")))
+        );
+        assert_eq!(LocalImage::parse("gcr.io/distroless/static"), None);
+        assert_eq!(
+            LocalImage::parse("oci-layout:bases")
+                .unwrap()
+                .relative_to(Path::new("/project"))
+                .to_string(),
+            "oci-layout:/project/bases"
+        );
+    }
+
+    #[test]
+    fn test_docker_archive_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("base.tar");
+        let layer = b"uncompressed layer".to_vec();
+        let image = ArchiveImage {
+            config_data: config("arm64"),
+            layers: vec![layer.clone()],
+        };
+        std::fs::write(&path, image.to_docker_archive(&[]).unwrap()).unwrap();
+
+        let base = LocalImage::DockerArchive(path);
+        assert_eq!(
+            base.platforms().unwrap(),
+            vec![Platform::new("linux", "arm64", None)]
+        );
+        let (manifest, config) = base
+            .image_data(&Platform::new("linux", "arm64", None))
+            .unwrap();
+        assert_eq!(config.rootfs.diff_ids, ["sha256:abc"]);
+        assert_eq!(manifest.layers.len(), 1);
+        assert_eq!(manifest.layers[0].media_type, media_types::DOCKER_LAYER);
+        assert_eq!(base.read_blob(&manifest.layers[0].digest).unwrap(), layer);
+        assert!(base
+            .image_data(&Platform::new("linux", "amd64", None))
+            .is_err());
+    }
+
+    #[test]
+    fn test_layout_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let mut entries = Vec::new();
+        for architecture in ["amd64", "arm64"] {
+            let config = layout.write_blob(&config(architecture)).unwrap();
+            let layer = layout.write_blob(architecture.as_bytes()).unwrap();
+            let manifest = serde_json::json!({
+                "schemaVersion": 2,
+                "mediaType": media_types::OCI_MANIFEST,
+                "config": {"mediaType": media_types::OCI_CONFIG, "digest": config, "size": 1},
+                "layers": [{"mediaType": media_types::OCI_LAYER_GZIP, "digest": layer, "size": 5}],
+            });
+            let data = serde_json::to_vec(&manifest).unwrap();
+            entries.push(serde_json::json!({
+                "mediaType": media_types::OCI_MANIFEST,
+                "digest": layout.write_blob(&data).unwrap(),
+                "size": data.len(),
+            }));
+        }
+        let index = serde_json::to_vec(&serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": media_types::OCI_INDEX,
+            "manifests": entries,
+        }))
+        .unwrap();
+        layout
+            .add_to_index(
+                crate::registry::ImageIndexEntry {
+                    media_type: media_types::OCI_INDEX.to_string(),
+                    digest: layout.write_blob(&index).unwrap(),
+                    size: index.len() as i64,
+                    platform: None,
+                    annotations: None,
+                },
+                None,
+            )
+            .unwrap();
+
+        let base = LocalImage::parse(&format!("oci-layout:{}", dir.path().display())).unwrap();
+        assert_eq!(
+            base.platforms().unwrap(),
+            vec![
+                Platform::new("linux", "amd64", None),
+                Platform::new("linux", "arm64", None)
+            ]
+        );
+        let (manifest, _) = base
+            .image_data(&Platform::new("linux", "arm64", None))
+            .unwrap();
+        assert_eq!(
+            base.read_blob(&manifest.layers[0].digest).unwrap(),
+            b"arm64"
+        );
+    }
+}
@@ -1,14 +1,35 @@
-use crate::registry::RegistryAuth;
+use crate::config::{FileOwnership, Include, LayerCompression, MediaTypeFamily};
+use crate::manifest::Platform;
+use crate::registry::{ImageReference, RegistryAuth};
 use anyhow::{Context, Result};
-use flate2::write::GzEncoder;
-use flate2::Compression;
 use serde::{Deserialize, Serialize};
 use sha256::digest;
-use std::fs::File;
-use std::io::Write;
+use std::collections::BTreeMap;
+use std::fs::{File, Metadata};
 use tar::Builder;
 use tracing::{debug, info};
 
+pub mod ca_certs;
+pub mod compress;
+pub mod estargz;
+pub mod local;
+
+/// Label recording when the image was built
+const CREATED_LABEL: &str = "org.opencontainers.image.created";
+
+/// Base image name meaning no base image at all, as in a Dockerfile's
+/// `FROM scratch`
+pub const SCRATCH: &str = "scratch";
+
+/// Annotations naming the base image and the digest it was resolved to
+const BASE_NAME_ANNOTATION: &str = "org.opencontainers.image.base.name";
+const BASE_DIGEST_ANNOTATION: &str = "org.opencontainers.image.base.digest";
+
+/// Whether `base_image` is scratch, which is never fetched
+pub fn is_scratch(base_image: &str) -> bool {
+    base_image == SCRATCH
+}
+
 /// Get the timestamp to use for reproducible builds.
 /// Respects SOURCE_DATE_EPOCH environment variable if set.
 fn get_build_timestamp() -> String {
@@ -22,28 +43,89 @@ fn get_build_timestamp() -> String {
     chrono::Utc::now().to_rfc3339()
 }
 
+/// Modification time of every entry in the application layer:
+/// SOURCE_DATE_EPOCH when set, otherwise the Unix epoch. Never the binary's
+/// own mtime, so rebuilding unchanged sources reproduces the layer.
+fn layer_mtime() -> u64 {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or(0)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageConfig {
     pub architecture: String,
     pub os: String,
+    /// CPU variant, e.g. `v7` for `linux/arm/v7`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
     pub config: Config,
     pub rootfs: RootFs,
     #[serde(default)]
     pub history: Vec<History>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl ImageConfig {
+    /// The config of the empty scratch image for `platform`: no layers, no
+    /// history and no settings to inherit
+    pub fn scratch(platform: &Platform) -> Self {
+        Self {
+            architecture: platform.architecture.clone(),
+            os: platform.os.clone(),
+            variant: platform.variant.clone(),
+            created: None,
+            config: Config::default(),
+            rootfs: RootFs {
+                fs_type: "layers".to_string(),
+                diff_ids: Vec::new(),
+            },
+            history: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
     #[serde(rename = "Env", default)]
     pub env: Vec<String>,
+    #[serde(
+        rename = "Entrypoint",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub entrypoint: Option<Vec<String>>,
     #[serde(rename = "Cmd")]
     pub cmd: Option<Vec<String>>,
     #[serde(rename = "WorkingDir", default)]
     pub working_dir: String,
     #[serde(rename = "User", default)]
     pub user: String,
+    #[serde(rename = "Labels", default, skip_serializing_if = "Option::is_none")]
+    pub labels: Option<BTreeMap<String, String>>,
+    #[serde(
+        rename = "ExposedPorts",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub exposed_ports: Option<BTreeMap<String, EmptyObject>>,
+    #[serde(rename = "Volumes", default, skip_serializing_if = "Option::is_none")]
+    pub volumes: Option<BTreeMap<String, EmptyObject>>,
+    #[serde(
+        rename = "StopSignal",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub stop_signal: Option<String>,
 }
 
+/// The `{}` values of `ExposedPorts` and `Volumes`, which are sets encoded as
+/// JSON objects
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmptyObject {}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RootFs {
     #[serde(rename = "type")]
@@ -69,6 +151,8 @@ pub struct Manifest {
     pub media_type: String,
     pub config: Descriptor,
     pub layers: Vec<Descriptor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -77,147 +161,511 @@ pub struct Descriptor {
     pub media_type: String,
     pub size: i64,
     pub digest: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, String>>,
 }
 
-/// Parse a platform string like "linux/amd64" or "linux/arm/v7" into (os, arch, variant).
-pub fn parse_platform_string(platform: &str) -> Result<(String, String, Option<String>)> {
-    let parts: Vec<&str> = platform.split('/').collect();
-    match parts.len() {
-        2 => Ok((parts[0].to_string(), parts[1].to_string(), None)),
-        3 => Ok((
-            parts[0].to_string(),
-            parts[1].to_string(),
-            Some(parts[2].to_string()),
-        )),
-        _ => anyhow::bail!("Invalid platform format: {}", platform),
-    }
+/// A layer krust built
+struct Layer {
+    /// The compressed layer
+    data: Vec<u8>,
+    /// Digest of the uncompressed layer
+    diff_id: String,
+    /// Annotations for the layer's descriptor, e.g. an eStargz layer's TOC
+    /// digest
+    annotations: Option<BTreeMap<String, String>>,
 }
 
 pub struct ImageBuilder {
     binary_path: PathBuf,
     #[allow(dead_code)]
     base_image: String,
-    platform: String,
+    platform: Platform,
+    ownership: FileOwnership,
+    entrypoint: Option<Vec<String>>,
+    args: Vec<String>,
+    working_dir: Option<String>,
+    labels: BTreeMap<String, String>,
+    env: BTreeMap<String, String>,
+    ports: Vec<String>,
+    volumes: Vec<String>,
+    stop_signal: Option<String>,
+    media_types: MediaTypeFamily,
+    compression: LayerCompression,
+    base_digest: Option<String>,
+    binaries: Vec<PathBuf>,
+    includes: Vec<Include>,
+    include_layer: bool,
+    include_ca_certs: bool,
 }
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// An entry in a layer krust writes
+#[derive(Debug, Clone, PartialEq)]
+enum LayerEntry {
+    /// A file copied from `source`, with permission bits `mode`
+    File { source: PathBuf, mode: u32 },
+    /// A directory, owned by root or else like the files
+    Dir { root_owned: bool },
+    /// A file krust supplies, owned by root and readable by everyone
+    Contents(&'static [u8]),
+}
 
 impl ImageBuilder {
-    pub fn new(binary_path: PathBuf, base_image: String, platform: String) -> Self {
+    pub fn new(binary_path: PathBuf, base_image: String, platform: Platform) -> Self {
         Self {
             binary_path,
             base_image,
             platform,
+            ownership: FileOwnership::default(),
+            entrypoint: None,
+            args: Vec::new(),
+            working_dir: None,
+            labels: BTreeMap::new(),
+            env: BTreeMap::new(),
+            ports: Vec::new(),
+            volumes: Vec::new(),
+            stop_signal: None,
+            media_types: MediaTypeFamily::default(),
+            compression: LayerCompression::default(),
+            base_digest: None,
+            binaries: Vec::new(),
+            includes: Vec::new(),
+            include_layer: false,
+            include_ca_certs: false,
         }
     }
 
+    /// Set the owner of files added to the application layer
+    pub fn with_ownership(mut self, ownership: FileOwnership) -> Self {
+        self.ownership = ownership;
+        self
+    }
+
+    /// Set the image's ENTRYPOINT instead of running the binary directly
+    pub fn with_entrypoint(mut self, entrypoint: Option<Vec<String>>) -> Self {
+        self.entrypoint = entrypoint;
+        self
+    }
+
+    /// Set the default arguments (CMD) passed to the entrypoint
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// Set the working directory instead of keeping the base image's
+    pub fn with_working_dir(mut self, working_dir: Option<String>) -> Self {
+        self.working_dir = working_dir;
+        self
+    }
+
+    /// Set labels on the image config, also written as manifest annotations
+    pub fn with_labels(mut self, labels: BTreeMap<String, String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Set environment variables in the image, overriding the base image's
+    pub fn with_env(mut self, env: BTreeMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Expose ports, as `PORT` or `PORT/PROTOCOL` (tcp when omitted), in
+    /// addition to the base image's
+    pub fn with_ports(mut self, ports: Vec<String>) -> Self {
+        self.ports = ports;
+        self
+    }
+
+    /// Declare volumes, as absolute paths, in addition to the base image's
+    pub fn with_volumes(mut self, volumes: Vec<String>) -> Self {
+        self.volumes = volumes;
+        self
+    }
+
+    /// Set the signal that stops the container instead of keeping the base
+    /// image's
+    pub fn with_stop_signal(mut self, stop_signal: Option<String>) -> Self {
+        self.stop_signal = stop_signal;
+        self
+    }
+
+    /// Write the image with OCI or Docker media types
+    pub fn with_media_types(mut self, media_types: MediaTypeFamily) -> Self {
+        self.media_types = media_types;
+        self
+    }
+
+    /// Compress the application layer with `compression`
+    pub fn with_compression(mut self, compression: LayerCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Add more binaries to `/app/`, next to the one that's the entrypoint
+    pub fn with_binaries(mut self, binaries: Vec<PathBuf>) -> Self {
+        self.binaries = binaries;
+        self
+    }
+
+    /// Add local files and directories to the image
+    pub fn with_includes(mut self, includes: Vec<Include>) -> Self {
+        self.includes = includes;
+        self
+    }
+
+    /// Put included files in a layer of their own beneath the application
+    /// layer, instead of in it
+    pub fn with_include_layer(mut self, include_layer: bool) -> Self {
+        self.include_layer = include_layer;
+        self
+    }
+
+    /// Add a layer with a CA certificate bundle beneath krust's other layers,
+    /// and point `SSL_CERT_FILE` at it unless the environment sets it
+    pub fn with_ca_certs(mut self, include_ca_certs: bool) -> Self {
+        self.include_ca_certs = include_ca_certs;
+        self
+    }
+
+    /// Build on the base image's manifest or index with this digest, rather
+    /// than whatever its tag points to when each platform is fetched
+    pub fn with_base_digest(mut self, digest: Option<String>) -> Self {
+        self.base_digest = digest;
+        self
+    }
+
+    /// The reference base image data is fetched from: by digest once the
+    /// base image is resolved
+    fn base_reference(&self) -> Result<String> {
+        match &self.base_digest {
+            Some(digest) => Ok(format!(
+                "{}@{}",
+                ImageReference::parse(&self.base_image)?.repository_url(),
+                digest
+            )),
+            None => Ok(self.base_image.clone()),
+        }
+    }
+
+    /// Manifest annotations: the labels, and the base image's name and
+    /// digest once it's resolved
+    fn manifest_annotations(&self, created: &str) -> BTreeMap<String, String> {
+        let mut annotations = self.image_labels(created);
+        if let Some(digest) = &self.base_digest {
+            annotations.insert(BASE_NAME_ANNOTATION.to_string(), self.base_image.clone());
+            annotations.insert(BASE_DIGEST_ANNOTATION.to_string(), digest.clone());
+        }
+        annotations
+    }
+
+    /// Media type of the compressed application layer
+    fn layer_media_type(&self) -> Result<&'static str> {
+        match (self.compression, self.media_types) {
+            (LayerCompression::Gzip(_) | LayerCompression::Estargz(_), media_types) => {
+                Ok(media_types.layer_gzip())
+            }
+            (LayerCompression::Zstd(_), MediaTypeFamily::Oci) => {
+                Ok(crate::registry::media_types::OCI_LAYER_ZSTD)
+            }
+            (LayerCompression::Zstd(_), MediaTypeFamily::Docker) => {
+                anyhow::bail!(
+                    "zstd compression needs OCI media types, but Docker media types were requested"
+                )
+            }
+        }
+    }
+
+    /// The configured labels, plus `org.opencontainers.image.created` when
+    /// they don't set it
+    fn image_labels(&self, created: &str) -> BTreeMap<String, String> {
+        let mut labels = self.labels.clone();
+        labels
+            .entry(CREATED_LABEL.to_string())
+            .or_insert_with(|| created.to_string());
+        labels
+    }
+
+    /// Build the image on its base. Returns the config, the layers krust
+    /// added (the last entries in the manifest's layers, the application
+    /// layer last) and the manifest.
     pub async fn build(
         &self,
         registry_client: &mut crate::registry::RegistryClient,
         auth: &RegistryAuth,
-    ) -> Result<(Vec<u8>, Vec<u8>, Manifest)> {
+    ) -> Result<(Vec<u8>, Vec<Vec<u8>>, Manifest)> {
         info!("Building container image");
 
-        let (_os, _arch, _variant) = self.parse_platform()?;
-
         // Fetch base image data
-        info!(
-            "Fetching base image: {} for platform: {}",
-            self.base_image, self.platform
-        );
-        let (base_manifest, base_config) = registry_client
-            .fetch_image_data(&self.base_image, &self.platform, auth)
-            .await
-            .context("Failed to fetch base image data")?;
+        let (base_layers, base_config) = if is_scratch(&self.base_image) {
+            info!("Building on scratch for platform: {}", self.platform);
+            (Vec::new(), ImageConfig::scratch(&self.platform))
+        } else {
+            let base_reference = self.base_reference()?;
+            info!(
+                "Fetching base image: {} for platform: {}",
+                base_reference, self.platform
+            );
+            let (base_manifest, base_config) = registry_client
+                .fetch_image_data(&base_reference, &self.platform, auth)
+                .await
+                .context("Failed to fetch base image data")?;
+            (base_manifest.layers, base_config)
+        };
 
-        // Create application layer
-        let (app_layer_data, app_diff_id) = self.create_layer()?;
-        let app_layer_digest = format!("sha256:{}", digest(&app_layer_data));
-        let app_layer_size = app_layer_data.len() as i64;
+        // Create the included files' and application layers
+        let layer_media_type = self.layer_media_type()?;
+        let layers = self.create_layers()?;
 
-        // Combine base image layers with application layer
+        // Combine base image layers with ours
         let mut all_layers = Vec::new();
-        for layer in &base_manifest.layers {
+        for layer in &base_layers {
             all_layers.push(Descriptor {
                 media_type: layer.media_type.clone(),
                 size: layer.size,
                 digest: layer.digest.clone(),
+                annotations: layer.annotations.clone(),
+            });
+        }
+        for layer in &layers {
+            all_layers.push(Descriptor {
+                media_type: layer_media_type.to_string(),
+                size: layer.data.len() as i64,
+                digest: format!("sha256:{}", digest(&layer.data)),
+                annotations: layer.annotations.clone(),
             });
         }
-
-        // Add the application layer
-        all_layers.push(Descriptor {
-            media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
-            size: app_layer_size,
-            digest: app_layer_digest,
-        });
 
         // Create merged config
-        let config = self.create_layered_config(&base_config, &app_diff_id)?;
-        let config_data = serde_json::to_vec_pretty(&config)?;
+        let diff_ids: Vec<&str> = layers.iter().map(|l| l.diff_id.as_str()).collect();
+        let config = self.create_layered_config(&base_config, &diff_ids)?;
+        let config_data =
+            crate::registry::encoding::to_json(&config, registry_client.json_format())?;
         let config_digest = format!("sha256:{}", digest(&config_data));
         let config_size = config_data.len() as i64;
 
-        // Create manifest
+        // Create manifest. Base layers keep their media types here and are
+        // converted to match when the manifest is pushed or written.
         let manifest = Manifest {
             schema_version: 2,
-            media_type: "application/vnd.oci.image.manifest.v1+json".to_string(),
+            media_type: self.media_types.manifest().to_string(),
             config: Descriptor {
-                media_type: "application/vnd.oci.image.config.v1+json".to_string(),
+                media_type: self.media_types.config().to_string(),
                 size: config_size,
                 digest: config_digest,
+                annotations: None,
             },
             layers: all_layers,
+            annotations: config
+                .created
+                .as_deref()
+                .map(|created| self.manifest_annotations(created)),
         };
 
-        Ok((config_data, app_layer_data, manifest))
+        let layer_data = layers.into_iter().map(|layer| layer.data).collect();
+        Ok((config_data, layer_data, manifest))
+    }
+
+    /// Entries of the application layer: `/app/` and the binaries, keyed by
+    /// their path in the layer
+    fn layer_files(&self) -> Result<BTreeMap<String, LayerEntry>> {
+        // /app/ itself belongs to root, like the directories of the base
+        let mut files =
+            BTreeMap::from([("app/".to_string(), LayerEntry::Dir { root_owned: true })]);
+        for binary in std::iter::once(&self.binary_path).chain(&self.binaries) {
+            let binary_name = binary
+                .file_name()
+                .context("Invalid binary path")?
+                .to_str()
+                .context("Invalid UTF-8 in binary name")?;
+            add_layer_entry(
+                &mut files,
+                format!("app/{}", binary_name),
+                LayerEntry::File {
+                    source: binary.clone(),
+                    mode: 0o755,
+                },
+            )?;
+        }
+        Ok(files)
+    }
+
+    /// Entries added with `include`, keyed by their path in the layer
+    fn include_files(&self) -> Result<BTreeMap<String, LayerEntry>> {
+        let mut files = BTreeMap::new();
+        for include in &self.includes {
+            let metadata = std::fs::metadata(&include.src)
+                .with_context(|| format!("Failed to read include {}", include.src.display()))?;
+            let name = include
+                .src
+                .file_name()
+                .and_then(|name| name.to_str())
+                .with_context(|| format!("Invalid include path {}", include.src.display()))?;
+            let dest = match &include.dest {
+                Some(dest) => dest.trim_start_matches('/').to_string(),
+                None => format!("app/{}", name),
+            };
+            if metadata.is_dir() {
+                add_include_dir(
+                    &mut files,
+                    &include.src,
+                    dest.trim_end_matches('/'),
+                    include.mode,
+                )?;
+            } else {
+                let path = if dest.is_empty() || dest.ends_with('/') {
+                    format!("{}{}", dest, name)
+                } else {
+                    dest
+                };
+                let mode = include.mode.unwrap_or_else(|| default_mode(&metadata));
+                add_layer_entry(
+                    &mut files,
+                    path,
+                    LayerEntry::File {
+                        source: include.src.clone(),
+                        mode,
+                    },
+                )?;
+            }
+        }
+        Ok(files)
+    }
+
+    /// Entries of the CA certificates layer: the bundle and its directories
+    fn ca_cert_files() -> BTreeMap<String, LayerEntry> {
+        let root_dir = LayerEntry::Dir { root_owned: true };
+        BTreeMap::from([
+            ("etc/".to_string(), root_dir.clone()),
+            ("etc/ssl/".to_string(), root_dir.clone()),
+            ("etc/ssl/certs/".to_string(), root_dir),
+            (
+                ca_certs::BUNDLE_PATH.trim_start_matches('/').to_string(),
+                LayerEntry::Contents(ca_certs::BUNDLE),
+            ),
+        ])
+    }
+
+    /// Entries of each layer krust adds, bottom first: the CA certificates
+    /// when they're included, the included files when they have a layer of
+    /// their own, then the application layer
+    fn layer_entries(&self) -> Result<Vec<BTreeMap<String, LayerEntry>>> {
+        let app = self.layer_files()?;
+        let included = self.include_files()?;
+        // Included files can't replace the binaries, even from a layer below
+        let mut merged = app.clone();
+        for (path, entry) in included.clone() {
+            add_layer_entry(&mut merged, path, entry)?;
+        }
+        let mut layers = Vec::new();
+        if self.include_ca_certs {
+            layers.push(Self::ca_cert_files());
+        }
+        if self.include_layer && !included.is_empty() {
+            layers.extend([included, app]);
+        } else {
+            layers.push(merged);
+        }
+        Ok(layers)
     }
 
-    fn parse_platform(&self) -> Result<(String, String, Option<String>)> {
-        parse_platform_string(&self.platform)
+    fn create_layers(&self) -> Result<Vec<Layer>> {
+        self.create_layers_at(layer_mtime())
     }
 
-    fn create_layer(&self) -> Result<(Vec<u8>, String)> {
+    /// Build each layer krust adds, with every entry's mtime set to `mtime`.
+    fn create_layers_at(&self, mtime: u64) -> Result<Vec<Layer>> {
         debug!("Creating layer from binary: {:?}", self.binary_path);
+        self.layer_entries()?
+            .iter()
+            .map(|entries| self.create_layer_at(entries, mtime))
+            .collect()
+    }
 
+    /// Build a layer from `entries`. Entries are written in path order, so
+    /// the layer depends only on the files' contents, modes and the
+    /// configured ownership.
+    fn create_layer_at(&self, entries: &BTreeMap<String, LayerEntry>, mtime: u64) -> Result<Layer> {
         let mut tar_data = Vec::new();
         {
             let mut tar = Builder::new(&mut tar_data);
 
-            // Add the binary to /app/
-            let mut file = File::open(&self.binary_path)?;
-            let binary_name = self
-                .binary_path
-                .file_name()
-                .context("Invalid binary path")?
-                .to_str()
-                .context("Invalid UTF-8 in binary name")?;
-
-            let mut header = tar::Header::new_gnu();
-            header.set_path(format!("app/{}", binary_name))?;
-            header.set_size(std::fs::metadata(&self.binary_path)?.len());
-            header.set_mode(0o755);
-            header.set_cksum();
-
-            tar.append(&header, &mut file)?;
+            for (path, entry) in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_path(path)?;
+                header.set_mtime(mtime);
+                let root_owned = matches!(
+                    entry,
+                    LayerEntry::Dir { root_owned: true } | LayerEntry::Contents(_)
+                );
+                if root_owned {
+                    header.set_uid(0);
+                    header.set_gid(0);
+                } else {
+                    header.set_uid(self.ownership.uid);
+                    header.set_gid(self.ownership.gid);
+                    header.set_username(&self.ownership.uname)?;
+                    header.set_groupname(&self.ownership.gname)?;
+                }
+                match entry {
+                    LayerEntry::Dir { .. } => {
+                        header.set_entry_type(tar::EntryType::Directory);
+                        header.set_size(0);
+                        header.set_mode(0o755);
+                        header.set_cksum();
+                        tar.append(&header, std::io::empty())?;
+                    }
+                    LayerEntry::File { source, mode } => {
+                        let mut file = File::open(source)
+                            .with_context(|| format!("Failed to open {}", source.display()))?;
+                        header.set_size(file.metadata()?.len());
+                        header.set_mode(*mode);
+                        header.set_cksum();
+                        tar.append(&header, &mut file)?;
+                    }
+                    LayerEntry::Contents(data) => {
+                        header.set_size(data.len() as u64);
+                        header.set_mode(0o644);
+                        header.set_cksum();
+                        tar.append(&header, *data)?;
+                    }
+                }
+            }
             tar.finish()?;
         }
 
+        // eStargz rewrites the tarball, so its diff ID is of the tarball as
+        // rewritten
+        if let LayerCompression::Estargz(level) = self.compression {
+            let estargz = estargz::build(&tar_data, level)?;
+            return Ok(Layer {
+                data: estargz.data,
+                diff_id: estargz.diff_id,
+                annotations: Some(estargz.annotations),
+            });
+        }
+
         // Calculate diff_id (digest of uncompressed tar)
         let diff_id = format!("sha256:{}", digest(&tar_data));
 
-        // Compress the tar
-        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(&tar_data)?;
-        let compressed = encoder.finish()?;
+        let compressed = compress::compress(&tar_data, self.compression)?;
 
-        Ok((compressed, diff_id))
+        Ok(Layer {
+            data: compressed,
+            diff_id,
+            annotations: None,
+        })
     }
 
+    /// The base image's config with ours applied, and `diff_ids` (the layers
+    /// krust added, the application layer last) on top of its layers
     fn create_layered_config(
         &self,
         base_config: &ImageConfig,
-        app_diff_id: &str,
+        diff_ids: &[&str],
     ) -> Result<ImageConfig> {
         let binary_name = self
             .binary_path
@@ -226,8 +674,29 @@ impl ImageBuilder {
             .to_str()
             .context("Invalid UTF-8 in binary name")?;
 
+        // The binary is the ENTRYPOINT and default arguments are the CMD, so
+        // `docker run <image> <args>` and Kubernetes `args:` replace only the
+        // arguments
+        let entrypoint = self
+            .entrypoint
+            .clone()
+            .unwrap_or_else(|| vec![format!("/app/{}", binary_name)]);
+        let cmd = Some(self.args.clone()).filter(|args| !args.is_empty());
+        let working_dir = match &self.working_dir {
+            Some(dir) if !dir.starts_with('/') => {
+                anyhow::bail!("working-dir must be an absolute path, got {}", dir)
+            }
+            Some(dir) => dir.clone(),
+            None => base_config.config.working_dir.clone(),
+        };
+
         // Merge environment variables (preserve base + add our own)
-        let mut merged_env = base_config.config.env.clone();
+        let mut env = self.env.clone();
+        if self.include_ca_certs {
+            env.entry(ca_certs::SSL_CERT_FILE.to_string())
+                .or_insert_with(|| ca_certs::BUNDLE_PATH.to_string());
+        }
+        let mut merged_env = merge_env(&base_config.config.env, &env);
 
         // Add PATH if not present
         if !merged_env.iter().any(|env| env.starts_with("PATH=")) {
@@ -236,27 +705,75 @@ impl ImageBuilder {
             );
         }
 
-        // Combine diff_ids (base layers + app layer)
+        // Ports and volumes add to the base image's
+        let mut exposed_ports = base_config.config.exposed_ports.clone();
+        for port in &self.ports {
+            exposed_ports
+                .get_or_insert_with(BTreeMap::new)
+                .insert(normalize_port(port)?, EmptyObject {});
+        }
+        let mut volumes = base_config.config.volumes.clone();
+        for volume in &self.volumes {
+            if !volume.starts_with('/') {
+                anyhow::bail!("volumes must be absolute paths, got {}", volume);
+            }
+            volumes
+                .get_or_insert_with(BTreeMap::new)
+                .insert(volume.clone(), EmptyObject {});
+        }
+        let stop_signal = self
+            .stop_signal
+            .clone()
+            .or_else(|| base_config.config.stop_signal.clone());
+
+        // Combine diff_ids (base layers + ours)
         let mut merged_diff_ids = base_config.rootfs.diff_ids.clone();
-        merged_diff_ids.push(app_diff_id.to_string());
+        merged_diff_ids.extend(diff_ids.iter().map(|id| id.to_string()));
 
-        // Combine history (base history + app history)
+        // Combine history (base history + one entry per layer of ours)
+        let created = get_build_timestamp();
         let mut merged_history = base_config.history.clone();
-        merged_history.push(History {
-            created: get_build_timestamp(),
-            created_by: "krust".to_string(),
-            comment: "Built with krust".to_string(),
-            empty_layer: false,
-        });
+        for (i, _) in diff_ids.iter().enumerate() {
+            let comment = if i + 1 == diff_ids.len() {
+                "Built with krust"
+            } else if i == 0 && self.include_ca_certs {
+                "CA certificates added by krust"
+            } else {
+                "Files included by krust"
+            };
+            merged_history.push(History {
+                created: created.clone(),
+                created_by: "krust".to_string(),
+                comment: comment.to_string(),
+                empty_layer: false,
+            });
+        }
+
+        // Base image labels are kept unless ours override them
+        let mut labels = base_config.config.labels.clone().unwrap_or_default();
+        labels.extend(self.image_labels(&created));
 
         Ok(ImageConfig {
             architecture: base_config.architecture.clone(),
             os: base_config.os.clone(),
+            // The variant the image is built for, which base images for
+            // arm64 often leave out
+            variant: self
+                .platform
+                .variant
+                .clone()
+                .or_else(|| base_config.variant.clone()),
+            created: Some(created),
             config: Config {
                 env: merged_env,
-                cmd: Some(vec![format!("/app/{}", binary_name)]),
-                working_dir: base_config.config.working_dir.clone(),
+                entrypoint: Some(entrypoint),
+                cmd,
+                working_dir,
                 user: base_config.config.user.clone(),
+                labels: Some(labels),
+                exposed_ports,
+                volumes,
+                stop_signal,
             },
             rootfs: RootFs {
                 fs_type: "layers".to_string(),
@@ -267,10 +784,132 @@ impl ImageBuilder {
     }
 }
 
+/// Add `entry` at `path`, failing if another file is already there.
+/// Directories may be added more than once; the first is kept.
+fn add_layer_entry(
+    entries: &mut BTreeMap<String, LayerEntry>,
+    path: String,
+    entry: LayerEntry,
+) -> Result<()> {
+    match (entries.get(&path), &entry) {
+        (None, _) => {
+            entries.insert(path, entry);
+        }
+        (Some(LayerEntry::Dir { .. }), LayerEntry::Dir { .. }) => {}
+        _ => anyhow::bail!(
+            "More than one file would be written to /{}",
+            path.trim_end_matches('/')
+        ),
+    }
+    Ok(())
+}
+
+/// Add the contents of the directory `source` under `path` (`""` for the
+/// root), recursively
+fn add_include_dir(
+    entries: &mut BTreeMap<String, LayerEntry>,
+    source: &Path,
+    path: &str,
+    mode: Option<u32>,
+) -> Result<()> {
+    if !path.is_empty() {
+        add_layer_entry(
+            entries,
+            format!("{}/", path),
+            LayerEntry::Dir { root_owned: false },
+        )?;
+    }
+    let dir = std::fs::read_dir(source)
+        .with_context(|| format!("Failed to read include {}", source.display()))?;
+    for child in dir {
+        let child = child?;
+        let name = child
+            .file_name()
+            .into_string()
+            .map_err(|name| anyhow::anyhow!("Invalid UTF-8 in include {:?}", name))?;
+        let child_path = if path.is_empty() {
+            name
+        } else {
+            format!("{}/{}", path, name)
+        };
+        let source = child.path();
+        let metadata = std::fs::metadata(&source)
+            .with_context(|| format!("Failed to read include {}", source.display()))?;
+        if metadata.is_dir() {
+            add_include_dir(entries, &source, &child_path, mode)?;
+        } else {
+            let mode = mode.unwrap_or_else(|| default_mode(&metadata));
+            add_layer_entry(entries, child_path, LayerEntry::File { source, mode })?;
+        }
+    }
+    Ok(())
+}
+
+/// Permission bits of an included file without a configured mode: 0755 if
+/// it's executable, otherwise 0644
+fn default_mode(metadata: &Metadata) -> u32 {
+    if is_executable(metadata) {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &Metadata) -> bool {
+    false
+}
+
+/// `port` as an `ExposedPorts` key, `PORT/PROTOCOL`
+fn normalize_port(port: &str) -> Result<String> {
+    let (number, protocol) = port.split_once('/').unwrap_or((port, "tcp"));
+    let protocol = protocol.to_ascii_lowercase();
+    match number.parse::<u16>() {
+        Ok(n) if n > 0 && matches!(protocol.as_str(), "tcp" | "udp" | "sctp") => {
+            Ok(format!("{}/{}", n, protocol))
+        }
+        _ => anyhow::bail!(
+            "Invalid port {}, expected PORT or PORT/PROTOCOL (tcp, udp or sctp)",
+            port
+        ),
+    }
+}
+
+/// `base` `KEY=VALUE` entries with `overrides` applied: a variable the base
+/// already sets keeps its position with the new value, and new variables
+/// follow in name order
+fn merge_env(base: &[String], overrides: &BTreeMap<String, String>) -> Vec<String> {
+    let mut remaining = overrides.clone();
+    let mut merged: Vec<String> = base
+        .iter()
+        .map(|entry| {
+            let name = entry
+                .split_once('=')
+                .map_or(entry.as_str(), |(name, _)| name);
+            match remaining.remove(name) {
+                Some(value) => format!("{}={}", name, value),
+                None => entry.clone(),
+            }
+        })
+        .collect();
+    merged.extend(
+        remaining
+            .into_iter()
+            .map(|(name, value)| format!("{}={}", name, value)),
+    );
+    merged
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Write;
+    use std::io::{Read, Write};
     use std::path::PathBuf;
     use tempfile::NamedTempFile;
 
@@ -286,14 +925,30 @@ mod tests {
         ImageConfig {
             architecture: "amd64".to_string(),
             os: "linux".to_string(),
+            variant: None,
+            created: None,
             config: Config {
                 env: vec![
                     "PATH=/usr/local/bin:/usr/bin:/bin".to_string(),
                     "SSL_CERT_FILE=/etc/ssl/certs/ca-certificates.crt".to_string(),
                 ],
+                entrypoint: Some(vec!["/bin/base".to_string()]),
                 cmd: None,
                 working_dir: "/".to_string(),
                 user: "nonroot:nonroot".to_string(),
+                labels: Some(BTreeMap::from([
+                    (
+                        "org.opencontainers.image.vendor".to_string(),
+                        "base".to_string(),
+                    ),
+                    (
+                        "org.opencontainers.image.title".to_string(),
+                        "base".to_string(),
+                    ),
+                ])),
+                exposed_ports: Some(BTreeMap::from([("9090/tcp".to_string(), EmptyObject {})])),
+                volumes: None,
+                stop_signal: Some("SIGQUIT".to_string()),
             },
             rootfs: RootFs {
                 fs_type: "layers".to_string(),
@@ -319,48 +974,20 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_parse_platform() {
-        let builder = ImageBuilder::new(
-            PathBuf::from("/tmp/test"),
-            "test-base".to_string(),
-            "linux/amd64".to_string(),
-        );
-
-        let (os, arch, variant) = builder.parse_platform().unwrap();
-        assert_eq!(os, "linux");
-        assert_eq!(arch, "amd64");
-        assert_eq!(variant, None);
-    }
-
-    #[test]
-    fn test_parse_platform_with_variant() {
-        let builder = ImageBuilder::new(
-            PathBuf::from("/tmp/test"),
-            "test-base".to_string(),
-            "linux/arm/v7".to_string(),
-        );
-
-        let (os, arch, variant) = builder.parse_platform().unwrap();
-        assert_eq!(os, "linux");
-        assert_eq!(arch, "arm");
-        assert_eq!(variant, Some("v7".to_string()));
-    }
-
     #[test]
     fn test_create_layered_config_preserves_base_environment() {
         let (binary_path, _guard) = create_test_binary();
         let builder = ImageBuilder::new(
             binary_path,
             "test-base".to_string(),
-            "linux/amd64".to_string(),
+            "linux/amd64".parse().unwrap(),
         );
 
         let base_config = create_base_image_config();
         let app_diff_id = "sha256:app_layer_diff_id";
 
         let result = builder
-            .create_layered_config(&base_config, app_diff_id)
+            .create_layered_config(&base_config, &[app_diff_id])
             .unwrap();
 
         // Check that base environment variables are preserved
@@ -387,14 +1014,14 @@ mod tests {
         let builder = ImageBuilder::new(
             binary_path,
             "test-base".to_string(),
-            "linux/amd64".to_string(),
+            "linux/amd64".parse().unwrap(),
         );
 
         let base_config = create_base_image_config();
         let app_diff_id = "sha256:app_layer_diff_id";
 
         let result = builder
-            .create_layered_config(&base_config, app_diff_id)
+            .create_layered_config(&base_config, &[app_diff_id])
             .unwrap();
 
         // Check that base diff_ids are preserved and app diff_id is appended
@@ -410,14 +1037,14 @@ mod tests {
         let builder = ImageBuilder::new(
             binary_path,
             "test-base".to_string(),
-            "linux/amd64".to_string(),
+            "linux/amd64".parse().unwrap(),
         );
 
         let base_config = create_base_image_config();
         let app_diff_id = "sha256:app_layer_diff_id";
 
         let result = builder
-            .create_layered_config(&base_config, app_diff_id)
+            .create_layered_config(&base_config, &[app_diff_id])
             .unwrap();
 
         // Check that base history is preserved and app history is appended
@@ -427,6 +1054,12 @@ mod tests {
         assert_eq!(result.history[2].created_by, "krust");
         assert_eq!(result.history[2].comment, "Built with krust");
         assert!(!result.history[2].empty_layer);
+
+        // The config creation time matches the app history entry
+        assert_eq!(
+            result.created.as_deref(),
+            Some(result.history[2].created.as_str())
+        );
     }
 
     #[test]
@@ -502,36 +1135,162 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_platform_invalid_format() {
+    fn test_create_layer_with_valid_binary() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(b"test binary content").unwrap();
+        temp_file.flush().unwrap();
+
         let builder = ImageBuilder::new(
-            PathBuf::from("/tmp/test"),
+            temp_file.path().to_path_buf(),
             "test-base".to_string(),
-            "linux-amd64".to_string(), // Wrong format
+            "linux/amd64".parse().unwrap(),
         );
 
-        let result = builder.parse_platform();
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("Invalid platform"));
+        let result = builder.create_layers().map(|mut layers| layers.remove(0));
+        assert!(result.is_ok());
+
+        let Layer {
+            data: compressed_data,
+            diff_id,
+            ..
+        } = result.unwrap();
+        assert!(!compressed_data.is_empty());
+        assert!(diff_id.starts_with("sha256:"));
     }
 
     #[test]
-    fn test_create_layer_with_valid_binary() {
+    fn test_create_layer_is_reproducible() {
         let mut temp_file = NamedTempFile::new().unwrap();
         temp_file.write_all(b"test binary content").unwrap();
         temp_file.flush().unwrap();
-
         let builder = ImageBuilder::new(
             temp_file.path().to_path_buf(),
             "test-base".to_string(),
-            "linux/amd64".to_string(),
+            "linux/amd64".parse().unwrap(),
         );
 
-        let result = builder.create_layer();
-        assert!(result.is_ok());
+        let Layer {
+            data: first,
+            diff_id: first_diff_id,
+            ..
+        } = builder.create_layers_at(1609459200).unwrap().remove(0);
+        // Touching the binary doesn't change the layer
+        temp_file
+            .as_file()
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+            .unwrap();
+        let Layer {
+            data: second,
+            diff_id: second_diff_id,
+            ..
+        } = builder.create_layers_at(1609459200).unwrap().remove(0);
+        assert_eq!(first_diff_id, second_diff_id);
+        assert_eq!(digest(&first), digest(&second));
+
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(first.as_slice()));
+        let entries: Vec<_> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let header = entry.header();
+                (
+                    entry.path().unwrap().display().to_string(),
+                    header.mtime().unwrap(),
+                    header.uid().unwrap(),
+                )
+            })
+            .collect();
+        let binary_name = temp_file.path().file_name().unwrap().to_str().unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ("app/".to_string(), 1609459200, 0),
+                (format!("app/{}", binary_name), 1609459200, 65532),
+            ]
+        );
 
-        let (compressed_data, diff_id) = result.unwrap();
-        assert!(!compressed_data.is_empty());
-        assert!(diff_id.starts_with("sha256:"));
+        let other_diff_id = builder.create_layers_at(0).unwrap().remove(0).diff_id;
+        assert_ne!(first_diff_id, other_diff_id);
+    }
+
+    #[test]
+    fn test_layer_media_type() {
+        let builder = |compression, media_types| {
+            ImageBuilder::new(
+                PathBuf::from("app"),
+                "test-base".to_string(),
+                "linux/amd64".parse().unwrap(),
+            )
+            .with_compression(compression)
+            .with_media_types(media_types)
+        };
+        assert_eq!(
+            builder(LayerCompression::Gzip(9), MediaTypeFamily::Docker)
+                .layer_media_type()
+                .unwrap(),
+            "application/vnd.docker.image.rootfs.diff.tar.gzip"
+        );
+        assert_eq!(
+            builder(LayerCompression::Zstd(3), MediaTypeFamily::Oci)
+                .layer_media_type()
+                .unwrap(),
+            "application/vnd.oci.image.layer.v1.tar+zstd"
+        );
+        assert!(builder(LayerCompression::Zstd(3), MediaTypeFamily::Docker)
+            .layer_media_type()
+            .is_err());
+        assert_eq!(
+            builder(LayerCompression::Estargz(6), MediaTypeFamily::Oci)
+                .layer_media_type()
+                .unwrap(),
+            "application/vnd.oci.image.layer.v1.tar+gzip"
+        );
+    }
+
+    #[test]
+    fn test_create_layer_sets_ownership() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(b"test binary content").unwrap();
+        temp_file.flush().unwrap();
+
+        let read_owner = |builder: ImageBuilder| {
+            let compressed_data = builder.create_layers().unwrap().remove(0).data;
+            let mut archive =
+                tar::Archive::new(flate2::read::GzDecoder::new(compressed_data.as_slice()));
+            let entry = archive
+                .entries()
+                .unwrap()
+                .map(Result::unwrap)
+                .find(|entry| entry.header().entry_type().is_file())
+                .unwrap();
+            let header = entry.header();
+            (
+                header.uid().unwrap(),
+                header.gid().unwrap(),
+                header.username().unwrap().unwrap().to_string(),
+            )
+        };
+
+        let builder = ImageBuilder::new(
+            temp_file.path().to_path_buf(),
+            "test-base".to_string(),
+            "linux/amd64".parse().unwrap(),
+        );
+        assert_eq!(read_owner(builder), (65532, 65532, "nonroot".to_string()));
+
+        let builder = ImageBuilder::new(
+            temp_file.path().to_path_buf(),
+            "test-base".to_string(),
+            "linux/amd64".parse().unwrap(),
+        )
+        .with_ownership(FileOwnership {
+            uid: 1000,
+            gid: 2000,
+            uname: "app".to_string(),
+            gname: "app".to_string(),
+        });
+        assert_eq!(read_owner(builder), (1000, 2000, "app".to_string()));
     }
 
     #[test]
@@ -539,10 +1298,10 @@ mod tests {
         let builder = ImageBuilder::new(
             PathBuf::from("/nonexistent/binary"),
             "test-base".to_string(),
-            "linux/amd64".to_string(),
+            "linux/amd64".parse().unwrap(),
         );
 
-        let result = builder.create_layer();
+        let result = builder.create_layers().map(|mut layers| layers.remove(0));
         assert!(result.is_err());
     }
 
@@ -552,7 +1311,7 @@ mod tests {
         let builder = ImageBuilder::new(
             binary_path,
             "test-base".to_string(),
-            "linux/amd64".to_string(),
+            "linux/amd64".parse().unwrap(),
         );
 
         let mut base_config = create_base_image_config();
@@ -561,7 +1320,7 @@ mod tests {
         let app_diff_id = "sha256:app_layer_diff_id";
 
         let result = builder
-            .create_layered_config(&base_config, app_diff_id)
+            .create_layered_config(&base_config, &[app_diff_id])
             .unwrap();
 
         // Should add PATH since it was missing
@@ -569,26 +1328,479 @@ mod tests {
     }
 
     #[test]
-    fn test_create_layered_config_sets_cmd() {
+    fn test_create_layered_config_sets_entrypoint() {
         let (binary_path, _guard) = create_test_binary();
         let builder = ImageBuilder::new(
             binary_path.clone(),
             "test-base".to_string(),
-            "linux/amd64".to_string(),
+            "linux/amd64".parse().unwrap(),
         );
 
         let base_config = create_base_image_config();
         let app_diff_id = "sha256:app_layer_diff_id";
 
         let result = builder
-            .create_layered_config(&base_config, app_diff_id)
+            .create_layered_config(&base_config, &[app_diff_id])
             .unwrap();
 
-        // Should set CMD to the binary
+        // The binary replaces the base image's entrypoint, with no default args
         let binary_name = binary_path.file_name().unwrap().to_str().unwrap();
         assert_eq!(
-            result.config.cmd,
+            result.config.entrypoint,
             Some(vec![format!("/app/{}", binary_name)])
         );
+        assert_eq!(result.config.cmd, None);
+    }
+
+    #[test]
+    fn test_create_layered_config_ports_volumes_stop_signal() {
+        let (binary_path, _guard) = create_test_binary();
+        let builder = ImageBuilder::new(
+            binary_path,
+            "test-base".to_string(),
+            "linux/amd64".parse().unwrap(),
+        );
+        let result = builder
+            .create_layered_config(&create_base_image_config(), &["sha256:app"])
+            .unwrap();
+        assert_eq!(
+            result.config.exposed_ports,
+            create_base_image_config().config.exposed_ports
+        );
+        assert_eq!(result.config.volumes, None);
+        assert_eq!(result.config.stop_signal.as_deref(), Some("SIGQUIT"));
+
+        let (binary_path, _guard) = create_test_binary();
+        let builder = ImageBuilder::new(
+            binary_path,
+            "test-base".to_string(),
+            "linux/amd64".parse().unwrap(),
+        )
+        .with_ports(vec!["8080".to_string(), "53/UDP".to_string()])
+        .with_volumes(vec!["/data".to_string()])
+        .with_stop_signal(Some("SIGTERM".to_string()));
+        let result = builder
+            .create_layered_config(&create_base_image_config(), &["sha256:app"])
+            .unwrap();
+        let json = serde_json::to_value(&result.config).unwrap();
+        assert_eq!(
+            json["ExposedPorts"],
+            serde_json::json!({"53/udp": {}, "8080/tcp": {}, "9090/tcp": {}})
+        );
+        assert_eq!(json["Volumes"], serde_json::json!({"/data": {}}));
+        assert_eq!(json["StopSignal"], "SIGTERM");
+    }
+
+    #[test]
+    fn test_normalize_port() {
+        assert_eq!(normalize_port("8080").unwrap(), "8080/tcp");
+        assert_eq!(normalize_port("53/udp").unwrap(), "53/udp");
+        assert_eq!(normalize_port("9/SCTP").unwrap(), "9/sctp");
+        for port in ["0", "http", "70000", "80/icmp"] {
+            assert!(normalize_port(port).is_err(), "{}", port);
+        }
+    }
+
+    #[test]
+    fn test_create_layered_config_on_scratch() {
+        let (binary_path, _guard) = create_test_binary();
+        let platform: Platform = "linux/arm64".parse().unwrap();
+        let builder = ImageBuilder::new(binary_path, SCRATCH.to_string(), platform.clone());
+        let scratch = ImageConfig::scratch(&platform);
+        assert_eq!(scratch.architecture, "arm64");
+        assert!(scratch.rootfs.diff_ids.is_empty());
+
+        let result = builder
+            .create_layered_config(&scratch, &["sha256:app"])
+            .unwrap();
+        assert_eq!(result.rootfs.diff_ids, vec!["sha256:app"]);
+        assert_eq!(result.history.len(), 1);
+        assert_eq!(result.config.user, "");
+        assert_eq!(result.config.working_dir, "");
+        assert_eq!(
+            result.config.env,
+            vec!["PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"]
+        );
+        assert!(result.config.exposed_ports.is_none());
+        assert!(is_scratch("scratch"));
+        assert!(!is_scratch("cgr.dev/chainguard/static"));
+    }
+
+    #[test]
+    fn test_base_digest() {
+        let builder = ImageBuilder::new(
+            PathBuf::from("app"),
+            "alpine:3.20".to_string(),
+            "linux/amd64".parse().unwrap(),
+        );
+        assert_eq!(builder.base_reference().unwrap(), "alpine:3.20");
+        assert!(!builder
+            .manifest_annotations("2024-01-01T00:00:00Z")
+            .contains_key(BASE_DIGEST_ANNOTATION));
+
+        let builder = builder.with_base_digest(Some("sha256:abc".to_string()));
+        assert_eq!(
+            builder.base_reference().unwrap(),
+            "registry-1.docker.io/library/alpine@sha256:abc"
+        );
+        let annotations = builder.manifest_annotations("2024-01-01T00:00:00Z");
+        assert_eq!(annotations[BASE_NAME_ANNOTATION], "alpine:3.20");
+        assert_eq!(annotations[BASE_DIGEST_ANNOTATION], "sha256:abc");
+    }
+
+    #[test]
+    fn test_create_layered_config_variant() {
+        let (binary_path, _guard) = create_test_binary();
+        let builder = ImageBuilder::new(
+            binary_path,
+            "test-base".to_string(),
+            "linux/arm/v7".parse().unwrap(),
+        );
+        let mut base_config = create_base_image_config();
+        base_config.architecture = "arm".to_string();
+        let result = builder
+            .create_layered_config(&base_config, &["sha256:app"])
+            .unwrap();
+        assert_eq!(result.variant.as_deref(), Some("v7"));
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["variant"], "v7");
+
+        // A variant only the base image sets is kept
+        let (binary_path, _guard) = create_test_binary();
+        let builder = ImageBuilder::new(
+            binary_path,
+            "test-base".to_string(),
+            "linux/arm64".parse().unwrap(),
+        );
+        base_config.architecture = "arm64".to_string();
+        base_config.variant = Some("v8".to_string());
+        let result = builder
+            .create_layered_config(&base_config, &["sha256:app"])
+            .unwrap();
+        assert_eq!(result.variant.as_deref(), Some("v8"));
+
+        let json = serde_json::to_value(create_base_image_config()).unwrap();
+        assert!(json.get("variant").is_none());
+    }
+
+    #[test]
+    fn test_merge_env() {
+        let base = vec![
+            "PATH=/usr/bin:/bin".to_string(),
+            "RUST_LOG=warn".to_string(),
+        ];
+        let overrides = BTreeMap::from([
+            ("RUST_LOG".to_string(), "info".to_string()),
+            ("PORT".to_string(), "8080".to_string()),
+        ]);
+        assert_eq!(
+            merge_env(&base, &overrides),
+            vec!["PATH=/usr/bin:/bin", "RUST_LOG=info", "PORT=8080"]
+        );
+        assert_eq!(merge_env(&base, &BTreeMap::new()), base);
+    }
+
+    #[test]
+    fn test_create_layered_config_labels() {
+        let (binary_path, _guard) = create_test_binary();
+        let builder = ImageBuilder::new(
+            binary_path,
+            "test-base".to_string(),
+            "linux/amd64".parse().unwrap(),
+        )
+        .with_labels(BTreeMap::from([(
+            "org.opencontainers.image.title".to_string(),
+            "app".to_string(),
+        )]));
+
+        let result = builder
+            .create_layered_config(&create_base_image_config(), &["sha256:app"])
+            .unwrap();
+        let labels = result.config.labels.unwrap();
+        assert_eq!(labels["org.opencontainers.image.vendor"], "base");
+        assert_eq!(labels["org.opencontainers.image.title"], "app");
+        assert_eq!(
+            labels[CREATED_LABEL],
+            result.created.unwrap(),
+            "created label matches the config"
+        );
+    }
+
+    #[test]
+    fn test_create_layered_config_entrypoint_args_working_dir() {
+        let (binary_path, _guard) = create_test_binary();
+        let builder = ImageBuilder::new(
+            binary_path,
+            "test-base".to_string(),
+            "linux/amd64".parse().unwrap(),
+        )
+        .with_entrypoint(Some(vec![
+            "/usr/bin/tini".to_string(),
+            "--".to_string(),
+            "/app/server".to_string(),
+        ]))
+        .with_args(vec!["--port".to_string(), "8080".to_string()])
+        .with_working_dir(Some("/data".to_string()));
+
+        let result = builder
+            .create_layered_config(&create_base_image_config(), &["sha256:app"])
+            .unwrap();
+        assert_eq!(
+            result.config.entrypoint,
+            Some(vec![
+                "/usr/bin/tini".to_string(),
+                "--".to_string(),
+                "/app/server".to_string()
+            ])
+        );
+        assert_eq!(
+            result.config.cmd,
+            Some(vec!["--port".to_string(), "8080".to_string()])
+        );
+        assert_eq!(result.config.working_dir, "/data");
+
+        let (binary_path, _guard) = create_test_binary();
+        let builder = ImageBuilder::new(
+            binary_path,
+            "test-base".to_string(),
+            "linux/amd64".parse().unwrap(),
+        )
+        .with_working_dir(Some("data".to_string()));
+        assert!(builder
+            .create_layered_config(&create_base_image_config(), &["sha256:app"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_layer_files_with_binaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = dir.path().join("app");
+        let worker = dir.path().join("worker");
+        let builder = ImageBuilder::new(
+            app.clone(),
+            "test-base".to_string(),
+            "linux/amd64".parse().unwrap(),
+        )
+        .with_binaries(vec![worker.clone()]);
+        assert_eq!(
+            builder.layer_files().unwrap(),
+            BTreeMap::from([
+                ("app/".to_string(), LayerEntry::Dir { root_owned: true }),
+                (
+                    "app/app".to_string(),
+                    LayerEntry::File {
+                        source: app.clone(),
+                        mode: 0o755
+                    }
+                ),
+                (
+                    "app/worker".to_string(),
+                    LayerEntry::File {
+                        source: worker,
+                        mode: 0o755
+                    }
+                ),
+            ])
+        );
+
+        // Two binaries can't share a name under /app/
+        let other = tempfile::tempdir().unwrap();
+        let builder =
+            ImageBuilder::new(app, "test-base".to_string(), "linux/amd64".parse().unwrap())
+                .with_binaries(vec![other.path().join("app")]);
+        assert!(builder.layer_files().is_err());
+    }
+
+    #[test]
+    fn test_create_layers_with_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("server");
+        std::fs::write(&binary, "binary").unwrap();
+        std::fs::write(dir.path().join("LICENSE"), "license").unwrap();
+        std::fs::create_dir_all(dir.path().join("config/tls")).unwrap();
+        std::fs::write(dir.path().join("config/app.toml"), "config").unwrap();
+        std::fs::write(dir.path().join("config/tls/ca.pem"), "ca").unwrap();
+
+        let includes = [
+            "LICENSE".parse::<Include>().unwrap(),
+            Include {
+                src: PathBuf::from("config"),
+                dest: Some("/etc/server".to_string()),
+                mode: Some(0o600),
+            },
+        ]
+        .iter()
+        .map(|include| include.relative_to(dir.path()))
+        .collect::<Vec<_>>();
+        let builder = ImageBuilder::new(
+            binary.clone(),
+            "test-base".to_string(),
+            "linux/amd64".parse().unwrap(),
+        )
+        .with_includes(includes);
+
+        let entries = |layer: &[u8]| -> Vec<(String, u32)> {
+            let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(layer));
+            archive
+                .entries()
+                .unwrap()
+                .map(|entry| {
+                    let entry = entry.unwrap();
+                    (
+                        entry.path().unwrap().display().to_string(),
+                        entry.header().mode().unwrap(),
+                    )
+                })
+                .collect()
+        };
+
+        // Included files go in the application layer by default
+        let layers = builder.create_layers_at(0).unwrap();
+        assert_eq!(layers.len(), 1);
+        assert_eq!(
+            entries(&layers[0].data),
+            vec![
+                ("app/".to_string(), 0o755),
+                ("app/LICENSE".to_string(), 0o644),
+                ("app/server".to_string(), 0o755),
+                ("etc/server/".to_string(), 0o755),
+                ("etc/server/app.toml".to_string(), 0o600),
+                ("etc/server/tls/".to_string(), 0o755),
+                ("etc/server/tls/ca.pem".to_string(), 0o600),
+            ]
+        );
+
+        // or beneath it in a layer of their own
+        let builder = builder.with_include_layer(true);
+        let layers = builder.create_layers_at(0).unwrap();
+        assert_eq!(layers.len(), 2);
+        assert_eq!(entries(&layers[0].data).len(), 5);
+        assert_eq!(
+            entries(&layers[1].data),
+            vec![
+                ("app/".to_string(), 0o755),
+                ("app/server".to_string(), 0o755)
+            ]
+        );
+        let config = builder
+            .create_layered_config(
+                &create_base_image_config(),
+                &[&layers[0].diff_id, &layers[1].diff_id],
+            )
+            .unwrap();
+        assert_eq!(config.rootfs.diff_ids.len(), 4);
+        assert_eq!(config.history.len(), 4);
+
+        // Included files can't replace the binary
+        let builder = ImageBuilder::new(
+            binary.clone(),
+            "test-base".to_string(),
+            "linux/amd64".parse().unwrap(),
+        )
+        .with_includes(vec![Include {
+            src: dir.path().join("LICENSE"),
+            dest: Some("/app/server".to_string()),
+            mode: None,
+        }])
+        .with_include_layer(true);
+        assert!(builder.create_layers_at(0).is_err());
+    }
+
+    #[test]
+    fn test_create_layers_with_ca_certs() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("server");
+        std::fs::write(&binary, "binary").unwrap();
+        let builder = ImageBuilder::new(
+            binary,
+            "scratch".to_string(),
+            "linux/amd64".parse().unwrap(),
+        )
+        .with_ca_certs(true);
+
+        // The bundle gets a root-owned layer of its own beneath the binary's
+        let layers = builder.create_layers_at(0).unwrap();
+        assert_eq!(layers.len(), 2);
+        let mut archive =
+            tar::Archive::new(flate2::read::GzDecoder::new(layers[0].data.as_slice()));
+        let entries: Vec<(String, u32, u64)> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let header = entry.header();
+                (
+                    entry.path().unwrap().display().to_string(),
+                    header.mode().unwrap(),
+                    header.uid().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("etc/".to_string(), 0o755, 0),
+                ("etc/ssl/".to_string(), 0o755, 0),
+                ("etc/ssl/certs/".to_string(), 0o755, 0),
+                ("etc/ssl/certs/ca-certificates.crt".to_string(), 0o644, 0),
+            ]
+        );
+
+        let config = builder
+            .create_layered_config(
+                &ImageConfig::scratch(&"linux/amd64".parse().unwrap()),
+                &[&layers[0].diff_id, &layers[1].diff_id],
+            )
+            .unwrap();
+        assert!(config
+            .config
+            .env
+            .contains(&"SSL_CERT_FILE=/etc/ssl/certs/ca-certificates.crt".to_string()));
+        assert_eq!(config.history[0].comment, "CA certificates added by krust");
+
+        // An SSL_CERT_FILE that's set explicitly is kept
+        let config = builder
+            .with_env(BTreeMap::from([(
+                "SSL_CERT_FILE".to_string(),
+                "/certs.pem".to_string(),
+            )]))
+            .create_layered_config(
+                &ImageConfig::scratch(&"linux/amd64".parse().unwrap()),
+                &[&layers[0].diff_id, &layers[1].diff_id],
+            )
+            .unwrap();
+        assert!(config
+            .config
+            .env
+            .contains(&"SSL_CERT_FILE=/certs.pem".to_string()));
+    }
+
+    #[test]
+    fn test_create_layers_estargz() {
+        let (binary, _path) = create_test_binary();
+        let builder = ImageBuilder::new(
+            binary,
+            "scratch".to_string(),
+            "linux/amd64".parse().unwrap(),
+        )
+        .with_compression(LayerCompression::Estargz(6));
+
+        // The diff ID is of the tarball as rewritten, TOC included
+        let layer = builder.create_layers_at(0).unwrap().remove(0);
+        let mut uncompressed = Vec::new();
+        flate2::read::MultiGzDecoder::new(layer.data.as_slice())
+            .read_to_end(&mut uncompressed)
+            .unwrap();
+        assert_eq!(layer.diff_id, format!("sha256:{}", digest(&uncompressed)));
+        let mut archive = tar::Archive::new(uncompressed.as_slice());
+        let last = archive.entries().unwrap().last().unwrap().unwrap();
+        assert_eq!(last.path().unwrap().to_str(), Some("stargz.index.json"));
+
+        let annotations = layer.annotations.unwrap();
+        assert!(annotations[estargz::TOC_DIGEST_ANNOTATION].starts_with("sha256:"));
+        assert_eq!(
+            annotations[estargz::UNCOMPRESSED_SIZE_ANNOTATION],
+            uncompressed.len().to_string()
+        );
     }
 }
@@ -0,0 +1,596 @@
+//! OCI image layout directories
+//!
+//! Reads and writes the on-disk format described by the OCI image spec:
+//! an `oci-layout` marker file, an `index.json` entry point and
+//! content-addressed blobs under `blobs/sha256/`.
+
+use crate::events::{self, Event};
+use crate::manifest::ImageIndex;
+use crate::registry::encoding::{EncodedManifest, JsonFormat};
+use crate::registry::{
+    is_index_media_type, layered_manifest, media_types, oci_image_index, Annotations,
+    ImageIndexEntry, ImageReference, OciDescriptor, OciImageIndex, OciImageManifest, RegistryAuth,
+    RegistryClient,
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::{debug, info};
+
+/// File in an artifact directory describing where its contents should be pushed
+pub const PUSH_PLAN_FILE: &str = "krust-push.json";
+
+/// Annotation naming an image within a layout's `index.json`
+pub const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LayoutMarker {
+    #[serde(rename = "imageLayoutVersion")]
+    image_layout_version: String,
+}
+
+/// An OCI image layout rooted at a directory
+#[derive(Debug, Clone)]
+pub struct OciLayout {
+    root: PathBuf,
+}
+
+impl OciLayout {
+    /// Open a layout directory, creating it (and its marker file) if needed.
+    pub fn create(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        std::fs::create_dir_all(root.join("blobs").join("sha256"))
+            .with_context(|| format!("Failed to create OCI layout at {}", root.display()))?;
+
+        let marker_path = root.join("oci-layout");
+        if !marker_path.exists() {
+            let marker = LayoutMarker {
+                image_layout_version: "1.0.0".to_string(),
+            };
+            std::fs::write(&marker_path, serde_json::to_vec(&marker)?)?;
+        }
+
+        let layout = Self { root };
+        if !layout.index_path().exists() {
+            layout.write_index(&OciImageIndex {
+                schema_version: 2,
+                media_type: media_types::OCI_INDEX.to_string(),
+                manifests: Vec::new(),
+                annotations: None,
+            })?;
+        }
+        Ok(layout)
+    }
+
+    /// Create an empty layout for `--artifact-dir`, whose push plan only
+    /// covers what this build writes. An artifact directory left by an
+    /// earlier build is cleared; any other non-empty directory is refused.
+    pub fn create_empty(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref();
+        let is_empty = match std::fs::read_dir(root) {
+            Ok(mut entries) => entries.next().is_none(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", root.display())),
+        };
+        if !is_empty {
+            if !root.join(PUSH_PLAN_FILE).exists() || !root.join("oci-layout").exists() {
+                anyhow::bail!(
+                    "{} is not empty and wasn't written by `krust build --artifact-dir`; use an empty directory",
+                    root.display()
+                );
+            }
+            debug!(
+                "Clearing the previous artifact directory {}",
+                root.display()
+            );
+            std::fs::remove_dir_all(root)
+                .with_context(|| format!("Failed to clear {}", root.display()))?;
+        }
+        Self::create(root)
+    }
+
+    /// Open an existing layout directory.
+    pub fn open(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        if !root.join("oci-layout").exists() {
+            anyhow::bail!("{} is not an OCI image layout", root.display());
+        }
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.root.join("index.json")
+    }
+
+    fn blob_path(&self, digest: &str) -> Result<PathBuf> {
+        let hex = digest
+            .strip_prefix("sha256:")
+            .with_context(|| format!("Unsupported digest: {}", digest))?;
+        Ok(self.root.join("blobs").join("sha256").join(hex))
+    }
+
+    /// Whether the layout already contains a blob.
+    pub fn has_blob(&self, digest: &str) -> bool {
+        self.blob_path(digest).map(|p| p.exists()).unwrap_or(false)
+    }
+
+    /// Write a blob and return its digest. Existing blobs are left untouched.
+    pub fn write_blob(&self, data: &[u8]) -> Result<String> {
+        let digest = format!("sha256:{}", sha256::digest(data));
+        let path = self.blob_path(&digest)?;
+        if path.exists() {
+            return Ok(digest);
+        }
+
+        // Write to a temporary file first so concurrent writers never observe partial blobs
+        static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let tmp_path = path.with_extension(format!(
+            "tmp-{}-{}",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&tmp_path, data)
+            .with_context(|| format!("Failed to write blob {}", digest))?;
+        std::fs::rename(&tmp_path, &path)
+            .with_context(|| format!("Failed to write blob {}", digest))?;
+        Ok(digest)
+    }
+
+    /// Read a blob by digest.
+    pub fn read_blob(&self, digest: &str) -> Result<Vec<u8>> {
+        let path = self.blob_path(digest)?;
+        std::fs::read(&path).with_context(|| {
+            format!(
+                "Blob {} not found in OCI layout {}",
+                digest,
+                self.root.display()
+            )
+        })
+    }
+
+    /// Read the layout's top-level `index.json`.
+    pub fn read_index(&self) -> Result<OciImageIndex> {
+        let data = std::fs::read(self.index_path())
+            .with_context(|| format!("Failed to read {}", self.index_path().display()))?;
+        serde_json::from_slice(&data).context("Failed to parse index.json")
+    }
+
+    fn write_index(&self, index: &OciImageIndex) -> Result<()> {
+        std::fs::write(self.index_path(), serde_json::to_vec_pretty(index)?)
+            .with_context(|| format!("Failed to write {}", self.index_path().display()))
+    }
+
+    /// Record a manifest or index blob in `index.json`, optionally naming it.
+    /// Any existing entry with the same name is replaced.
+    pub fn add_to_index(&self, mut entry: ImageIndexEntry, ref_name: Option<&str>) -> Result<()> {
+        let mut index = self.read_index()?;
+
+        if let Some(name) = ref_name {
+            index.manifests.retain(|m| {
+                m.annotations
+                    .as_ref()
+                    .and_then(|a| a.get(REF_NAME_ANNOTATION))
+                    .map(|n| n != name)
+                    .unwrap_or(true)
+            });
+            entry
+                .annotations
+                .get_or_insert_with(Annotations::new)
+                .insert(REF_NAME_ANNOTATION.to_string(), name.to_string());
+        } else {
+            index.manifests.retain(|m| m.digest != entry.digest);
+        }
+
+        index.manifests.push(entry);
+        self.write_index(&index)
+    }
+}
+
+impl OciLayout {
+    /// Write a layered image (config, krust's layers, base layers and
+    /// manifest) into the layout. Base layers are fetched from the base image
+    /// so the layout is self-contained. Returns the manifest digest and size.
+    pub async fn write_layered_image(
+        &self,
+        registry_client: &mut RegistryClient,
+        config_data: &[u8],
+        layer_data: &[Vec<u8>],
+        manifest: &crate::image::Manifest,
+        base_image: &str,
+        base_auth: &RegistryAuth,
+    ) -> Result<(String, usize)> {
+        self.write_blob(config_data)?;
+        for layer in layer_data {
+            self.write_blob(layer)?;
+        }
+
+        // The layers below krust's come from the base image
+        for layer in &manifest.layers[..manifest.layers.len().saturating_sub(layer_data.len())] {
+            if self.has_blob(&layer.digest) {
+                continue;
+            }
+            debug!("Fetching base layer {} into OCI layout", layer.digest);
+            let descriptor = OciDescriptor {
+                media_type: layer.media_type.clone(),
+                digest: layer.digest.clone(),
+                size: layer.size,
+                urls: None,
+                annotations: None,
+            };
+            let data = registry_client
+                .pull_cached_blob(base_image, &descriptor, base_auth)
+                .await?;
+            self.write_blob(&data)?;
+        }
+
+        let oci_manifest = layered_manifest(manifest);
+        let encoded = EncodedManifest::new(
+            &oci_manifest,
+            &oci_manifest.media_type,
+            registry_client.json_format(),
+        )?;
+        let digest = self.write_blob(&encoded.data)?;
+        Ok((digest, encoded.data.len()))
+    }
+
+    /// Write an image index over platform manifests already in the layout and
+    /// record it in `index.json`. Returns the index digest.
+    pub fn write_image_index(
+        &self,
+        index: &ImageIndex,
+        ref_name: Option<&str>,
+        format: JsonFormat,
+    ) -> Result<String> {
+        let index = oci_image_index(index);
+        let encoded = EncodedManifest::new(&index, &index.media_type, format)?;
+        let digest = self.write_blob(&encoded.data)?;
+
+        self.add_to_index(
+            ImageIndexEntry {
+                media_type: index.media_type.clone(),
+                digest: digest.clone(),
+                size: encoded.size(),
+                platform: None,
+                annotations: None,
+            },
+            ref_name,
+        )?;
+        Ok(digest)
+    }
+
+    async fn push_manifest_blobs(
+        &self,
+        registry_client: &mut RegistryClient,
+        repository: &str,
+        manifest_digest: &str,
+        auth: &RegistryAuth,
+    ) -> Result<()> {
+        let body = self.read_blob(manifest_digest)?;
+        let manifest: OciImageManifest =
+            serde_json::from_slice(&body).context("Failed to parse manifest in OCI layout")?;
+
+        let repository_url = ImageReference::parse(repository)?.repository_url();
+        for descriptor in manifest.config.iter().chain(manifest.layers.iter()) {
+            let data = self.read_blob(&descriptor.digest)?;
+            registry_client
+                .push_blob(repository, &data, &descriptor.digest, auth)
+                .await?;
+        }
+        for layer in &manifest.layers {
+            events::emit(Event::LayerPushed {
+                repository: repository_url.clone(),
+                digest: layer.digest.clone(),
+                size: layer.size,
+            });
+        }
+
+        registry_client
+            .put_manifest_raw(
+                repository,
+                manifest_digest,
+                &body,
+                &manifest.media_type,
+                auth,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Push the image `digest` in `index.json` to a repository, by digest
+    /// and then to each of `tags`. Returns the pushed reference by digest.
+    pub async fn push_to_registry(
+        &self,
+        registry_client: &mut RegistryClient,
+        repository: &str,
+        digest: &str,
+        tags: &[String],
+        auth: &RegistryAuth,
+    ) -> Result<String> {
+        let reference = ImageReference::parse(repository)?;
+        let entry = self
+            .read_index()?
+            .manifests
+            .into_iter()
+            .find(|entry| entry.digest == digest)
+            .with_context(|| {
+                format!(
+                    "Image {} isn't in the OCI layout at {}",
+                    digest,
+                    self.root.display()
+                )
+            })?;
+
+        let body = self.read_blob(&entry.digest)?;
+        if is_index_media_type(&entry.media_type) {
+            let index: OciImageIndex = serde_json::from_slice(&body)
+                .context("Failed to parse image index in OCI layout")?;
+            for child in &index.manifests {
+                self.push_manifest_blobs(registry_client, repository, &child.digest, auth)
+                    .await?;
+            }
+            registry_client
+                .put_manifest_raw(repository, &entry.digest, &body, &entry.media_type, auth)
+                .await?;
+        } else {
+            self.push_manifest_blobs(registry_client, repository, &entry.digest, auth)
+                .await?;
+        }
+        for tag in tags {
+            registry_client
+                .put_manifest_raw(repository, tag, &body, &entry.media_type, auth)
+                .await?;
+        }
+
+        let image_ref = format!("{}@{}", reference.repository_url(), entry.digest);
+        info!("Pushed {} from OCI layout", image_ref);
+        Ok(image_ref)
+    }
+}
+
+/// What a read-only build would have pushed, written alongside the OCI
+/// layout so a separate, credentialed job can publish it with `krust push`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PushPlan {
+    /// Repository the images should be pushed to
+    pub repository: String,
+    /// Tags to apply to the image index. Plans written with a single `tag`
+    /// are still read.
+    #[serde(
+        default,
+        alias = "tag",
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub tags: Vec<String>,
+    /// Digest of the image index in the layout
+    pub digest: String,
+    /// Platforms included in the index
+    pub platforms: Vec<String>,
+}
+
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(tag) => vec![tag],
+        OneOrMany::Many(tags) => tags,
+    })
+}
+
+impl PushPlan {
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(PUSH_PLAN_FILE);
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn read(dir: &Path) -> Result<Self> {
+        let path = dir.join(PUSH_PLAN_FILE);
+        let data = std::fs::read(&path).with_context(|| {
+            format!(
+                "Failed to read {} - was this directory produced by `krust build --artifact-dir`?",
+                path.display()
+            )
+        })?;
+        serde_json::from_slice(&data).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn entry(digest: &str) -> ImageIndexEntry {
+        ImageIndexEntry {
+            media_type: media_types::OCI_INDEX.to_string(),
+            digest: digest.to_string(),
+            size: 1,
+            platform: None,
+            annotations: None,
+        }
+    }
+
+    #[test]
+    fn test_create_layout() {
+        let dir = tempdir().unwrap();
+        let layout = OciLayout::create(dir.path().join("out")).unwrap();
+
+        let marker = std::fs::read_to_string(layout.root().join("oci-layout")).unwrap();
+        assert!(marker.contains("\"imageLayoutVersion\":\"1.0.0\""));
+        assert!(layout.read_index().unwrap().manifests.is_empty());
+        assert!(OciLayout::open(layout.root()).is_ok());
+    }
+
+    #[test]
+    fn test_create_empty_layout() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("out");
+        let layout = OciLayout::create_empty(&root).unwrap();
+        let stale = layout.write_blob(b"stale").unwrap();
+        PushPlan {
+            repository: "ttl.sh/test/app".to_string(),
+            tags: Vec::new(),
+            digest: stale.clone(),
+            platforms: Vec::new(),
+        }
+        .write(&root)
+        .unwrap();
+
+        // An earlier artifact directory is cleared
+        let layout = OciLayout::create_empty(&root).unwrap();
+        assert!(!layout.has_blob(&stale));
+        assert!(!root.join(PUSH_PLAN_FILE).exists());
+
+        // Anything else is left alone
+        std::fs::write(dir.path().join("notes.txt"), "keep").unwrap();
+        let err = OciLayout::create_empty(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("not empty"), "{}", err);
+        assert!(dir.path().join("notes.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_push_missing_digest() {
+        let dir = tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let mut client = RegistryClient::new().unwrap();
+        let err = layout
+            .push_to_registry(
+                &mut client,
+                "registry.invalid/test/app",
+                "sha256:abc",
+                &[],
+                &RegistryAuth::Anonymous,
+            )
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("isn't in the OCI layout"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_open_rejects_non_layout() {
+        let dir = tempdir().unwrap();
+        assert!(OciLayout::open(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_write_and_read_blob() {
+        let dir = tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+
+        let digest = layout.write_blob(b"hello").unwrap();
+        assert_eq!(digest, format!("sha256:{}", sha256::digest("hello")));
+        assert!(layout.has_blob(&digest));
+        assert_eq!(layout.read_blob(&digest).unwrap(), b"hello");
+        assert!(layout.read_blob("sha256:missing").is_err());
+    }
+
+    #[test]
+    fn test_push_plan_round_trip() {
+        let dir = tempdir().unwrap();
+        let plan = PushPlan {
+            repository: "ttl.sh/test/app".to_string(),
+            tags: vec!["v1".to_string(), "latest".to_string()],
+            digest: "sha256:abc".to_string(),
+            platforms: vec!["linux/amd64".to_string()],
+        };
+        plan.write(dir.path()).unwrap();
+        assert_eq!(PushPlan::read(dir.path()).unwrap(), plan);
+    }
+
+    #[test]
+    fn test_push_plan_single_tag() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(PUSH_PLAN_FILE),
+            r#"{"repository":"ttl.sh/test/app","tag":"v1","digest":"sha256:abc","platforms":[]}"#,
+        )
+        .unwrap();
+        assert_eq!(PushPlan::read(dir.path()).unwrap().tags, vec!["v1"]);
+    }
+
+    #[test]
+    fn test_push_plan_missing() {
+        let dir = tempdir().unwrap();
+        let err = PushPlan::read(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("--artifact-dir"));
+    }
+
+    #[test]
+    fn test_write_image_index() {
+        use crate::manifest::{IndexAnnotations, ManifestDescriptor, Platform};
+
+        let dir = tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let mut annotations = IndexAnnotations::default();
+        annotations.index.insert(
+            "org.opencontainers.image.revision".to_string(),
+            "abc123".to_string(),
+        );
+        annotations
+            .manifests
+            .insert("com.example.team".to_string(), "infra".to_string());
+        let index = ImageIndex::new(vec![ManifestDescriptor {
+            media_type: media_types::OCI_MANIFEST.to_string(),
+            size: 10,
+            digest: "sha256:platform".to_string(),
+            platform: Platform {
+                architecture: "amd64".to_string(),
+                os: "linux".to_string(),
+                variant: None,
+            },
+            annotations: None,
+        }])
+        .with_annotations(&annotations);
+        let digest = layout
+            .write_image_index(&index, Some("latest"), JsonFormat::Pretty)
+            .unwrap();
+
+        let index: OciImageIndex =
+            serde_json::from_slice(&layout.read_blob(&digest).unwrap()).unwrap();
+        assert_eq!(index.manifests.len(), 1);
+        assert_eq!(index.manifests[0].digest, "sha256:platform");
+        assert_eq!(index.annotations, Some(annotations.index));
+        assert_eq!(index.manifests[0].annotations, Some(annotations.manifests));
+
+        let top = layout.read_index().unwrap();
+        assert_eq!(top.manifests.len(), 1);
+        assert_eq!(top.manifests[0].digest, digest);
+    }
+
+    #[test]
+    fn test_add_to_index_replaces_named_entries() {
+        let dir = tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+
+        layout
+            .add_to_index(entry("sha256:a"), Some("latest"))
+            .unwrap();
+        layout
+            .add_to_index(entry("sha256:b"), Some("latest"))
+            .unwrap();
+        layout.add_to_index(entry("sha256:c"), None).unwrap();
+
+        let index = layout.read_index().unwrap();
+        assert_eq!(index.manifests.len(), 2);
+        assert_eq!(index.manifests[0].digest, "sha256:b");
+        assert_eq!(
+            index.manifests[0].annotations.as_ref().unwrap()[REF_NAME_ANNOTATION],
+            "latest"
+        );
+        assert_eq!(index.manifests[1].digest, "sha256:c");
+    }
+}
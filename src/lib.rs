@@ -1,10 +1,20 @@
 pub mod auth;
 pub mod builder;
+pub mod bundle;
+pub mod cache;
 pub mod cli;
 pub mod config;
+pub mod events;
 pub mod image;
+pub mod layout;
+pub mod lockfile;
 pub mod manifest;
+pub mod paths;
+pub mod publish;
 pub mod registry;
 pub mod resolve;
+pub mod scan;
+pub mod serve;
+pub mod sign;
 
 pub use anyhow::Result;
@@ -0,0 +1,83 @@
+//! `krust.lock`, which pins base images to digests
+//!
+//! `krust build --pin-base` resolves the base image's tag and records the
+//! digest next to the project's Cargo.toml. Later builds of the project use
+//! the recorded digest instead of resolving the tag again, so they start
+//! from exactly the same base until it's pinned again.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub const LOCKFILE_NAME: &str = "krust.lock";
+
+const HEADER: &str = "# Base image digests pinned by `krust build --pin-base`.\n\
+                      # Pin again to update them.\n\n";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Lockfile {
+    /// Digests of base images, keyed by the reference they're configured as
+    #[serde(default, rename = "base-images")]
+    pub base_images: BTreeMap<String, String>,
+}
+
+impl Lockfile {
+    /// Path of the lockfile for the project in `project_path`
+    pub fn path(project_path: &Path) -> PathBuf {
+        project_path.join(LOCKFILE_NAME)
+    }
+
+    /// Load the lockfile at `path`, or an empty one if there is none
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self).context("Failed to serialize lockfile")?;
+        std::fs::write(path, format!("{}{}", HEADER, content))
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The digest `base_image` is pinned to, if any
+    pub fn base_digest(&self, base_image: &str) -> Option<&str> {
+        self.base_images.get(base_image).map(String::as_str)
+    }
+
+    pub fn pin_base(&mut self, base_image: &str, digest: &str) {
+        self.base_images
+            .insert(base_image.to_string(), digest.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = Lockfile::path(dir.path());
+        let mut lock = Lockfile::load(&path).unwrap();
+        assert_eq!(lock, Lockfile::default());
+
+        lock.pin_base("cgr.dev/chainguard/static:latest", "sha256:abc");
+        lock.save(&path).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# Base image digests"));
+        assert!(content.contains("[base-images]"));
+
+        let lock = Lockfile::load(&path).unwrap();
+        assert_eq!(
+            lock.base_digest("cgr.dev/chainguard/static:latest"),
+            Some("sha256:abc")
+        );
+        assert_eq!(lock.base_digest("alpine:latest"), None);
+    }
+}
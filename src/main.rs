@@ -1,18 +1,57 @@
 use anyhow::{Context, Result};
+use async_trait::async_trait;
 use clap::Parser;
+use indicatif::HumanBytes;
 use krust::{
-    auth::resolve_auth,
-    builder::{get_rust_target_triple, RustBuilder},
-    cli::{Cli, Commands},
-    config::Config,
-    image::{parse_platform_string, ImageBuilder},
-    manifest::{ManifestDescriptor, Platform},
-    registry::RegistryClient,
-    resolve::{find_krust_references, read_yaml_files, replace_krust_references},
+    builder::{
+        cancel,
+        git::{source_labels, GitSource},
+        metadata::{package_dir, root_package_path},
+        rust_target_triple, RustBuilder,
+    },
+    bundle::Bundle,
+    cache::{self, parse_size},
+    cli::{
+        cache::format_table as format_cache_table, github, inspect::format_table,
+        progress::CliProgress, CacheCommand, Cli, Commands, ConfigCommand,
+    },
+    config::{BuildConfig, Config, ImageName, MediaTypeFamily, Naming, ProjectConfig, SbomFormat},
+    events::{self, Event, EventFormat},
+    image::{is_scratch, local::LocalImage, ImageBuilder},
+    layout::{OciLayout, PushPlan},
+    lockfile::{Lockfile, LOCKFILE_NAME},
+    manifest::{IndexAnnotations, Platform},
+    paths::Paths,
+    publish::{
+        BuildMetadata, Cluster, ClusterPublisher, DaemonPublisher, ImageMetadata, ImageStore,
+        LayoutPublisher, PlatformImage, PlatformMetadata, Publisher, RegistryPublisher,
+        TarballPublisher,
+    },
+    registry::{
+        cache::ManifestCache,
+        inspect::inspect_image,
+        offline::missing_content,
+        prune::{
+            group_tags_by_digest, parse_age, retained_digests, without_children, RemoteImage,
+            RetentionPolicy,
+        },
+        timeout::with_deadline,
+        ImageReference, RegistryAuth, RegistryClient,
+    },
+    resolve::{find_krust_references, read_yaml_files, replace_krust_references, split_package},
+    scan::{scan_image, scanner},
+    serve::{
+        parse_platforms, serve, BuildRequest, BuildResponse, BuildService, ResolveRequest,
+        ResolveResponse,
+    },
+    sign::{http_client, open_key, KeySigner, KeylessSigner, Rekor, Signer, Verifier},
 };
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use tracing::info;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
 use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
@@ -22,6 +61,8 @@ async fn main() -> Result<()> {
     // Initialize logging to stderr
     let filter = if cli.verbose {
         EnvFilter::new("debug")
+    } else if cli.quiet {
+        EnvFilter::new("warn")
     } else {
         EnvFilter::new("info")
     };
@@ -30,38 +71,308 @@ async fn main() -> Result<()> {
         .with_writer(std::io::stderr)
         .init();
 
+    match (cli.events, &cli.events_file) {
+        (Some(EventFormat::Json), Some(path)) => events::to_file(path)?,
+        (Some(EventFormat::Json), None) => events::to_stderr()?,
+        (None, _) => {}
+    }
+
+    // Ctrl-C stops running builds along with their cargo processes. A second
+    // one, or one while nothing is building, exits right away.
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if cancel::is_cancelled() || cancel::running() == 0 {
+                std::process::exit(130);
+            }
+            warn!("Interrupted, stopping builds (press Ctrl-C again to exit now)");
+            cancel::cancel();
+        }
+    });
+
+    let config_profile = cli.config_profile.as_deref();
     match cli.command {
         Commands::Build {
             path,
+            package,
+            all_bins,
+            bins,
+            entrypoint,
             platform,
+            profile,
+            build_concurrency,
+            libc,
+            oci,
+            docker,
+            locked,
+            offline,
+            frozen,
+            offline_images,
+            build_timeout,
+            rebuild,
+            pin_base,
+            verify_base,
             no_push,
+            push,
+            create_repo,
+            verify,
+            sign,
+            sign_key,
+            scan,
+            scan_severity,
+            sbom,
+            artifact_dir,
+            oci_layout,
+            load,
+            containerd_namespace,
+            load_into,
+            tarball,
+            tarball_format,
+            metadata_file,
+            github_outputs,
             tag,
+            annotation,
+            manifest_annotation,
+            image_label,
+            image_env,
+            include,
+            include_layer,
+            bare,
+            base_import_paths,
+            preserve_import_paths,
             repo,
             cargo_args,
         } => {
-            let config = Config::load()?;
+            let started = Instant::now();
+
+            // Local outputs replace the registry unless --push is given explicitly
+            let local_output = load.is_some() || tarball.is_some() || load_into.is_some();
+            let push = push.unwrap_or(!no_push && !local_output && oci_layout.is_none());
+            if verify && !push {
+                anyhow::bail!("--verify checks a pushed image and can't be used without pushing");
+            }
+            if (sign || sign_key.is_some()) && !push {
+                anyhow::bail!(
+                    "--sign and --sign-key sign a pushed image and can't be used without pushing"
+                );
+            }
+            let load = load.map(ImageStore::resolve).transpose()?;
+            if load == Some(ImageStore::Docker) && containerd_namespace.is_some() {
+                anyhow::bail!(
+                    "--containerd-namespace applies to containerd image stores, but the image is loaded into docker"
+                );
+            }
             let project_path = path.unwrap_or_else(|| PathBuf::from("."));
+            let git_source = GitSource::parse(&project_path.to_string_lossy());
+            let project_path = match &git_source {
+                Some(source) => source.checkout(&Paths::new().cache_dir)?,
+                None => project_path,
+            };
+            let project_path = package_dir(&project_path, package.as_deref())?;
+            let mut config = Config::load_for_project(&project_path, config_profile)?;
+            let tag = config.tags(tag);
+            config.registry.create_repositories |= create_repo;
+            if let Some(naming) = Naming::from_flags(bare, base_import_paths, preserve_import_paths)
+            {
+                config.naming = naming;
+            }
+            if let Some(libc) = libc {
+                config.build.libc = libc;
+            }
+            if oci {
+                config.build.media_types = MediaTypeFamily::Oci;
+            } else if docker {
+                config.build.media_types = MediaTypeFamily::Docker;
+            }
+            config.build.locked |= locked || frozen;
+            config.build.offline |= offline || frozen;
+            config.build.offline_images |= offline_images;
+            if config.build.offline_images {
+                if push {
+                    anyhow::bail!(
+                        "--offline-images can't push to a registry; write to --tarball, --oci-layout or --artifact-dir, or pass --no-push"
+                    );
+                }
+                if load.is_some() || load_into.is_some() {
+                    anyhow::bail!(
+                        "--offline-images can only write to --tarball, --oci-layout or --artifact-dir"
+                    );
+                }
+                if pin_base {
+                    anyhow::bail!("--pin-base resolves the base image from its registry and can't be used with --offline-images");
+                }
+            }
+            config.build.verify_base |= verify_base;
+            if config.build.verify_base && config.build.offline_images {
+                anyhow::bail!(
+                    "Verifying the base image reads its signatures from the registry and can't be done with --offline-images"
+                );
+            }
+            config.build.scan |= scan;
+            if let Some(severity) = scan_severity {
+                config.scan.severity = severity;
+            }
+            if build_timeout.is_some() {
+                config.build.timeout_secs = build_timeout;
+            }
+            if let Some(sbom) = sbom {
+                config.build.sbom = sbom;
+            }
+            // Chosen now so a missing scanner fails before resolving or building anything
+            let scanner = config
+                .build
+                .scan
+                .then(|| scanner(&config.scan, &config.registry))
+                .transpose()?;
+
+            if config.build.sbom != SbomFormat::None && !push {
+                warn!("SBOMs are only attached to pushed images; none will be generated");
+                config.build.sbom = SbomFormat::None;
+            }
 
             // Load project-specific config from Cargo.toml
-            let project_config = Config::load_project_config(&project_path)?;
+            let mut project_config = config.project_config(&project_path)?;
+            if profile.is_some() {
+                project_config.profile = profile;
+            }
+            // Labels from git, then the project config, then flags
+            let mut labels = source_labels(&project_path);
+            labels.extend(std::mem::take(&mut project_config.labels));
+            labels.extend(image_label);
+            project_config.labels = labels;
+            project_config.image_env.extend(image_env);
+            // Included paths in the project config are relative to the
+            // project, and those from flags to the working directory
+            project_config.include = project_config
+                .include
+                .iter()
+                .map(|i| i.relative_to(&project_path))
+                .chain(include)
+                .collect();
+            project_config.include_layer |= include_layer;
 
-            // Determine base image (project config takes precedence)
+            // Determine base image (project config takes precedence, with
+            // local images relative to the project)
             let base_image = project_config
                 .base_image
+                .as_deref()
+                .map(|image| base_image_relative_to(image, &project_path))
                 .unwrap_or(config.base_image.clone());
+            if let Some(warning) = config.build.libc.base_image_warning(&base_image) {
+                warn!("{}", warning);
+            }
 
-            // Build repository name from KRUST_REPO and project name
-            let repo = repo.context("KRUST_REPO must be set")?;
-            let project_name = get_project_name(&project_path)?;
-            let target_repo = format!("{}/{}", repo, project_name);
+            // Build repository names from KRUST_REPO and the package, one
+            // per binary with --all-bins
+            let repo = match &load_into {
+                Some(cluster) => repo.unwrap_or_else(|| cluster.default_repo().to_string()),
+                None => config.repo(repo)?,
+            };
+            let (package, package_path) = root_package_path(
+                &project_path,
+                git_source.as_ref().map(GitSource::name).as_deref(),
+            )?;
+            let image_name = |image: &str, target: &str| {
+                config.naming.repository(&ImageName {
+                    repo: &repo,
+                    package: &package.name,
+                    target,
+                    image,
+                    path: &package_path,
+                })
+            };
+            // Library-only packages can still build examples
+            if !cargo_args.iter().any(|a| a == "--example") {
+                package.ensure_binaries()?;
+            }
+            let binaries = image_binaries(&package.binaries(), bins, entrypoint)?;
+            if !binaries.is_empty() && cargo_args.iter().any(|a| a == "--bin" || a == "--example") {
+                anyhow::bail!(
+                    "--bins selects the binaries and can't be combined with --bin or --example"
+                );
+            }
+            let images: Vec<(String, Vec<String>)> = if all_bins {
+                if cargo_args.iter().any(|a| a == "--bin" || a == "--example") {
+                    anyhow::bail!("--all-bins builds every binary and can't be combined with --bin or --example");
+                }
+                package
+                    .binaries()
+                    .into_iter()
+                    .map(|bin| {
+                        let mut args = cargo_args.clone();
+                        args.extend(["--bin".to_string(), bin.to_string()]);
+                        (image_name(&package.image_name(bin), bin), args)
+                    })
+                    .collect()
+            } else {
+                let target = cargo_target(&cargo_args)
+                    .or(binaries.first().map(String::as_str))
+                    .or(package.default_binary().ok())
+                    .unwrap_or(&package.name)
+                    .to_string();
+                vec![(image_name(&package.name, &target), cargo_args)]
+            };
+            if let Some((name, _)) = images
+                .iter()
+                .enumerate()
+                .find(|(i, (name, _))| images[..*i].iter().any(|(other, _)| other == name))
+                .map(|(_, image)| image)
+            {
+                anyhow::bail!(
+                    "--all-bins builds an image per binary, but naming {} gives several of them the name {}; \
+                     use a naming template with {{target}}",
+                    config.naming,
+                    name
+                );
+            }
 
             // Initialize registry client
-            let mut registry_client = RegistryClient::new()?;
+            let mut registry_client =
+                registry_client(&config)?.with_offline(config.build.offline_images);
+            if push {
+                for (target_repo, _) in &images {
+                    registry_client.ensure_repository(target_repo).await?;
+                }
+            }
+
+            // Build every platform from the same base image
+            let base_digest =
+                resolve_base_digest(&mut registry_client, &project_path, &base_image, pin_base)
+                    .await?;
+            verify_base_image(
+                &config,
+                &mut registry_client,
+                &base_image,
+                base_digest.as_deref(),
+            )
+            .await?;
+            let base_reference = match &base_digest {
+                Some(digest) => format!(
+                    "{}@{}",
+                    ImageReference::parse(&base_image)?.repository_url(),
+                    digest
+                ),
+                None => base_image.clone(),
+            };
 
             // Determine platforms to build for
             let platforms = if let Some(platforms) = platform {
                 // Use explicitly specified platforms
                 platforms
+            } else if let Some(cluster) = &load_into {
+                // Clusters run images for their nodes' architecture
+                vec![cluster.platform()]
+            } else if local_output {
+                // Local image stores run images for the host
+                vec![Platform::host()]
+            } else if !project_config.platforms.is_empty() {
+                // Use the platforms the project is configured for
+                project_config.platforms.clone()
+            } else if !config.platforms.is_empty() {
+                // Use the platforms configured for every project
+                config.platforms.clone()
+            } else if is_scratch(&base_image) {
+                // scratch has no platforms of its own
+                default_platforms()
             } else {
                 // Detect platforms from base image
                 info!(
@@ -69,94 +380,276 @@ async fn main() -> Result<()> {
                     base_image
                 );
                 // Get auth for the base image registry
-                let base_auth = resolve_auth(&base_image)?;
+                let base_auth = registry_client.auth_for(&base_image).await?;
 
                 match registry_client
-                    .get_image_platforms(&base_image, &base_auth)
+                    .get_image_platforms(&base_reference, &base_auth)
                     .await
                 {
                     Ok(detected_platforms) => {
                         if detected_platforms.is_empty() {
                             info!("No platforms detected, using defaults");
-                            vec!["linux/amd64".to_string(), "linux/arm64".to_string()]
+                            default_platforms()
                         } else {
-                            info!("Detected platforms: {:?}", detected_platforms);
+                            info!(
+                                "Detected platforms: {}",
+                                join_platforms(&detected_platforms)
+                            );
                             detected_platforms
                         }
                     }
                     Err(e) => {
                         info!("Failed to detect platforms: {}. Using defaults.", e);
-                        vec!["linux/amd64".to_string(), "linux/arm64".to_string()]
+                        default_platforms()
                     }
                 }
             };
 
-            // Build for each platform concurrently
-            let mut tasks = Vec::new();
+            // Offline, fail before building if any of the base is missing
+            if let (Some(cache), Some(digest)) = (
+                registry_client
+                    .manifest_cache()
+                    .filter(|_| registry_client.is_offline()),
+                &base_digest,
+            ) {
+                // Outputs that copy the whole image need the base layers too
+                let layers = tarball.is_some()
+                    || oci_layout.is_some()
+                    || artifact_dir.is_some()
+                    || scanner.is_some();
+                let missing = missing_content(cache, &base_image, digest, &platforms, layers)?;
+                if !missing.is_empty() {
+                    anyhow::bail!(
+                        "--offline-images needs base image content that isn't in the local cache:\n  {}\nBuild once with registry access, writing to --tarball or --oci-layout, to cache it",
+                        missing.join("\n  ")
+                    );
+                }
+            }
 
-            for platform_str in platforms.clone() {
-                let project_path = project_path.clone();
-                let base_image = base_image.clone();
-                let target_repo = target_repo.clone();
-                let cargo_args = cargo_args.clone();
-                let no_push_flag = no_push;
+            // Compile platforms concurrently, up to one per CPU by default
+            let build_concurrency = build_concurrency.map(|n| n as usize).unwrap_or_else(|| {
+                std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(1)
+            });
+            let build_slots = Arc::new(Semaphore::new(build_concurrency));
 
-                let task = tokio::spawn(async move {
-                    let descriptor = build_and_push_platform(
-                        &project_path,
-                        &base_image,
-                        &target_repo,
-                        &platform_str,
-                        cargo_args,
-                        !no_push_flag,
-                    )
-                    .await?;
+            // Flags override annotations set in the project config
+            let mut annotations = IndexAnnotations {
+                index: project_config.annotations.clone(),
+                manifests: project_config.manifest_annotations.clone(),
+            };
+            annotations.index.extend(annotation);
+            annotations.manifests.extend(manifest_annotation);
 
-                    Ok::<_, anyhow::Error>(descriptor)
-                });
+            let mut metadata = BuildMetadata::new(&base_image, base_digest.as_deref());
 
-                tasks.push(task);
+            // Start the artifact directory afresh, so nothing from an earlier
+            // build sits in the layout beside what the push plan names
+            if let Some(dir) = &artifact_dir {
+                OciLayout::create_empty(dir)?;
             }
 
-            // Wait for all builds to complete
-            let mut manifest_descriptors = Vec::new();
-            for task in tasks {
-                let result = task.await.context("Build task panicked")??;
-                if let Some(descriptor) = result {
-                    manifest_descriptors.push(descriptor);
+            for (target_repo, cargo_args) in images {
+                let mut publishers: Vec<Arc<dyn Publisher>> = Vec::new();
+                if let Some(dir) = artifact_dir.clone() {
+                    publishers.push(Arc::new(
+                        LayoutPublisher::new(
+                            registry_client.clone(),
+                            dir,
+                            &target_repo,
+                            tag.clone(),
+                        )?
+                        .with_annotations(annotations.clone()),
+                    ));
+                }
+                if let Some(dir) = oci_layout.clone() {
+                    publishers.push(Arc::new(
+                        LayoutPublisher::new(
+                            registry_client.clone(),
+                            dir,
+                            &target_repo,
+                            tag.clone(),
+                        )?
+                        .with_annotations(annotations.clone())
+                        .with_push_plan(false),
+                    ));
+                }
+                // Local image stores need a name; use :latest if no tag was given
+                let repo_tags: Vec<String> = if tag.is_empty() {
+                    vec![format!("{}:latest", target_repo)]
+                } else {
+                    tag.iter()
+                        .map(|t| format!("{}:{}", target_repo, t))
+                        .collect()
+                };
+                if let Some(path) = tarball.clone() {
+                    publishers.push(Arc::new(
+                        TarballPublisher::new(registry_client.clone(), path, repo_tags.clone())
+                            .with_format(tarball_format)
+                            .with_annotations(annotations.clone()),
+                    ));
+                }
+                if let Some(store) = load {
+                    publishers.push(Arc::new(
+                        DaemonPublisher::new(registry_client.clone(), store, repo_tags.clone())
+                            .with_namespace(containerd_namespace.clone()),
+                    ));
+                }
+                if let Some(cluster) = load_into.clone() {
+                    publishers.push(Arc::new(ClusterPublisher::new(
+                        registry_client.clone(),
+                        cluster,
+                        &target_repo,
+                        tag.clone(),
+                    )));
+                }
+                if push {
+                    let signer = signer(&config, sign || config.sign, sign_key.as_deref())?;
+                    publishers.push(Arc::new(
+                        RegistryPublisher::new(registry_client.clone(), &target_repo, tag.clone())
+                            .with_verify(verify)
+                            .with_debuginfo(config.build.push_debuginfo)
+                            .with_signer(signer)
+                            .with_annotations(annotations.clone()),
+                    ));
                 }
-            }
 
-            // Always push manifest list if not --no-push (even for single platform)
-            if !no_push {
-                let image_ref = push_tagged_manifest_list(
-                    &mut registry_client,
+                if platforms.len() > 1 {
+                    if let Some(publisher) = publishers.iter().find(|p| !p.multi_platform()) {
+                        anyhow::bail!(
+                            "{} output holds a single platform but {} were requested; choose one with --platform",
+                            publisher.name(),
+                            platforms.len()
+                        );
+                    }
+                }
+
+                // Build for each platform concurrently
+                let mut tasks = Vec::new();
+
+                let platform_build = PlatformBuild {
+                    project_path: project_path.clone(),
+                    base_image: base_image.clone(),
+                    cargo_args,
+                    build_config: config.build.with_project(&project_config),
+                    project_config: project_config.clone(),
+                    registry_client: registry_client.clone(),
+                    build_slots: build_slots.clone(),
+                    rebuild,
+                    base_digest: base_digest.clone(),
+                    binaries: binaries.clone(),
+                };
+
+                for platform in platforms.clone() {
+                    let platform_build = platform_build.clone();
+                    let publishers = publishers.clone();
+                    let scanner = scanner.clone();
+                    let scan_config = config.scan.clone();
+
+                    let task = tokio::spawn(async move {
+                        let image = build_platform(&platform_build, &platform).await?;
+
+                        // Nothing is published from an image that fails its scan
+                        if let Some(scanner) = scanner {
+                            scan_image(
+                                scanner.as_ref(),
+                                &scan_config,
+                                &image,
+                                &mut platform_build.registry_client.clone(),
+                            )
+                            .await?;
+                        }
+
+                        let mut descriptors = Vec::new();
+                        for publisher in &publishers {
+                            descriptors.push(publisher.publish_image(&image).await?);
+                        }
+
+                        let descriptor = image.descriptor(&platform_build.registry_client)?;
+                        let summary = PlatformMetadata::new(&image, &descriptor);
+                        Ok::<_, anyhow::Error>((descriptors, (descriptor, summary)))
+                    });
+
+                    tasks.push(task);
+                }
+
+                // Wait for all builds to complete, collecting descriptors per publisher
+                let mut manifest_descriptors = vec![Vec::new(); publishers.len()];
+                let (mut image_descriptors, mut platform_metadata) = (Vec::new(), Vec::new());
+                for task in tasks {
+                    let (descriptors, (image_descriptor, summary)) =
+                        task.await.context("Build task panicked")??;
+                    for (i, descriptor) in descriptors.into_iter().enumerate() {
+                        manifest_descriptors[i].push(descriptor);
+                    }
+                    image_descriptors.push(image_descriptor);
+                    platform_metadata.push(summary);
+                }
+                let mut image_metadata = ImageMetadata::new(
                     &target_repo,
-                    manifest_descriptors,
                     &tag,
-                )
-                .await?;
+                    image_descriptors,
+                    &annotations,
+                    registry_client.json_format(),
+                    platform_metadata,
+                )?;
 
-                // Output the manifest list reference (always by digest)
-                println!("{}", image_ref);
-            } else {
-                info!(
-                    "Successfully built image for {} platform(s)",
-                    platforms.len()
-                );
-                info!("Skipping push (--no-push specified)");
+                if publishers.is_empty() {
+                    info!(
+                        "Successfully built image for {} platform(s)",
+                        platforms.len()
+                    );
+                    info!("Skipping push (--no-push specified)");
+                }
+
+                for (publisher, descriptors) in publishers.iter().zip(manifest_descriptors) {
+                    // Output the published reference (always by digest for registries)
+                    if let Some(reference) = publisher.finish(descriptors).await? {
+                        println!("{}", reference);
+                        image_metadata.references.push(reference);
+                    }
+                }
+                metadata.images.push(image_metadata);
+            }
+
+            let metadata = metadata.with_duration(started.elapsed());
+            if let Some(path) = metadata_file {
+                metadata.write(&path)?;
+            }
+            if github_outputs {
+                github::write(&metadata)?;
             }
         }
         Commands::Resolve {
             filenames,
             platform,
+            bare,
+            base_import_paths,
+            preserve_import_paths,
             repo,
             tag,
+            load_into,
         } => {
-            let resolved_yaml = resolve_yaml_files(filenames, platform, repo, tag).await?;
+            let mut config = Config::load_for_project(Path::new("."), config_profile)?;
+            if let Some(naming) = Naming::from_flags(bare, base_import_paths, preserve_import_paths)
+            {
+                config.naming = naming;
+            }
+            let mut registry_client = registry_client(&config)?;
+            let resolved = resolve_yaml_files(
+                &config,
+                &mut registry_client,
+                filenames,
+                platform,
+                repo,
+                tag,
+                load_into,
+            )
+            .await?;
 
             // Output all documents separated by ---
-            for (i, doc) in resolved_yaml.iter().enumerate() {
+            for (i, (_, doc)) in resolved.documents.iter().enumerate() {
                 if i > 0 {
                     println!("---");
                 }
@@ -166,13 +659,37 @@ async fn main() -> Result<()> {
         Commands::Apply {
             filenames,
             platform,
+            bare,
+            base_import_paths,
+            preserve_import_paths,
             repo,
             tag,
+            load_into,
         } => {
-            let resolved_yaml = resolve_yaml_files(filenames, platform, repo, tag).await?;
+            let mut config = Config::load_for_project(Path::new("."), config_profile)?;
+            if let Some(naming) = Naming::from_flags(bare, base_import_paths, preserve_import_paths)
+            {
+                config.naming = naming;
+            }
+            let mut registry_client = registry_client(&config)?;
+            let resolved = resolve_yaml_files(
+                &config,
+                &mut registry_client,
+                filenames,
+                platform,
+                repo,
+                tag,
+                load_into,
+            )
+            .await?;
 
             // Combine all documents and pipe to kubectl
-            let combined_yaml = resolved_yaml.join("---\n");
+            let docs: Vec<&str> = resolved
+                .documents
+                .iter()
+                .map(|(_, doc)| doc.as_str())
+                .collect();
+            let combined_yaml = docs.join("---\n");
 
             // Execute kubectl apply
             let mut kubectl = std::process::Command::new("kubectl")
@@ -196,6 +713,300 @@ async fn main() -> Result<()> {
                 std::process::exit(status.code().unwrap_or(1));
             }
         }
+        Commands::Bundle {
+            filenames,
+            platform,
+            bare,
+            base_import_paths,
+            preserve_import_paths,
+            repo,
+            tag,
+            output,
+            artifact,
+        } => {
+            let mut config = Config::load_for_project(Path::new("."), config_profile)?;
+            if let Some(naming) = Naming::from_flags(bare, base_import_paths, preserve_import_paths)
+            {
+                config.naming = naming;
+            }
+            let mut registry_client = registry_client(&config)?;
+            let resolved = resolve_yaml_files(
+                &config,
+                &mut registry_client,
+                filenames,
+                platform,
+                repo,
+                tag,
+                None,
+            )
+            .await?;
+            let bundle = Bundle::new(resolved.images.into_iter().collect(), resolved.documents);
+
+            if let Some(path) = output {
+                bundle.write(&path)?;
+                info!("Wrote bundle to {}", path.display());
+                println!("{}", path.display());
+            }
+
+            if let Some(target) = artifact {
+                let auth = registry_client.auth_for(&target).await?;
+                let bundle_ref = with_deadline(
+                    &config.registry,
+                    &format!("pushing bundle to {}", target),
+                    bundle.push(&mut registry_client, &target, &auth),
+                )
+                .await?;
+                println!("{}", bundle_ref);
+            }
+        }
+        Commands::Copy { src, dst } => {
+            let config = Config::load(config_profile)?;
+            let mut registry_client = registry_client(&config)?;
+            let src_auth = registry_client.auth_for(&src).await?;
+            let dst_auth = registry_client.auth_for(&dst).await?;
+            registry_client.ensure_repository(&dst).await?;
+            let image_ref = with_deadline(
+                &config.registry,
+                &format!("copying {} to {}", src, dst),
+                registry_client.copy_image(&src, &dst, &src_auth, &dst_auth),
+            )
+            .await?;
+
+            info!("Copied {} to {}", src, image_ref);
+            println!("{}", image_ref);
+        }
+        Commands::Inspect {
+            image,
+            platform,
+            json,
+        } => {
+            let config = Config::load(config_profile)?;
+            let mut registry_client = registry_client(&config)?;
+            let auth = registry_client.auth_for(&image).await?;
+            let details = with_deadline(
+                &config.registry,
+                &format!("inspecting {}", image),
+                inspect_image(&mut registry_client, &image, platform.as_ref(), &auth),
+            )
+            .await?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&details)?);
+            } else {
+                print!("{}", format_table(&details));
+            }
+        }
+        Commands::Digest { image } => {
+            let config = Config::load(config_profile)?;
+            let mut registry_client = registry_client(&config)?;
+            let auth = registry_client.auth_for(&image).await?;
+            let digest = with_deadline(
+                &config.registry,
+                &format!("resolving {}", image),
+                registry_client.get_manifest_digest(&image, &auth),
+            )
+            .await?;
+            println!("{}", digest);
+        }
+        Commands::Tag { image, tags } => {
+            let config = Config::load(config_profile)?;
+            let mut registry_client = registry_client(&config)?;
+            let auth = registry_client.auth_for(&image).await?;
+            let tagged = with_deadline(
+                &config.registry,
+                &format!("tagging {}", image),
+                registry_client.tag_image(&image, &tags, &auth),
+            )
+            .await?;
+            for image_ref in tagged {
+                println!("{}", image_ref);
+            }
+        }
+        Commands::Tags { repo, json } => {
+            let config = Config::load(config_profile)?;
+            let mut registry_client = registry_client(&config)?;
+            let auth = registry_client.auth_for(&repo).await?;
+            let tags = with_deadline(
+                &config.registry,
+                &format!("listing tags for {}", repo),
+                registry_client.list_tags(&repo, &auth),
+            )
+            .await?;
+
+            if json {
+                let output = serde_json::json!({ "name": repo, "tags": tags });
+                println!("{}", serde_json::to_string_pretty(&output)?);
+            } else {
+                for tag in tags {
+                    println!("{}", tag);
+                }
+            }
+        }
+        Commands::DeleteImage { image } => {
+            let config = Config::load(config_profile)?;
+            let mut registry_client = registry_client(&config)?;
+            let auth = registry_client.auth_for(&image).await?;
+            let deleted_refs = with_deadline(
+                &config.registry,
+                &format!("deleting {}", image),
+                registry_client.delete_image(&image, &auth),
+            )
+            .await?;
+            for deleted in deleted_refs {
+                println!("{}", deleted);
+            }
+        }
+        Commands::PruneRemote {
+            repo,
+            keep,
+            older_than,
+            dry_run,
+        } => {
+            let older_than = older_than.as_deref().map(parse_age).transpose()?;
+            let policy = RetentionPolicy { keep, older_than };
+            let config = Config::load(config_profile)?;
+            with_deadline(
+                &config.registry,
+                &format!("pruning {}", repo),
+                prune_remote(&repo, &policy, dry_run, &config),
+            )
+            .await?;
+        }
+        Commands::Push { dir } => {
+            let plan = PushPlan::read(&dir)?;
+            let layout = OciLayout::open(&dir)?;
+
+            info!(
+                "Pushing {} ({}) from {}",
+                plan.repository,
+                plan.platforms.join(", "),
+                dir.display()
+            );
+
+            let config = Config::load(config_profile)?;
+            let mut registry_client = registry_client(&config)?;
+            let auth = registry_client.auth_for(&plan.repository).await?;
+            registry_client.ensure_repository(&plan.repository).await?;
+            let pushed = with_deadline(
+                &config.registry,
+                &format!("pushing {}", plan.repository),
+                layout.push_to_registry(
+                    &mut registry_client,
+                    &plan.repository,
+                    &plan.digest,
+                    &plan.tags,
+                    &auth,
+                ),
+            )
+            .await?;
+            println!("{}", pushed);
+        }
+        Commands::Serve {
+            listen,
+            insecure_no_auth,
+            repo,
+        } => {
+            let config = Config::load_for_project(Path::new("."), config_profile)?;
+            let registry_client = registry_client(&config)?;
+            let listener = tokio::net::TcpListener::bind(listen)
+                .await
+                .with_context(|| format!("Failed to listen on {}", listen))?;
+            info!("Serving builds on http://{}", listener.local_addr()?);
+            serve(
+                listener,
+                Arc::new(BuildServer {
+                    repo: repo.or_else(|| config.default_registry.clone()),
+                    config,
+                    registry_client,
+                }),
+                insecure_no_auth,
+            )
+            .await?;
+        }
+        Commands::Cache { command } => {
+            let cache_dir = Paths::new().cache_dir;
+            match command {
+                CacheCommand::Ls { json } => {
+                    let entries = cache::entries(&cache_dir)?;
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&entries)?);
+                    } else {
+                        print!("{}", format_cache_table(&entries));
+                    }
+                }
+                CacheCommand::Prune {
+                    older_than,
+                    max_size,
+                    dry_run,
+                } => {
+                    let older_than = older_than
+                        .as_deref()
+                        .map(|age| parse_age(age)?.to_std().context("Age must not be negative"))
+                        .transpose()?;
+                    let max_size = max_size.as_deref().map(parse_size).transpose()?;
+                    let entries = cache::entries(&cache_dir)?;
+                    let prune = cache::prunable(&entries, older_than, max_size);
+                    let mut freed = 0;
+                    for entry in prune {
+                        if !dry_run {
+                            entry.remove()?;
+                        }
+                        freed += entry.size;
+                        println!("{}", entry.path.display());
+                    }
+                    info!(
+                        "{} {} from {}",
+                        if dry_run { "Would free" } else { "Freed" },
+                        HumanBytes(freed),
+                        cache_dir.display()
+                    );
+                }
+                CacheCommand::Clear => {
+                    let freed = cache::clear(&cache_dir)?;
+                    info!("Freed {} from {}", HumanBytes(freed), cache_dir.display());
+                }
+            }
+        }
+        Commands::Config { command } => match command {
+            ConfigCommand::Path => {
+                println!("{}", Paths::new().config_file().display());
+            }
+            ConfigCommand::View { resolved: false } => {
+                let path = Paths::new().config_file();
+                if path.exists() {
+                    print!("{}", std::fs::read_to_string(&path)?);
+                } else {
+                    info!("No config file at {}", path.display());
+                }
+            }
+            ConfigCommand::View { resolved: true } => {
+                let mut config = Config::load(config_profile)?;
+                config.profile.clear();
+                print!("{}", toml::to_string_pretty(&config)?);
+            }
+            ConfigCommand::Get { key } => {
+                print!("{}", Config::load(config_profile)?.get(&key)?);
+            }
+            ConfigCommand::Set { key, value } => {
+                let path = Config::set(&key, &value, config_profile)?;
+                info!("Set {} in {}", key, path.display());
+            }
+            ConfigCommand::Validate { path } => {
+                let report = Config::validate(&path)?;
+                for problem in &report.problems {
+                    println!("{}", problem);
+                }
+                if !report.problems.is_empty() {
+                    anyhow::bail!(
+                        "Found {} problem(s) in krust's configuration",
+                        report.problems.len()
+                    );
+                }
+                for file in &report.files {
+                    println!("{}: OK", file.display());
+                }
+            }
+        },
         Commands::Version => {
             println!("krust {}", env!("CARGO_PKG_VERSION"));
         }
@@ -204,113 +1015,274 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-/// Build a binary and push an image for a single platform.
-/// Returns a ManifestDescriptor if push is true, None otherwise.
-async fn build_and_push_platform(
-    project_path: &Path,
-    base_image: &str,
-    target_repo: &str,
-    platform_str: &str,
+/// Settings shared by every per-platform build of a project
+#[derive(Debug, Clone)]
+struct PlatformBuild {
+    project_path: PathBuf,
+    base_image: String,
     cargo_args: Vec<String>,
-    push: bool,
-) -> Result<Option<ManifestDescriptor>> {
-    info!("Building for platform: {}", platform_str);
+    build_config: BuildConfig,
+    project_config: ProjectConfig,
+    /// Shared so per-platform builds reuse one session and its blob cache
+    registry_client: RegistryClient,
+    /// Limits how many platforms compile at once
+    build_slots: Arc<Semaphore>,
+    /// Build even when the sources are unchanged since the cached build
+    rebuild: bool,
+    /// Digest the base image was resolved to, unless it's scratch
+    base_digest: Option<String>,
+    /// Binaries to build into the image with --bins, the entrypoint first.
+    /// Empty to build the package's default binary.
+    binaries: Vec<String>,
+}
+
+/// Build a binary and its container image for a single platform.
+async fn build_platform(build: &PlatformBuild, platform: &Platform) -> Result<PlatformImage> {
+    let PlatformBuild {
+        project_path,
+        base_image,
+        cargo_args,
+        build_config,
+        project_config,
+        registry_client,
+        build_slots,
+        rebuild,
+        base_digest,
+        binaries,
+    } = build;
+
+    info!("Building for platform: {}", platform);
 
     // Build the Rust binary for this platform
-    let target = get_rust_target_triple(platform_str)?;
-    let builder = RustBuilder::new(project_path, &target).with_cargo_args(cargo_args);
-    let build_result = builder.build()?;
+    let target = rust_target_triple(platform, build_config.libc)?;
+    events::emit(Event::BuildStarted {
+        platform: platform.to_string(),
+        target: target.clone(),
+    });
+    let cargo_args = [build_config.cargo_args.clone(), cargo_args.clone()].concat();
+    let builder = RustBuilder::new(project_path, &target)
+        .with_cargo_args(cargo_args.clone())
+        .with_env(build_config.env.clone())
+        .with_target_dir(build_config.target_dir.clone())
+        .with_backend(build_config.backend_for(platform))
+        .with_toolchain(build_config.toolchain.clone())
+        .with_strip(build_config.strip)
+        .with_optimization(build_config.opt_level.clone(), build_config.lto.clone())
+        .with_profile(project_config.profile.clone())
+        .with_features(project_config.features_for(platform))
+        .with_no_default_features(project_config.no_default_features)
+        .with_split_debuginfo(build_config.split_debuginfo)
+        .with_auditable(build_config.auditable)
+        .with_locked(build_config.locked)
+        .with_offline(build_config.offline)
+        .with_timeout(build_config.timeout_secs.map(Duration::from_secs))
+        .with_cache_dir((!build_config.no_cache).then(|| Paths::new().cache_dir.join("builds")))
+        .with_rebuild(*rebuild)
+        .with_hooks(
+            project_config.pre_build.clone(),
+            project_config.post_build.clone(),
+        );
+    // With --bins, each binary is built on its own so the build cache and
+    // cargo's binary selection work per binary. The first is the entrypoint.
+    let builders: Vec<RustBuilder> = if binaries.is_empty() {
+        vec![builder]
+    } else {
+        binaries
+            .iter()
+            .map(|bin| {
+                let mut args = cargo_args.clone();
+                args.extend(["--bin".to_string(), bin.clone()]);
+                builder.clone().with_cargo_args(args)
+            })
+            .collect()
+    };
+    let sbom_format = build_config.sbom;
+    let (mut build_results, sbom) = {
+        let _slot = build_slots.acquire().await.context("Build slots closed")?;
+        tokio::task::spawn_blocking(move || {
+            let results = builders
+                .iter()
+                .map(RustBuilder::build)
+                .collect::<Result<Vec<_>>>()?;
+            // Binaries built with --bins come from the same package, so one
+            // SBOM covers them all
+            let sbom = builders[0].sbom(sbom_format, &results[0].binary_path)?;
+            anyhow::Ok((results, sbom))
+        })
+        .await
+        .context("Build task panicked")??
+    };
+    let build_result = build_results.remove(0);
 
     // Build container image for this platform
     let image_builder = ImageBuilder::new(
         build_result.binary_path,
         base_image.to_string(),
-        platform_str.to_string(),
-    );
+        platform.clone(),
+    )
+    .with_binaries(build_results.into_iter().map(|r| r.binary_path).collect())
+    .with_ownership(project_config.ownership.clone())
+    .with_entrypoint(project_config.entrypoint.clone())
+    .with_args(project_config.args.clone())
+    .with_working_dir(project_config.working_dir.clone())
+    .with_labels(project_config.labels.clone())
+    .with_env(project_config.image_env.clone())
+    .with_ports(project_config.ports.clone())
+    .with_volumes(project_config.volumes.clone())
+    .with_stop_signal(project_config.stop_signal.clone())
+    .with_includes(project_config.include.clone())
+    .with_include_layer(project_config.include_layer)
+    .with_ca_certs(project_config.include_ca_certs)
+    .with_media_types(build_config.media_types)
+    .with_compression(build_config.compression)
+    .with_base_digest(base_digest.clone());
 
-    // Create a registry client for this task
-    let mut registry_client = RegistryClient::new()?;
+    // Clone the shared registry client for this task
+    let mut registry_client = registry_client.clone();
 
-    let base_auth = resolve_auth(base_image)?;
+    let base_auth = if is_scratch(base_image) || LocalImage::parse(base_image).is_some() {
+        RegistryAuth::Anonymous
+    } else {
+        registry_client.auth_for(base_image).await?
+    };
     let (config_data, layer_data, manifest) = image_builder
         .build(&mut registry_client, &base_auth)
         .await?;
 
-    if !push {
-        return Ok(None);
-    }
+    Ok(PlatformImage {
+        platform: platform.clone(),
+        config_data,
+        layer_data,
+        manifest,
+        base_image: base_image.clone(),
+        base_auth,
+        debuginfo: build_result.debug_path,
+        sbom,
+    })
+}
 
-    info!("Pushing image for platform: {}", platform_str);
-
-    let push_auth = resolve_auth(target_repo)?;
-    let app_layer_media_type = manifest
-        .layers
-        .last()
-        .map(|l| l.media_type.clone())
-        .unwrap_or_else(|| "application/vnd.oci.image.layer.v1.tar+gzip".to_string());
-
-    let (digest_ref, manifest_size) = registry_client
-        .push_layered_image(
-            target_repo,
-            config_data,
-            layer_data,
-            app_layer_media_type,
-            &manifest,
-            &push_auth,
-            base_image,
-            &base_auth,
-        )
-        .await?;
+/// Delete images in a remote repository that fall outside the retention policy
+async fn prune_remote(
+    repo: &str,
+    policy: &RetentionPolicy,
+    dry_run: bool,
+    config: &Config,
+) -> Result<()> {
+    let mut registry_client = registry_client(config)?;
+    let auth = registry_client.auth_for(repo).await?;
 
-    let (os, arch, variant) = parse_platform_string(platform_str)?;
-    let digest = digest_ref.split('@').next_back().unwrap_or("").to_string();
+    let tags = registry_client.list_tags(repo, &auth).await?;
+    info!("Found {} tag(s) in {}", tags.len(), repo);
 
-    info!("Pushed platform image: {} ({})", digest_ref, platform_str);
+    // Resolve each tag to the digest it points at
+    let mut tagged = Vec::new();
+    for tag in tags {
+        let (_, _, digest) = registry_client
+            .get_manifest_raw(&format!("{}:{}", repo, tag), &auth)
+            .await?;
+        tagged.push((tag, digest));
+    }
 
-    Ok(Some(ManifestDescriptor {
-        media_type: "application/vnd.oci.image.manifest.v1+json".to_string(),
-        size: manifest_size as i64,
-        digest,
-        platform: Platform {
-            architecture: arch,
-            os,
-            variant,
-        },
-    }))
-}
+    let mut images = group_tags_by_digest(tagged);
 
-/// Push a manifest list, optionally tagged.
-async fn push_tagged_manifest_list(
-    registry_client: &mut RegistryClient,
-    target_repo: &str,
-    manifest_descriptors: Vec<ManifestDescriptor>,
-    tag: &Option<String>,
-) -> Result<String> {
-    info!("Creating and pushing manifest list...");
+    // Images pushed by digest only, where the registry can list them
+    match registry_client.list_untagged(repo, &auth).await? {
+        Some(untagged) => {
+            info!("Found {} untagged digest(s) in {}", untagged.len(), repo);
+            images.extend(
+                untagged
+                    .into_iter()
+                    .filter(|digest| !images.iter().any(|image| image.digest == *digest))
+                    .map(|digest| RemoteImage {
+                        digest,
+                        tags: Vec::new(),
+                        created: None,
+                        children: Vec::new(),
+                    })
+                    .collect::<Vec<_>>(),
+            );
+        }
+        None => info!(
+            "{} can't list untagged images; only tagged images are considered",
+            repo
+        ),
+    }
 
-    let has_tag = tag.is_some();
-    let manifest_target = if let Some(tag_name) = tag {
-        format!("{}:{}", target_repo, tag_name)
-    } else {
-        target_repo.to_string()
-    };
+    for image in &mut images {
+        let image_ref = format!("{}@{}", repo, image.digest);
+        image.children = registry_client.image_children(&image_ref, &auth).await?;
+    }
+    let mut images = without_children(images);
+    for image in &mut images {
+        image.created = registry_client
+            .get_image_created(&format!("{}@{}", repo, image.digest), &auth)
+            .await?;
+        if image.created.is_none() {
+            info!(
+                "Keeping {} ({}): creation time unknown",
+                image.digest,
+                image.tags.join(", ")
+            );
+        }
+    }
+
+    let selected = policy.select(&images, chrono::Utc::now());
+    if selected.is_empty() {
+        info!("Nothing to prune");
+        return Ok(());
+    }
+
+    // Platform images shared with a kept index stay, and ones shared by
+    // two deleted indexes are only deleted once
+    let retained = retained_digests(&images, &selected);
+    let mut keep = retained.clone();
+    for image in selected {
+        let image_ref = format!("{}@{}", repo, image.digest);
+        if retained.contains(&image.digest) {
+            info!("Keeping {}: a kept index refers to it", image_ref);
+        } else if keep.contains(&image.digest) {
+            continue;
+        } else if dry_run {
+            info!("Would delete {} ({})", image_ref, image.tags.join(", "));
+            println!("{}", image_ref);
+        } else {
+            for deleted in registry_client
+                .delete_image_keeping(&image_ref, &keep, &auth)
+                .await?
+            {
+                println!("{}", deleted);
+                keep.extend(deleted.split('@').nth(1).map(str::to_string));
+            }
+        }
+    }
 
-    let final_auth = resolve_auth(&manifest_target)?;
+    Ok(())
+}
 
-    registry_client
-        .push_manifest_list(&manifest_target, manifest_descriptors, &final_auth, has_tag)
-        .await
+/// YAML files with their krust:// references resolved
+struct ResolvedYaml {
+    /// (filename, resolved content) in input order
+    documents: Vec<(String, String)>,
+    /// krust:// path to the pushed image reference
+    images: HashMap<String, String>,
 }
 
-/// Resolve krust:// references in YAML files
+/// Resolve krust:// references in YAML files, pushing each image or, with
+/// `load_into`, loading it into a local cluster
 async fn resolve_yaml_files(
+    config: &Config,
+    registry_client: &mut RegistryClient,
     filenames: Vec<PathBuf>,
-    platform: Option<Vec<String>>,
+    platform: Option<Vec<Platform>>,
     repo: Option<String>,
-    tag: Option<String>,
-) -> Result<Vec<String>> {
-    let repo = repo.context("KRUST_REPO must be set")?;
-    let config = Config::load()?;
+    tag: Vec<String>,
+    load_into: Option<Cluster>,
+) -> Result<ResolvedYaml> {
+    let repo = match &load_into {
+        Some(cluster) => repo.unwrap_or_else(|| cluster.default_repo().to_string()),
+        None => config.repo(repo)?,
+    };
+    let tag = config.tags(tag);
 
     // Collect all YAML content and find all krust:// references
     let mut all_yaml_files = Vec::new();
@@ -332,83 +1304,509 @@ async fn resolve_yaml_files(
 
     // Build and push images for each unique reference
     let mut replacements = HashMap::new();
-    let mut registry_client = RegistryClient::new()?;
-
     for krust_path in all_references {
-        info!("Building image for: krust://{}", krust_path);
+        let image_ref = build_reference(
+            config,
+            registry_client,
+            &krust_path,
+            platform.as_deref(),
+            &repo,
+            &tag,
+            load_into.as_ref(),
+        )
+        .await?;
+        info!("Resolved krust://{} -> {}", krust_path, image_ref);
+        replacements.insert(krust_path, image_ref);
+    }
 
-        let project_path = PathBuf::from(&krust_path);
-        if !project_path.exists() {
-            anyhow::bail!("Path does not exist: {}", krust_path);
-        }
+    // Replace references in all YAML files and return resolved docs
+    let mut documents = Vec::new();
 
-        let project_name = get_project_name(&project_path)?;
-        let target_repo = format!("{}/{}", repo, project_name);
+    for (filename, content) in &all_yaml_files {
+        info!("Resolving references in: {}", filename);
+        let resolved = replace_krust_references(content, &replacements)?;
+        documents.push((filename.clone(), resolved));
+    }
 
-        let project_config = Config::load_project_config(&project_path)?;
-        let base_image = project_config
-            .base_image
-            .unwrap_or(config.base_image.clone());
+    Ok(ResolvedYaml {
+        documents,
+        images: replacements,
+    })
+}
 
-        let platforms = if let Some(ref platforms) = platform {
-            platforms.clone()
-        } else {
-            vec!["linux/amd64".to_string()]
-        };
+/// Build the project a krust:// reference names and push it or, with
+/// `load_into`, load it into a local cluster. Returns the image reference
+/// to use in its place.
+async fn build_reference(
+    config: &Config,
+    registry_client: &mut RegistryClient,
+    krust_path: &str,
+    platform: Option<&[Platform]>,
+    repo: &str,
+    tag: &[String],
+    load_into: Option<&Cluster>,
+) -> Result<String> {
+    info!("Building image for: krust://{}", krust_path);
+    // Chosen now so a missing scanner or bad key fails before building anything
+    let scanner = config
+        .build
+        .scan
+        .then(|| scanner(&config.scan, &config.registry))
+        .transpose()?;
+    let signer = signer(config, config.sign && load_into.is_none(), None)?;
 
-        // Build for each platform
-        let mut manifest_descriptors = Vec::new();
-        for platform_str in &platforms {
-            if let Some(descriptor) = build_and_push_platform(
-                &project_path,
-                &base_image,
-                &target_repo,
-                platform_str,
-                Vec::new(),
-                true,
-            )
-            .await?
-            {
-                manifest_descriptors.push(descriptor);
+    // In git references the fragment is the revision, not a member
+    let git_source = GitSource::parse(krust_path);
+    let project_path = match &git_source {
+        Some(source) => source.checkout(&Paths::new().cache_dir)?,
+        None => {
+            let (path, package) = split_package(krust_path);
+            let project_path = PathBuf::from(path);
+            if !project_path.exists() {
+                anyhow::bail!("Path does not exist: {}", path);
             }
+            package_dir(&project_path, package)?
         }
+    };
 
-        // Push manifest list
-        let image_ref = push_tagged_manifest_list(
-            &mut registry_client,
+    let (package, package_path) = root_package_path(
+        &project_path,
+        git_source.as_ref().map(GitSource::name).as_deref(),
+    )?;
+    package.ensure_binaries()?;
+    let target_repo = config.naming.repository(&ImageName {
+        repo,
+        package: &package.name,
+        target: package.default_binary().unwrap_or(&package.name),
+        image: &package.name,
+        path: &package_path,
+    });
+    if load_into.is_none() {
+        registry_client.ensure_repository(&target_repo).await?;
+    }
+
+    let mut project_config = config.project_config(&project_path)?;
+    let mut labels = source_labels(&project_path);
+    labels.extend(std::mem::take(&mut project_config.labels));
+    project_config.labels = labels;
+    project_config.include = project_config
+        .include
+        .iter()
+        .map(|i| i.relative_to(&project_path))
+        .collect();
+    let base_image = project_config
+        .base_image
+        .as_deref()
+        .map(|image| base_image_relative_to(image, &project_path))
+        .unwrap_or(config.base_image.clone());
+    if let Some(warning) = config.build.libc.base_image_warning(&base_image) {
+        warn!("{}", warning);
+    }
+    let base_digest =
+        resolve_base_digest(registry_client, &project_path, &base_image, false).await?;
+    verify_base_image(config, registry_client, &base_image, base_digest.as_deref()).await?;
+
+    let platforms = if let Some(platforms) = platform {
+        platforms.to_vec()
+    } else if let Some(cluster) = load_into {
+        vec![cluster.platform()]
+    } else if !project_config.platforms.is_empty() {
+        project_config.platforms.clone()
+    } else if !config.platforms.is_empty() {
+        config.platforms.clone()
+    } else {
+        vec![Platform::new("linux", "amd64", None)]
+    };
+
+    let platform_build = PlatformBuild {
+        project_path: project_path.clone(),
+        base_image: base_image.clone(),
+        cargo_args: Vec::new(),
+        build_config: config.build.with_project(&project_config),
+        project_config: project_config.clone(),
+        registry_client: registry_client.clone(),
+        build_slots: Arc::new(Semaphore::new(1)),
+        rebuild: false,
+        base_digest,
+        binaries: Vec::new(),
+    };
+    let publisher: Box<dyn Publisher> = match load_into.cloned() {
+        Some(cluster) => Box::new(ClusterPublisher::new(
+            registry_client.clone(),
+            cluster,
             &target_repo,
-            manifest_descriptors,
-            &tag,
+            tag.to_vec(),
+        )),
+        None => Box::new(
+            RegistryPublisher::new(registry_client.clone(), &target_repo, tag.to_vec())
+                .with_debuginfo(config.build.push_debuginfo)
+                .with_signer(signer)
+                .with_annotations(IndexAnnotations {
+                    index: project_config.annotations.clone(),
+                    manifests: project_config.manifest_annotations.clone(),
+                }),
+        ),
+    };
+    if platforms.len() > 1 && !publisher.multi_platform() {
+        anyhow::bail!(
+            "{} output holds a single platform but {} were requested; choose one with --platform",
+            publisher.name(),
+            platforms.len()
+        );
+    }
+
+    // Build and push each platform
+    let mut manifest_descriptors = Vec::new();
+    for platform in &platforms {
+        let image = build_platform(&platform_build, platform).await?;
+        // Nothing is published from an image that fails its scan
+        if let Some(scanner) = &scanner {
+            scan_image(
+                scanner.as_ref(),
+                &config.scan,
+                &image,
+                &mut registry_client.clone(),
+            )
+            .await?;
+        }
+        manifest_descriptors.push(publisher.publish_image(&image).await?);
+    }
+
+    // Push manifest list
+    publisher
+        .finish(manifest_descriptors)
+        .await?
+        .context("Publishing did not return a reference")
+}
+
+/// Requests to `krust serve`, all sharing one configuration and registry
+/// session
+struct BuildServer {
+    config: Config,
+    registry_client: RegistryClient,
+    /// Repository prefix for requests that don't give one
+    repo: Option<String>,
+}
+
+#[async_trait]
+impl BuildService for BuildServer {
+    async fn build(&self, request: BuildRequest) -> Result<BuildResponse> {
+        let repo = request.repo.or_else(|| self.repo.clone()).context(
+            "The request has no repo and the service has no KRUST_REPO or default_registry",
+        )?;
+        let platforms = parse_platforms(&request.platforms)?;
+        let image = build_reference(
+            &self.config,
+            &mut self.registry_client.clone(),
+            &request.path,
+            platforms.as_deref(),
+            &repo,
+            &self.config.tags(request.tags),
+            None,
         )
         .await?;
+        Ok(BuildResponse { image })
+    }
 
-        info!("Resolved krust://{} -> {}", krust_path, image_ref);
-        replacements.insert(krust_path, image_ref);
+    async fn resolve(&self, request: ResolveRequest) -> Result<ResolveResponse> {
+        let resolved = resolve_yaml_files(
+            &self.config,
+            &mut self.registry_client.clone(),
+            request.files,
+            parse_platforms(&request.platforms)?,
+            request.repo.or_else(|| self.repo.clone()),
+            request.tags,
+            None,
+        )
+        .await?;
+        let documents: Vec<&str> = resolved
+            .documents
+            .iter()
+            .map(|(_, doc)| doc.as_str())
+            .collect();
+        Ok(ResolveResponse {
+            documents: documents.join("---\n"),
+            images: resolved.images.into_iter().collect(),
+        })
     }
+}
 
-    // Replace references in all YAML files and return resolved docs
-    let mut output_docs = Vec::new();
+/// Registry client for a CLI command, reporting blob transfer progress on
+/// stderr and caching base image metadata
+fn registry_client(config: &Config) -> Result<RegistryClient> {
+    Ok(RegistryClient::with_settings(&config.registry)?
+        .with_registries(&config.registries)?
+        .with_progress(Arc::new(CliProgress::new()))
+        .with_manifest_cache(Some(ManifestCache::new(
+            Paths::new().cache_dir.join("manifests"),
+        ))))
+}
 
-    for (filename, content) in &all_yaml_files {
-        info!("Resolving references in: {}", filename);
-        let resolved = replace_krust_references(content, &replacements)?;
-        output_docs.push(resolved);
+/// The signer for `--sign` (keyless) or `--sign-key`, if either was given.
+/// Keys are opened now so a bad key or password fails before building.
+fn signer(config: &Config, sign: bool, sign_key: Option<&str>) -> Result<Option<Arc<dyn Signer>>> {
+    let signing = &config.signing;
+    if let Some(key) = sign_key {
+        let rekor = signing
+            .tlog_upload
+            .then(|| {
+                anyhow::Ok(Rekor::new(
+                    http_client(&config.registry)?,
+                    &signing.rekor_url,
+                ))
+            })
+            .transpose()?;
+        return Ok(Some(Arc::new(
+            KeySigner::new(open_key(key)?).with_rekor(rekor),
+        )));
+    }
+    if sign {
+        return Ok(Some(Arc::new(
+            KeylessSigner::new(&config.registry)?
+                .with_fulcio_url(&signing.fulcio_url)
+                .with_rekor_url(&signing.rekor_url),
+        )));
+    }
+    Ok(None)
+}
+
+/// The digest to build `base_image` from: the one pinned in krust.lock, or
+/// else resolved from the registry now. With `pin`, the image is always
+/// resolved and its digest written to krust.lock. None for scratch and for
+/// local images, which are read as they are.
+async fn resolve_base_digest(
+    registry_client: &mut RegistryClient,
+    project_path: &Path,
+    base_image: &str,
+    pin: bool,
+) -> Result<Option<String>> {
+    if is_scratch(base_image) || LocalImage::parse(base_image).is_some() {
+        return Ok(None);
+    }
+    if let Some(digest) = ImageReference::parse(base_image)?.digest {
+        return Ok(Some(digest));
+    }
+
+    let lock_path = Lockfile::path(project_path);
+    let mut lock = Lockfile::load(&lock_path)?;
+    if !pin {
+        if let Some(digest) = lock.base_digest(base_image) {
+            info!(
+                "Using base image {} at {} from {}",
+                base_image, digest, LOCKFILE_NAME
+            );
+            return Ok(Some(digest.to_string()));
+        }
+    }
+
+    let auth = registry_client.auth_for(base_image).await?;
+    let digest = registry_client
+        .resolve_digest(base_image, &auth, pin)
+        .await
+        .with_context(|| format!("Failed to resolve base image {}", base_image))?;
+    info!("Resolved base image {} to {}", base_image, digest);
+    if pin {
+        lock.pin_base(base_image, &digest);
+        lock.save(&lock_path)?;
+        info!(
+            "Pinned base image {} in {}",
+            base_image,
+            lock_path.display()
+        );
+    }
+    Ok(Some(digest))
+}
+
+/// With `build.verify_base`, check that the base image at `digest` is
+/// signed by a key or identity in `[base_policy]`, failing the build if not
+async fn verify_base_image(
+    config: &Config,
+    registry_client: &mut RegistryClient,
+    base_image: &str,
+    digest: Option<&str>,
+) -> Result<()> {
+    if !config.build.verify_base || is_scratch(base_image) {
+        return Ok(());
+    }
+    let Some(digest) = digest else {
+        anyhow::bail!(
+            "Local base image {} has no signatures to verify; disable verify_base to use it",
+            base_image
+        );
+    };
+    let verifier = Verifier::new(&config.base_policy)?;
+    let repository = ImageReference::parse(base_image)?.repository_url();
+    let auth = registry_client.auth_for(base_image).await?;
+    let signer = verifier
+        .verify(registry_client, &repository, digest, &auth)
+        .await
+        .with_context(|| format!("Base image {} failed verification", base_image))?;
+    info!("Verified base image {} signed by {}", base_image, signer);
+    Ok(())
+}
+
+/// `base_image` with the path of a local image (`oci-layout:DIR` or
+/// `docker-archive:FILE`) resolved against `dir`
+fn base_image_relative_to(base_image: &str, dir: &Path) -> String {
+    match LocalImage::parse(base_image) {
+        Some(local) => local.relative_to(dir).to_string(),
+        None => base_image.to_string(),
+    }
+}
+
+/// Platforms to build when none are given and the base image can't tell us
+fn default_platforms() -> Vec<Platform> {
+    vec![
+        Platform::new("linux", "amd64", None),
+        Platform::new("linux", "arm64", None),
+    ]
+}
+
+/// The binaries to put in one image for --bins, with the entrypoint first.
+/// Every one must be a binary target of the package.
+fn image_binaries(
+    available: &[&str],
+    bins: Vec<String>,
+    entrypoint: Option<String>,
+) -> Result<Vec<String>> {
+    let mut binaries = Vec::new();
+    for bin in bins {
+        if !available.contains(&bin.as_str()) {
+            anyhow::bail!(
+                "Package has no binary target named '{}' (available: {})",
+                bin,
+                available.join(", ")
+            );
+        }
+        if !binaries.contains(&bin) {
+            binaries.push(bin);
+        }
+    }
+    if let Some(entrypoint) = entrypoint {
+        let position = binaries
+            .iter()
+            .position(|bin| *bin == entrypoint)
+            .with_context(|| format!("--entrypoint {} isn't one of --bins", entrypoint))?;
+        let entrypoint = binaries.remove(position);
+        binaries.insert(0, entrypoint);
     }
+    Ok(binaries)
+}
+
+/// The binary or example `cargo_args` select with `--bin` or `--example`
+fn cargo_target(cargo_args: &[String]) -> Option<&str> {
+    cargo_args
+        .iter()
+        .enumerate()
+        .find_map(|(i, arg)| match arg.as_str() {
+            "--bin" | "--example" => cargo_args.get(i + 1).map(String::as_str),
+            arg => arg
+                .strip_prefix("--bin=")
+                .or_else(|| arg.strip_prefix("--example=")),
+        })
+}
 
-    Ok(output_docs)
+fn join_platforms(platforms: &[Platform]) -> String {
+    platforms
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
-fn get_project_name(project_path: &Path) -> Result<String> {
-    let cargo_toml_path = project_path.join("Cargo.toml");
-    let content = std::fs::read_to_string(&cargo_toml_path).context("Failed to read Cargo.toml")?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{BodyExt, Full};
+    use krust::config::RegistryConfig;
+    use std::sync::Mutex;
+
+    /// Serve a registry that accepts every push over plain HTTP, recording
+    /// the paths manifests are pushed to
+    async fn serve_push_registry() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let manifests = Arc::new(Mutex::new(Vec::new()));
+        let pushed = manifests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let pushed = pushed.clone();
+                let handler = hyper::service::service_fn(
+                    move |request: hyper::Request<hyper::body::Incoming>| {
+                        let pushed = pushed.clone();
+                        async move {
+                            let (parts, body) = request.into_parts();
+                            body.collect().await.unwrap();
+                            let path = parts.uri.path().to_string();
+                            let response = hyper::Response::builder();
+                            let response = match parts.method {
+                                hyper::Method::POST => response
+                                    .status(202)
+                                    .header("Location", format!("{}upload", path)),
+                                hyper::Method::PUT if path.contains("/manifests/") => {
+                                    pushed.lock().unwrap().push(path);
+                                    response.status(201)
+                                }
+                                hyper::Method::PUT => response.status(201),
+                                _ if path == "/v2/" => response,
+                                _ => response.status(404),
+                            };
+                            response.body(Full::new(bytes::Bytes::new()))
+                        }
+                    },
+                );
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), handler),
+                );
+            }
+        });
+        (host, manifests)
+    }
 
-    let manifest: toml::Value = toml::from_str(&content).context("Failed to parse Cargo.toml")?;
+    #[tokio::test]
+    async fn test_build_reference_signs() {
+        let (host, manifests) = serve_push_registry().await;
+        let project = tempfile::tempdir().unwrap();
+        std::fs::write(
+            project.path().join("Cargo.toml"),
+            "[package]\nname = \"signed-app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        )
+        .unwrap();
+        std::fs::create_dir(project.path().join("src")).unwrap();
+        std::fs::write(project.path().join("src/main.rs"), "fn main() {}\n").unwrap();
 
-    let name = manifest
-        .get("package")
-        .and_then(|p| p.get("name"))
-        .and_then(|n| n.as_str())
-        .context("Failed to get package name from Cargo.toml")?;
+        let mut config = Config {
+            base_image: "scratch".to_string(),
+            sign: true,
+            ..Default::default()
+        };
+        config.registries.insert(
+            host.clone(),
+            RegistryConfig {
+                insecure: true,
+                ..Default::default()
+            },
+        );
+        // Fails whether or not an identity token is at hand
+        config.signing.fulcio_url = format!("http://{}", host);
+        let mut registry_client = RegistryClient::with_settings(&config.registry)
+            .unwrap()
+            .with_registries(&config.registries)
+            .unwrap();
 
-    Ok(name.to_string())
+        let err = build_reference(
+            &config,
+            &mut registry_client,
+            project.path().to_str().unwrap(),
+            Some(&[Platform::new("linux", "amd64", None)]),
+            &format!("{}/test", host),
+            &[],
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("Failed to sign"), "{:#}", err);
+        assert!(manifests.lock().unwrap().len() >= 2);
+    }
 }
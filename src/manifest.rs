@@ -1,4 +1,8 @@
+use crate::registry::media_types;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
 
 /// OCI Image Index (manifest list) for multi-arch support
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -8,6 +12,16 @@ pub struct ImageIndex {
     #[serde(rename = "mediaType")]
     pub media_type: String,
     pub manifests: Vec<ManifestDescriptor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, String>>,
+}
+
+/// Annotations for an image index and for each platform entry in it,
+/// e.g. `org.opencontainers.image.source` or `org.opencontainers.image.revision`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndexAnnotations {
+    pub index: BTreeMap<String, String>,
+    pub manifests: BTreeMap<String, String>,
 }
 
 /// Descriptor for a platform-specific manifest in the index
@@ -18,10 +32,12 @@ pub struct ManifestDescriptor {
     pub size: i64,
     pub digest: String,
     pub platform: Platform,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, String>>,
 }
 
-/// Platform information for a manifest
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A target platform such as `linux/amd64` or `linux/arm/v7`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Platform {
     pub architecture: String,
     pub os: String,
@@ -29,12 +45,239 @@ pub struct Platform {
     pub variant: Option<String>,
 }
 
+impl Platform {
+    pub fn new(os: &str, architecture: &str, variant: Option<&str>) -> Self {
+        Self {
+            architecture: architecture.to_string(),
+            os: os.to_string(),
+            variant: variant.map(|v| v.to_string()),
+        }
+    }
+
+    /// The Linux platform matching the host's architecture, for images that
+    /// will run locally
+    pub fn host() -> Self {
+        let architecture = match std::env::consts::ARCH {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            "x86" => "386",
+            other => other,
+        };
+        Self::new("linux", architecture, None)
+    }
+
+    /// Whether `other` satisfies this platform. A platform without a variant
+    /// matches any variant of the same os and architecture.
+    pub fn matches(&self, other: &Platform) -> bool {
+        self.os == other.os
+            && self.architecture == other.architecture
+            && match &self.variant {
+                Some(variant) => other.variant.as_deref() == Some(variant.as_str()),
+                None => true,
+            }
+    }
+
+    fn sort_key(&self) -> (&str, &str, Option<&str>) {
+        (&self.os, &self.architecture, self.variant.as_deref())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = anyhow::Error;
+
+    /// Parse `os/arch` or `os/arch/variant`.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let parts: Vec<&str> = s.split('/').collect();
+        if parts.iter().any(|p| p.is_empty()) {
+            anyhow::bail!(
+                "Invalid platform format: {} (expected os/arch[/variant])",
+                s
+            );
+        }
+        match parts.as_slice() {
+            [os, arch] => Ok(Self::new(os, arch, None)),
+            [os, arch, variant] => Ok(Self::new(os, arch, Some(variant))),
+            _ => anyhow::bail!(
+                "Invalid platform format: {} (expected os/arch[/variant])",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(variant) = &self.variant {
+            write!(f, "/{}", variant)?;
+        }
+        Ok(())
+    }
+}
+
 impl ImageIndex {
-    pub fn new(manifests: Vec<ManifestDescriptor>) -> Self {
+    /// Create an index over platform manifests. Entries are sorted by
+    /// (os, architecture, variant) so the index digest does not depend on
+    /// the order platforms were built in. Docker manifests are listed in a
+    /// Docker manifest list, anything else in an OCI index.
+    pub fn new(mut manifests: Vec<ManifestDescriptor>) -> Self {
+        manifests.sort_by(|a, b| a.platform.sort_key().cmp(&b.platform.sort_key()));
+        let docker = !manifests.is_empty()
+            && manifests
+                .iter()
+                .all(|m| m.media_type == media_types::DOCKER_MANIFEST);
+        let media_type = if docker {
+            media_types::DOCKER_MANIFEST_LIST
+        } else {
+            media_types::OCI_INDEX
+        };
         Self {
             schema_version: 2,
-            media_type: "application/vnd.oci.image.index.v1+json".to_string(),
+            media_type: media_type.to_string(),
             manifests,
+            annotations: None,
+        }
+    }
+
+    /// Annotate the index and every entry in it. Entry annotations are
+    /// merged over any the descriptors already carry.
+    pub fn with_annotations(mut self, annotations: &IndexAnnotations) -> Self {
+        if !annotations.index.is_empty() {
+            self.annotations = Some(annotations.index.clone());
         }
+        if !annotations.manifests.is_empty() {
+            for manifest in &mut self.manifests {
+                manifest
+                    .annotations
+                    .get_or_insert_with(BTreeMap::new)
+                    .extend(annotations.manifests.clone());
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(os: &str, architecture: &str, variant: Option<&str>) -> ManifestDescriptor {
+        ManifestDescriptor {
+            media_type: "application/vnd.oci.image.manifest.v1+json".to_string(),
+            size: 1,
+            digest: format!("sha256:{}-{}", os, architecture),
+            platform: Platform {
+                architecture: architecture.to_string(),
+                os: os.to_string(),
+                variant: variant.map(|v| v.to_string()),
+            },
+            annotations: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_platform() {
+        let platform: Platform = "linux/amd64".parse().unwrap();
+        assert_eq!(platform, Platform::new("linux", "amd64", None));
+        assert_eq!(platform.to_string(), "linux/amd64");
+
+        let platform: Platform = "linux/arm/v7".parse().unwrap();
+        assert_eq!(platform, Platform::new("linux", "arm", Some("v7")));
+        assert_eq!(platform.to_string(), "linux/arm/v7");
+    }
+
+    #[test]
+    fn test_parse_platform_invalid_format() {
+        for invalid in ["linux-amd64", "linux/", "/amd64", "linux/arm/v7/extra", ""] {
+            let err = invalid.parse::<Platform>().unwrap_err();
+            assert!(err.to_string().contains("Invalid platform"), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_host_platform_uses_oci_names() {
+        let host = Platform::host();
+        assert_eq!(host.os, "linux");
+        assert!(!["x86_64", "aarch64"].contains(&host.architecture.as_str()));
+    }
+
+    #[test]
+    fn test_platform_matches() {
+        let arm: Platform = "linux/arm".parse().unwrap();
+        let armv7: Platform = "linux/arm/v7".parse().unwrap();
+        let armv6: Platform = "linux/arm/v6".parse().unwrap();
+
+        assert!(arm.matches(&armv7));
+        assert!(armv7.matches(&armv7));
+        assert!(!armv7.matches(&armv6));
+        assert!(!armv7.matches(&arm));
+        assert!(!arm.matches(&"linux/arm64".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_index_entries_are_sorted() {
+        let a = ImageIndex::new(vec![
+            descriptor("linux", "arm64", None),
+            descriptor("linux", "arm", Some("v7")),
+            descriptor("linux", "amd64", None),
+            descriptor("linux", "arm", Some("v6")),
+        ]);
+        let b = ImageIndex::new(vec![
+            descriptor("linux", "amd64", None),
+            descriptor("linux", "arm", Some("v6")),
+            descriptor("linux", "arm", Some("v7")),
+            descriptor("linux", "arm64", None),
+        ]);
+
+        let platforms: Vec<(String, Option<String>)> = a
+            .manifests
+            .iter()
+            .map(|m| (m.platform.architecture.clone(), m.platform.variant.clone()))
+            .collect();
+        assert_eq!(
+            platforms,
+            vec![
+                ("amd64".to_string(), None),
+                ("arm".to_string(), Some("v6".to_string())),
+                ("arm".to_string(), Some("v7".to_string())),
+                ("arm64".to_string(), None),
+            ]
+        );
+        assert_eq!(
+            serde_json::to_string(&a).unwrap(),
+            serde_json::to_string(&b).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_index_media_type() {
+        let oci = ImageIndex::new(vec![descriptor("linux", "amd64", None)]);
+        assert_eq!(oci.media_type, media_types::OCI_INDEX);
+
+        let mut docker = descriptor("linux", "amd64", None);
+        docker.media_type = media_types::DOCKER_MANIFEST.to_string();
+        let index = ImageIndex::new(vec![docker]);
+        assert_eq!(index.media_type, media_types::DOCKER_MANIFEST_LIST);
+    }
+
+    #[test]
+    fn test_index_annotations() {
+        let mut existing = descriptor("linux", "amd64", None);
+        existing.annotations = Some(BTreeMap::from([("a".to_string(), "1".to_string())]));
+
+        let unannotated =
+            ImageIndex::new(vec![existing.clone()]).with_annotations(&IndexAnnotations::default());
+        assert!(unannotated.annotations.is_none());
+        assert_eq!(unannotated.manifests[0].annotations, existing.annotations);
+
+        let annotations = IndexAnnotations {
+            index: BTreeMap::from([("rev".to_string(), "abc".to_string())]),
+            manifests: BTreeMap::from([("b".to_string(), "2".to_string())]),
+        };
+        let index = ImageIndex::new(vec![existing]).with_annotations(&annotations);
+        assert_eq!(index.annotations, Some(annotations.index));
+        let entry = index.manifests[0].annotations.as_ref().unwrap();
+        assert_eq!(entry.len(), 2);
+        assert_eq!(entry["b"], "2");
     }
 }
@@ -0,0 +1,121 @@
+//! Locations of krust's configuration and cache
+//!
+//! `KRUST_HOME` relocates everything under one directory (useful in CI).
+//! Otherwise the XDG base directory variables are honored on every OS,
+//! falling back to the platform defaults. `KRUST_CONFIG_DIR` and
+//! `KRUST_CACHE_DIR` move one directory each, overriding both.
+
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Paths {
+    /// Configuration, e.g. `config.toml`
+    pub config_dir: PathBuf,
+    /// Data that can be recreated, e.g. downloaded blobs
+    pub cache_dir: PathBuf,
+}
+
+impl Paths {
+    /// Resolve paths from the process environment.
+    pub fn new() -> Self {
+        Self::from_env(|name| std::env::var(name).ok())
+    }
+
+    /// Resolve paths using `var` to look up environment variables.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Self {
+        let var = |name: &str| var(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+        let paths = Self::defaults(var);
+        Self {
+            config_dir: var("KRUST_CONFIG_DIR").unwrap_or(paths.config_dir),
+            cache_dir: var("KRUST_CACHE_DIR").unwrap_or(paths.cache_dir),
+        }
+    }
+
+    /// Paths under `KRUST_HOME`, or else the XDG or platform directories
+    fn defaults(var: impl Fn(&str) -> Option<PathBuf>) -> Self {
+        if let Some(home) = var("KRUST_HOME") {
+            return Self {
+                config_dir: home.join("config"),
+                cache_dir: home.join("cache"),
+            };
+        }
+
+        let fallback = || PathBuf::from(".krust");
+        Self {
+            config_dir: var("XDG_CONFIG_HOME")
+                .or_else(dirs::config_dir)
+                .map(|d| d.join("krust"))
+                .unwrap_or_else(fallback),
+            cache_dir: var("XDG_CACHE_HOME")
+                .or_else(dirs::cache_dir)
+                .map(|d| d.join("krust"))
+                .unwrap_or_else(|| fallback().join("cache")),
+        }
+    }
+
+    /// The global configuration file
+    pub fn config_file(&self) -> PathBuf {
+        self.config_dir.join("config.toml")
+    }
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn paths(vars: &[(&str, &str)]) -> Paths {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Paths::from_env(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_krust_home_overrides_everything() {
+        let p = paths(&[
+            ("KRUST_HOME", "/ci/krust"),
+            ("XDG_CACHE_HOME", "/xdg/cache"),
+        ]);
+        assert_eq!(p.config_dir, PathBuf::from("/ci/krust/config"));
+        assert_eq!(p.cache_dir, PathBuf::from("/ci/krust/cache"));
+        assert_eq!(
+            p.config_file(),
+            PathBuf::from("/ci/krust/config/config.toml")
+        );
+    }
+
+    #[test]
+    fn test_xdg_variables() {
+        let p = paths(&[
+            ("XDG_CONFIG_HOME", "/xdg/config"),
+            ("XDG_CACHE_HOME", "/xdg/cache"),
+        ]);
+        assert_eq!(p.config_dir, PathBuf::from("/xdg/config/krust"));
+        assert_eq!(p.cache_dir, PathBuf::from("/xdg/cache/krust"));
+    }
+
+    #[test]
+    fn test_directory_variables() {
+        let p = paths(&[
+            ("KRUST_HOME", "/ci/krust"),
+            ("KRUST_CACHE_DIR", "/mnt/cache"),
+        ]);
+        assert_eq!(p.cache_dir, PathBuf::from("/mnt/cache"));
+        assert_eq!(p.config_dir, PathBuf::from("/ci/krust/config"));
+    }
+
+    #[test]
+    fn test_empty_variables_are_ignored() {
+        let p = paths(&[("KRUST_HOME", ""), ("XDG_CACHE_HOME", "/xdg/cache")]);
+        assert_eq!(p.cache_dir, PathBuf::from("/xdg/cache/krust"));
+        assert!(p.config_dir.ends_with("krust"));
+    }
+}
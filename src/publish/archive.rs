@@ -0,0 +1,352 @@
+//! Publish to a tarball: a docker-archive (the `docker save` / `docker load`
+//! format) or an oci-archive (an OCI image layout in a tar)
+
+use super::{PlatformImage, Publisher};
+use crate::layout::OciLayout;
+use crate::manifest::{ImageIndex, IndexAnnotations, ManifestDescriptor};
+use crate::registry::RegistryClient;
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::info;
+
+/// Formats `--tarball` can write
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TarballFormat {
+    /// docker-archive, for `docker load`, holding a single platform
+    #[default]
+    Docker,
+    /// oci-archive, an OCI image layout in a tar, holding every platform
+    Oci,
+}
+
+/// An image with all of its layers, ready to be archived
+#[derive(Debug, Clone)]
+pub struct ArchiveImage {
+    pub config_data: Vec<u8>,
+    /// Layer blobs, base layers first
+    pub layers: Vec<Vec<u8>>,
+}
+
+impl ArchiveImage {
+    /// Gather the config and every layer of a built image.
+    pub async fn collect(image: &PlatformImage, registry_client: &RegistryClient) -> Result<Self> {
+        let mut registry_client = registry_client.clone();
+        Ok(Self {
+            config_data: image.config_data.clone(),
+            layers: image.layers(&mut registry_client).await?,
+        })
+    }
+
+    /// Serialize as a docker-archive tarball with the given `repo:tag` names.
+    /// Blobs are stored under `blobs/sha256/` and layers keep their
+    /// compression, which `docker load` and `ctr images import` both accept.
+    pub fn to_docker_archive(&self, repo_tags: &[String]) -> Result<Vec<u8>> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct ArchiveManifest<'a> {
+            config: String,
+            repo_tags: &'a [String],
+            layers: Vec<String>,
+        }
+
+        let blob_path = |data: &[u8]| format!("blobs/sha256/{}", sha256::digest(data));
+
+        let mut data = Vec::new();
+        {
+            let mut tar = tar::Builder::new(&mut data);
+
+            let config = blob_path(&self.config_data);
+            append_file(&mut tar, &config, &self.config_data)?;
+
+            let mut layers = Vec::with_capacity(self.layers.len());
+            for layer in &self.layers {
+                let path = blob_path(layer);
+                if !layers.contains(&path) {
+                    append_file(&mut tar, &path, layer)?;
+                }
+                layers.push(path);
+            }
+
+            let manifest = serde_json::to_vec(&[ArchiveManifest {
+                config,
+                repo_tags,
+                layers,
+            }])?;
+            append_file(&mut tar, "manifest.json", &manifest)?;
+
+            tar.finish()?;
+        }
+        Ok(data)
+    }
+}
+
+/// Holds the one image a single-platform publisher accepts
+#[derive(Debug, Default)]
+pub(crate) struct ImageSlot(Mutex<Option<ArchiveImage>>);
+
+impl ImageSlot {
+    pub(crate) fn store(&self, image: ArchiveImage, destination: &str) -> Result<()> {
+        let mut slot = self.0.lock().unwrap();
+        if slot.is_some() {
+            bail!(
+                "{} holds a single platform; choose one with --platform",
+                destination
+            );
+        }
+        *slot = Some(image);
+        Ok(())
+    }
+
+    pub(crate) fn take(&self) -> Option<ArchiveImage> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+/// Writes a single-platform image to a docker-archive tarball, or every
+/// platform to an oci-archive
+pub struct TarballPublisher {
+    registry_client: RegistryClient,
+    path: PathBuf,
+    repo_tags: Vec<String>,
+    format: TarballFormat,
+    annotations: IndexAnnotations,
+    image: ImageSlot,
+}
+
+impl TarballPublisher {
+    pub fn new(registry_client: RegistryClient, path: PathBuf, repo_tags: Vec<String>) -> Self {
+        Self {
+            registry_client,
+            path,
+            repo_tags,
+            format: TarballFormat::default(),
+            annotations: IndexAnnotations::default(),
+            image: ImageSlot::default(),
+        }
+    }
+
+    /// Write the tarball in `format` instead of as a docker-archive
+    pub fn with_format(mut self, format: TarballFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Annotate the image index of an oci-archive and its entries
+    pub fn with_annotations(mut self, annotations: IndexAnnotations) -> Self {
+        self.annotations = annotations;
+        self
+    }
+
+    /// Directory an oci-archive's layout is written to before it's archived,
+    /// next to the tarball
+    fn staging_dir(&self) -> PathBuf {
+        let name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        self.path
+            .with_file_name(format!(".{}.{}.oci", name, std::process::id()))
+    }
+
+    async fn publish_to_layout(&self, image: &PlatformImage) -> Result<ManifestDescriptor> {
+        let layout = OciLayout::create(self.staging_dir())?;
+        let mut registry_client = self.registry_client.clone();
+        let (digest, manifest_size) = layout
+            .write_layered_image(
+                &mut registry_client,
+                &image.config_data,
+                &image.layer_data,
+                &image.manifest,
+                &image.base_image,
+                &image.base_auth,
+            )
+            .await?;
+        Ok(ManifestDescriptor {
+            media_type: image.manifest.media_type.clone(),
+            size: manifest_size as i64,
+            digest,
+            platform: image.platform.clone(),
+            annotations: None,
+        })
+    }
+
+    /// Write the staged layout's index, then archive the layout to the tarball
+    fn finish_layout(&self, descriptors: Vec<ManifestDescriptor>) -> Result<()> {
+        let staging = self.staging_dir();
+        let layout = OciLayout::open(&staging)?;
+        let index = ImageIndex::new(descriptors).with_annotations(&self.annotations);
+        // Tools importing the archive take the image's name from its ref name
+        layout.write_image_index(
+            &index,
+            self.repo_tags.first().map(String::as_str),
+            self.registry_client.json_format(),
+        )?;
+        let archive = archive_dir(layout.root())?;
+        std::fs::remove_dir_all(&staging)
+            .with_context(|| format!("Failed to remove {}", staging.display()))?;
+        std::fs::write(&self.path, archive)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+#[async_trait]
+impl Publisher for TarballPublisher {
+    fn name(&self) -> &'static str {
+        "tarball"
+    }
+
+    async fn publish_image(&self, image: &PlatformImage) -> Result<ManifestDescriptor> {
+        if self.format == TarballFormat::Oci {
+            return self.publish_to_layout(image).await;
+        }
+        let archived = ArchiveImage::collect(image, &self.registry_client).await?;
+        self.image.store(archived, "--tarball")?;
+        image.descriptor(&self.registry_client)
+    }
+
+    async fn finish(&self, descriptors: Vec<ManifestDescriptor>) -> Result<Option<String>> {
+        match self.format {
+            TarballFormat::Docker => {
+                let image = self.image.take().context("No image was built")?;
+                std::fs::write(&self.path, image.to_docker_archive(&self.repo_tags)?)
+                    .with_context(|| format!("Failed to write {}", self.path.display()))?;
+            }
+            TarballFormat::Oci => self.finish_layout(descriptors)?,
+        }
+        info!(
+            "Wrote {} to {}",
+            self.repo_tags.join(", "),
+            self.path.display()
+        );
+        Ok(Some(self.path.display().to_string()))
+    }
+
+    fn multi_platform(&self) -> bool {
+        self.format == TarballFormat::Oci
+    }
+}
+
+/// Archive the files of an OCI layout (`oci-layout`, `index.json` and the
+/// blobs) in path order
+fn archive_dir(root: &Path) -> Result<Vec<u8>> {
+    let mut paths = vec!["oci-layout".to_string(), "index.json".to_string()];
+    let mut blobs = Vec::new();
+    for entry in std::fs::read_dir(root.join("blobs").join("sha256"))? {
+        blobs.push(format!(
+            "blobs/sha256/{}",
+            entry?.file_name().to_string_lossy()
+        ));
+    }
+    blobs.sort();
+    paths.extend(blobs);
+
+    let mut data = Vec::new();
+    {
+        let mut tar = tar::Builder::new(&mut data);
+        for path in paths {
+            let content = std::fs::read(root.join(&path))
+                .with_context(|| format!("Failed to read {}", path))?;
+            append_file(&mut tar, &path, &content)?;
+        }
+        tar.finish()?;
+    }
+    Ok(data)
+}
+
+fn append_file<W: Write>(tar: &mut tar::Builder<W>, path: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_path(path)?;
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_cksum();
+    tar.append(&header, data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::io::Read;
+
+    fn sample_image() -> ArchiveImage {
+        ArchiveImage {
+            config_data: br#"{"architecture":"amd64","os":"linux"}"#.to_vec(),
+            layers: vec![b"base".to_vec(), b"app".to_vec()],
+        }
+    }
+
+    #[test]
+    fn test_docker_archive_contents() {
+        let data = sample_image()
+            .to_docker_archive(&["ttl.sh/test/app:latest".to_string()])
+            .unwrap();
+
+        let mut files = BTreeMap::new();
+        for entry in tar::Archive::new(data.as_slice()).entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().to_string();
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content).unwrap();
+            files.insert(path, content);
+        }
+
+        let manifest: serde_json::Value = serde_json::from_slice(&files["manifest.json"]).unwrap();
+        let manifest = &manifest[0];
+        assert_eq!(manifest["RepoTags"][0], "ttl.sh/test/app:latest");
+
+        let config = manifest["Config"].as_str().unwrap();
+        assert_eq!(files[config], sample_image().config_data);
+
+        let layers: Vec<&str> = manifest["Layers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|l| l.as_str().unwrap())
+            .collect();
+        assert_eq!(layers.len(), 2);
+        assert_eq!(files[layers[0]], b"base");
+        assert_eq!(files[layers[1]], b"app");
+    }
+
+    #[test]
+    fn test_archive_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let b = layout.write_blob(b"b").unwrap();
+        let a = layout.write_blob(b"a").unwrap();
+
+        let data = archive_dir(dir.path()).unwrap();
+        let paths: Vec<String> = tar::Archive::new(data.as_slice())
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        let mut blobs = vec![
+            format!("blobs/sha256/{}", a.trim_start_matches("sha256:")),
+            format!("blobs/sha256/{}", b.trim_start_matches("sha256:")),
+        ];
+        blobs.sort();
+        assert_eq!(paths[..2], ["oci-layout", "index.json"]);
+        assert_eq!(paths[2..], blobs);
+    }
+
+    #[test]
+    fn test_image_slot_holds_one_image() {
+        let slot = ImageSlot::default();
+        slot.store(sample_image(), "--tarball").unwrap();
+        let err = slot.store(sample_image(), "--tarball").unwrap_err();
+        assert!(err.to_string().contains("--platform"));
+        assert!(slot.take().is_some());
+        assert!(slot.take().is_none());
+    }
+}
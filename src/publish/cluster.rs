@@ -0,0 +1,261 @@
+//! Side-load images into a local Kubernetes cluster (kind or minikube)
+
+use super::archive::{ArchiveImage, ImageSlot};
+use super::{PlatformImage, Publisher};
+use crate::manifest::{ManifestDescriptor, Platform};
+use crate::registry::RegistryClient;
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use tracing::{info, warn};
+
+/// A local cluster images can be loaded into without a registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cluster {
+    /// A kind cluster, by name (`kind` unless given)
+    Kind(Option<String>),
+    /// A minikube cluster, by profile (`minikube` unless given)
+    Minikube(Option<String>),
+}
+
+impl FromStr for Cluster {
+    type Err = anyhow::Error;
+
+    /// Parse `kind[:CLUSTER]` or `minikube[:PROFILE]`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, name) = match s.split_once(':') {
+            Some((kind, name)) if !name.is_empty() => (kind, Some(name.to_string())),
+            Some(_) => bail!("Invalid cluster: {} (expected kind[:CLUSTER])", s),
+            None => (s, None),
+        };
+        match kind {
+            "kind" => Ok(Cluster::Kind(name)),
+            "minikube" => Ok(Cluster::Minikube(name)),
+            _ => bail!(
+                "Unknown cluster type: {} (expected kind[:CLUSTER] or minikube[:PROFILE])",
+                kind
+            ),
+        }
+    }
+}
+
+impl Cluster {
+    /// Repository prefix for images loaded into the cluster when KRUST_REPO
+    /// isn't set; the images are never pulled, so it needn't be a registry
+    pub fn default_repo(&self) -> &'static str {
+        match self {
+            Cluster::Kind(_) => "kind.local",
+            Cluster::Minikube(_) => "minikube.local",
+        }
+    }
+
+    /// The kubeconfig context kind and minikube create for the cluster
+    pub fn kube_context(&self) -> String {
+        match self {
+            Cluster::Kind(name) => format!("kind-{}", name.as_deref().unwrap_or("kind")),
+            Cluster::Minikube(profile) => profile.as_deref().unwrap_or("minikube").to_string(),
+        }
+    }
+
+    /// The platform the cluster's nodes run, asked of kubectl. Falls back to
+    /// the host's, which local clusters almost always share.
+    pub fn platform(&self) -> Platform {
+        let context = self.kube_context();
+        let output = Command::new("kubectl")
+            .args([
+                "--context",
+                &context,
+                "get",
+                "nodes",
+                "-o",
+                "jsonpath={.items[0].status.nodeInfo.architecture}",
+            ])
+            .stderr(Stdio::null())
+            .output();
+        match output {
+            Ok(output) if output.status.success() && !output.stdout.is_empty() => {
+                let architecture = String::from_utf8_lossy(&output.stdout);
+                Platform::new("linux", architecture.trim(), None)
+            }
+            _ => {
+                let host = Platform::host();
+                warn!(
+                    "Could not detect the architecture of {} with kubectl, building for {}",
+                    context, host
+                );
+                host
+            }
+        }
+    }
+
+    /// The command that loads the docker-archive at `archive` into the cluster
+    pub fn load_command(&self, archive: &Path) -> Command {
+        match self {
+            Cluster::Kind(name) => {
+                let mut cmd = Command::new("kind");
+                cmd.args(["load", "image-archive"]).arg(archive);
+                if let Some(name) = name {
+                    cmd.args(["--name", name]);
+                }
+                cmd
+            }
+            Cluster::Minikube(profile) => {
+                let mut cmd = Command::new("minikube");
+                cmd.args(["image", "load"]).arg(archive);
+                if let Some(profile) = profile {
+                    cmd.args(["--profile", profile]);
+                }
+                cmd
+            }
+        }
+    }
+}
+
+/// Loads a single-platform image into a kind or minikube cluster's nodes
+pub struct ClusterPublisher {
+    registry_client: RegistryClient,
+    cluster: Cluster,
+    repository: String,
+    repo_tags: Vec<String>,
+    image: ImageSlot,
+}
+
+impl ClusterPublisher {
+    pub fn new(
+        registry_client: RegistryClient,
+        cluster: Cluster,
+        repository: &str,
+        tags: Vec<String>,
+    ) -> Self {
+        let repo_tags = tags
+            .iter()
+            .map(|t| format!("{}:{}", repository, t))
+            .collect();
+        Self {
+            registry_client,
+            cluster,
+            repository: repository.to_string(),
+            repo_tags,
+            image: ImageSlot::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl Publisher for ClusterPublisher {
+    fn name(&self) -> &'static str {
+        match self.cluster {
+            Cluster::Kind(_) => "kind",
+            Cluster::Minikube(_) => "minikube",
+        }
+    }
+
+    async fn publish_image(&self, image: &PlatformImage) -> Result<ManifestDescriptor> {
+        let archived = ArchiveImage::collect(image, &self.registry_client).await?;
+        self.image.store(archived, "--load-into")?;
+        image.descriptor(&self.registry_client)
+    }
+
+    /// Load the image tagged with its config digest as well as any tags
+    /// given. The digest tag names this build uniquely and isn't `latest`,
+    /// so pods using it never try to pull it.
+    async fn finish(&self, _descriptors: Vec<ManifestDescriptor>) -> Result<Option<String>> {
+        let image = self.image.take().context("No image was built")?;
+        let reference = format!(
+            "{}:{}",
+            self.repository,
+            sha256::digest(image.config_data.as_slice())
+        );
+        let mut repo_tags = vec![reference.clone()];
+        repo_tags.extend(self.repo_tags.iter().cloned());
+        let archive = image.to_docker_archive(&repo_tags)?;
+
+        let path = std::env::temp_dir().join(format!("krust-load-{}.tar", std::process::id()));
+        std::fs::write(&path, archive)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        let mut cmd = self.cluster.load_command(&path);
+        let program = cmd.get_program().to_string_lossy().to_string();
+        let status = cmd
+            .stdout(Stdio::null())
+            .status()
+            .with_context(|| format!("Failed to execute {} - is it installed?", program));
+        let _ = std::fs::remove_file(&path);
+        let status = status?;
+        if !status.success() {
+            bail!("{} failed to load the image ({})", program, status);
+        }
+
+        info!(
+            "Loaded {} into {}",
+            repo_tags.join(", "),
+            self.cluster.kube_context()
+        );
+        Ok(Some(reference))
+    }
+
+    fn multi_platform(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cmd: &Command) -> Vec<String> {
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_cluster() {
+        assert_eq!("kind".parse::<Cluster>().unwrap(), Cluster::Kind(None));
+        assert_eq!(
+            "kind:dev".parse::<Cluster>().unwrap(),
+            Cluster::Kind(Some("dev".to_string()))
+        );
+        assert_eq!(
+            "minikube".parse::<Cluster>().unwrap(),
+            Cluster::Minikube(None)
+        );
+        assert!("kind:".parse::<Cluster>().is_err());
+        assert!("k3d".parse::<Cluster>().is_err());
+    }
+
+    #[test]
+    fn test_kube_context() {
+        assert_eq!(Cluster::Kind(None).kube_context(), "kind-kind");
+        assert_eq!(
+            Cluster::Kind(Some("dev".to_string())).kube_context(),
+            "kind-dev"
+        );
+        assert_eq!(
+            Cluster::Minikube(Some("demo".to_string())).kube_context(),
+            "demo"
+        );
+    }
+
+    #[test]
+    fn test_load_command() {
+        let archive = Path::new("/tmp/image.tar");
+        assert_eq!(
+            args(&Cluster::Kind(Some("dev".to_string())).load_command(archive)),
+            [
+                "kind",
+                "load",
+                "image-archive",
+                "/tmp/image.tar",
+                "--name",
+                "dev"
+            ]
+        );
+        assert_eq!(
+            args(&Cluster::Minikube(None).load_command(archive)),
+            ["minikube", "image", "load", "/tmp/image.tar"]
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! Publish to a local container image store
+
+use super::archive::{ArchiveImage, ImageSlot};
+use super::{PlatformImage, Publisher};
+use crate::manifest::ManifestDescriptor;
+use crate::registry::RegistryClient;
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use clap::ValueEnum;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use tracing::info;
+
+/// containerd namespace images are imported into unless another is given:
+/// the one the kubelet's CRI plugin reads
+const DEFAULT_CONTAINERD_NAMESPACE: &str = "k8s.io";
+
+/// Local image stores krust can load images into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImageStore {
+    /// Docker if its daemon is running, otherwise the first of nerdctl, k3s
+    /// and ctr that's installed
+    Auto,
+    /// The Docker daemon, via `docker load`
+    Docker,
+    /// containerd, via `ctr images import`
+    Containerd,
+    /// containerd, via `nerdctl load`
+    Nerdctl,
+    /// k3s's embedded containerd, via `k3s ctr images import`
+    K3s,
+}
+
+impl ImageStore {
+    /// The store `Auto` stands for on this machine; other stores are
+    /// returned as they are
+    pub fn resolve(self) -> Result<ImageStore> {
+        if self != ImageStore::Auto {
+            return Ok(self);
+        }
+        if succeeds("docker", &["info"]) {
+            return Ok(ImageStore::Docker);
+        }
+        for (program, store) in [
+            ("nerdctl", ImageStore::Nerdctl),
+            ("k3s", ImageStore::K3s),
+            ("ctr", ImageStore::Containerd),
+        ] {
+            if succeeds(program, &["--version"]) {
+                return Ok(store);
+            }
+        }
+        bail!("No container runtime found to load the image into; install Docker, nerdctl, k3s or containerd, or use --tarball")
+    }
+
+    /// The command that reads a docker-archive on stdin and imports it into
+    /// `namespace` (containerd stores only; nerdctl uses its own default
+    /// unless one is given)
+    pub fn import_command(&self, namespace: Option<&str>) -> Command {
+        let containerd_namespace = namespace.unwrap_or(DEFAULT_CONTAINERD_NAMESPACE);
+        match self {
+            ImageStore::Auto | ImageStore::Docker => {
+                let mut cmd = Command::new("docker");
+                cmd.arg("load");
+                cmd
+            }
+            ImageStore::Containerd => {
+                let mut cmd = Command::new("ctr");
+                cmd.args(["-n", containerd_namespace, "images", "import", "-"]);
+                cmd
+            }
+            ImageStore::Nerdctl => {
+                let mut cmd = Command::new("nerdctl");
+                if let Some(namespace) = namespace {
+                    cmd.args(["--namespace", namespace]);
+                }
+                cmd.arg("load");
+                cmd
+            }
+            ImageStore::K3s => {
+                let mut cmd = Command::new("k3s");
+                cmd.args(["ctr", "-n", containerd_namespace, "images", "import", "-"]);
+                cmd
+            }
+        }
+    }
+}
+
+/// Whether `program args` runs and exits successfully
+fn succeeds(program: &str, args: &[&str]) -> bool {
+    Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+/// Loads a single-platform image into a local image store
+pub struct DaemonPublisher {
+    registry_client: RegistryClient,
+    store: ImageStore,
+    namespace: Option<String>,
+    repo_tags: Vec<String>,
+    image: ImageSlot,
+}
+
+impl DaemonPublisher {
+    pub fn new(registry_client: RegistryClient, store: ImageStore, repo_tags: Vec<String>) -> Self {
+        Self {
+            registry_client,
+            store,
+            namespace: None,
+            repo_tags,
+            image: ImageSlot::default(),
+        }
+    }
+
+    /// Import into this containerd namespace instead of the store's default
+    pub fn with_namespace(mut self, namespace: Option<String>) -> Self {
+        self.namespace = namespace;
+        self
+    }
+}
+
+#[async_trait]
+impl Publisher for DaemonPublisher {
+    fn name(&self) -> &'static str {
+        match self.store {
+            ImageStore::Auto | ImageStore::Docker => "docker",
+            ImageStore::Containerd => "containerd",
+            ImageStore::Nerdctl => "nerdctl",
+            ImageStore::K3s => "k3s",
+        }
+    }
+
+    async fn publish_image(&self, image: &PlatformImage) -> Result<ManifestDescriptor> {
+        let archived = ArchiveImage::collect(image, &self.registry_client).await?;
+        self.image.store(archived, "--load")?;
+        image.descriptor(&self.registry_client)
+    }
+
+    async fn finish(&self, _descriptors: Vec<ManifestDescriptor>) -> Result<Option<String>> {
+        let image = self.image.take().context("No image was built")?;
+        let archive = image.to_docker_archive(&self.repo_tags)?;
+
+        let mut cmd = self.store.import_command(self.namespace.as_deref());
+        let program = cmd.get_program().to_string_lossy().to_string();
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Failed to execute {} - is it installed?", program))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(&archive)
+                .with_context(|| format!("Failed to write image to {}", program))?;
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("{} failed to import the image ({})", program, status);
+        }
+
+        info!("Loaded {} into {}", self.repo_tags.join(", "), self.name());
+        Ok(self.repo_tags.first().cloned())
+    }
+
+    fn multi_platform(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cmd: &Command) -> Vec<String> {
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_import_command() {
+        assert_eq!(
+            args(&ImageStore::Docker.import_command(None)),
+            ["docker", "load"]
+        );
+        assert_eq!(
+            args(&ImageStore::Containerd.import_command(None)),
+            ["ctr", "-n", "k8s.io", "images", "import", "-"]
+        );
+        assert_eq!(
+            args(&ImageStore::K3s.import_command(Some("default"))),
+            ["k3s", "ctr", "-n", "default", "images", "import", "-"]
+        );
+        assert_eq!(
+            args(&ImageStore::Nerdctl.import_command(None)),
+            ["nerdctl", "load"]
+        );
+        assert_eq!(
+            args(&ImageStore::Nerdctl.import_command(Some("k8s.io"))),
+            ["nerdctl", "--namespace", "k8s.io", "load"]
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! Publish to an OCI image layout directory
+
+use super::{PlatformImage, Publisher};
+use crate::layout::{OciLayout, PushPlan};
+use crate::manifest::{ImageIndex, IndexAnnotations, ManifestDescriptor};
+use crate::registry::RegistryClient;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::path::PathBuf;
+use tracing::info;
+
+/// Writes images to an OCI layout, by default along with a [`PushPlan`] so
+/// they can be published later with `krust push`.
+pub struct LayoutPublisher {
+    registry_client: RegistryClient,
+    dir: PathBuf,
+    repository: String,
+    tags: Vec<String>,
+    annotations: IndexAnnotations,
+    push_plan: bool,
+}
+
+impl LayoutPublisher {
+    /// Create the layout directory if needed.
+    pub fn new(
+        registry_client: RegistryClient,
+        dir: PathBuf,
+        repository: &str,
+        tags: Vec<String>,
+    ) -> Result<Self> {
+        OciLayout::create(&dir)?;
+        Ok(Self {
+            registry_client,
+            dir,
+            repository: repository.to_string(),
+            tags,
+            annotations: IndexAnnotations::default(),
+            push_plan: true,
+        })
+    }
+
+    /// Whether to write a `krust-push.json` next to the layout. Without one,
+    /// the layout is the build's output and its digest is reported instead.
+    pub fn with_push_plan(mut self, push_plan: bool) -> Self {
+        self.push_plan = push_plan;
+        self
+    }
+
+    /// Annotate the image index and its entries
+    pub fn with_annotations(mut self, annotations: IndexAnnotations) -> Self {
+        self.annotations = annotations;
+        self
+    }
+}
+
+#[async_trait]
+impl Publisher for LayoutPublisher {
+    fn name(&self) -> &'static str {
+        "oci-layout"
+    }
+
+    async fn publish_image(&self, image: &PlatformImage) -> Result<ManifestDescriptor> {
+        let layout = OciLayout::open(&self.dir)?;
+        let mut registry_client = self.registry_client.clone();
+        let (digest, manifest_size) = layout
+            .write_layered_image(
+                &mut registry_client,
+                &image.config_data,
+                &image.layer_data,
+                &image.manifest,
+                &image.base_image,
+                &image.base_auth,
+            )
+            .await?;
+        info!("Wrote platform image {} ({})", digest, image.platform);
+
+        Ok(ManifestDescriptor {
+            media_type: image.manifest.media_type.clone(),
+            size: manifest_size as i64,
+            digest,
+            platform: image.platform.clone(),
+            annotations: None,
+        })
+    }
+
+    async fn finish(&self, descriptors: Vec<ManifestDescriptor>) -> Result<Option<String>> {
+        let layout = OciLayout::open(&self.dir)?;
+        let platforms = descriptors.iter().map(|d| d.platform.to_string()).collect();
+        let index = ImageIndex::new(descriptors).with_annotations(&self.annotations);
+        let digest = layout.write_image_index(
+            &index,
+            // The layout names the index once; the plan records every tag
+            self.tags.first().map(String::as_str),
+            self.registry_client.json_format(),
+        )?;
+        info!("Wrote OCI layout to {}", self.dir.display());
+        if !self.push_plan {
+            return Ok(Some(format!("{}@{}", self.dir.display(), digest)));
+        }
+        PushPlan {
+            repository: self.repository.clone(),
+            tags: self.tags.clone(),
+            digest,
+            platforms,
+        }
+        .write(&self.dir)?;
+        Ok(None)
+    }
+}
@@ -0,0 +1,190 @@
+//! A machine-readable summary of a build, for `--metadata-file`
+//!
+//! CI jobs read it to fill in deploy manifests and release notes without
+//! parsing krust's output. Digests are those of the images as krust encodes
+//! them, so they're the same whichever outputs the build wrote to.
+
+use super::PlatformImage;
+use crate::manifest::{ImageIndex, IndexAnnotations, ManifestDescriptor};
+use crate::registry::encoding::{EncodedManifest, JsonFormat};
+use crate::registry::oci_image_index;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+/// Everything one `krust build` produced
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BuildMetadata {
+    /// One image per binary built
+    pub images: Vec<ImageMetadata>,
+    /// Base image as configured, e.g. `cgr.dev/chainguard/static:latest`
+    pub base_image: String,
+    /// Digest the base image was resolved to. None for scratch and local
+    /// base images.
+    pub base_digest: Option<String>,
+    /// Wall-clock time the build took
+    pub duration_secs: f64,
+}
+
+/// A multi-platform image and where it was published
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ImageMetadata {
+    /// Repository the image is named for, e.g. `ghcr.io/org/app`
+    pub name: String,
+    /// Digest of the image index
+    pub digest: String,
+    pub tags: Vec<String>,
+    /// What each output reported: a reference by digest for registries, a
+    /// path for tarballs and layouts
+    pub references: Vec<String>,
+    pub platforms: Vec<PlatformMetadata>,
+}
+
+/// One platform's image in an index
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PlatformMetadata {
+    pub platform: String,
+    /// Digest of the platform's image manifest
+    pub digest: String,
+    /// Size of the manifest in bytes
+    pub manifest_size: i64,
+    /// Compressed size of the image in bytes: its config and every layer,
+    /// including the base image's
+    pub size: i64,
+    /// Compressed size of the layers krust added
+    pub added_size: i64,
+}
+
+impl PlatformMetadata {
+    /// Describe `image`, whose manifest is `descriptor`
+    pub fn new(image: &PlatformImage, descriptor: &ManifestDescriptor) -> Self {
+        let layers: i64 = image.manifest.layers.iter().map(|l| l.size).sum();
+        Self {
+            platform: image.platform.to_string(),
+            digest: descriptor.digest.clone(),
+            manifest_size: descriptor.size,
+            size: image.manifest.config.size + layers,
+            added_size: image.layer_data.iter().map(|l| l.len() as i64).sum(),
+        }
+    }
+}
+
+impl ImageMetadata {
+    /// Describe the index of `descriptors` that's published as `name`
+    pub fn new(
+        name: &str,
+        tags: &[String],
+        descriptors: Vec<ManifestDescriptor>,
+        annotations: &IndexAnnotations,
+        json_format: JsonFormat,
+        platforms: Vec<PlatformMetadata>,
+    ) -> Result<Self> {
+        let index = ImageIndex::new(descriptors).with_annotations(annotations);
+        let oci_index = oci_image_index(&index);
+        let encoded = EncodedManifest::new(&oci_index, &oci_index.media_type, json_format)?;
+        Ok(Self {
+            name: name.to_string(),
+            digest: encoded.digest,
+            tags: tags.to_vec(),
+            references: Vec::new(),
+            platforms,
+        })
+    }
+}
+
+impl BuildMetadata {
+    pub fn new(base_image: &str, base_digest: Option<&str>) -> Self {
+        Self {
+            images: Vec::new(),
+            base_image: base_image.to_string(),
+            base_digest: base_digest.map(str::to_string),
+            duration_secs: 0.0,
+        }
+    }
+
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        // Milliseconds are as precise as a build's duration needs to be
+        self.duration_secs = duration.as_millis() as f64 / 1000.0;
+        self
+    }
+
+    /// Write the metadata to `path` as JSON
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        std::fs::write(path, json)
+            .with_context(|| format!("Failed to write build metadata to {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Platform;
+    use tempfile::tempdir;
+
+    fn descriptor(digest: &str, arch: &str) -> ManifestDescriptor {
+        ManifestDescriptor {
+            media_type: crate::registry::media_types::OCI_MANIFEST.to_string(),
+            size: 500,
+            digest: digest.to_string(),
+            platform: Platform::new("linux", arch, None),
+            annotations: None,
+        }
+    }
+
+    #[test]
+    fn test_index_digest_matches_pushed_index() {
+        let descriptors = vec![
+            descriptor(&format!("sha256:{}", "a".repeat(64)), "amd64"),
+            descriptor(&format!("sha256:{}", "b".repeat(64)), "arm64"),
+        ];
+        let annotations = IndexAnnotations::default();
+        let image = ImageMetadata::new(
+            "ghcr.io/org/app",
+            &["v1".to_string()],
+            descriptors.clone(),
+            &annotations,
+            JsonFormat::default(),
+            Vec::new(),
+        )
+        .unwrap();
+
+        let oci_index = oci_image_index(&ImageIndex::new(descriptors));
+        let encoded =
+            EncodedManifest::new(&oci_index, &oci_index.media_type, JsonFormat::default()).unwrap();
+        assert_eq!(image.digest, encoded.digest);
+        assert_eq!(image.tags, vec!["v1"]);
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("metadata.json");
+        let mut metadata = BuildMetadata::new("cgr.dev/chainguard/static:latest", Some("sha256:b"))
+            .with_duration(Duration::from_millis(12_345));
+        metadata.images.push(ImageMetadata {
+            name: "ghcr.io/org/app".to_string(),
+            digest: "sha256:i".to_string(),
+            tags: Vec::new(),
+            references: vec!["ghcr.io/org/app@sha256:i".to_string()],
+            platforms: vec![PlatformMetadata {
+                platform: "linux/amd64".to_string(),
+                digest: "sha256:m".to_string(),
+                manifest_size: 500,
+                size: 3000,
+                added_size: 1000,
+            }],
+        });
+        metadata.write(&path).unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["duration_secs"], 12.345);
+        assert_eq!(json["base_digest"], "sha256:b");
+        assert_eq!(json["images"][0]["platforms"][0]["platform"], "linux/amd64");
+        let read: BuildMetadata = serde_json::from_value(json).unwrap();
+        assert_eq!(read, metadata);
+    }
+}
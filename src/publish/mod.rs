@@ -0,0 +1,116 @@
+//! Destinations for built images
+//!
+//! A [`Publisher`] receives each platform's image as it is built and then
+//! finishes once every platform is done (e.g. by pushing an image index or
+//! loading a tarball). `krust build` runs one publisher per selected output:
+//! a registry (`--push`), an OCI layout (`--artifact-dir`), a tarball
+//! (`--tarball`), a local image store (`--load`) or a local Kubernetes cluster
+//! (`--load-into`).
+
+pub mod archive;
+pub mod cluster;
+pub mod daemon;
+pub mod layout;
+pub mod metadata;
+pub mod registry;
+
+pub use archive::{TarballFormat, TarballPublisher};
+pub use cluster::{Cluster, ClusterPublisher};
+pub use daemon::{DaemonPublisher, ImageStore};
+pub use layout::LayoutPublisher;
+pub use metadata::{BuildMetadata, ImageMetadata, PlatformMetadata};
+pub use registry::RegistryPublisher;
+
+use crate::builder::sbom::Sbom;
+use crate::image::{Descriptor, Manifest};
+use crate::manifest::{ManifestDescriptor, Platform};
+use crate::registry::encoding::EncodedManifest;
+use crate::registry::{layered_manifest, OciDescriptor, RegistryAuth, RegistryClient};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::path::PathBuf;
+
+/// A single-platform image produced by the builder
+#[derive(Debug, Clone)]
+pub struct PlatformImage {
+    pub platform: Platform,
+    pub config_data: Vec<u8>,
+    /// The layers krust added, the application layer last. They're the last
+    /// entries in `manifest.layers`; the rest come from the base image.
+    pub layer_data: Vec<Vec<u8>>,
+    pub manifest: Manifest,
+    /// Image the base layers come from
+    pub base_image: String,
+    pub base_auth: RegistryAuth,
+    /// Debug info split from the binary, if any
+    pub debuginfo: Option<PathBuf>,
+    /// SBOM of the binary's crates, with `--sbom`
+    pub sbom: Option<Sbom>,
+}
+
+impl PlatformImage {
+    /// Descriptor for this image's manifest, as it would be pushed or written
+    pub fn descriptor(&self, registry_client: &RegistryClient) -> Result<ManifestDescriptor> {
+        let oci_manifest = layered_manifest(&self.manifest);
+        let encoded = EncodedManifest::new(
+            &oci_manifest,
+            &oci_manifest.media_type,
+            registry_client.json_format(),
+        )?;
+        Ok(ManifestDescriptor {
+            media_type: oci_manifest.media_type,
+            size: encoded.size(),
+            digest: encoded.digest,
+            platform: self.platform.clone(),
+            annotations: None,
+        })
+    }
+
+    /// Fetch every layer of the image, base layers first. Used by publishers
+    /// that must produce a self-contained copy of the image.
+    pub async fn layers(&self, registry_client: &mut RegistryClient) -> Result<Vec<Vec<u8>>> {
+        let mut layers = Vec::with_capacity(self.manifest.layers.len());
+        for layer in self.base_layers() {
+            let descriptor = OciDescriptor {
+                media_type: layer.media_type.clone(),
+                digest: layer.digest.clone(),
+                size: layer.size,
+                urls: None,
+                annotations: None,
+            };
+            layers.push(
+                registry_client
+                    .pull_cached_blob(&self.base_image, &descriptor, &self.base_auth)
+                    .await?
+                    .to_vec(),
+            );
+        }
+        layers.extend(self.layer_data.iter().cloned());
+        Ok(layers)
+    }
+
+    /// Descriptors of the layers that come from the base image
+    pub fn base_layers(&self) -> &[Descriptor] {
+        let layers = &self.manifest.layers;
+        &layers[..layers.len().saturating_sub(self.layer_data.len())]
+    }
+}
+
+/// A destination for built images
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    /// Short name used in log messages
+    fn name(&self) -> &'static str;
+
+    /// Publish a single platform's image. Called concurrently for each platform.
+    async fn publish_image(&self, image: &PlatformImage) -> Result<ManifestDescriptor>;
+
+    /// Finish once every platform has been published. Returns the reference
+    /// to report to the user, if the destination has one.
+    async fn finish(&self, descriptors: Vec<ManifestDescriptor>) -> Result<Option<String>>;
+
+    /// Whether this publisher can hold more than one platform of an image
+    fn multi_platform(&self) -> bool {
+        true
+    }
+}
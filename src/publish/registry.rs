@@ -0,0 +1,382 @@
+//! Publish to a remote registry
+
+use super::{PlatformImage, Publisher};
+use crate::builder::debuginfo;
+use crate::events::{self, Event};
+use crate::manifest::{ImageIndex, IndexAnnotations, ManifestDescriptor};
+use crate::registry::timeout::with_deadline;
+use crate::registry::verify::verify_pushed_image;
+use crate::registry::{
+    Annotations, ImageReference, OciDescriptor, OciImageManifest, RegistryAuth, RegistryClient,
+};
+use crate::sign::{sign_image, Signer};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::info;
+
+/// Pushes each platform image by digest, then an image index referencing
+/// all of them, by digest and to each of the given tags.
+pub struct RegistryPublisher {
+    registry_client: RegistryClient,
+    repository: String,
+    tags: Vec<String>,
+    verify: bool,
+    annotations: IndexAnnotations,
+    debuginfo: bool,
+    signer: Option<Arc<dyn Signer>>,
+}
+
+impl RegistryPublisher {
+    pub fn new(registry_client: RegistryClient, repository: &str, tags: Vec<String>) -> Self {
+        Self {
+            registry_client,
+            repository: repository.to_string(),
+            tags,
+            verify: false,
+            annotations: IndexAnnotations::default(),
+            debuginfo: false,
+            signer: None,
+        }
+    }
+
+    /// Push split debug info as an artifact whose subject is the platform image
+    pub fn with_debuginfo(mut self, debuginfo: bool) -> Self {
+        self.debuginfo = debuginfo;
+        self
+    }
+
+    async fn push_debuginfo(
+        &self,
+        registry_client: &mut RegistryClient,
+        path: &Path,
+        subject: OciDescriptor,
+        auth: &RegistryAuth,
+    ) -> Result<String> {
+        let data = std::fs::read(path)
+            .with_context(|| format!("Failed to read debug info {}", path.display()))?;
+        let title = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.push_artifact(
+            registry_client,
+            debuginfo::ARTIFACT_TYPE,
+            debuginfo::LAYER_MEDIA_TYPE,
+            &data,
+            &title,
+            subject,
+            auth,
+        )
+        .await
+    }
+
+    /// Push `data` as the single file, named `title`, of an artifact whose
+    /// subject is a platform image. Returns the artifact reference by digest.
+    #[allow(clippy::too_many_arguments)]
+    async fn push_artifact(
+        &self,
+        registry_client: &mut RegistryClient,
+        artifact_type: &str,
+        media_type: &str,
+        data: &[u8],
+        title: &str,
+        subject: OciDescriptor,
+        auth: &RegistryAuth,
+    ) -> Result<String> {
+        let digest = format!("sha256:{}", sha256::digest(data));
+        registry_client
+            .push_blob(&self.repository, data, &digest, auth)
+            .await?;
+
+        let layer = OciDescriptor {
+            media_type: media_type.to_string(),
+            digest,
+            size: data.len() as i64,
+            urls: None,
+            annotations: Some(Annotations::from([(
+                "org.opencontainers.image.title".to_string(),
+                title.to_string(),
+            )])),
+        };
+        events::emit(Event::LayerPushed {
+            repository: ImageReference::parse(&self.repository)?.repository_url(),
+            digest: layer.digest.clone(),
+            size: layer.size,
+        });
+        let manifest = OciImageManifest::artifact(artifact_type, None, vec![layer], Some(subject));
+        let digest = registry_client
+            .push_manifest(&self.repository, &manifest, auth)
+            .await?;
+        Ok(format!("{}@{}", self.repository, digest))
+    }
+
+    /// Annotate the image index and its entries
+    pub fn with_annotations(mut self, annotations: IndexAnnotations) -> Self {
+        self.annotations = annotations;
+        self
+    }
+
+    /// Sign the image index once it's pushed
+    pub fn with_signer(mut self, signer: Option<Arc<dyn Signer>>) -> Self {
+        self.signer = signer;
+        self
+    }
+
+    /// Read the image back after pushing and check it matches what was uploaded
+    pub fn with_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+}
+
+#[async_trait]
+impl Publisher for RegistryPublisher {
+    fn name(&self) -> &'static str {
+        "registry"
+    }
+
+    async fn publish_image(&self, image: &PlatformImage) -> Result<ManifestDescriptor> {
+        info!("Pushing image for platform: {}", image.platform);
+
+        // Clones share the session and its blob cache
+        let mut registry_client = self.registry_client.clone();
+        let push_auth = registry_client.auth_for(&self.repository).await?;
+
+        let (digest_ref, manifest_size) = with_deadline(
+            self.registry_client.settings(),
+            &format!("pushing {} ({})", self.repository, image.platform),
+            registry_client.push_layered_image(
+                &self.repository,
+                image.config_data.clone(),
+                image.layer_data.clone(),
+                &image.manifest,
+                &push_auth,
+                &image.base_image,
+                &image.base_auth,
+            ),
+        )
+        .await?;
+
+        let digest = digest_ref.split('@').next_back().unwrap_or("").to_string();
+        info!("Pushed platform image: {} ({})", digest_ref, image.platform);
+
+        let subject = OciDescriptor {
+            media_type: image.manifest.media_type.clone(),
+            digest: digest.clone(),
+            size: manifest_size as i64,
+            urls: None,
+            annotations: None,
+        };
+        if let (true, Some(path)) = (self.debuginfo, &image.debuginfo) {
+            let debuginfo_ref = self
+                .push_debuginfo(&mut registry_client, path, subject.clone(), &push_auth)
+                .await?;
+            info!("Pushed debug info: {} ({})", debuginfo_ref, image.platform);
+        }
+        if let Some(sbom) = &image.sbom {
+            let sbom_ref = self
+                .push_artifact(
+                    &mut registry_client,
+                    sbom.media_type,
+                    sbom.media_type,
+                    &sbom.data,
+                    sbom.file_name,
+                    subject,
+                    &push_auth,
+                )
+                .await?;
+            info!("Pushed SBOM: {} ({})", sbom_ref, image.platform);
+        }
+
+        Ok(ManifestDescriptor {
+            media_type: image.manifest.media_type.clone(),
+            size: manifest_size as i64,
+            digest,
+            platform: image.platform.clone(),
+            annotations: None,
+        })
+    }
+
+    async fn finish(&self, descriptors: Vec<ManifestDescriptor>) -> Result<Option<String>> {
+        info!("Creating and pushing manifest list...");
+
+        let index = ImageIndex::new(descriptors).with_annotations(&self.annotations);
+        let mut registry_client = self.registry_client.clone();
+        let auth = registry_client.auth_for(&self.repository).await?;
+        let image_ref = with_deadline(
+            self.registry_client.settings(),
+            &format!("pushing manifest list to {}", self.repository),
+            registry_client.push_manifest_list(&self.repository, &index, &auth, &self.tags),
+        )
+        .await?;
+        for tag in &self.tags {
+            info!("Tagged {} as {}:{}", image_ref, self.repository, tag);
+        }
+        events::emit(Event::IndexPushed {
+            image: image_ref.clone(),
+            digest: image_ref.split('@').next_back().unwrap_or("").to_string(),
+            tags: self.tags.clone(),
+        });
+
+        if self.verify {
+            let report = with_deadline(
+                self.registry_client.settings(),
+                &format!("verifying {}", image_ref),
+                verify_pushed_image(&mut registry_client, &image_ref, &index, &auth),
+            )
+            .await?;
+            report.log();
+            if !report.passed() {
+                anyhow::bail!("Verification of {} failed", image_ref);
+            }
+        }
+
+        if let Some(signer) = &self.signer {
+            let digest = image_ref.split('@').next_back().unwrap_or("");
+            info!("Signing {} ({})", image_ref, signer.name());
+            let signature_ref = with_deadline(
+                self.registry_client.settings(),
+                &format!("signing {}", image_ref),
+                sign_image(
+                    signer.as_ref(),
+                    &mut registry_client,
+                    &self.repository,
+                    digest,
+                    &auth,
+                ),
+            )
+            .await?;
+            info!("Pushed signature: {}", signature_ref);
+        }
+
+        Ok(Some(image_ref))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RegistryConfig;
+    use crate::image::{Descriptor, Manifest};
+    use crate::manifest::Platform;
+    use crate::registry::media_types;
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Full};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Serve a registry that accepts every push over plain HTTP, keeping the
+    /// manifests pushed by their path
+    async fn serve_push_registry() -> (String, Arc<Mutex<HashMap<String, Vec<u8>>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let manifests = Arc::new(Mutex::new(HashMap::new()));
+        let pushed = manifests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let pushed = pushed.clone();
+                let handler = hyper::service::service_fn(
+                    move |request: hyper::Request<hyper::body::Incoming>| {
+                        let pushed = pushed.clone();
+                        async move {
+                            let (parts, body) = request.into_parts();
+                            let body = body.collect().await.unwrap().to_bytes();
+                            let path = parts.uri.path().to_string();
+                            let response = hyper::Response::builder();
+                            let response = match parts.method {
+                                hyper::Method::POST => response
+                                    .status(202)
+                                    .header("Location", format!("{}upload", path)),
+                                hyper::Method::PUT if path.contains("/manifests/") => {
+                                    pushed.lock().unwrap().insert(path, body.to_vec());
+                                    response.status(201)
+                                }
+                                hyper::Method::PUT => response.status(201),
+                                _ if path == "/v2/" => response,
+                                _ => response.status(404),
+                            };
+                            response.body(Full::new(Bytes::new()))
+                        }
+                    },
+                );
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), handler),
+                );
+            }
+        });
+        (host, manifests)
+    }
+
+    #[tokio::test]
+    async fn test_publish_debuginfo() {
+        let (host, manifests) = serve_push_registry().await;
+        let registries = HashMap::from([(
+            host.clone(),
+            RegistryConfig {
+                insecure: true,
+                ..Default::default()
+            },
+        )]);
+        let client = RegistryClient::new()
+            .unwrap()
+            .with_registries(&registries)
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let debuginfo = dir.path().join("app.debug");
+        std::fs::write(&debuginfo, b"debug info").unwrap();
+        let (config_data, layer) = (b"{}".to_vec(), b"app".to_vec());
+        let descriptor = |media_type: &str, data: &[u8]| Descriptor {
+            media_type: media_type.to_string(),
+            size: data.len() as i64,
+            digest: format!("sha256:{}", sha256::digest(data)),
+            annotations: None,
+        };
+        let image = PlatformImage {
+            platform: "linux/amd64".parse::<Platform>().unwrap(),
+            manifest: Manifest {
+                schema_version: 2,
+                media_type: media_types::OCI_MANIFEST.to_string(),
+                config: descriptor(media_types::OCI_CONFIG, &config_data),
+                layers: vec![descriptor(media_types::OCI_LAYER_GZIP, &layer)],
+                annotations: None,
+            },
+            config_data,
+            layer_data: vec![layer],
+            base_image: "scratch".to_string(),
+            base_auth: RegistryAuth::Anonymous,
+            debuginfo: Some(debuginfo),
+            sbom: None,
+        };
+
+        let repository = format!("{}/test/app", host);
+        let pushed = RegistryPublisher::new(client.clone(), &repository, Vec::new())
+            .publish_image(&image)
+            .await
+            .unwrap();
+        assert_eq!(manifests.lock().unwrap().len(), 1);
+
+        RegistryPublisher::new(client, &repository, Vec::new())
+            .with_debuginfo(true)
+            .publish_image(&image)
+            .await
+            .unwrap();
+        let artifacts: Vec<serde_json::Value> = manifests
+            .lock()
+            .unwrap()
+            .values()
+            .map(|body| serde_json::from_slice(body).unwrap())
+            .filter(|manifest: &serde_json::Value| manifest["artifactType"].is_string())
+            .collect();
+        assert_eq!(artifacts.len(), 1);
+        assert_eq!(artifacts[0]["artifactType"], debuginfo::ARTIFACT_TYPE);
+        assert_eq!(artifacts[0]["subject"]["digest"], pushed.digest);
+        assert_eq!(
+            artifacts[0]["layers"][0]["digest"],
+            format!("sha256:{}", sha256::digest(b"debug info"))
+        );
+    }
+}
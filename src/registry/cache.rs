@@ -0,0 +1,193 @@
+//! On-disk cache of base image metadata
+//!
+//! Manifests, indexes and configs are stored by digest and never expire,
+//! since a digest always names the same content. So are the base image
+//! layers fetched to write tarballs and OCI layouts, which lets later
+//! builds run with `--offline-images`. Tags are stored with the
+//! digest they resolved to and when, and are trusted for
+//! `manifest_cache_ttl_secs`. An expired tag is still used, with a warning,
+//! when the registry can't be reached, so builds work offline once the
+//! cache is warm.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+/// A tag's digest as last resolved
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedTag {
+    /// Normalized reference, e.g. `registry-1.docker.io/library/alpine:3.20`
+    pub reference: String,
+    pub digest: String,
+    /// Seconds since the Unix epoch
+    pub resolved_at: u64,
+}
+
+impl CachedTag {
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(now().saturating_sub(self.resolved_at))
+    }
+
+    pub fn is_fresh(&self, ttl: Duration) -> bool {
+        self.age() < ttl
+    }
+}
+
+/// Content-addressed manifests and configs, and tag resolutions, under a
+/// directory (by default `<cache dir>/manifests`)
+#[derive(Debug, Clone)]
+pub struct ManifestCache {
+    dir: PathBuf,
+}
+
+impl ManifestCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn content_path(&self, digest: &str) -> Option<PathBuf> {
+        let (algorithm, hex) = digest.split_once(':')?;
+        if algorithm != "sha256" || hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(self.dir.join(algorithm).join(hex))
+    }
+
+    fn tag_path(&self, reference: &str) -> PathBuf {
+        self.dir
+            .join("tags")
+            .join(format!("{}.json", sha256::digest(reference)))
+    }
+
+    /// Cached content with `digest`. Content that doesn't match its digest
+    /// is discarded.
+    pub fn get(&self, digest: &str) -> Option<Vec<u8>> {
+        let path = self.content_path(digest)?;
+        let data = std::fs::read(&path).ok()?;
+        if format!("sha256:{}", sha256::digest(data.as_slice())) != digest {
+            debug!("Discarding corrupt cache entry {}", path.display());
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+        debug!("Using cached {}", digest);
+        crate::cache::touch(&path);
+        Some(data)
+    }
+
+    /// Whether content with `digest` is cached, without reading it
+    pub fn contains(&self, digest: &str) -> bool {
+        self.content_path(digest).is_some_and(|path| path.is_file())
+    }
+
+    /// Store content under its digest
+    pub fn put(&self, digest: &str, data: &[u8]) -> Result<()> {
+        let path = self
+            .content_path(digest)
+            .with_context(|| format!("Can't cache content with digest {}", digest))?;
+        if path.exists() {
+            return Ok(());
+        }
+        write_atomic(&path, data)
+    }
+
+    /// The digest `reference` last resolved to, however long ago
+    pub fn tag(&self, reference: &str) -> Option<CachedTag> {
+        let data = std::fs::read(self.tag_path(reference)).ok()?;
+        serde_json::from_slice::<CachedTag>(&data)
+            .ok()
+            .filter(|tag| tag.reference == reference)
+    }
+
+    /// Record that `reference` resolved to `digest` just now
+    pub fn put_tag(&self, reference: &str, digest: &str) -> Result<()> {
+        let tag = CachedTag {
+            reference: reference.to_string(),
+            digest: digest.to_string(),
+            resolved_at: now(),
+        };
+        write_atomic(&self.tag_path(reference), &serde_json::to_vec(&tag)?)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Write through a temporary file so concurrent builds never read a
+/// partial entry
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let dir = path.parent().context("Cache path has no parent")?;
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create cache directory {}", dir.display()))?;
+    let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+    std::fs::write(&tmp, data)
+        .with_context(|| format!("Failed to write cache entry {}", tmp.display()))?;
+    std::fs::rename(&tmp, path)
+        .with_context(|| format!("Failed to write cache entry {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_content() {
+        let dir = tempdir().unwrap();
+        let cache = ManifestCache::new(dir.path());
+        let data = br#"{"schemaVersion":2}"#;
+        let digest = format!("sha256:{}", sha256::digest(data.as_slice()));
+
+        assert!(cache.get(&digest).is_none());
+        cache.put(&digest, data).unwrap();
+        assert_eq!(cache.get(&digest).unwrap(), data);
+        assert!(cache.contains(&digest));
+        assert!(!cache.contains("sha256:abc"));
+        assert!(dir
+            .path()
+            .join("sha256")
+            .join(digest.trim_start_matches("sha256:"))
+            .exists());
+
+        // Corrupt entries are dropped rather than served
+        let path = cache.content_path(&digest).unwrap();
+        std::fs::write(&path, b"tampered").unwrap();
+        assert!(cache.get(&digest).is_none());
+        assert!(!path.exists());
+
+        assert!(cache.put("sha512:abc", data).is_err());
+        assert!(cache.put("sha256:../../etc", data).is_err());
+        assert!(cache.get("latest").is_none());
+    }
+
+    #[test]
+    fn test_tags() {
+        let dir = tempdir().unwrap();
+        let cache = ManifestCache::new(dir.path());
+        let reference = "cgr.dev/chainguard/static:latest";
+
+        assert!(cache.tag(reference).is_none());
+        cache.put_tag(reference, "sha256:abc").unwrap();
+        let tag = cache.tag(reference).unwrap();
+        assert_eq!(tag.digest, "sha256:abc");
+        assert!(tag.is_fresh(Duration::from_secs(60)));
+        assert!(!tag.is_fresh(Duration::ZERO));
+        assert!(cache.tag("cgr.dev/chainguard/static:other").is_none());
+
+        let stale = CachedTag {
+            resolved_at: now() - 600,
+            ..tag
+        };
+        assert!(stale.age() >= Duration::from_secs(600));
+        assert!(!stale.is_fresh(Duration::from_secs(300)));
+    }
+}
@@ -0,0 +1,316 @@
+//! `WWW-Authenticate` challenge parsing and the credentials sent in response
+//!
+//! A registry may offer several challenges, in one header or across
+//! several (Harbor, for instance, sends both Basic and Bearer). Bearer is
+//! preferred since it yields a scoped token; registries that only offer
+//! Basic get the credentials directly on every request.
+
+use base64::Engine;
+use std::collections::HashMap;
+
+/// Parameters of a Bearer challenge, used to request a token
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AuthChallenge {
+    pub realm: String,
+    pub service: String,
+    pub scope: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Challenge {
+    Bearer(AuthChallenge),
+    Basic {
+        realm: String,
+    },
+    /// A scheme krust doesn't support, e.g. Negotiate
+    Other(String),
+}
+
+/// A resource scope to request in a token, e.g. `repository:user/app:pull,push`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Scope {
+    pub repository: String,
+    pub actions: &'static str,
+}
+
+impl Scope {
+    pub fn pull(repository: &str) -> Self {
+        Self {
+            repository: repository.to_string(),
+            actions: "pull",
+        }
+    }
+
+    pub fn push(repository: &str) -> Self {
+        Self {
+            repository: repository.to_string(),
+            actions: "pull,push",
+        }
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "repository:{}:{}", self.repository, self.actions)
+    }
+}
+
+/// Build the token endpoint URL for a Bearer challenge. Each scope is sent
+/// as its own `scope` parameter so one token can cover several
+/// repositories; with no scopes, the challenge's own scope is requested.
+pub(crate) fn token_url(challenge: &AuthChallenge, scopes: &[Scope]) -> anyhow::Result<String> {
+    let mut url = reqwest::Url::parse(&challenge.realm)
+        .map_err(|e| anyhow::anyhow!("Invalid token realm URL {}: {}", challenge.realm, e))?;
+    {
+        let mut query = url.query_pairs_mut();
+        if !challenge.service.is_empty() {
+            query.append_pair("service", &challenge.service);
+        }
+        if scopes.is_empty() {
+            if !challenge.scope.is_empty() {
+                query.append_pair("scope", &challenge.scope);
+            }
+        } else {
+            for scope in scopes {
+                query.append_pair("scope", &scope.to_string());
+            }
+        }
+    }
+    Ok(url.to_string())
+}
+
+/// The `Authorization` header value to send with registry requests
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Authorization {
+    Bearer(String),
+    /// Base64-encoded `username:password`
+    Basic(String),
+}
+
+impl Authorization {
+    pub fn basic(username: &str, password: &str) -> Self {
+        let credentials = format!("{}:{}", username, password);
+        Self::Basic(base64::engine::general_purpose::STANDARD.encode(credentials.as_bytes()))
+    }
+
+    pub fn header_value(&self) -> String {
+        match self {
+            Authorization::Bearer(token) => format!("Bearer {}", token),
+            Authorization::Basic(encoded) => format!("Basic {}", encoded),
+        }
+    }
+}
+
+/// Parse every challenge from one or more `WWW-Authenticate` header values.
+pub(crate) fn parse_challenges<'a>(headers: impl IntoIterator<Item = &'a str>) -> Vec<Challenge> {
+    let mut parsed: Vec<(String, HashMap<String, String>)> = Vec::new();
+
+    for header in headers {
+        for item in split_unquoted_commas(header) {
+            let item = item.trim();
+            if item.is_empty() {
+                continue;
+            }
+
+            // "Scheme" or "Scheme param=value" starts a new challenge
+            let space = item.find(char::is_whitespace);
+            let equals = item.find('=');
+            let param = match (space, equals) {
+                (Some(s), Some(e)) if s < e => {
+                    parsed.push((item[..s].to_string(), HashMap::new()));
+                    item[s..].trim()
+                }
+                (Some(s), None) => {
+                    parsed.push((item[..s].to_string(), HashMap::new()));
+                    continue;
+                }
+                (None, None) => {
+                    parsed.push((item.to_string(), HashMap::new()));
+                    continue;
+                }
+                _ => item,
+            };
+
+            if let (Some((key, value)), Some((_, params))) =
+                (param.split_once('='), parsed.last_mut())
+            {
+                params.insert(
+                    key.trim().to_ascii_lowercase(),
+                    value.trim().trim_matches('"').to_string(),
+                );
+            }
+        }
+    }
+
+    parsed
+        .into_iter()
+        .filter_map(|(scheme, mut params)| {
+            let mut take = |key: &str| params.remove(key).unwrap_or_default();
+            match scheme.to_ascii_lowercase().as_str() {
+                "bearer" => {
+                    let realm = take("realm");
+                    // A Bearer challenge without a realm gives us nowhere to get a token
+                    (!realm.is_empty()).then(|| {
+                        Challenge::Bearer(AuthChallenge {
+                            realm,
+                            service: take("service"),
+                            scope: take("scope"),
+                        })
+                    })
+                }
+                "basic" => Some(Challenge::Basic {
+                    realm: take("realm"),
+                }),
+                _ => Some(Challenge::Other(scheme)),
+            }
+        })
+        .collect()
+}
+
+/// The Bearer challenge if one was offered
+pub(crate) fn bearer(challenges: &[Challenge]) -> Option<&AuthChallenge> {
+    challenges.iter().find_map(|c| match c {
+        Challenge::Bearer(challenge) => Some(challenge),
+        _ => None,
+    })
+}
+
+/// Whether Basic is offered (used only when there is no Bearer challenge)
+pub(crate) fn offers_basic(challenges: &[Challenge]) -> bool {
+    challenges
+        .iter()
+        .any(|c| matches!(c, Challenge::Basic { .. }))
+}
+
+/// Split on commas that aren't inside a quoted string
+fn split_unquoted_commas(header: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in header.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                items.push(&header[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&header[start..]);
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_bearer_challenge() {
+        let challenges = parse_challenges([
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/alpine:pull""#,
+        ]);
+        assert_eq!(
+            challenges,
+            vec![Challenge::Bearer(AuthChallenge {
+                realm: "https://auth.docker.io/token".to_string(),
+                service: "registry.docker.io".to_string(),
+                scope: "repository:library/alpine:pull".to_string(),
+            })]
+        );
+    }
+
+    #[test]
+    fn test_basic_and_bearer_in_one_header() {
+        let challenges = parse_challenges([
+            r#"Basic realm="Harbor", Bearer realm="https://harbor.local/service/token",service="harbor-registry""#,
+        ]);
+        assert_eq!(challenges.len(), 2);
+        assert_eq!(
+            challenges[0],
+            Challenge::Basic {
+                realm: "Harbor".to_string()
+            }
+        );
+        let bearer = bearer(&challenges).unwrap();
+        assert_eq!(bearer.realm, "https://harbor.local/service/token");
+        assert_eq!(bearer.service, "harbor-registry");
+    }
+
+    #[test]
+    fn test_challenges_across_headers() {
+        let challenges = parse_challenges([
+            r#"Basic realm="registry""#,
+            r#"Bearer realm="https://r.local/token",scope="repository:a/b:pull,push""#,
+        ]);
+        assert_eq!(
+            bearer(&challenges).unwrap().scope,
+            "repository:a/b:pull,push"
+        );
+        assert!(offers_basic(&challenges));
+    }
+
+    #[test]
+    fn test_basic_only() {
+        let challenges = parse_challenges([r#"Basic realm="Registry Realm""#]);
+        assert!(bearer(&challenges).is_none());
+        assert!(offers_basic(&challenges));
+
+        let challenges = parse_challenges(["Basic"]);
+        assert!(offers_basic(&challenges));
+    }
+
+    #[test]
+    fn test_unsupported_and_incomplete_challenges() {
+        let challenges = parse_challenges(["Negotiate", r#"Bearer service="x""#]);
+        assert_eq!(challenges, vec![Challenge::Other("Negotiate".to_string())]);
+    }
+
+    #[test]
+    fn test_token_url_with_multiple_scopes() {
+        let challenge = AuthChallenge {
+            realm: "https://auth.example.com/token".to_string(),
+            service: "registry.example.com".to_string(),
+            scope: String::new(),
+        };
+        let url = token_url(
+            &challenge,
+            &[Scope::pull("base/static"), Scope::push("user/app")],
+        )
+        .unwrap();
+        assert_eq!(
+            url,
+            "https://auth.example.com/token?service=registry.example.com\
+             &scope=repository%3Abase%2Fstatic%3Apull\
+             &scope=repository%3Auser%2Fapp%3Apull%2Cpush"
+        );
+    }
+
+    #[test]
+    fn test_token_url_falls_back_to_challenge_scope() {
+        let challenge = AuthChallenge {
+            realm: "https://auth.example.com/token".to_string(),
+            service: String::new(),
+            scope: "repository:a:pull".to_string(),
+        };
+        assert_eq!(
+            token_url(&challenge, &[]).unwrap(),
+            "https://auth.example.com/token?scope=repository%3Aa%3Apull"
+        );
+    }
+
+    #[test]
+    fn test_authorization_header_values() {
+        assert_eq!(
+            Authorization::Bearer("abc".to_string()).header_value(),
+            "Bearer abc"
+        );
+        assert_eq!(
+            Authorization::basic("user", "pass").header_value(),
+            "Basic dXNlcjpwYXNz"
+        );
+    }
+}
@@ -0,0 +1,346 @@
+//! Amazon ECR repositories
+//!
+//! Unlike most registries, ECR doesn't create a repository on first push;
+//! pushing to one that doesn't exist fails with `NAME_UNKNOWN`. When
+//! `create_repositories` is enabled, krust creates missing repositories
+//! through the ECR API before pushing, using the ambient AWS credentials.
+//! The API also lists the untagged images `krust prune-remote` considers.
+
+use anyhow::Result;
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::{BehaviorVersion, Region, SdkConfig};
+use aws_sdk_ecr::error::DisplayErrorContext;
+use aws_sdk_ecr::types::{ListImagesFilter, TagStatus};
+use tokio::sync::OnceCell;
+use tracing::{debug, info};
+
+/// AWS config and credentials, loaded the first time an ECR registry is used
+static SDK_CONFIG: OnceCell<SdkConfig> = OnceCell::const_new();
+
+/// An ECR registry host, e.g. `123456789012.dkr.ecr.us-west-2.amazonaws.com`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EcrRegistry {
+    pub account_id: String,
+    pub region: String,
+}
+
+impl EcrRegistry {
+    /// Recognize an ECR registry host, including FIPS and China endpoints
+    pub fn parse(registry: &str) -> Option<Self> {
+        let host = registry.split(':').next()?;
+        let host = host
+            .strip_suffix(".amazonaws.com")
+            .or_else(|| host.strip_suffix(".amazonaws.com.cn"))?;
+
+        let mut parts = host.splitn(4, '.');
+        let account_id = parts.next()?;
+        if parts.next()? != "dkr" {
+            return None;
+        }
+        let service = parts.next()?;
+        if service != "ecr" && service != "ecr-fips" {
+            return None;
+        }
+        let region = parts.next()?;
+
+        let valid_account =
+            account_id.len() == 12 && account_id.chars().all(|c| c.is_ascii_digit());
+        if !valid_account || region.is_empty() || region.contains('.') {
+            return None;
+        }
+        Some(Self {
+            account_id: account_id.to_string(),
+            region: region.to_string(),
+        })
+    }
+
+    /// An ECR API client for this registry's region. AWS credentials are
+    /// found the way the AWS CLI finds them, once per process.
+    pub async fn client(&self) -> aws_sdk_ecr::Client {
+        let region = Region::new(self.region.clone());
+        let config = SDK_CONFIG
+            .get_or_init(|| async {
+                let region = RegionProviderChain::default_provider().or_else(region.clone());
+                aws_config::defaults(BehaviorVersion::latest())
+                    .region(region)
+                    .load()
+                    .await
+            })
+            .await;
+        aws_sdk_ecr::Client::from_conf(
+            aws_sdk_ecr::config::Builder::from(config)
+                .region(region)
+                .build(),
+        )
+    }
+
+    /// The AWS CLI command that creates `repository`
+    pub fn create_command(&self, repository: &str) -> String {
+        format!(
+            "aws ecr create-repository --registry-id {} --region {} --repository-name {}",
+            self.account_id, self.region, repository
+        )
+    }
+
+    /// Create `repository` unless it already exists. Returns true if it was created.
+    pub async fn ensure_repository(&self, repository: &str) -> Result<bool> {
+        ensure_repository(&self.client().await, &self.account_id, repository).await
+    }
+
+    /// Digests of the images in `repository` that have no tags
+    pub async fn list_untagged(&self, repository: &str) -> Result<Vec<String>> {
+        list_untagged(&self.client().await, &self.account_id, repository).await
+    }
+}
+
+async fn ensure_repository(
+    client: &aws_sdk_ecr::Client,
+    account_id: &str,
+    repository: &str,
+) -> Result<bool> {
+    let describe = client
+        .describe_repositories()
+        .registry_id(account_id)
+        .repository_names(repository)
+        .send()
+        .await;
+    match describe {
+        Ok(_) => {
+            debug!("ECR repository {} exists", repository);
+            return Ok(false);
+        }
+        Err(e)
+            if e.as_service_error()
+                .is_some_and(|e| e.is_repository_not_found_exception()) => {}
+        Err(e) => anyhow::bail!(
+            "Failed to look up ECR repository {}: {}",
+            repository,
+            DisplayErrorContext(e)
+        ),
+    }
+
+    info!("Creating ECR repository {}", repository);
+    client
+        .create_repository()
+        .registry_id(account_id)
+        .repository_name(repository)
+        .send()
+        .await
+        .map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to create ECR repository {}: {}",
+                repository,
+                DisplayErrorContext(e)
+            )
+        })?;
+    Ok(true)
+}
+
+async fn list_untagged(
+    client: &aws_sdk_ecr::Client,
+    account_id: &str,
+    repository: &str,
+) -> Result<Vec<String>> {
+    let image_ids = client
+        .list_images()
+        .registry_id(account_id)
+        .repository_name(repository)
+        .filter(
+            ListImagesFilter::builder()
+                .tag_status(TagStatus::Untagged)
+                .build(),
+        )
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await
+        .map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to list untagged images in ECR repository {}: {}",
+                repository,
+                DisplayErrorContext(e)
+            )
+        })?;
+    Ok(image_ids
+        .into_iter()
+        .filter_map(|id| id.image_digest)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Serve the ECR API with no repositories except those created through
+    /// it, and one untagged image per ListImages page. Records each request's
+    /// X-Amz-Target and body.
+    async fn serve_ecr() -> (String, Arc<Mutex<Vec<(String, serde_json::Value)>>>) {
+        use http_body_util::{BodyExt, Full};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let log = log.clone();
+                let handler = hyper::service::service_fn(
+                    move |request: hyper::Request<hyper::body::Incoming>| {
+                        let log = log.clone();
+                        async move {
+                            let target = request
+                                .headers()
+                                .get("x-amz-target")
+                                .and_then(|value| value.to_str().ok())
+                                .unwrap_or_default()
+                                .rsplit('.')
+                                .next()
+                                .unwrap_or_default()
+                                .to_string();
+                            let body = request.into_body().collect().await.unwrap().to_bytes();
+                            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                            let mut log = log.lock().unwrap();
+                            let created = log.iter().any(|(t, _)| t == "CreateRepository");
+                            log.push((target.clone(), body.clone()));
+                            let (status, reply) = match target.as_str() {
+                                "DescribeRepositories" if !created => (
+                                    400,
+                                    serde_json::json!({"__type": "RepositoryNotFoundException", "message": "not found"}),
+                                ),
+                                "DescribeRepositories" | "CreateRepository" => {
+                                    (200, serde_json::json!({}))
+                                }
+                                "ListImages" => match body["nextToken"].as_str() {
+                                    None => (
+                                        200,
+                                        serde_json::json!({"imageIds": [{"imageDigest": "sha256:a"}], "nextToken": "page2"}),
+                                    ),
+                                    Some(_) => (
+                                        200,
+                                        serde_json::json!({"imageIds": [{"imageDigest": "sha256:b"}]}),
+                                    ),
+                                },
+                                _ => (
+                                    400,
+                                    serde_json::json!({"__type": "UnknownOperationException"}),
+                                ),
+                            };
+                            hyper::Response::builder()
+                                .status(status)
+                                .header("Content-Type", "application/x-amz-json-1.1")
+                                .body(Full::new(bytes::Bytes::from(reply.to_string())))
+                        }
+                    },
+                );
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), handler),
+                );
+            }
+        });
+        (endpoint, requests)
+    }
+
+    fn test_client(endpoint: String) -> aws_sdk_ecr::Client {
+        aws_sdk_ecr::Client::from_conf(
+            aws_sdk_ecr::Config::builder()
+                .behavior_version(BehaviorVersion::latest())
+                .region(Region::new("us-west-2"))
+                .credentials_provider(aws_sdk_ecr::config::Credentials::new(
+                    "AKID", "secret", None, None, "test",
+                ))
+                .endpoint_url(endpoint)
+                .build(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_ensure_repository() {
+        let (endpoint, requests) = serve_ecr().await;
+        let client = test_client(endpoint);
+        assert!(ensure_repository(&client, "123456789012", "team/app")
+            .await
+            .unwrap());
+        assert!(!ensure_repository(&client, "123456789012", "team/app")
+            .await
+            .unwrap());
+
+        let requests = requests.lock().unwrap();
+        let targets: Vec<_> = requests.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(
+            targets,
+            [
+                "DescribeRepositories",
+                "CreateRepository",
+                "DescribeRepositories"
+            ]
+        );
+        assert_eq!(
+            requests[0].1["repositoryNames"],
+            serde_json::json!(["team/app"])
+        );
+        assert_eq!(requests[1].1["registryId"], "123456789012");
+        assert_eq!(requests[1].1["repositoryName"], "team/app");
+    }
+
+    #[tokio::test]
+    async fn test_list_untagged() {
+        let (endpoint, requests) = serve_ecr().await;
+        let digests = list_untagged(&test_client(endpoint), "123456789012", "team/app")
+            .await
+            .unwrap();
+        assert_eq!(digests, ["sha256:a", "sha256:b"]);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].1["filter"]["tagStatus"], "UNTAGGED");
+        assert_eq!(requests[0].1["repositoryName"], "team/app");
+    }
+
+    #[test]
+    fn test_parse_ecr_registry() {
+        assert_eq!(
+            EcrRegistry::parse("123456789012.dkr.ecr.us-west-2.amazonaws.com"),
+            Some(EcrRegistry {
+                account_id: "123456789012".to_string(),
+                region: "us-west-2".to_string(),
+            })
+        );
+        assert_eq!(
+            EcrRegistry::parse("123456789012.dkr.ecr-fips.us-east-1.amazonaws.com")
+                .unwrap()
+                .region,
+            "us-east-1"
+        );
+        assert_eq!(
+            EcrRegistry::parse("123456789012.dkr.ecr.cn-north-1.amazonaws.com.cn")
+                .unwrap()
+                .region,
+            "cn-north-1"
+        );
+    }
+
+    #[test]
+    fn test_parse_non_ecr_registries() {
+        for registry in [
+            "ghcr.io",
+            "public.ecr.aws",
+            "localhost:5000",
+            "12345.dkr.ecr.us-west-2.amazonaws.com",
+            "123456789012.dkr.ecr.us-west-2.example.com",
+            "123456789012.s3.us-west-2.amazonaws.com",
+        ] {
+            assert_eq!(EcrRegistry::parse(registry), None, "{}", registry);
+        }
+    }
+
+    #[test]
+    fn test_create_command() {
+        let ecr = EcrRegistry::parse("123456789012.dkr.ecr.us-west-2.amazonaws.com").unwrap();
+        assert_eq!(
+            ecr.create_command("team/app"),
+            "aws ecr create-repository --registry-id 123456789012 --region us-west-2 --repository-name team/app"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! Manifest serialization
+//!
+//! Manifests are encoded exactly once; the digest is computed over those
+//! bytes and the same bytes are pushed or written, so the digest krust
+//! reports always matches what a registry or another tool will compute.
+
+use anyhow::Result;
+use serde::Serialize;
+
+/// How JSON documents (manifests, indexes and configs) are serialized
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JsonFormat {
+    /// Indented output, as krust has always produced
+    #[default]
+    Pretty,
+    /// Compact output with lexicographically sorted keys
+    Canonical,
+}
+
+/// Serialize a value in the given format.
+pub fn to_json<T: Serialize>(value: &T, format: JsonFormat) -> Result<Vec<u8>> {
+    match format {
+        JsonFormat::Pretty => Ok(serde_json::to_vec_pretty(value)?),
+        // serde_json::Value keeps object keys in a BTreeMap, so round-tripping
+        // through it sorts them at every level
+        JsonFormat::Canonical => Ok(serde_json::to_vec(&serde_json::to_value(value)?)?),
+    }
+}
+
+/// A manifest or index serialized once, along with the digest of those bytes
+#[derive(Debug, Clone)]
+pub struct EncodedManifest {
+    pub media_type: String,
+    pub data: Vec<u8>,
+    pub digest: String,
+}
+
+impl EncodedManifest {
+    pub fn new<T: Serialize>(value: &T, media_type: &str, format: JsonFormat) -> Result<Self> {
+        let data = to_json(value, format)?;
+        let digest = format!("sha256:{}", sha256::digest(data.as_slice()));
+        Ok(Self {
+            media_type: media_type.to_string(),
+            data,
+            digest,
+        })
+    }
+
+    pub fn size(&self) -> i64 {
+        self.data.len() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Sample {
+        #[serde(rename = "schemaVersion")]
+        schema_version: i32,
+        #[serde(rename = "mediaType")]
+        media_type: String,
+        annotations: std::collections::HashMap<String, String>,
+    }
+
+    fn sample() -> Sample {
+        let mut annotations = std::collections::HashMap::new();
+        annotations.insert("z".to_string(), "1".to_string());
+        annotations.insert("a".to_string(), "2".to_string());
+        Sample {
+            schema_version: 2,
+            media_type: "application/vnd.oci.image.manifest.v1+json".to_string(),
+            annotations,
+        }
+    }
+
+    #[test]
+    fn test_canonical_json_is_compact_and_sorted() {
+        let data = to_json(&sample(), JsonFormat::Canonical).unwrap();
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            r#"{"annotations":{"a":"2","z":"1"},"mediaType":"application/vnd.oci.image.manifest.v1+json","schemaVersion":2}"#
+        );
+    }
+
+    #[test]
+    fn test_pretty_json() {
+        let data = to_json(&sample(), JsonFormat::Pretty).unwrap();
+        assert!(String::from_utf8(data)
+            .unwrap()
+            .contains("\n  \"schemaVersion\": 2"));
+    }
+
+    #[test]
+    fn test_encoded_manifest_digest_matches_bytes() {
+        let encoded =
+            EncodedManifest::new(&sample(), "application/json", JsonFormat::Canonical).unwrap();
+        assert_eq!(
+            encoded.digest,
+            format!("sha256:{}", sha256::digest(encoded.data.as_slice()))
+        );
+        assert_eq!(encoded.size(), encoded.data.len() as i64);
+    }
+}
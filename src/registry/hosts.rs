@@ -0,0 +1,224 @@
+//! Per-registry settings from `[registries."<host>"]`
+//!
+//! A registry can be reached over plain HTTP (`insecure`), trust extra CA
+//! certificates, allow requests longer than the default timeout, or be read
+//! through a pull-through mirror. Registries that change how connections are
+//! made get an HTTP client of their own; the rest share the default one.
+
+use super::ImageReference;
+use crate::config::{RegistryConfig, RegistrySettings};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Registry settings keyed by host, as requests name it
+#[derive(Debug, Clone, Default)]
+pub struct Hosts {
+    configs: HashMap<String, RegistryConfig>,
+    clients: HashMap<String, reqwest::Client>,
+}
+
+/// The default HTTP client settings for registry traffic
+pub(super) fn client_builder(settings: &RegistrySettings) -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .user_agent(settings.user_agent())
+        .connect_timeout(settings.connect_timeout())
+        .timeout(settings.timeout())
+}
+
+/// The host `key` names as image references resolve it: without a scheme or
+/// trailing slash, and with Docker Hub's aliases mapped to its registry
+pub fn registry_host(key: &str) -> String {
+    let host = key
+        .trim()
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .trim_end_matches('/');
+    match host {
+        "docker.io" | "index.docker.io" | "index.docker.io/v1" => {
+            "registry-1.docker.io".to_string()
+        }
+        _ => host.to_string(),
+    }
+}
+
+impl Hosts {
+    pub fn new(
+        settings: &RegistrySettings,
+        registries: &HashMap<String, RegistryConfig>,
+    ) -> Result<Self> {
+        let mut hosts = Hosts::default();
+        for (key, config) in registries {
+            let host = registry_host(key);
+            if config.ca_file.is_some() || config.timeout_secs.is_some() {
+                let client = host_client(settings, config)
+                    .with_context(|| format!("Invalid settings for registry {}", key))?;
+                hosts.clients.insert(host.clone(), client);
+            }
+            hosts.configs.insert(host, config.clone());
+        }
+        Ok(hosts)
+    }
+
+    /// Settings for `registry`, if any were configured
+    pub fn get(&self, registry: &str) -> Option<&RegistryConfig> {
+        self.configs.get(registry)
+    }
+
+    /// Configured registries, keyed by host
+    pub fn configs(&self) -> &HashMap<String, RegistryConfig> {
+        &self.configs
+    }
+
+    /// The HTTP client for `registry`, if it needs one of its own
+    pub fn client(&self, registry: &str) -> Option<&reqwest::Client> {
+        self.clients.get(registry)
+    }
+
+    /// `scheme://registry`, the base of every URL for `registry`
+    pub fn base_url(&self, registry: &str) -> String {
+        let insecure = self.get(registry).is_some_and(|c| c.insecure);
+        format!("{}://{}", if insecure { "http" } else { "https" }, registry)
+    }
+
+    /// `reference` on its registry's mirror, if it has one. A mirror given
+    /// as `host/path` serves repositories under `path`.
+    pub fn mirror(&self, reference: &ImageReference) -> Option<ImageReference> {
+        let mirror = self.get(&reference.registry)?.mirror.as_deref()?;
+        let mirror = mirror
+            .trim()
+            .trim_start_matches("https://")
+            .trim_start_matches("http://")
+            .trim_end_matches('/');
+        let (host, prefix) = match mirror.split_once('/') {
+            Some((host, prefix)) => (host, Some(prefix)),
+            None => (mirror, None),
+        };
+        Some(ImageReference {
+            registry: registry_host(host),
+            repository: match prefix {
+                Some(prefix) => format!("{}/{}", prefix, reference.repository),
+                None => reference.repository.clone(),
+            },
+            tag: reference.tag.clone(),
+            digest: reference.digest.clone(),
+        })
+    }
+}
+
+fn host_client(settings: &RegistrySettings, config: &RegistryConfig) -> Result<reqwest::Client> {
+    let mut builder = client_builder(settings);
+    if let Some(secs) = config.timeout_secs {
+        builder = builder.timeout(Duration::from_secs(secs));
+    }
+    if let Some(path) = &config.ca_file {
+        let pem = std::fs::read(path)
+            .with_context(|| format!("Failed to read CA file {}", path.display()))?;
+        let certs = reqwest::Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("Failed to parse CA file {}", path.display()))?;
+        if certs.is_empty() {
+            anyhow::bail!("CA file {} has no certificates", path.display());
+        }
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts(entries: &[(&str, RegistryConfig)]) -> Hosts {
+        let registries = entries
+            .iter()
+            .map(|(host, config)| (host.to_string(), config.clone()))
+            .collect();
+        Hosts::new(&RegistrySettings::default(), &registries).unwrap()
+    }
+
+    #[test]
+    fn test_registry_host() {
+        assert_eq!(registry_host("ghcr.io"), "ghcr.io");
+        assert_eq!(registry_host("https://ghcr.io/"), "ghcr.io");
+        assert_eq!(registry_host("localhost:5000"), "localhost:5000");
+        assert_eq!(registry_host("docker.io"), "registry-1.docker.io");
+        assert_eq!(
+            registry_host("https://index.docker.io/v1/"),
+            "registry-1.docker.io"
+        );
+    }
+
+    #[test]
+    fn test_base_url() {
+        let hosts = hosts(&[(
+            "localhost:5000",
+            RegistryConfig {
+                insecure: true,
+                ..Default::default()
+            },
+        )]);
+        assert_eq!(hosts.base_url("localhost:5000"), "http://localhost:5000");
+        assert_eq!(hosts.base_url("ghcr.io"), "https://ghcr.io");
+    }
+
+    #[test]
+    fn test_mirror() {
+        let hosts = hosts(&[
+            (
+                "docker.io",
+                RegistryConfig {
+                    mirror: Some("mirror.gcr.io".to_string()),
+                    ..Default::default()
+                },
+            ),
+            (
+                "ghcr.io",
+                RegistryConfig {
+                    mirror: Some("https://harbor.corp/ghcr-proxy/".to_string()),
+                    ..Default::default()
+                },
+            ),
+        ]);
+
+        let alpine = ImageReference::parse("alpine:3.20").unwrap();
+        let mirrored = hosts.mirror(&alpine).unwrap();
+        assert_eq!(mirrored.reference(), "mirror.gcr.io/library/alpine:3.20");
+
+        let app = ImageReference::parse("ghcr.io/org/app:v1").unwrap();
+        let mirrored = hosts.mirror(&app).unwrap();
+        assert_eq!(mirrored.reference(), "harbor.corp/ghcr-proxy/org/app:v1");
+
+        let other = ImageReference::parse("quay.io/org/app").unwrap();
+        assert!(hosts.mirror(&other).is_none());
+    }
+
+    #[test]
+    fn test_ca_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ca.pem");
+        std::fs::write(&path, "not a certificate").unwrap();
+        let registries = HashMap::from([(
+            "registry.corp".to_string(),
+            RegistryConfig {
+                ca_file: Some(path),
+                ..Default::default()
+            },
+        )]);
+        let err = Hosts::new(&RegistrySettings::default(), &registries).unwrap_err();
+        assert!(format!("{:#}", err).contains("registry.corp"));
+
+        let registries = HashMap::from([(
+            "registry.corp".to_string(),
+            RegistryConfig {
+                timeout_secs: Some(900),
+                ..Default::default()
+            },
+        )]);
+        let hosts = Hosts::new(&RegistrySettings::default(), &registries).unwrap();
+        assert!(hosts.client("registry.corp").is_some());
+        assert!(hosts.client("ghcr.io").is_none());
+    }
+}
@@ -0,0 +1,164 @@
+//! Describe a remote image: its manifests, configs, layers and platforms
+
+use super::{
+    is_index_media_type, media_types, ImageReference, OciImageIndex, OciImageManifest,
+    RegistryAuth, RegistryClient,
+};
+use crate::manifest::Platform;
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+/// An image or image index as stored in a registry
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageDetails {
+    /// The inspected reference, by digest
+    pub reference: String,
+    #[serde(rename = "mediaType")]
+    pub media_type: String,
+    pub digest: String,
+    /// The index document, for multi-platform images
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<serde_json::Value>,
+    /// One entry per platform image (a single entry for a plain manifest)
+    pub images: Vec<PlatformDetails>,
+}
+
+/// A single-platform image
+#[derive(Debug, Clone, Serialize)]
+pub struct PlatformDetails {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Platform>,
+    pub digest: String,
+    pub manifest: serde_json::Value,
+    pub config: serde_json::Value,
+    pub layers: Vec<LayerDetails>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LayerDetails {
+    #[serde(rename = "mediaType")]
+    pub media_type: String,
+    pub digest: String,
+    pub size: i64,
+}
+
+impl PlatformDetails {
+    /// Compressed size of all layers
+    pub fn total_size(&self) -> i64 {
+        self.layers.iter().map(|l| l.size).sum()
+    }
+}
+
+/// Fetch an image's manifest, or every platform manifest of an index, along
+/// with their configs. `platform` limits an index to matching entries.
+pub async fn inspect_image(
+    registry_client: &mut RegistryClient,
+    image_ref: &str,
+    platform: Option<&Platform>,
+    auth: &RegistryAuth,
+) -> Result<ImageDetails> {
+    let reference = ImageReference::parse(image_ref)?;
+    let repository = reference.repository_url();
+    let (body, media_type, digest) = registry_client.get_manifest_raw(image_ref, auth).await?;
+    media_types::reject_schema1(&media_type, image_ref)?;
+
+    let mut details = ImageDetails {
+        reference: format!("{}@{}", repository, digest),
+        media_type: media_type.clone(),
+        digest: digest.clone(),
+        index: None,
+        images: Vec::new(),
+    };
+
+    if is_index_media_type(&media_type) {
+        let index: OciImageIndex =
+            serde_json::from_slice(&body).context("Failed to parse image index")?;
+        details.index = Some(serde_json::from_slice(&body)?);
+
+        for entry in &index.manifests {
+            if let (Some(requested), Some(entry_platform)) = (platform, &entry.platform) {
+                if !requested.matches(entry_platform) {
+                    continue;
+                }
+            }
+            let entry_ref = format!("{}@{}", repository, entry.digest);
+            let (body, _, _) = registry_client.get_manifest_raw(&entry_ref, auth).await?;
+            details.images.push(
+                platform_details(
+                    registry_client,
+                    &entry_ref,
+                    entry.platform.clone(),
+                    &entry.digest,
+                    &body,
+                    auth,
+                )
+                .await?,
+            );
+        }
+        if details.images.is_empty() {
+            if let Some(requested) = platform {
+                anyhow::bail!(
+                    "No manifest found for platform {} in {}",
+                    requested,
+                    image_ref
+                );
+            }
+        }
+    } else {
+        let image =
+            platform_details(registry_client, image_ref, None, &digest, &body, auth).await?;
+        details.images.push(image);
+    }
+
+    Ok(details)
+}
+
+async fn platform_details(
+    registry_client: &mut RegistryClient,
+    image_ref: &str,
+    platform: Option<Platform>,
+    digest: &str,
+    body: &[u8],
+    auth: &RegistryAuth,
+) -> Result<PlatformDetails> {
+    let manifest: OciImageManifest = serde_json::from_slice(body)
+        .with_context(|| format!("Failed to parse manifest {}", digest))?;
+
+    let config = match &manifest.config {
+        Some(descriptor) => {
+            let data = registry_client
+                .pull_blob(image_ref, descriptor, auth)
+                .await?;
+            serde_json::from_slice(&data).unwrap_or(serde_json::Value::Null)
+        }
+        None => serde_json::Value::Null,
+    };
+
+    // Single manifests carry their platform in the config
+    let platform = platform.or_else(|| {
+        Some(Platform {
+            os: config.get("os")?.as_str()?.to_string(),
+            architecture: config.get("architecture")?.as_str()?.to_string(),
+            variant: config
+                .get("variant")
+                .and_then(|v| v.as_str())
+                .map(String::from),
+        })
+    });
+
+    Ok(PlatformDetails {
+        platform,
+        digest: digest.to_string(),
+        manifest: serde_json::from_slice(body)?,
+        config,
+        layers: manifest
+            .layers
+            .iter()
+            .map(|l| LayerDetails {
+                media_type: l.media_type.clone(),
+                digest: l.digest.clone(),
+                size: l.size,
+            })
+            .collect(),
+    })
+}
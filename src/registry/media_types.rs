@@ -0,0 +1,358 @@
+//! Media type constants and consistency checks for image manifests
+
+use super::OciImageManifest;
+use anyhow::Result;
+use tracing::warn;
+
+pub const OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+pub const OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+pub const OCI_CONFIG: &str = "application/vnd.oci.image.config.v1+json";
+pub const OCI_LAYER: &str = "application/vnd.oci.image.layer.v1.tar";
+pub const OCI_LAYER_GZIP: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+pub const OCI_LAYER_ZSTD: &str = "application/vnd.oci.image.layer.v1.tar+zstd";
+pub const OCI_LAYER_NONDISTRIBUTABLE_GZIP: &str =
+    "application/vnd.oci.image.layer.nondistributable.v1.tar+gzip";
+
+/// Media type of the empty JSON blob used as the config of OCI 1.1 artifacts
+pub const OCI_EMPTY: &str = "application/vnd.oci.empty.v1+json";
+/// Contents and digest of the empty JSON blob
+pub const EMPTY_JSON: &[u8] = b"{}";
+pub const EMPTY_DIGEST: &str =
+    "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a";
+
+pub const DOCKER_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+pub const DOCKER_MANIFEST_LIST: &str = "application/vnd.docker.distribution.manifest.list.v2+json";
+pub const DOCKER_CONFIG: &str = "application/vnd.docker.container.image.v1+json";
+pub const DOCKER_LAYER: &str = "application/vnd.docker.image.rootfs.diff.tar";
+pub const DOCKER_LAYER_GZIP: &str = "application/vnd.docker.image.rootfs.diff.tar.gzip";
+pub const DOCKER_LAYER_FOREIGN_GZIP: &str =
+    "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip";
+
+/// Legacy Docker schema1 manifests, which krust can read but not build on
+pub const DOCKER_MANIFEST_V1: &str = "application/vnd.docker.distribution.manifest.v1+json";
+pub const DOCKER_MANIFEST_V1_SIGNED: &str =
+    "application/vnd.docker.distribution.manifest.v1+prettyjws";
+
+/// Manifest media types accepted when fetching an image that may be multi-platform
+pub const MANIFEST_ACCEPT: [&str; 4] = [
+    OCI_INDEX,
+    DOCKER_MANIFEST_LIST,
+    OCI_MANIFEST,
+    DOCKER_MANIFEST,
+];
+
+/// Manifest media types accepted when fetching a single-platform image
+pub const IMAGE_MANIFEST_ACCEPT: [&str; 2] = [OCI_MANIFEST, DOCKER_MANIFEST];
+
+/// Determine the media type of a fetched manifest. The response's
+/// Content-Type is authoritative when it names a manifest type; otherwise
+/// (e.g. `application/json` from some registries) the body's `mediaType`
+/// field is used. Schema1 manifests, which have no `mediaType`, are
+/// recognized by their `schemaVersion`.
+pub fn manifest_media_type(content_type: Option<&str>, body: &[u8]) -> Option<String> {
+    let from_header = content_type
+        .and_then(|ct| ct.split(';').next())
+        .map(str::trim)
+        .filter(|ct| MANIFEST_ACCEPT.contains(ct) || is_schema1(ct));
+    if let Some(media_type) = from_header {
+        return Some(media_type.to_string());
+    }
+
+    let body = serde_json::from_slice::<serde_json::Value>(body).ok();
+    let from_body = body.as_ref().and_then(|v| match v.get("mediaType") {
+        Some(media_type) => media_type.as_str().map(String::from),
+        None if v.get("schemaVersion").and_then(|v| v.as_i64()) == Some(1) => {
+            Some(DOCKER_MANIFEST_V1.to_string())
+        }
+        None => None,
+    });
+    from_body.or_else(|| content_type.map(String::from))
+}
+
+/// Whether a media type is a Docker schema1 manifest
+pub fn is_schema1(media_type: &str) -> bool {
+    media_type == DOCKER_MANIFEST_V1 || media_type == DOCKER_MANIFEST_V1_SIGNED
+}
+
+/// Fail with an actionable error if a fetched manifest is schema1. Schema1
+/// lacks the config blob and layer diff IDs krust needs to layer on top of an
+/// image, so it can't be converted reliably.
+pub fn reject_schema1(media_type: &str, image_ref: &str) -> Result<()> {
+    if is_schema1(media_type) {
+        anyhow::bail!(
+            "{} is stored as a deprecated Docker schema1 manifest ({}), which krust does not support. \
+             Re-push it with a current tool (e.g. `crane copy`, or `docker pull` and `docker push` \
+             with a recent Docker) so the registry serves a schema2 or OCI manifest",
+            image_ref,
+            media_type
+        );
+    }
+    Ok(())
+}
+
+/// Check the OCI 1.1 rules for artifact manifests: they must be OCI image
+/// manifests, and an empty config requires `artifactType` to say what the
+/// artifact is.
+pub fn validate_artifact(manifest: &OciImageManifest) -> Result<()> {
+    let empty_config = manifest
+        .config
+        .as_ref()
+        .is_some_and(|c| c.media_type == OCI_EMPTY);
+    if empty_config && manifest.artifact_type.is_none() {
+        anyhow::bail!("Manifest has an empty config but no artifactType");
+    }
+    if (manifest.artifact_type.is_some() || manifest.subject.is_some())
+        && manifest.media_type != OCI_MANIFEST
+    {
+        anyhow::bail!(
+            "Artifact manifests must use media type {}, not {}",
+            OCI_MANIFEST,
+            manifest.media_type
+        );
+    }
+    Ok(())
+}
+
+/// Map a config or layer media type to its OCI equivalent.
+/// Unknown media types (e.g. artifact types) are returned unchanged.
+pub fn to_oci(media_type: &str) -> &str {
+    match media_type {
+        DOCKER_CONFIG => OCI_CONFIG,
+        DOCKER_LAYER => OCI_LAYER,
+        DOCKER_LAYER_GZIP => OCI_LAYER_GZIP,
+        DOCKER_LAYER_FOREIGN_GZIP => OCI_LAYER_NONDISTRIBUTABLE_GZIP,
+        DOCKER_MANIFEST => OCI_MANIFEST,
+        DOCKER_MANIFEST_LIST => OCI_INDEX,
+        other => other,
+    }
+}
+
+/// Map a config or layer media type to its Docker equivalent.
+/// Media types without a Docker equivalent are returned unchanged.
+pub fn to_docker(media_type: &str) -> &str {
+    match media_type {
+        OCI_CONFIG => DOCKER_CONFIG,
+        OCI_LAYER => DOCKER_LAYER,
+        OCI_LAYER_GZIP => DOCKER_LAYER_GZIP,
+        OCI_LAYER_NONDISTRIBUTABLE_GZIP => DOCKER_LAYER_FOREIGN_GZIP,
+        OCI_MANIFEST => DOCKER_MANIFEST,
+        OCI_INDEX => DOCKER_MANIFEST_LIST,
+        other => other,
+    }
+}
+
+/// Rewrite config and layer media types so they match the manifest's own
+/// media type family (OCI or Docker), warning about every conversion.
+/// Blob contents are identical across families, so digests stay valid.
+/// Returns the number of descriptors that were converted.
+pub fn make_consistent(manifest: &mut OciImageManifest) -> usize {
+    let convert: fn(&str) -> &str = match manifest.media_type.as_str() {
+        OCI_MANIFEST => to_oci,
+        DOCKER_MANIFEST => to_docker,
+        _ => return 0,
+    };
+
+    let mut converted = 0;
+    let descriptors = manifest.config.iter_mut().chain(manifest.layers.iter_mut());
+    for descriptor in descriptors {
+        let target = convert(&descriptor.media_type);
+        if target != descriptor.media_type {
+            warn!(
+                "Converting {} media type {} to {} to match manifest type {}",
+                descriptor.digest, descriptor.media_type, target, manifest.media_type
+            );
+            descriptor.media_type = target.to_string();
+            converted += 1;
+        }
+    }
+
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::OciDescriptor;
+
+    fn descriptor(media_type: &str, digest: &str) -> OciDescriptor {
+        OciDescriptor {
+            media_type: media_type.to_string(),
+            digest: digest.to_string(),
+            size: 1,
+            urls: None,
+            annotations: None,
+        }
+    }
+
+    #[test]
+    fn test_round_trip_conversions() {
+        for media_type in [
+            DOCKER_CONFIG,
+            DOCKER_LAYER,
+            DOCKER_LAYER_GZIP,
+            DOCKER_LAYER_FOREIGN_GZIP,
+            DOCKER_MANIFEST,
+            DOCKER_MANIFEST_LIST,
+        ] {
+            assert_eq!(to_docker(to_oci(media_type)), media_type);
+        }
+        assert_eq!(to_oci(OCI_LAYER_ZSTD), OCI_LAYER_ZSTD);
+        assert_eq!(to_docker(OCI_LAYER_ZSTD), OCI_LAYER_ZSTD);
+    }
+
+    #[test]
+    fn test_manifest_media_type_prefers_content_type() {
+        let body = br#"{"schemaVersion":2,"manifests":[]}"#;
+        assert_eq!(
+            manifest_media_type(Some("application/vnd.oci.image.index.v1+json"), body).unwrap(),
+            OCI_INDEX
+        );
+        assert_eq!(
+            manifest_media_type(
+                Some("application/vnd.docker.distribution.manifest.list.v2+json; charset=utf-8"),
+                body
+            )
+            .unwrap(),
+            DOCKER_MANIFEST_LIST
+        );
+    }
+
+    #[test]
+    fn test_manifest_media_type_falls_back_to_body() {
+        let body = format!(r#"{{"schemaVersion":2,"mediaType":"{}"}}"#, DOCKER_MANIFEST);
+        assert_eq!(
+            manifest_media_type(Some("application/json"), body.as_bytes()).unwrap(),
+            DOCKER_MANIFEST
+        );
+        assert_eq!(
+            manifest_media_type(None, body.as_bytes()).unwrap(),
+            DOCKER_MANIFEST
+        );
+        assert_eq!(
+            manifest_media_type(Some("application/json"), b"{}").unwrap(),
+            "application/json"
+        );
+        assert!(manifest_media_type(None, b"{}").is_none());
+    }
+
+    #[test]
+    fn test_manifest_media_type_detects_schema1() {
+        assert_eq!(
+            manifest_media_type(Some(DOCKER_MANIFEST_V1_SIGNED), b"{}").unwrap(),
+            DOCKER_MANIFEST_V1_SIGNED
+        );
+        let body = br#"{"schemaVersion":1,"name":"library/old","fsLayers":[]}"#;
+        assert_eq!(
+            manifest_media_type(Some("application/json"), body).unwrap(),
+            DOCKER_MANIFEST_V1
+        );
+    }
+
+    #[test]
+    fn test_reject_schema1() {
+        let err = reject_schema1(DOCKER_MANIFEST_V1_SIGNED, "registry.local/old:1").unwrap_err();
+        assert!(err.to_string().contains("schema1"));
+        assert!(err.to_string().contains("registry.local/old:1"));
+        assert!(reject_schema1(DOCKER_MANIFEST, "registry.local/new:1").is_ok());
+    }
+
+    #[test]
+    fn test_make_consistent_oci_manifest_with_docker_layers() {
+        let mut manifest = OciImageManifest {
+            schema_version: 2,
+            media_type: OCI_MANIFEST.to_string(),
+            artifact_type: None,
+            config: Some(descriptor(DOCKER_CONFIG, "sha256:config")),
+            layers: vec![
+                descriptor(DOCKER_LAYER_GZIP, "sha256:base"),
+                descriptor(OCI_LAYER_GZIP, "sha256:app"),
+            ],
+            subject: None,
+            annotations: None,
+        };
+
+        assert_eq!(make_consistent(&mut manifest), 2);
+        assert_eq!(manifest.config.unwrap().media_type, OCI_CONFIG);
+        assert!(manifest
+            .layers
+            .iter()
+            .all(|l| l.media_type == OCI_LAYER_GZIP));
+    }
+
+    #[test]
+    fn test_make_consistent_docker_manifest() {
+        let mut manifest = OciImageManifest {
+            schema_version: 2,
+            media_type: DOCKER_MANIFEST.to_string(),
+            artifact_type: None,
+            config: Some(descriptor(OCI_CONFIG, "sha256:config")),
+            layers: vec![descriptor(OCI_LAYER_GZIP, "sha256:app")],
+            subject: None,
+            annotations: None,
+        };
+
+        assert_eq!(make_consistent(&mut manifest), 2);
+        assert_eq!(manifest.config.unwrap().media_type, DOCKER_CONFIG);
+        assert_eq!(manifest.layers[0].media_type, DOCKER_LAYER_GZIP);
+    }
+
+    #[test]
+    fn test_make_consistent_already_consistent() {
+        let mut manifest = OciImageManifest {
+            schema_version: 2,
+            media_type: OCI_MANIFEST.to_string(),
+            artifact_type: None,
+            config: Some(descriptor(OCI_CONFIG, "sha256:config")),
+            layers: vec![descriptor(OCI_LAYER_GZIP, "sha256:app")],
+            subject: None,
+            annotations: None,
+        };
+
+        assert_eq!(make_consistent(&mut manifest), 0);
+    }
+
+    #[test]
+    fn test_empty_digest() {
+        assert_eq!(
+            format!("sha256:{}", sha256::digest(EMPTY_JSON)),
+            EMPTY_DIGEST
+        );
+    }
+
+    #[test]
+    fn test_validate_artifact() {
+        let sbom = OciImageManifest::artifact(
+            "application/spdx+json",
+            None,
+            vec![descriptor("application/spdx+json", "sha256:sbom")],
+            Some(descriptor(OCI_MANIFEST, "sha256:image")),
+        );
+        assert!(validate_artifact(&sbom).is_ok());
+
+        let mut untyped = sbom.clone();
+        untyped.artifact_type = None;
+        assert!(validate_artifact(&untyped)
+            .unwrap_err()
+            .to_string()
+            .contains("artifactType"));
+
+        let mut docker = sbom;
+        docker.media_type = DOCKER_MANIFEST.to_string();
+        assert!(validate_artifact(&docker).is_err());
+    }
+
+    #[test]
+    fn test_make_consistent_leaves_artifact_config() {
+        let mut manifest = OciImageManifest::artifact(
+            "application/vnd.example+type",
+            Some(descriptor(
+                "application/vnd.example.config+json",
+                "sha256:c",
+            )),
+            vec![],
+            None,
+        );
+        assert_eq!(make_consistent(&mut manifest), 0);
+        assert!(manifest.is_artifact());
+    }
+}
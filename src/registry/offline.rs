@@ -0,0 +1,171 @@
+//! Checking that a base image can be built on without a registry
+//!
+//! With `--offline-images` everything comes from the manifest cache, so
+//! before building, the base image is walked from its digest down to every
+//! layer the build will read, and each piece that isn't cached is reported.
+//! A build that's going to fail then fails once, listing all of it, rather
+//! than on the first miss.
+
+use super::cache::ManifestCache;
+use super::{is_index_media_type, media_types, ImageReference, OciImageIndex, OciImageManifest};
+use crate::manifest::Platform;
+use anyhow::{Context, Result};
+
+/// Everything needed to build `platforms` on `base_image` at `digest` that
+/// isn't in `cache`. Layers are only needed by outputs that copy the whole
+/// image, like tarballs and OCI layouts, so they're checked with `layers`.
+pub fn missing_content(
+    cache: &ManifestCache,
+    base_image: &str,
+    digest: &str,
+    platforms: &[Platform],
+    layers: bool,
+) -> Result<Vec<String>> {
+    let repository = ImageReference::parse(base_image)?.repository_url();
+    let Some(body) = cache.get(digest) else {
+        return Ok(vec![format!("manifest {}@{}", repository, digest)]);
+    };
+    let media_type = media_types::manifest_media_type(None, &body)
+        .with_context(|| format!("Unable to determine manifest media type for {}", base_image))?;
+
+    let mut missing = Vec::new();
+    if !is_index_media_type(&media_type) {
+        let manifest: OciImageManifest =
+            serde_json::from_slice(&body).context("Failed to parse image manifest")?;
+        missing_image_content(cache, &manifest, &repository, layers, &mut missing);
+        return Ok(missing);
+    }
+
+    let index: OciImageIndex =
+        serde_json::from_slice(&body).context("Failed to parse image index")?;
+    for platform in platforms {
+        let entry = index
+            .manifests
+            .iter()
+            .find(|entry| entry.platform.as_ref().is_some_and(|p| platform.matches(p)));
+        let Some(entry) = entry else {
+            missing.push(format!("{} image in {}@{}", platform, repository, digest));
+            continue;
+        };
+        let Some(body) = cache.get(&entry.digest) else {
+            missing.push(format!(
+                "{} manifest {}@{}",
+                platform, repository, entry.digest
+            ));
+            continue;
+        };
+        let manifest: OciImageManifest =
+            serde_json::from_slice(&body).context("Failed to parse platform manifest")?;
+        missing_image_content(cache, &manifest, &repository, layers, &mut missing);
+    }
+    missing.dedup();
+    Ok(missing)
+}
+
+fn missing_image_content(
+    cache: &ManifestCache,
+    manifest: &OciImageManifest,
+    repository: &str,
+    layers: bool,
+    missing: &mut Vec<String>,
+) {
+    if let Some(config) = &manifest.config {
+        if !cache.contains(&config.digest) {
+            missing.push(format!("config {}@{}", repository, config.digest));
+        }
+    }
+    if layers {
+        for layer in &manifest.layers {
+            if !cache.contains(&layer.digest) {
+                missing.push(format!(
+                    "layer {}@{} ({} bytes)",
+                    repository, layer.digest, layer.size
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn put(cache: &ManifestCache, data: &[u8]) -> String {
+        let digest = format!("sha256:{}", sha256::digest(data));
+        cache.put(&digest, data).unwrap();
+        digest
+    }
+
+    fn digest_of(data: &[u8]) -> String {
+        format!("sha256:{}", sha256::digest(data))
+    }
+
+    #[test]
+    fn test_missing_content() {
+        let dir = tempdir().unwrap();
+        let cache = ManifestCache::new(dir.path());
+        let base = "cgr.dev/chainguard/static:latest";
+        let config = put(&cache, br#"{"os":"linux","architecture":"amd64"}"#);
+        let layer = digest_of(b"layer");
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": media_types::OCI_MANIFEST,
+            "config": {"mediaType": media_types::OCI_CONFIG, "digest": config, "size": 2},
+            "layers": [{"mediaType": media_types::OCI_LAYER_GZIP, "digest": layer, "size": 5}]
+        });
+        let manifest = put(&cache, manifest.to_string().as_bytes());
+        let index = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": media_types::OCI_INDEX,
+            "manifests": [
+                {"mediaType": media_types::OCI_MANIFEST, "digest": manifest, "size": 1,
+                 "platform": {"os": "linux", "architecture": "amd64"}},
+                {"mediaType": media_types::OCI_MANIFEST, "digest": digest_of(b"arm64"), "size": 1,
+                 "platform": {"os": "linux", "architecture": "arm64"}}
+            ]
+        });
+        let index = put(&cache, index.to_string().as_bytes());
+        let amd64 = vec![Platform::new("linux", "amd64", None)];
+        let arm64 = Platform::new("linux", "arm64", None);
+        let riscv = Platform::new("linux", "riscv64", None);
+
+        // Configs are enough unless layers are needed
+        assert!(missing_content(&cache, base, &index, &amd64, false)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            missing_content(&cache, base, &index, &amd64, true).unwrap(),
+            vec![format!(
+                "layer cgr.dev/chainguard/static@{} (5 bytes)",
+                layer
+            )]
+        );
+        put(&cache, b"layer");
+        assert!(missing_content(&cache, base, &index, &amd64, true)
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            missing_content(&cache, base, &index, &[arm64, riscv], false).unwrap(),
+            vec![
+                format!(
+                    "linux/arm64 manifest cgr.dev/chainguard/static@{}",
+                    digest_of(b"arm64")
+                ),
+                format!("linux/riscv64 image in cgr.dev/chainguard/static@{}", index),
+            ]
+        );
+
+        // Single-platform bases are checked directly
+        assert!(missing_content(&cache, base, &manifest, &amd64, true)
+            .unwrap()
+            .is_empty());
+
+        let unknown = digest_of(b"unknown");
+        assert_eq!(
+            missing_content(&cache, base, &unknown, &amd64, false).unwrap(),
+            vec![format!("manifest cgr.dev/chainguard/static@{}", unknown)]
+        );
+    }
+}
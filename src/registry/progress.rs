@@ -0,0 +1,150 @@
+//! Progress reporting for blob transfers
+//!
+//! A [`ProgressReporter`] attached to a `RegistryClient` receives an event
+//! for every chunk of a blob downloaded by `pull_blob` or uploaded by
+//! `push_blob`, with a final event when the transfer completes.
+
+use bytes::Bytes;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Size of the chunks uploads are streamed in
+const UPLOAD_CHUNK_SIZE: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Download,
+    Upload,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressEvent {
+    pub digest: String,
+    pub direction: Direction,
+    /// Bytes transferred so far
+    pub transferred: u64,
+    /// Expected size, if known
+    pub total: Option<u64>,
+    /// Average rate since the transfer started, in bytes per second
+    pub rate: f64,
+    /// Whether this is the final event for the transfer
+    pub done: bool,
+}
+
+/// Receives blob transfer progress. Called from transfer tasks, so
+/// implementations must be cheap and thread-safe.
+pub trait ProgressReporter: Send + Sync + Debug {
+    fn report(&self, event: &ProgressEvent);
+}
+
+/// Tracks a single transfer and emits its events
+#[derive(Debug)]
+pub(crate) struct Transfer {
+    reporter: Arc<dyn ProgressReporter>,
+    digest: String,
+    direction: Direction,
+    total: Option<u64>,
+    transferred: u64,
+    started: Instant,
+}
+
+impl Transfer {
+    pub fn new(
+        reporter: Arc<dyn ProgressReporter>,
+        digest: &str,
+        direction: Direction,
+        total: Option<u64>,
+    ) -> Self {
+        Self {
+            reporter,
+            digest: digest.to_string(),
+            direction,
+            total,
+            transferred: 0,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn advance(&mut self, bytes: usize) {
+        self.transferred += bytes as u64;
+        self.emit(false);
+    }
+
+    pub fn finish(&mut self) {
+        self.emit(true);
+    }
+
+    fn emit(&self, done: bool) {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            self.transferred as f64 / elapsed
+        } else {
+            0.0
+        };
+        self.reporter.report(&ProgressEvent {
+            digest: self.digest.clone(),
+            direction: self.direction,
+            transferred: self.transferred,
+            total: self.total,
+            rate,
+            done,
+        });
+    }
+}
+
+/// A request body that reports upload progress as it is streamed
+pub(crate) fn upload_body(data: &[u8], mut transfer: Transfer) -> reqwest::Body {
+    let data = Bytes::copy_from_slice(data);
+    let chunks: Vec<Bytes> = (0..data.len())
+        .step_by(UPLOAD_CHUNK_SIZE)
+        .map(|start| data.slice(start..(start + UPLOAD_CHUNK_SIZE).min(data.len())))
+        .collect();
+    let last = chunks.len();
+
+    let stream =
+        futures_util::stream::iter(chunks.into_iter().enumerate().map(move |(i, chunk)| {
+            transfer.advance(chunk.len());
+            if i + 1 == last {
+                transfer.finish();
+            }
+            Ok::<_, std::io::Error>(chunk)
+        }));
+    reqwest::Body::wrap_stream(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct Recorder(Mutex<Vec<ProgressEvent>>);
+
+    impl ProgressReporter for Recorder {
+        fn report(&self, event: &ProgressEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[test]
+    fn test_transfer_events() {
+        let recorder = Arc::new(Recorder::default());
+        let mut transfer = Transfer::new(
+            recorder.clone(),
+            "sha256:abc",
+            Direction::Download,
+            Some(10),
+        );
+        transfer.advance(4);
+        transfer.advance(6);
+        transfer.finish();
+
+        let events = recorder.0.lock().unwrap();
+        let transferred: Vec<u64> = events.iter().map(|e| e.transferred).collect();
+        assert_eq!(transferred, vec![4, 10, 10]);
+        assert!(!events[1].done);
+        assert!(events[2].done);
+        assert_eq!(events[2].total, Some(10));
+    }
+}
@@ -0,0 +1,256 @@
+//! Retention policies for pruning images from a remote repository
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{HashMap, HashSet};
+
+/// A remote image (identified by digest) and the tags that point at it
+#[derive(Debug, Clone)]
+pub struct RemoteImage {
+    pub digest: String,
+    /// Empty for images pushed by digest only
+    pub tags: Vec<String>,
+    pub created: Option<DateTime<Utc>>,
+    /// Digests of the platform images of an index
+    pub children: Vec<String>,
+}
+
+/// Which images to keep when pruning a repository
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    /// Always keep this many of the most recently created images
+    pub keep: Option<usize>,
+    /// Only delete images created longer ago than this
+    pub older_than: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    /// Select the images that fall outside the policy.
+    ///
+    /// Images are ordered newest first; the first `keep` are retained, and of
+    /// the rest only those older than `older_than` are selected. Images with
+    /// an unknown creation time are never selected.
+    pub fn select<'a>(
+        &self,
+        images: &'a [RemoteImage],
+        now: DateTime<Utc>,
+    ) -> Vec<&'a RemoteImage> {
+        let mut dated: Vec<&RemoteImage> = images.iter().filter(|i| i.created.is_some()).collect();
+        dated.sort_by_key(|image| std::cmp::Reverse(image.created));
+
+        let cutoff = self.older_than.map(|d| now - d);
+
+        dated
+            .into_iter()
+            .skip(self.keep.unwrap_or(0))
+            .filter(|image| match (cutoff, image.created) {
+                (Some(cutoff), Some(created)) => created < cutoff,
+                _ => true,
+            })
+            .collect()
+    }
+}
+
+/// Leave out untagged images that are platform images of a listed index:
+/// they go with their index rather than being counted or pruned on their own
+pub fn without_children(images: Vec<RemoteImage>) -> Vec<RemoteImage> {
+    let children: HashSet<String> = images
+        .iter()
+        .flat_map(|image| image.children.iter().cloned())
+        .collect();
+    images
+        .into_iter()
+        .filter(|image| !image.tags.is_empty() || !children.contains(&image.digest))
+        .collect()
+}
+
+/// Digests that must survive deleting `selected`: every image not selected,
+/// and the platform images those that are indexes refer to
+pub fn retained_digests(images: &[RemoteImage], selected: &[&RemoteImage]) -> HashSet<String> {
+    let selected: HashSet<&str> = selected.iter().map(|image| image.digest.as_str()).collect();
+    images
+        .iter()
+        .filter(|image| !selected.contains(image.digest.as_str()))
+        .flat_map(|image| std::iter::once(&image.digest).chain(&image.children))
+        .cloned()
+        .collect()
+}
+
+/// Group `(tag, digest)` pairs by digest, preserving first-seen order.
+pub fn group_tags_by_digest(tagged: Vec<(String, String)>) -> Vec<RemoteImage> {
+    let mut order = Vec::new();
+    let mut tags_by_digest: HashMap<String, Vec<String>> = HashMap::new();
+
+    for (tag, digest) in tagged {
+        let tags = tags_by_digest.entry(digest.clone()).or_insert_with(|| {
+            order.push(digest.clone());
+            Vec::new()
+        });
+        tags.push(tag);
+    }
+
+    order
+        .into_iter()
+        .map(|digest| RemoteImage {
+            tags: tags_by_digest.remove(&digest).unwrap_or_default(),
+            digest,
+            created: None,
+            children: Vec::new(),
+        })
+        .collect()
+}
+
+/// Parse a human-friendly age like "30d", "12h", "2w" or "90m".
+pub fn parse_age(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let amount: i64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid age '{}': expected e.g. 30d, 12h, 2w", value))?;
+
+    let age = match unit {
+        "s" => Duration::try_seconds(amount),
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" | "" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        _ => anyhow::bail!(
+            "Invalid age unit '{}' in '{}': use s, m, h, d or w",
+            unit,
+            value
+        ),
+    };
+    age.ok_or_else(|| anyhow::anyhow!("Age '{}' is too large", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(digest: &str, days_ago: Option<i64>, now: DateTime<Utc>) -> RemoteImage {
+        RemoteImage {
+            digest: digest.to_string(),
+            tags: vec![],
+            created: days_ago.map(|d| now - Duration::days(d)),
+            children: vec![],
+        }
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("30d").unwrap(), Duration::days(30));
+        assert_eq!(parse_age("12h").unwrap(), Duration::hours(12));
+        assert_eq!(parse_age("2w").unwrap(), Duration::weeks(2));
+        assert_eq!(parse_age("90m").unwrap(), Duration::minutes(90));
+        assert_eq!(parse_age("45s").unwrap(), Duration::seconds(45));
+        assert_eq!(parse_age("7").unwrap(), Duration::days(7));
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("10y").is_err());
+        assert!(parse_age("9999999999999999w")
+            .unwrap_err()
+            .to_string()
+            .contains("too large"));
+        assert!(parse_age("99999999999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_untagged_children() {
+        let now = Utc::now();
+        let mut index = image("sha256:index", Some(1), now);
+        index.tags = vec!["v1".to_string()];
+        index.children = vec!["sha256:amd64".to_string(), "sha256:arm64".to_string()];
+        let mut old_index = image("sha256:old", Some(40), now);
+        old_index.children = vec!["sha256:amd64".to_string(), "sha256:gone".to_string()];
+        let images = without_children(vec![
+            index,
+            old_index,
+            image("sha256:amd64", Some(40), now),
+            image("sha256:gone", Some(40), now),
+            image("sha256:loose", Some(40), now),
+        ]);
+        let digests: Vec<&str> = images.iter().map(|i| i.digest.as_str()).collect();
+        assert_eq!(digests, ["sha256:index", "sha256:old", "sha256:loose"]);
+
+        let policy = RetentionPolicy {
+            keep: None,
+            older_than: Some(Duration::days(30)),
+        };
+        let selected = policy.select(&images, now);
+        let digests: Vec<&str> = selected.iter().map(|i| i.digest.as_str()).collect();
+        assert_eq!(digests, ["sha256:old", "sha256:loose"]);
+
+        // The kept index still needs the platform image it shares
+        let retained = retained_digests(&images, &selected);
+        assert!(retained.contains("sha256:amd64"));
+        assert!(retained.contains("sha256:index"));
+        assert!(!retained.contains("sha256:gone"));
+        assert!(!retained.contains("sha256:old"));
+    }
+
+    #[test]
+    fn test_select_keep_newest() {
+        let now = Utc::now();
+        let images = vec![
+            image("sha256:old", Some(10), now),
+            image("sha256:new", Some(1), now),
+            image("sha256:mid", Some(5), now),
+        ];
+        let policy = RetentionPolicy {
+            keep: Some(1),
+            older_than: None,
+        };
+        let selected: Vec<&str> = policy
+            .select(&images, now)
+            .iter()
+            .map(|i| i.digest.as_str())
+            .collect();
+        assert_eq!(selected, vec!["sha256:mid", "sha256:old"]);
+    }
+
+    #[test]
+    fn test_select_keep_and_older_than() {
+        let now = Utc::now();
+        let images = vec![
+            image("sha256:a", Some(40), now),
+            image("sha256:b", Some(35), now),
+            image("sha256:c", Some(20), now),
+            image("sha256:d", Some(1), now),
+        ];
+        let policy = RetentionPolicy {
+            keep: Some(1),
+            older_than: Some(Duration::days(30)),
+        };
+        let selected: Vec<&str> = policy
+            .select(&images, now)
+            .iter()
+            .map(|i| i.digest.as_str())
+            .collect();
+        assert_eq!(selected, vec!["sha256:b", "sha256:a"]);
+    }
+
+    #[test]
+    fn test_select_skips_unknown_creation_time() {
+        let now = Utc::now();
+        let images = vec![image("sha256:unknown", None, now)];
+        let policy = RetentionPolicy::default();
+        assert!(policy.select(&images, now).is_empty());
+    }
+
+    #[test]
+    fn test_group_tags_by_digest() {
+        let grouped = group_tags_by_digest(vec![
+            ("latest".to_string(), "sha256:a".to_string()),
+            ("v1".to_string(), "sha256:b".to_string()),
+            ("v2".to_string(), "sha256:a".to_string()),
+        ]);
+        assert_eq!(grouped.len(), 2);
+        assert_eq!(grouped[0].digest, "sha256:a");
+        assert_eq!(grouped[0].tags, vec!["latest", "v2"]);
+        assert_eq!(grouped[1].digest, "sha256:b");
+        assert_eq!(grouped[1].tags, vec!["v1"]);
+    }
+}
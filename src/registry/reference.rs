@@ -0,0 +1,353 @@
+//! Image reference parsing and validation
+//!
+//! References follow the distribution spec grammar:
+//! `[domain[:port]/]path-component[/path-component...][:tag][@digest]`.
+//! Parse errors point at the offending segment of the reference.
+
+use anyhow::Result;
+
+/// Longest repository name (domain and path) the spec allows
+const MAX_NAME_LEN: usize = 255;
+/// Longest tag the spec allows
+const MAX_TAG_LEN: usize = 128;
+
+#[derive(Debug, Clone)]
+pub struct ImageReference {
+    pub registry: String,
+    pub repository: String,
+    pub tag: Option<String>,
+    pub digest: Option<String>,
+}
+
+impl ImageReference {
+    pub fn parse(reference: &str) -> Result<Self> {
+        let input = reference.trim();
+        if input.is_empty() {
+            anyhow::bail!("Invalid image reference: reference is empty");
+        }
+        let invalid = |start: usize, segment: &str, reason: &str| -> anyhow::Error {
+            invalid_reference(input, start, segment.len(), reason)
+        };
+
+        // Split on @ for digest
+        let (name_tag, digest) = match input.find('@') {
+            Some(at) => (&input[..at], Some((at + 1, &input[at + 1..]))),
+            None => (input, None),
+        };
+
+        // A tag follows the last ':' after the last '/'; earlier colons belong to a port
+        let last_component = name_tag.rfind('/').map_or(0, |i| i + 1);
+        let (name, tag) = match name_tag[last_component..].find(':') {
+            Some(i) => {
+                let colon = last_component + i;
+                (
+                    &name_tag[..colon],
+                    Some((colon + 1, &name_tag[colon + 1..])),
+                )
+            }
+            None => (name_tag, None),
+        };
+
+        if name.is_empty() {
+            return Err(invalid(0, name_tag, "repository name is empty"));
+        }
+        if name.len() > MAX_NAME_LEN {
+            return Err(invalid(
+                0,
+                name,
+                &format!("repository name is longer than {} characters", MAX_NAME_LEN),
+            ));
+        }
+
+        // Split registry from repository. The first component is a registry
+        // if it looks like a hostname (has a '.' or port) or is localhost.
+        let (domain, path, path_start) = match name.split_once('/') {
+            Some((first, rest))
+                if first.contains('.') || first.contains(':') || first == "localhost" =>
+            {
+                (Some(first), rest, first.len() + 1)
+            }
+            _ => (None, name, 0),
+        };
+
+        if let Some(domain) = domain {
+            validate_domain(domain).map_err(|reason| invalid(0, domain, reason))?;
+        }
+
+        let mut offset = path_start;
+        for component in path.split('/') {
+            if !is_path_component(component) {
+                let reason = if component.is_empty() {
+                    "repository path component is empty"
+                } else if component.chars().any(|c| c.is_ascii_uppercase()) {
+                    "repository path components must be lowercase"
+                } else {
+                    "repository path components may only contain lowercase letters, digits \
+                     and single '.', '_', '__' or '-' separators"
+                };
+                return Err(invalid(offset, component, reason));
+            }
+            offset += component.len() + 1;
+        }
+
+        if let Some((start, tag)) = tag {
+            validate_tag(tag).map_err(|reason| invalid(start, tag, &reason))?;
+        }
+        if let Some((start, digest)) = digest {
+            validate_digest(digest).map_err(|reason| invalid(start, digest, &reason))?;
+        }
+
+        let (registry, repository) = match domain {
+            // Handle docker.io redirect
+            Some("docker.io") | Some("index.docker.io") => {
+                ("registry-1.docker.io".to_string(), path.to_string())
+            }
+            Some(domain) => (domain.to_string(), path.to_string()),
+            // No explicit registry, assume registry-1.docker.io (Docker Hub)
+            None if !path.contains('/') => (
+                "registry-1.docker.io".to_string(),
+                format!("library/{}", path),
+            ),
+            None => ("registry-1.docker.io".to_string(), path.to_string()),
+        };
+
+        Ok(ImageReference {
+            registry,
+            repository,
+            tag: tag.map(|(_, t)| t.to_string()),
+            digest: digest.map(|(_, d)| d.to_string()),
+        })
+    }
+
+    pub fn reference(&self) -> String {
+        if let Some(digest) = &self.digest {
+            format!("{}@{}", self.repository_url(), digest)
+        } else {
+            format!(
+                "{}:{}",
+                self.repository_url(),
+                self.tag.as_deref().unwrap_or("latest")
+            )
+        }
+    }
+
+    pub fn repository_url(&self) -> String {
+        format!("{}/{}", self.registry, self.repository)
+    }
+}
+
+/// Build an error quoting the reference with the bad segment underlined
+fn invalid_reference(input: &str, start: usize, len: usize, reason: &str) -> anyhow::Error {
+    let prefix = input[..start].chars().count();
+    let width = input[start..start + len].chars().count().max(1);
+    anyhow::anyhow!(
+        "Invalid image reference {:?}: {}\n  {}\n  {}{}",
+        input,
+        reason,
+        input,
+        " ".repeat(prefix),
+        "^".repeat(width)
+    )
+}
+
+/// `host[:port]`, where host is dot-separated alphanumeric labels (which may
+/// contain inner hyphens) or a bracketed IPv6 address
+fn validate_domain(domain: &str) -> std::result::Result<(), &'static str> {
+    let (host, port) = if domain.starts_with('[') {
+        let end = domain.find(']').ok_or("unterminated IPv6 address")?;
+        let ip = &domain[1..end];
+        if ip.is_empty() || !ip.chars().all(|c| c.is_ascii_hexdigit() || c == ':') {
+            return Err("invalid IPv6 address");
+        }
+        match &domain[end + 1..] {
+            "" => (None, None),
+            rest => (
+                None,
+                Some(rest.strip_prefix(':').ok_or("invalid registry port")?),
+            ),
+        }
+    } else {
+        match domain.split_once(':') {
+            Some((host, port)) => (Some(host), Some(port)),
+            None => (Some(domain), None),
+        }
+    };
+
+    if let Some(host) = host {
+        let valid_label = |label: &str| {
+            !label.is_empty()
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        };
+        if !host.split('.').all(valid_label) {
+            return Err("invalid registry hostname");
+        }
+    }
+    if let Some(port) = port {
+        if port.is_empty() || !port.chars().all(|c| c.is_ascii_digit()) {
+            return Err("registry port must be a number");
+        }
+    }
+    Ok(())
+}
+
+/// `[a-z0-9]+((\.|_|__|-+)[a-z0-9]+)*`
+fn is_path_component(component: &str) -> bool {
+    let bytes = component.as_bytes();
+    let alnum = |b: u8| b.is_ascii_lowercase() || b.is_ascii_digit();
+    if bytes.is_empty() || !alnum(bytes[0]) || !alnum(bytes[bytes.len() - 1]) {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if alnum(bytes[i]) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && !alnum(bytes[i]) {
+            i += 1;
+        }
+        let separator = &component[start..i];
+        let valid = matches!(separator, "." | "_" | "__") || separator.bytes().all(|b| b == b'-');
+        if !valid {
+            return false;
+        }
+    }
+    true
+}
+
+/// `[A-Za-z0-9_][A-Za-z0-9_.-]{0,127}`
+fn validate_tag(tag: &str) -> std::result::Result<(), String> {
+    let word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    match tag.chars().next() {
+        None => return Err("tag is empty".to_string()),
+        Some(first) if !word(first) => {
+            return Err("tag must start with a letter, digit or '_'".to_string())
+        }
+        _ => {}
+    }
+    if tag.len() > MAX_TAG_LEN {
+        return Err(format!("tag is longer than {} characters", MAX_TAG_LEN));
+    }
+    if !tag.chars().all(|c| word(c) || c == '.' || c == '-') {
+        return Err("tag may only contain letters, digits, '_', '.' and '-'".to_string());
+    }
+    Ok(())
+}
+
+/// `algorithm:encoded`, with length and charset checks for registered algorithms
+fn validate_digest(digest: &str) -> std::result::Result<(), String> {
+    let (algorithm, encoded) = digest
+        .split_once(':')
+        .ok_or_else(|| "digest must be of the form algorithm:hex".to_string())?;
+
+    let hex_len = match algorithm {
+        "sha256" => 64,
+        "sha512" => 128,
+        other => {
+            return Err(format!(
+                "unsupported digest algorithm {:?} (expected sha256 or sha512)",
+                other
+            ))
+        }
+    };
+    if encoded.len() != hex_len {
+        return Err(format!(
+            "{} digest must be {} hex characters, got {}",
+            algorithm,
+            hex_len,
+            encoded.len()
+        ));
+    }
+    if !encoded
+        .chars()
+        .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+    {
+        return Err(format!("{} digest must be lowercase hex", algorithm));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn error(reference: &str) -> String {
+        ImageReference::parse(reference).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_tag_and_digest() {
+        let r = ImageReference::parse(&format!("ghcr.io/user/app:v1@{}", DIGEST)).unwrap();
+        assert_eq!(r.repository, "user/app");
+        assert_eq!(r.tag.as_deref(), Some("v1"));
+        assert_eq!(r.digest.as_deref(), Some(DIGEST));
+    }
+
+    #[test]
+    fn test_port_without_tag() {
+        let r = ImageReference::parse("localhost:5000/app").unwrap();
+        assert_eq!(r.registry, "localhost:5000");
+        assert_eq!(r.repository, "app");
+        assert_eq!(r.tag, None);
+
+        let r = ImageReference::parse("[::1]:5000/app:dev").unwrap();
+        assert_eq!(r.registry, "[::1]:5000");
+        assert_eq!(r.tag.as_deref(), Some("dev"));
+    }
+
+    #[test]
+    fn test_path_components() {
+        for valid in ["a", "a.b", "a_b", "a__b", "a---b", "0ab"] {
+            assert!(is_path_component(valid), "{}", valid);
+        }
+        for invalid in ["", "A", "a.", "-a", "a..b", "a___b", "a.-b", "a b"] {
+            assert!(!is_path_component(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_uppercase_repository_is_highlighted() {
+        let err = error("ghcr.io/User/app");
+        assert!(err.contains("must be lowercase"), "{}", err);
+        assert!(
+            err.ends_with("\n  ghcr.io/User/app\n          ^^^^"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_invalid_tags() {
+        assert!(error("alpine:").contains("tag is empty"));
+        assert!(error("alpine:.hidden").contains("must start with"));
+        assert!(error("alpine:v1+build").contains("may only contain"));
+        assert!(error(&format!("alpine:{}", "a".repeat(129))).contains("longer than 128"));
+        assert!(ImageReference::parse(&format!("alpine:{}", "a".repeat(128))).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_digests() {
+        assert!(error("alpine@sha256:abc").contains("64 hex characters"));
+        assert!(error("alpine@md5:abc").contains("unsupported digest algorithm"));
+        assert!(error("alpine@e3b0c442").contains("algorithm:hex"));
+        assert!(
+            error(&DIGEST.to_uppercase().replace("SHA256", "alpine@sha256"))
+                .contains("lowercase hex")
+        );
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        assert!(error("").contains("empty"));
+        assert!(error("ghcr.io//app").contains("component is empty"));
+        assert!(error("ghcr.io:abc/app").contains("port must be a number"));
+        assert!(error("-bad.io/app").contains("invalid registry hostname"));
+        assert!(error(&format!("ghcr.io/{}", "a".repeat(255))).contains("longer than 255"));
+    }
+}
@@ -0,0 +1,309 @@
+//! Per-registry authentication state shared by clones of a `RegistryClient`
+//!
+//! Credentials are resolved once per registry (again when temporary ones
+//! are about to expire), the Bearer challenge from
+//! the `/v2/` ping is remembered, and tokens are reused for as long as
+//! they're valid and cover the requested scopes. A multi-platform build
+//! then authenticates a handful of times instead of once per request.
+
+use super::challenge::{AuthChallenge, Authorization, Scope};
+use super::RegistryAuth;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Token lifetime to assume when the token endpoint doesn't say (per the
+/// distribution token spec)
+pub(crate) const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Tokens are refreshed this long before they expire
+const EXPIRY_MARGIN: Duration = Duration::from_secs(10);
+
+/// Temporary credentials, like ECR tokens, are resolved again this long
+/// before they expire, so they outlast the pushes that start with them
+const CREDENTIALS_EXPIRY_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// The outcome of authenticating for a set of scopes
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Grant {
+    /// The registry needs no token, or accepts Basic credentials directly:
+    /// valid for any scope
+    Any(Option<Authorization>),
+    /// A token for the requested scopes, valid for the given duration
+    Scoped(Authorization, Duration),
+    /// No token could be obtained; requests are sent without one
+    Denied,
+}
+
+impl Grant {
+    pub fn authorization(&self) -> Option<Authorization> {
+        match self {
+            Grant::Any(authorization) => authorization.clone(),
+            Grant::Scoped(authorization, _) => Some(authorization.clone()),
+            Grant::Denied => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CachedCredentials {
+    auth: RegistryAuth,
+    /// None for credentials that don't expire
+    expires_at: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct CachedGrant {
+    /// None for grants that cover every scope
+    scopes: Option<Vec<Scope>>,
+    authorization: Option<Authorization>,
+    expires_at: Option<Instant>,
+}
+
+impl CachedGrant {
+    fn covers(&self, requested: &[Scope], now: Instant) -> bool {
+        if self.expires_at.is_some_and(|at| now >= at) {
+            return false;
+        }
+        match &self.scopes {
+            None => true,
+            Some(scopes) => requested.iter().all(|r| {
+                scopes.iter().any(|s| {
+                    s.repository == r.repository
+                        && r.actions
+                            .split(',')
+                            .all(|a| s.actions.split(',').any(|b| a == b))
+                })
+            }),
+        }
+    }
+}
+
+/// Authentication state, keyed by registry and the identity of the credentials used
+#[derive(Debug, Default)]
+pub(crate) struct Sessions {
+    credentials: Mutex<HashMap<String, CachedCredentials>>,
+    challenges: Mutex<HashMap<String, AuthChallenge>>,
+    grants: Mutex<HashMap<(String, String), Vec<CachedGrant>>>,
+}
+
+impl Sessions {
+    /// Credentials for a registry, resolving them with `resolve` the first
+    /// time and whenever the cached ones are about to expire. `resolve`
+    /// returns the credentials and when they expire, if they do.
+    pub async fn credentials<F>(
+        &self,
+        registry: &str,
+        resolve: impl FnOnce() -> F,
+    ) -> anyhow::Result<RegistryAuth>
+    where
+        F: Future<Output = anyhow::Result<(RegistryAuth, Option<SystemTime>)>>,
+    {
+        let now = Instant::now();
+        if let Some(cached) = self.credentials.lock().unwrap().get(registry) {
+            if cached.expires_at.is_none_or(|at| now < at) {
+                return Ok(cached.auth.clone());
+            }
+        }
+        let (auth, expires_at) = resolve().await?;
+        let expires_at = expires_at.map(|at| {
+            let lifetime = at.duration_since(SystemTime::now()).unwrap_or_default();
+            now + lifetime.saturating_sub(CREDENTIALS_EXPIRY_MARGIN)
+        });
+        self.credentials.lock().unwrap().insert(
+            registry.to_string(),
+            CachedCredentials {
+                auth: auth.clone(),
+                expires_at,
+            },
+        );
+        Ok(auth)
+    }
+
+    /// The Bearer challenge a registry issued earlier in this session
+    pub fn challenge(&self, registry: &str) -> Option<AuthChallenge> {
+        self.challenges.lock().unwrap().get(registry).cloned()
+    }
+
+    pub fn remember_challenge(&self, registry: &str, challenge: &AuthChallenge) {
+        self.challenges
+            .lock()
+            .unwrap()
+            .insert(registry.to_string(), challenge.clone());
+    }
+
+    /// A still-valid authorization covering every requested scope, if one is cached.
+    /// The outer Option distinguishes "nothing cached" from "cached: no auth needed".
+    pub fn cached(
+        &self,
+        registry: &str,
+        identity: &str,
+        scopes: &[Scope],
+    ) -> Option<Option<Authorization>> {
+        let now = Instant::now();
+        self.grants
+            .lock()
+            .unwrap()
+            .get(&(registry.to_string(), identity.to_string()))?
+            .iter()
+            .find(|grant| grant.covers(scopes, now))
+            .map(|grant| grant.authorization.clone())
+    }
+
+    pub fn store(&self, registry: &str, identity: &str, scopes: &[Scope], grant: &Grant) {
+        let cached = match grant {
+            Grant::Any(authorization) => CachedGrant {
+                scopes: None,
+                authorization: authorization.clone(),
+                expires_at: None,
+            },
+            Grant::Scoped(authorization, lifetime) => CachedGrant {
+                scopes: Some(scopes.to_vec()),
+                authorization: Some(authorization.clone()),
+                expires_at: Some(Instant::now() + lifetime.saturating_sub(EXPIRY_MARGIN)),
+            },
+            // Don't cache failures; credentials or permissions may change
+            Grant::Denied => return,
+        };
+
+        let now = Instant::now();
+        let mut grants = self.grants.lock().unwrap();
+        let entry = grants
+            .entry((registry.to_string(), identity.to_string()))
+            .or_default();
+        entry.retain(|g| g.expires_at.is_none_or(|at| now < at));
+        entry.push(cached);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(t: &str) -> Authorization {
+        Authorization::Bearer(t.to_string())
+    }
+
+    #[test]
+    fn test_push_token_covers_pull() {
+        let sessions = Sessions::default();
+        sessions.store(
+            "ghcr.io",
+            "anonymous",
+            &[Scope::push("user/app")],
+            &Grant::Scoped(token("t1"), DEFAULT_TOKEN_LIFETIME),
+        );
+
+        assert_eq!(
+            sessions.cached("ghcr.io", "anonymous", &[Scope::pull("user/app")]),
+            Some(Some(token("t1")))
+        );
+        assert_eq!(
+            sessions.cached("ghcr.io", "anonymous", &[Scope::push("user/app")]),
+            Some(Some(token("t1")))
+        );
+        assert_eq!(
+            sessions.cached("ghcr.io", "anonymous", &[Scope::pull("user/other")]),
+            None
+        );
+        assert_eq!(
+            sessions.cached("ghcr.io", "basic:user", &[Scope::pull("user/app")]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_pull_token_does_not_cover_push() {
+        let sessions = Sessions::default();
+        sessions.store(
+            "ghcr.io",
+            "anonymous",
+            &[Scope::pull("user/app")],
+            &Grant::Scoped(token("t1"), DEFAULT_TOKEN_LIFETIME),
+        );
+        assert_eq!(
+            sessions.cached("ghcr.io", "anonymous", &[Scope::push("user/app")]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_expired_tokens_are_not_reused() {
+        let sessions = Sessions::default();
+        sessions.store(
+            "ghcr.io",
+            "anonymous",
+            &[Scope::pull("user/app")],
+            &Grant::Scoped(token("t1"), Duration::from_secs(5)),
+        );
+        assert_eq!(
+            sessions.cached("ghcr.io", "anonymous", &[Scope::pull("user/app")]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_unscoped_grants_cover_everything() {
+        let sessions = Sessions::default();
+        sessions.store("localhost:5000", "anonymous", &[], &Grant::Any(None));
+        assert_eq!(
+            sessions.cached("localhost:5000", "anonymous", &[Scope::push("any/repo")]),
+            Some(None)
+        );
+
+        sessions.store("ghcr.io", "anonymous", &[Scope::pull("a")], &Grant::Denied);
+        assert_eq!(
+            sessions.cached("ghcr.io", "anonymous", &[Scope::pull("a")]),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_credentials_resolved_once() {
+        let sessions = Sessions::default();
+        let mut calls = 0;
+        for _ in 0..3 {
+            sessions
+                .credentials("ghcr.io", || {
+                    calls += 1;
+                    async { Ok((RegistryAuth::Anonymous, None)) }
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(calls, 1);
+    }
+
+    #[tokio::test]
+    async fn test_expiring_credentials_resolved_again() {
+        let sessions = Sessions::default();
+        let mut calls = 0;
+        for _ in 0..2 {
+            sessions
+                .credentials("123456789012.dkr.ecr.us-west-2.amazonaws.com", || {
+                    calls += 1;
+                    // Within the expiry margin, so already too old to reuse
+                    let expires_at = SystemTime::now() + Duration::from_secs(60);
+                    async move { Ok((RegistryAuth::Anonymous, Some(expires_at))) }
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(calls, 2);
+
+        // Credentials good for hours are reused
+        let mut calls = 0;
+        for _ in 0..2 {
+            sessions
+                .credentials("ghcr.io", || {
+                    calls += 1;
+                    let expires_at = SystemTime::now() + Duration::from_secs(12 * 60 * 60);
+                    async move { Ok((RegistryAuth::Anonymous, Some(expires_at))) }
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,128 @@
+//! Deadlines for registry operations and classification of timeout failures
+
+use crate::config::RegistrySettings;
+use anyhow::Result;
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+/// A registry operation ran out of time, as opposed to failing outright
+#[derive(Debug)]
+pub struct TimeoutError {
+    /// What was being done, e.g. "pushing ghcr.io/user/app"
+    pub operation: String,
+    /// The limit that was exceeded
+    pub after: Duration,
+    /// Config key controlling the limit
+    pub setting: &'static str,
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Timed out after {}s while {} (raise {} in the krust config)",
+            self.after.as_secs(),
+            self.operation,
+            self.setting
+        )
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+/// Run a registry operation under the configured overall deadline.
+///
+/// Failures caused by a per-request timeout are tagged with a
+/// [`TimeoutError`] as well, so slow registries are reported distinctly
+/// from other errors.
+pub async fn with_deadline<T>(
+    settings: &RegistrySettings,
+    operation: &str,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let result = match settings.deadline() {
+        Some(deadline) => match tokio::time::timeout(deadline, fut).await {
+            Ok(result) => result,
+            Err(_) => {
+                return Err(TimeoutError {
+                    operation: operation.to_string(),
+                    after: deadline,
+                    setting: "registry.deadline_secs",
+                }
+                .into())
+            }
+        },
+        None => fut.await,
+    };
+
+    result.map_err(|err| match request_timeout(&err, settings) {
+        Some((after, setting)) => err.context(TimeoutError {
+            operation: operation.to_string(),
+            after,
+            setting,
+        }),
+        None => err,
+    })
+}
+
+/// If an error was caused by a reqwest timeout, return the limit and its config key
+fn request_timeout(
+    err: &anyhow::Error,
+    settings: &RegistrySettings,
+) -> Option<(Duration, &'static str)> {
+    let reqwest_err = err
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<reqwest::Error>())?;
+    if !reqwest_err.is_timeout() {
+        return None;
+    }
+    if reqwest_err.is_connect() {
+        Some((settings.connect_timeout(), "registry.connect_timeout_secs"))
+    } else {
+        Some((settings.timeout(), "registry.timeout_secs"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_deadline_exceeded() {
+        let settings = RegistrySettings {
+            deadline_secs: Some(0),
+            ..Default::default()
+        };
+        let err = with_deadline(&settings, "pushing test", async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        })
+        .await
+        .unwrap_err();
+
+        let timeout = err.downcast_ref::<TimeoutError>().unwrap();
+        assert_eq!(timeout.setting, "registry.deadline_secs");
+        assert!(err.to_string().contains("while pushing test"));
+    }
+
+    #[tokio::test]
+    async fn test_other_errors_pass_through() {
+        let settings = RegistrySettings::default();
+        let err = with_deadline::<()>(&settings, "pushing test", async { anyhow::bail!("denied") })
+            .await
+            .unwrap_err();
+
+        assert!(err.downcast_ref::<TimeoutError>().is_none());
+        assert_eq!(err.to_string(), "denied");
+    }
+
+    #[tokio::test]
+    async fn test_no_deadline() {
+        let settings = RegistrySettings::default();
+        let value = with_deadline(&settings, "pushing test", async { Ok(42) })
+            .await
+            .unwrap();
+        assert_eq!(value, 42);
+    }
+}
@@ -0,0 +1,332 @@
+//! Read-back verification of pushed images (`krust build --verify`)
+//!
+//! After a push, the image index, one platform manifest and its config are
+//! fetched again by digest and compared with what was uploaded. If Docker is
+//! available, the image's binary is then run with `--version` as a smoke test.
+
+use super::encoding::EncodedManifest;
+use super::{
+    oci_image_index, OciDescriptor, OciImageIndex, OciImageManifest, RegistryAuth, RegistryClient,
+};
+use crate::manifest::{ImageIndex, ManifestDescriptor, Platform};
+use anyhow::{Context, Result};
+use std::fmt;
+use tracing::{info, warn};
+
+/// Outcome of a single verification check
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckStatus {
+    Passed,
+    Failed(String),
+    Skipped(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.status {
+            CheckStatus::Passed => write!(f, "ok      {}", self.name),
+            CheckStatus::Failed(reason) => write!(f, "FAILED  {}: {}", self.name, reason),
+            CheckStatus::Skipped(reason) => write!(f, "skipped {}: {}", self.name, reason),
+        }
+    }
+}
+
+/// Results of verifying one pushed image
+#[derive(Debug, Clone)]
+pub struct VerificationReport {
+    pub image_ref: String,
+    pub checks: Vec<Check>,
+}
+
+impl VerificationReport {
+    /// Whether no check failed (skipped checks don't count against it)
+    pub fn passed(&self) -> bool {
+        !self
+            .checks
+            .iter()
+            .any(|c| matches!(c.status, CheckStatus::Failed(_)))
+    }
+
+    /// Log the report as a verification section
+    pub fn log(&self) {
+        info!("Verification of {}:", self.image_ref);
+        for check in &self.checks {
+            match check.status {
+                CheckStatus::Failed(_) => warn!("  {}", check),
+                _ => info!("  {}", check),
+            }
+        }
+    }
+}
+
+/// Check fetched bytes against the digest and size they were pushed with
+pub fn check_content(name: &str, data: &[u8], digest: &str, size: i64) -> Check {
+    let actual_digest = format!("sha256:{}", sha256::digest(data));
+    let status = if actual_digest != digest {
+        CheckStatus::Failed(format!("expected digest {}, got {}", digest, actual_digest))
+    } else if data.len() as i64 != size {
+        CheckStatus::Failed(format!("expected {} bytes, got {}", size, data.len()))
+    } else {
+        CheckStatus::Passed
+    };
+    Check {
+        name: name.to_string(),
+        status,
+    }
+}
+
+/// Check that a fetched index lists exactly the platform manifests that were pushed
+pub fn check_index_entries(index: &OciImageIndex, expected: &[ManifestDescriptor]) -> Check {
+    let mut problems = Vec::new();
+    for descriptor in expected {
+        match index
+            .manifests
+            .iter()
+            .find(|e| e.digest == descriptor.digest)
+        {
+            Some(entry) if entry.size != descriptor.size => problems.push(format!(
+                "{} has size {}, expected {}",
+                descriptor.digest, entry.size, descriptor.size
+            )),
+            Some(_) => {}
+            None => problems.push(format!(
+                "{} ({}) is missing",
+                descriptor.digest, descriptor.platform
+            )),
+        }
+    }
+    if index.manifests.len() != expected.len() {
+        problems.push(format!(
+            "index lists {} manifests, expected {}",
+            index.manifests.len(),
+            expected.len()
+        ));
+    }
+
+    Check {
+        name: "index entries".to_string(),
+        status: if problems.is_empty() {
+            CheckStatus::Passed
+        } else {
+            CheckStatus::Failed(problems.join("; "))
+        },
+    }
+}
+
+/// Verify a pushed image index by reading it back from the registry.
+/// `index_ref` must be `repository@digest` and `pushed` the index as it
+/// was pushed.
+pub async fn verify_pushed_image(
+    registry_client: &mut RegistryClient,
+    index_ref: &str,
+    pushed: &ImageIndex,
+    auth: &RegistryAuth,
+) -> Result<VerificationReport> {
+    let (repository, digest) = index_ref
+        .split_once('@')
+        .context("Verification needs an image reference by digest")?;
+    let mut checks = Vec::new();
+
+    // Re-encode the index as it was pushed to learn its expected size
+    let expected = &pushed.manifests;
+    let pushed_index = oci_image_index(pushed);
+    let encoded = EncodedManifest::new(
+        &pushed_index,
+        &pushed_index.media_type,
+        registry_client.json_format(),
+    )?;
+
+    let (body, _, _) = registry_client.get_manifest_raw(index_ref, auth).await?;
+    checks.push(check_content("image index", &body, digest, encoded.size()));
+    let index: OciImageIndex =
+        serde_json::from_slice(&body).context("Failed to parse pushed image index")?;
+    checks.push(check_index_entries(&index, expected));
+
+    // Read back one platform image, preferring one the host can run
+    let host = Platform::host();
+    let Some(descriptor) = expected
+        .iter()
+        .find(|d| host.matches(&d.platform))
+        .or_else(|| expected.first())
+    else {
+        return Ok(VerificationReport {
+            image_ref: index_ref.to_string(),
+            checks,
+        });
+    };
+
+    let platform_ref = format!("{}@{}", repository, descriptor.digest);
+    let (body, _, _) = registry_client
+        .get_manifest_raw(&platform_ref, auth)
+        .await?;
+    checks.push(check_content(
+        &format!("{} manifest", descriptor.platform),
+        &body,
+        &descriptor.digest,
+        descriptor.size,
+    ));
+    let manifest: OciImageManifest =
+        serde_json::from_slice(&body).context("Failed to parse pushed platform manifest")?;
+
+    let config_descriptor: &OciDescriptor = manifest
+        .config
+        .as_ref()
+        .context("Pushed platform manifest has no config")?;
+    let config_data = registry_client
+        .pull_blob(&platform_ref, config_descriptor, auth)
+        .await?;
+    checks.push(check_content(
+        &format!("{} config", descriptor.platform),
+        &config_data,
+        &config_descriptor.digest,
+        config_descriptor.size,
+    ));
+
+    let entrypoint = serde_json::from_slice::<serde_json::Value>(&config_data)
+        .ok()
+        .and_then(|c| {
+            let config = &c["config"];
+            config["Entrypoint"][0]
+                .as_str()
+                .or(config["Cmd"][0].as_str())
+                .map(String::from)
+        });
+    checks.push(smoke_test(&platform_ref, &descriptor.platform, entrypoint.as_deref()).await);
+
+    Ok(VerificationReport {
+        image_ref: index_ref.to_string(),
+        checks,
+    })
+}
+
+/// Run the image's binary with `--version` under Docker, if Docker is available
+async fn smoke_test(image_ref: &str, platform: &Platform, entrypoint: Option<&str>) -> Check {
+    let name = "smoke test (--version)".to_string();
+    let skipped = |reason: &str| Check {
+        name: name.clone(),
+        status: CheckStatus::Skipped(reason.to_string()),
+    };
+
+    let Some(entrypoint) = entrypoint else {
+        return skipped("image has no command");
+    };
+    let docker_available = tokio::process::Command::new("docker")
+        .arg("version")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .await
+        .is_ok_and(|s| s.success());
+    if !docker_available {
+        return skipped("docker is not available");
+    }
+
+    let output = tokio::process::Command::new("docker")
+        .args(["run", "--rm", "--platform", &platform.to_string()])
+        .args(["--entrypoint", entrypoint, image_ref, "--version"])
+        .output()
+        .await;
+    let status = match output {
+        Ok(output) if output.status.success() => CheckStatus::Passed,
+        Ok(output) => CheckStatus::Failed(format!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+        Err(e) => CheckStatus::Failed(e.to_string()),
+    };
+    Check { name, status }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::ImageIndexEntry;
+
+    fn descriptor(digest: &str, size: i64) -> ManifestDescriptor {
+        ManifestDescriptor {
+            media_type: crate::registry::media_types::OCI_MANIFEST.to_string(),
+            size,
+            digest: digest.to_string(),
+            platform: Platform::new("linux", "amd64", None),
+            annotations: None,
+        }
+    }
+
+    fn index(entries: &[(&str, i64)]) -> OciImageIndex {
+        OciImageIndex {
+            schema_version: 2,
+            media_type: crate::registry::media_types::OCI_INDEX.to_string(),
+            manifests: entries
+                .iter()
+                .map(|(digest, size)| ImageIndexEntry {
+                    media_type: crate::registry::media_types::OCI_MANIFEST.to_string(),
+                    digest: digest.to_string(),
+                    size: *size,
+                    platform: None,
+                    annotations: None,
+                })
+                .collect(),
+            annotations: None,
+        }
+    }
+
+    #[test]
+    fn test_check_content() {
+        let data = b"manifest";
+        let digest = format!("sha256:{}", sha256::digest(&data[..]));
+        assert_eq!(
+            check_content("m", data, &digest, 8).status,
+            CheckStatus::Passed
+        );
+        assert!(matches!(
+            check_content("m", data, &digest, 9).status,
+            CheckStatus::Failed(_)
+        ));
+        assert!(matches!(
+            check_content("m", data, "sha256:other", 8).status,
+            CheckStatus::Failed(_)
+        ));
+    }
+
+    #[test]
+    fn test_check_index_entries() {
+        let expected = vec![descriptor("sha256:a", 10)];
+        assert_eq!(
+            check_index_entries(&index(&[("sha256:a", 10)]), &expected).status,
+            CheckStatus::Passed
+        );
+        assert!(matches!(
+            check_index_entries(&index(&[("sha256:a", 11)]), &expected).status,
+            CheckStatus::Failed(_)
+        ));
+        assert!(matches!(
+            check_index_entries(&index(&[("sha256:a", 10), ("sha256:b", 5)]), &expected).status,
+            CheckStatus::Failed(_)
+        ));
+    }
+
+    #[test]
+    fn test_report_passes_with_skipped_checks() {
+        let report = VerificationReport {
+            image_ref: "ttl.sh/test@sha256:a".to_string(),
+            checks: vec![
+                Check {
+                    name: "index".to_string(),
+                    status: CheckStatus::Passed,
+                },
+                Check {
+                    name: "smoke".to_string(),
+                    status: CheckStatus::Skipped("no docker".to_string()),
+                },
+            ],
+        };
+        assert!(report.passed());
+    }
+}
@@ -19,6 +19,16 @@ pub fn find_krust_references(yaml_content: &str) -> Result<HashSet<String>> {
     Ok(references)
 }
 
+/// Split a reference like `./path#member` into the project path and the
+/// workspace member to build, if one is named
+pub fn split_package(reference: &str) -> (&str, Option<&str>) {
+    match reference.split_once('#') {
+        Some((path, package)) if !package.is_empty() => (path, Some(package)),
+        Some((path, _)) => (path, None),
+        None => (reference, None),
+    }
+}
+
 /// Recursively search for krust:// references in a YAML value
 fn find_references_in_value(value: &Yaml, references: &mut HashSet<String>) {
     match value {
@@ -199,6 +209,13 @@ image: krust://./app2
         assert!(refs.contains("./app2"));
     }
 
+    #[test]
+    fn test_split_package() {
+        assert_eq!(split_package("./app"), ("./app", None));
+        assert_eq!(split_package("./ws#server"), ("./ws", Some("server")));
+        assert_eq!(split_package("./ws#"), ("./ws", None));
+    }
+
     #[test]
     fn test_read_yaml_files_single_file() {
         use std::fs;
@@ -0,0 +1,155 @@
+//! Scanning with a remote service
+//!
+//! The image's OCI layout is POSTed to the service as an uncompressed tar,
+//! and the service replies with the vulnerabilities it found:
+//!
+//! ```json
+//! {"findings": [{"id": "CVE-2024-1234", "package": "openssl", "version": "3.0.0", "severity": "HIGH"}]}
+//! ```
+
+use super::{parse_severity, Finding, Scanner};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::path::Path;
+use tracing::debug;
+
+/// Environment variable holding a bearer token for the scanning service
+pub const TOKEN_ENV: &str = "KRUST_SCAN_TOKEN";
+
+/// A scanning service at a URL
+pub struct HttpScanner {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HttpScanner {
+    pub fn new(client: reqwest::Client, url: &str) -> Self {
+        Self {
+            client,
+            url: url.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Report {
+    #[serde(default)]
+    findings: Vec<ReportedFinding>,
+}
+
+#[derive(Deserialize)]
+struct ReportedFinding {
+    id: String,
+    package: String,
+    #[serde(default)]
+    version: String,
+    #[serde(default)]
+    severity: String,
+}
+
+#[async_trait]
+impl Scanner for HttpScanner {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    async fn scan(&self, layout: &Path) -> Result<Vec<Finding>> {
+        let body = tar_layout(layout)?;
+        debug!("Uploading {} bytes to {}", body.len(), self.url);
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/x-tar")
+            .body(body);
+        if let Ok(token) = std::env::var(TOKEN_ENV) {
+            request = request.bearer_auth(token);
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach scanning service {}", self.url))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!(
+                "Scanning service {} returned {}: {}",
+                self.url,
+                status,
+                body.trim()
+            );
+        }
+        parse_report(&response.bytes().await?)
+    }
+}
+
+/// The layout directory as a tar archive, paths relative to its root
+fn tar_layout(layout: &Path) -> Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(Vec::new());
+    builder.follow_symlinks(false);
+    builder
+        .append_dir_all(".", layout)
+        .with_context(|| format!("Failed to archive {}", layout.display()))?;
+    Ok(builder.into_inner()?)
+}
+
+fn parse_report(body: &[u8]) -> Result<Vec<Finding>> {
+    let report: Report =
+        serde_json::from_slice(body).context("Scanning service returned an invalid report")?;
+    Ok(report
+        .findings
+        .into_iter()
+        .map(|f| Finding {
+            id: f.id,
+            package: f.package,
+            version: f.version,
+            severity: parse_severity(&f.severity),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Severity;
+
+    #[test]
+    fn test_parse_report() {
+        let body = br#"{"findings": [
+            {"id": "CVE-2024-1234", "package": "openssl", "version": "3.0.0", "severity": "HIGH"},
+            {"id": "CVE-2024-5678", "package": "zlib"}
+        ]}"#;
+        let findings = parse_report(body).unwrap();
+        assert_eq!(
+            findings[0],
+            Finding {
+                id: "CVE-2024-1234".to_string(),
+                package: "openssl".to_string(),
+                version: "3.0.0".to_string(),
+                severity: Severity::High,
+            }
+        );
+        assert_eq!(findings[1].severity, Severity::Unknown);
+        assert!(parse_report(b"{}").unwrap().is_empty());
+        assert!(parse_report(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_tar_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("oci-layout"), "{}").unwrap();
+        std::fs::create_dir_all(dir.path().join("blobs/sha256")).unwrap();
+        std::fs::write(dir.path().join("blobs/sha256/abc"), "blob").unwrap();
+
+        let archive = tar_layout(dir.path()).unwrap();
+        let mut archive = tar::Archive::new(archive.as_slice());
+        let mut paths: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().display().to_string())
+            .collect();
+        paths.sort();
+        assert!(paths.contains(&"oci-layout".to_string()));
+        assert!(paths.contains(&"blobs/sha256/abc".to_string()));
+    }
+}
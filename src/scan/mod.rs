@@ -0,0 +1,280 @@
+//! Scanning built images for vulnerabilities
+//!
+//! With `--scan`, each platform image is written to a temporary OCI layout
+//! and handed to a [`Scanner`] before any output receives it, so an image
+//! with findings at or above `scan.severity` is never tagged, loaded or
+//! written. Scanners are external tools (trivy, grype) or a service the
+//! layout is uploaded to.
+
+pub mod http;
+pub mod tools;
+
+pub use http::HttpScanner;
+pub use tools::{Grype, Trivy};
+
+use crate::config::{RegistrySettings, ScanConfig, ScannerKind, Severity};
+use crate::layout::OciLayout;
+use crate::publish::PlatformImage;
+use crate::registry::{ImageIndexEntry, RegistryClient};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// A vulnerability reported in one of the image's packages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// e.g. `CVE-2024-1234` or `GHSA-xxxx-xxxx-xxxx`
+    pub id: String,
+    pub package: String,
+    pub version: String,
+    pub severity: Severity,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}) in {} {}",
+            self.id, self.severity, self.package, self.version
+        )
+    }
+}
+
+/// Something that can find vulnerabilities in an image
+#[async_trait]
+pub trait Scanner: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Findings for the single image in the OCI layout at `layout`
+    async fn scan(&self, layout: &Path) -> Result<Vec<Finding>>;
+}
+
+/// The scanner `config` selects. `auto` picks the HTTP backend when a URL is
+/// configured, and otherwise whichever of trivy and grype is installed.
+pub fn scanner(config: &ScanConfig, settings: &RegistrySettings) -> Result<Arc<dyn Scanner>> {
+    let http = |url: &str| -> Result<Arc<dyn Scanner>> {
+        Ok(Arc::new(HttpScanner::new(
+            crate::sign::http_client(settings)?,
+            url,
+        )))
+    };
+    match config.scanner {
+        ScannerKind::Trivy => Ok(Arc::new(Trivy)),
+        ScannerKind::Grype => Ok(Arc::new(Grype)),
+        ScannerKind::Http => http(
+            config
+                .url
+                .as_deref()
+                .context("The http scanner needs scan.url set to the scanning service")?,
+        ),
+        ScannerKind::Auto => {
+            if let Some(url) = &config.url {
+                return http(url);
+            }
+            if installed("trivy") {
+                return Ok(Arc::new(Trivy));
+            }
+            if installed("grype") {
+                return Ok(Arc::new(Grype));
+            }
+            anyhow::bail!(
+                "No vulnerability scanner found for --scan; install trivy or grype, or set scan.url"
+            )
+        }
+    }
+}
+
+fn installed(program: &str) -> bool {
+    Command::new(program)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+/// Read a scanner's severity name, e.g. `CRITICAL` or `Negligible`
+pub fn parse_severity(name: &str) -> Severity {
+    match name.to_ascii_lowercase().as_str() {
+        "critical" => Severity::Critical,
+        "high" => Severity::High,
+        "medium" | "moderate" => Severity::Medium,
+        "low" | "negligible" => Severity::Low,
+        _ => Severity::Unknown,
+    }
+}
+
+/// The findings that fail the build under `config`, most severe first
+pub fn blocking<'a>(findings: &'a [Finding], config: &ScanConfig) -> Vec<&'a Finding> {
+    let mut blocking: Vec<&Finding> = findings
+        .iter()
+        .filter(|f| f.severity >= config.severity && !config.ignore.contains(&f.id))
+        .collect();
+    blocking.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.id.cmp(&b.id)));
+    blocking.dedup_by(|a, b| a.id == b.id && a.package == b.package);
+    blocking
+}
+
+/// Scan one platform image, failing if it has blocking findings
+pub async fn scan_image(
+    scanner: &dyn Scanner,
+    config: &ScanConfig,
+    image: &PlatformImage,
+    registry_client: &mut RegistryClient,
+) -> Result<()> {
+    let descriptor = image.descriptor(registry_client)?;
+    let dir = std::env::temp_dir().join(format!(
+        "krust-scan-{}-{}",
+        std::process::id(),
+        descriptor.digest.trim_start_matches("sha256:")
+    ));
+    let findings = write_and_scan(scanner, image, registry_client, &dir).await;
+    if let Err(e) = std::fs::remove_dir_all(&dir) {
+        warn!("Failed to remove {}: {}", dir.display(), e);
+    }
+    let findings = findings
+        .with_context(|| format!("{} failed to scan {}", scanner.name(), image.platform))?;
+
+    let blocking = blocking(&findings, config);
+    if !blocking.is_empty() {
+        let list: Vec<String> = blocking.iter().map(|f| f.to_string()).collect();
+        anyhow::bail!(
+            "{} found {} vulnerabilities at or above {} severity in the {} image:\n  {}",
+            scanner.name(),
+            blocking.len(),
+            config.severity,
+            image.platform,
+            list.join("\n  ")
+        );
+    }
+    info!(
+        "{} found {} vulnerabilities in the {} image, none at or above {} severity",
+        scanner.name(),
+        findings.len(),
+        image.platform,
+        config.severity
+    );
+    Ok(())
+}
+
+/// Write `image` as the only image in a layout at `dir` and scan it
+async fn write_and_scan(
+    scanner: &dyn Scanner,
+    image: &PlatformImage,
+    registry_client: &mut RegistryClient,
+    dir: &Path,
+) -> Result<Vec<Finding>> {
+    let layout = OciLayout::create(dir)?;
+    let (digest, size) = layout
+        .write_layered_image(
+            registry_client,
+            &image.config_data,
+            &image.layer_data,
+            &image.manifest,
+            &image.base_image,
+            &image.base_auth,
+        )
+        .await?;
+    layout.add_to_index(
+        ImageIndexEntry {
+            media_type: image.manifest.media_type.clone(),
+            digest,
+            size: size as i64,
+            platform: Some(image.platform.clone()),
+            annotations: None,
+        },
+        None,
+    )?;
+    info!(
+        "Scanning the {} image with {}",
+        image.platform,
+        scanner.name()
+    );
+    scanner.scan(dir).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finding(id: &str, severity: Severity) -> Finding {
+        Finding {
+            id: id.to_string(),
+            package: "openssl".to_string(),
+            version: "3.0.0".to_string(),
+            severity,
+        }
+    }
+
+    #[test]
+    fn test_parse_severity() {
+        assert_eq!(parse_severity("CRITICAL"), Severity::Critical);
+        assert_eq!(parse_severity("High"), Severity::High);
+        assert_eq!(parse_severity("moderate"), Severity::Medium);
+        assert_eq!(parse_severity("Negligible"), Severity::Low);
+        assert_eq!(parse_severity("UNKNOWN"), Severity::Unknown);
+        assert_eq!(parse_severity(""), Severity::Unknown);
+    }
+
+    #[test]
+    fn test_blocking() {
+        let findings = vec![
+            finding("CVE-1", Severity::Medium),
+            finding("CVE-2", Severity::High),
+            finding("CVE-3", Severity::Critical),
+            finding("CVE-4", Severity::Critical),
+            finding("CVE-3", Severity::Critical),
+        ];
+        let config = ScanConfig {
+            ignore: vec!["CVE-4".to_string()],
+            ..Default::default()
+        };
+        let ids: Vec<&str> = blocking(&findings, &config)
+            .iter()
+            .map(|f| f.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["CVE-3", "CVE-2"]);
+
+        let config = ScanConfig {
+            severity: Severity::Critical,
+            ..Default::default()
+        };
+        assert_eq!(blocking(&findings, &config).len(), 2);
+        assert!(blocking(&[finding("CVE-1", Severity::Unknown)], &config).is_empty());
+    }
+
+    #[test]
+    fn test_finding_display() {
+        assert_eq!(
+            finding("CVE-2024-1", Severity::High).to_string(),
+            "CVE-2024-1 (high) in openssl 3.0.0"
+        );
+    }
+
+    #[test]
+    fn test_scanner_selection() {
+        let settings = RegistrySettings::default();
+        let config = ScanConfig {
+            url: Some("https://scanner.example.com/scan".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(scanner(&config, &settings).unwrap().name(), "http");
+
+        let config = ScanConfig {
+            scanner: ScannerKind::Grype,
+            ..config
+        };
+        assert_eq!(scanner(&config, &settings).unwrap().name(), "grype");
+
+        let config = ScanConfig {
+            scanner: ScannerKind::Http,
+            ..Default::default()
+        };
+        let err = scanner(&config, &settings).err().unwrap();
+        assert!(err.to_string().contains("scan.url"));
+    }
+}
@@ -0,0 +1,208 @@
+//! Scanners run as external tools
+
+use super::{parse_severity, Finding, Scanner};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::path::Path;
+use tokio::process::Command;
+
+/// Aqua Security's trivy, reading the layout with `--input`
+pub struct Trivy;
+
+/// Anchore's grype, reading the layout as an `oci-dir:` source
+pub struct Grype;
+
+#[async_trait]
+impl Scanner for Trivy {
+    fn name(&self) -> &'static str {
+        "trivy"
+    }
+
+    async fn scan(&self, layout: &Path) -> Result<Vec<Finding>> {
+        let mut command = Command::new("trivy");
+        command.args(["image", "--quiet", "--scanners", "vuln", "--format", "json"]);
+        command.arg("--input").arg(layout);
+        parse_trivy(&run(command, "trivy").await?)
+    }
+}
+
+#[async_trait]
+impl Scanner for Grype {
+    fn name(&self) -> &'static str {
+        "grype"
+    }
+
+    async fn scan(&self, layout: &Path) -> Result<Vec<Finding>> {
+        let mut command = Command::new("grype");
+        command.arg(format!("oci-dir:{}", layout.display()));
+        command.args(["--quiet", "--output", "json"]);
+        parse_grype(&run(command, "grype").await?)
+    }
+}
+
+/// Run a scanner, returning its standard output
+async fn run(mut command: Command, program: &str) -> Result<Vec<u8>> {
+    let output = command
+        .output()
+        .await
+        .with_context(|| format!("Failed to execute {} - is it installed?", program))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TrivyReport {
+    #[serde(default)]
+    results: Vec<TrivyResult>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TrivyResult {
+    // null when a target has no vulnerabilities
+    vulnerabilities: Option<Vec<TrivyVulnerability>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TrivyVulnerability {
+    #[serde(rename = "VulnerabilityID")]
+    vulnerability_id: String,
+    pkg_name: String,
+    #[serde(default)]
+    installed_version: String,
+    #[serde(default)]
+    severity: String,
+}
+
+/// Findings from `trivy --format json` output
+fn parse_trivy(output: &[u8]) -> Result<Vec<Finding>> {
+    let report: TrivyReport =
+        serde_json::from_slice(output).context("Failed to parse trivy's JSON report")?;
+    Ok(report
+        .results
+        .into_iter()
+        .flat_map(|r| r.vulnerabilities.unwrap_or_default())
+        .map(|v| Finding {
+            id: v.vulnerability_id,
+            package: v.pkg_name,
+            version: v.installed_version,
+            severity: parse_severity(&v.severity),
+        })
+        .collect())
+}
+
+#[derive(Deserialize)]
+struct GrypeReport {
+    #[serde(default)]
+    matches: Vec<GrypeMatch>,
+}
+
+#[derive(Deserialize)]
+struct GrypeMatch {
+    vulnerability: GrypeVulnerability,
+    artifact: GrypeArtifact,
+}
+
+#[derive(Deserialize)]
+struct GrypeVulnerability {
+    id: String,
+    #[serde(default)]
+    severity: String,
+}
+
+#[derive(Deserialize)]
+struct GrypeArtifact {
+    name: String,
+    #[serde(default)]
+    version: String,
+}
+
+/// Findings from `grype --output json` output
+fn parse_grype(output: &[u8]) -> Result<Vec<Finding>> {
+    let report: GrypeReport =
+        serde_json::from_slice(output).context("Failed to parse grype's JSON report")?;
+    Ok(report
+        .matches
+        .into_iter()
+        .map(|m| Finding {
+            id: m.vulnerability.id,
+            package: m.artifact.name,
+            version: m.artifact.version,
+            severity: parse_severity(&m.vulnerability.severity),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Severity;
+
+    #[test]
+    fn test_parse_trivy() {
+        let output = br#"{
+            "SchemaVersion": 2,
+            "ArtifactName": "/tmp/krust-scan",
+            "Results": [
+                {"Target": "app (alpine 3.19)", "Vulnerabilities": [
+                    {"VulnerabilityID": "CVE-2024-0727", "PkgName": "libcrypto3",
+                     "InstalledVersion": "3.1.4-r2", "Severity": "MEDIUM"}
+                ]},
+                {"Target": "app", "Class": "lang-pkgs", "Vulnerabilities": null},
+                {"Target": "Cargo.lock", "Vulnerabilities": [
+                    {"VulnerabilityID": "GHSA-xxxx", "PkgName": "h2",
+                     "InstalledVersion": "0.3.20", "Severity": "HIGH"}
+                ]}
+            ]
+        }"#;
+        assert_eq!(
+            parse_trivy(output).unwrap(),
+            vec![
+                Finding {
+                    id: "CVE-2024-0727".to_string(),
+                    package: "libcrypto3".to_string(),
+                    version: "3.1.4-r2".to_string(),
+                    severity: Severity::Medium,
+                },
+                Finding {
+                    id: "GHSA-xxxx".to_string(),
+                    package: "h2".to_string(),
+                    version: "0.3.20".to_string(),
+                    severity: Severity::High,
+                },
+            ]
+        );
+        // Clean images have no Results at all
+        assert!(parse_trivy(br#"{"SchemaVersion": 2}"#).unwrap().is_empty());
+        assert!(parse_trivy(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_parse_grype() {
+        let output = br#"{
+            "matches": [
+                {"vulnerability": {"id": "CVE-2023-5678", "severity": "Critical"},
+                 "artifact": {"name": "openssl", "version": "3.0.0", "type": "apk"}},
+                {"vulnerability": {"id": "CVE-2023-0001", "severity": "Negligible"},
+                 "artifact": {"name": "busybox", "version": "1.36"}}
+            ],
+            "source": {"type": "image"}
+        }"#;
+        let findings = parse_grype(output).unwrap();
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].id, "CVE-2023-5678");
+        assert_eq!(findings[0].package, "openssl");
+        assert_eq!(findings[0].severity, Severity::Critical);
+        assert_eq!(findings[1].severity, Severity::Low);
+        assert!(parse_grype(b"{}").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,555 @@
+//! `krust serve`: a long-lived build service
+//!
+//! CI farms and editor integrations that call krust over and over pay for
+//! loading configuration, resolving credentials and authenticating with
+//! registries on every run. The service does that once and keeps the
+//! registry sessions, blob existence cache and build cache warm between
+//! requests. It speaks JSON over HTTP/1.1:
+//!
+//! - `POST /v1/build` builds and pushes one project:
+//!   `{"path": "./app", "platforms": ["linux/amd64"], "tags": ["dev"]}`
+//!   returns `{"image": "ghcr.io/user/app@sha256:..."}`
+//! - `POST /v1/resolve` resolves krust:// references in YAML files:
+//!   `{"files": ["config/"]}` returns `{"documents": "...", "images": {...}}`
+//! - `GET /healthz` returns `ok`
+//!
+//! Failures are returned as `{"error": "..."}`. When `KRUST_SERVE_TOKEN` is
+//! set, requests must carry it as a bearer token. Building runs the
+//! project's build scripts and proc macros, so the service won't listen on
+//! a non-loopback address without a token unless told to. Browsers can send
+//! cross-origin POSTs to loopback addresses too, so requests must be JSON,
+//! mustn't come from a web page (carry an `Origin`) and, on loopback, must
+//! name the service's own address as their `Host`.
+
+use crate::manifest::Platform;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::Incoming;
+use hyper::{header, Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+/// Environment variable holding the bearer token clients must send
+pub const TOKEN_ENV: &str = "KRUST_SERVE_TOKEN";
+
+/// Largest request body accepted
+const MAX_BODY: usize = 1 << 20;
+
+const JSON: &str = "application/json";
+const TEXT: &str = "text/plain";
+
+/// A response's status, Content-Type and body
+type Reply = (StatusCode, &'static str, Bytes);
+
+/// Build and push one project
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuildRequest {
+    /// Project directory, optionally with `#member`, or a git URL, as in a
+    /// krust:// reference
+    pub path: String,
+    /// e.g. `linux/arm64`; defaults to the configured platforms, or
+    /// `linux/amd64`
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    /// Repository prefix, overriding the service's
+    pub repo: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BuildResponse {
+    /// The pushed image, by digest
+    pub image: String,
+}
+
+/// Resolve krust:// references in YAML files, building each image
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResolveRequest {
+    /// YAML files or directories of them
+    pub files: Vec<PathBuf>,
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    pub repo: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResolveResponse {
+    /// The resolved documents, separated by `---`
+    pub documents: String,
+    /// krust:// path to the pushed image it resolved to
+    pub images: BTreeMap<String, String>,
+}
+
+/// What the service does for each request. Implementations keep whatever
+/// state should outlive a request, like registry sessions.
+#[async_trait]
+pub trait BuildService: Send + Sync {
+    async fn build(&self, request: BuildRequest) -> Result<BuildResponse>;
+    async fn resolve(&self, request: ResolveRequest) -> Result<ResolveResponse>;
+}
+
+/// A problem with the request rather than the build, answered with 400
+#[derive(Debug)]
+pub struct BadRequest(pub String);
+
+impl std::fmt::Display for BadRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for BadRequest {}
+
+/// Parse requested platforms, None if there were none
+pub fn parse_platforms(platforms: &[String]) -> Result<Option<Vec<Platform>>> {
+    if platforms.is_empty() {
+        return Ok(None);
+    }
+    platforms
+        .iter()
+        .map(|p| p.parse::<Platform>())
+        .collect::<Result<Vec<_>>>()
+        .map(Some)
+        .map_err(|e| BadRequest(format!("{:#}", e)).into())
+}
+
+/// Serve requests on `listener` until the process exits. Without a token,
+/// only a loopback address is served, unless `insecure_no_auth` is set.
+pub async fn serve(
+    listener: TcpListener,
+    service: Arc<dyn BuildService>,
+    insecure_no_auth: bool,
+) -> Result<()> {
+    let token = std::env::var(TOKEN_ENV).ok().filter(|t| !t.is_empty());
+    let addr = listener.local_addr()?;
+    check_exposure(&addr, token.is_some(), insecure_no_auth)?;
+    let token: Option<Arc<str>> = token.map(Into::into);
+
+    loop {
+        let (stream, peer) = listener
+            .accept()
+            .await
+            .context("Failed to accept connection")?;
+        let service = service.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            let handler = hyper::service::service_fn(move |request| {
+                let service = service.clone();
+                let token = token.clone();
+                async move {
+                    Ok::<_, std::convert::Infallible>(
+                        handle(service.as_ref(), token.as_deref(), &addr, request).await,
+                    )
+                }
+            });
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(hyper_util::rt::TokioIo::new(stream), handler)
+                .await
+            {
+                warn!("Connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Refuse to serve anyone who can reach `addr` without a token: a build
+/// runs the project's code and pushes with the service's credentials
+fn check_exposure(
+    addr: &std::net::SocketAddr,
+    has_token: bool,
+    insecure_no_auth: bool,
+) -> Result<()> {
+    if has_token || addr.ip().is_loopback() {
+        return Ok(());
+    }
+    if !insecure_no_auth {
+        anyhow::bail!(
+            "Refusing to serve on {} without {}: anyone who can reach it could run code and push with this service's credentials. Set {}, listen on a loopback address, or pass --insecure-no-auth",
+            addr,
+            TOKEN_ENV,
+            TOKEN_ENV
+        );
+    }
+    warn!(
+        "Serving on {} without {}; anyone who can reach it can build and push",
+        addr, TOKEN_ENV
+    );
+    Ok(())
+}
+
+async fn handle(
+    service: &dyn BuildService,
+    token: Option<&str>,
+    addr: &std::net::SocketAddr,
+    request: Request<Incoming>,
+) -> Response<Full<Bytes>> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let (status, content_type, body) = if !authorized(request.headers(), token) {
+        error(StatusCode::UNAUTHORIZED, "Missing or wrong bearer token")
+    } else if let Err(reply) = check_origin(request.headers(), addr) {
+        reply
+    } else if method == Method::POST && !is_json(request.headers()) {
+        error(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Requests must have Content-Type: application/json",
+        )
+    } else {
+        match Limited::new(request.into_body(), MAX_BODY).collect().await {
+            Ok(body) => route(service, &method, &path, &body.to_bytes()).await,
+            Err(e) => error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                &format!("Failed to read request body: {}", e),
+            ),
+        }
+    };
+    info!("{} {} {}", method, path, status.as_u16());
+
+    let mut response = Response::new(Full::new(body));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static(content_type),
+    );
+    response
+}
+
+/// Whether a request with `headers` carries the bearer `token`, if one is
+/// required
+fn authorized(headers: &header::HeaderMap, token: Option<&str>) -> bool {
+    token.is_none_or(|token| {
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .is_some_and(|sent| {
+                aws_lc_rs::constant_time::verify_slices_are_equal(sent.as_bytes(), token.as_bytes())
+                    .is_ok()
+            })
+    })
+}
+
+/// Refuse requests made by web pages, which browsers send with an `Origin`,
+/// and, on loopback, ones for another `Host`, as a DNS rebinding attack
+/// sends. Non-loopback addresses need a token or `--insecure-no-auth`.
+fn check_origin(headers: &header::HeaderMap, addr: &std::net::SocketAddr) -> Result<(), Reply> {
+    if headers.contains_key(header::ORIGIN) {
+        return Err(error(
+            StatusCode::FORBIDDEN,
+            "Cross-origin requests aren't accepted",
+        ));
+    }
+    if !addr.ip().is_loopback() {
+        return Ok(());
+    }
+    let host = headers.get(header::HOST).and_then(|h| h.to_str().ok());
+    let allowed = [addr.to_string(), format!("localhost:{}", addr.port())];
+    if !host.is_some_and(|host| allowed.iter().any(|a| a.eq_ignore_ascii_case(host))) {
+        return Err(error(
+            StatusCode::FORBIDDEN,
+            &format!("Host must be {}", allowed.join(" or ")),
+        ));
+    }
+    Ok(())
+}
+
+/// Whether the request body is declared to be JSON
+fn is_json(headers: &header::HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case(JSON))
+}
+
+/// Dispatch a request, returning the response status, Content-Type and body
+pub async fn route(service: &dyn BuildService, method: &Method, path: &str, body: &[u8]) -> Reply {
+    match (method, path) {
+        (&Method::GET, "/healthz") => (StatusCode::OK, TEXT, Bytes::from_static(b"ok")),
+        (&Method::POST, "/v1/build") => call(body, |request| service.build(request)).await,
+        (&Method::POST, "/v1/resolve") => call(body, |request| service.resolve(request)).await,
+        (_, "/healthz" | "/v1/build" | "/v1/resolve") => {
+            error(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed")
+        }
+        _ => error(
+            StatusCode::NOT_FOUND,
+            &format!("No such endpoint: {}", path),
+        ),
+    }
+}
+
+async fn call<Req, Resp, F, Fut>(body: &[u8], f: F) -> Reply
+where
+    Req: serde::de::DeserializeOwned,
+    Resp: Serialize,
+    F: FnOnce(Req) -> Fut,
+    Fut: std::future::Future<Output = Result<Resp>>,
+{
+    let request = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return error(StatusCode::BAD_REQUEST, &format!("Invalid request: {}", e)),
+    };
+    match f(request).await {
+        Ok(response) => match serde_json::to_vec(&response) {
+            Ok(body) => (StatusCode::OK, JSON, body.into()),
+            Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        },
+        Err(e) if e.chain().any(|cause| cause.is::<BadRequest>()) => {
+            error(StatusCode::BAD_REQUEST, &format!("{:#}", e))
+        }
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &format!("{:#}", e)),
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Reply {
+    let body = serde_json::json!({ "error": message });
+    (status, JSON, body.to_string().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Echoes requests back, failing builds of `fail`
+    struct Fake;
+
+    #[async_trait]
+    impl BuildService for Fake {
+        async fn build(&self, request: BuildRequest) -> Result<BuildResponse> {
+            if request.path == "fail" {
+                anyhow::bail!("cargo build failed");
+            }
+            let platforms = parse_platforms(&request.platforms)?.unwrap_or_default();
+            Ok(BuildResponse {
+                image: format!(
+                    "{}/{}:{}",
+                    request.repo.unwrap_or_default(),
+                    request.path,
+                    platforms.len()
+                ),
+            })
+        }
+
+        async fn resolve(&self, request: ResolveRequest) -> Result<ResolveResponse> {
+            Ok(ResolveResponse {
+                documents: format!("{} files", request.files.len()),
+                images: BTreeMap::new(),
+            })
+        }
+    }
+
+    async fn call_route(method: Method, path: &str, body: &str) -> (StatusCode, String) {
+        let (status, _, body) = route(&Fake, &method, path, body.as_bytes()).await;
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_route() {
+        assert_eq!(
+            call_route(Method::GET, "/healthz", "").await,
+            (StatusCode::OK, "ok".to_string())
+        );
+        assert_eq!(
+            call_route(
+                Method::POST,
+                "/v1/build",
+                r#"{"path": "app", "repo": "ghcr.io/user", "platforms": ["linux/amd64", "linux/arm64"]}"#
+            )
+            .await,
+            (
+                StatusCode::OK,
+                r#"{"image":"ghcr.io/user/app:2"}"#.to_string()
+            )
+        );
+        assert_eq!(
+            call_route(Method::POST, "/v1/resolve", r#"{"files": ["a.yaml"]}"#).await,
+            (
+                StatusCode::OK,
+                r#"{"documents":"1 files","images":{}}"#.to_string()
+            )
+        );
+
+        let (status, body) = call_route(Method::POST, "/v1/build", r#"{"path": "fail"}"#).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body, r#"{"error":"cargo build failed"}"#);
+
+        let (status, body) = call_route(Method::POST, "/v1/build", r#"{"paths": []}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("Invalid request"));
+
+        let (status, body) = call_route(
+            Method::POST,
+            "/v1/build",
+            r#"{"path": "app", "platforms": ["linux"]}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("linux"));
+
+        assert_eq!(
+            call_route(Method::GET, "/v1/build", "").await.0,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            call_route(Method::GET, "/v2/build", "").await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn test_authorized() {
+        let mut headers = header::HeaderMap::new();
+        assert!(authorized(&headers, None));
+        assert!(!authorized(&headers, Some("secret")));
+        headers.insert(
+            header::AUTHORIZATION,
+            header::HeaderValue::from_static("Bearer wrong"),
+        );
+        assert!(!authorized(&headers, Some("secret")));
+        headers.insert(
+            header::AUTHORIZATION,
+            header::HeaderValue::from_static("Bearer secret"),
+        );
+        assert!(authorized(&headers, Some("secret")));
+        assert!(!authorized(&headers, Some("secret2")));
+    }
+
+    #[test]
+    fn test_check_exposure() {
+        let loopback = "127.0.0.1:8080".parse().unwrap();
+        let any = "0.0.0.0:8080".parse().unwrap();
+        assert!(check_exposure(&loopback, false, false).is_ok());
+        assert!(check_exposure(&any, true, false).is_ok());
+        let err = check_exposure(&any, false, false).unwrap_err();
+        assert!(err.to_string().contains("--insecure-no-auth"), "{}", err);
+        assert!(check_exposure(&any, false, true).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(Fake), false));
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(format!("http://{}/v1/build", addr))
+            .json(&serde_json::json!({"path": "app", "repo": "ttl.sh/user"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["image"], "ttl.sh/user/app:0");
+
+        let response = client
+            .get(format!("http://{}/healthz", addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        assert_eq!(response.text().await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn test_serve_rejects_browser_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(Fake), false));
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/v1/build", addr);
+        let body = r#"{"path": "https://evil.example/repo"}"#;
+
+        // A "simple" cross-origin request a web page can send without a
+        // preflight
+        let response = client
+            .post(&url)
+            .header("Content-Type", "text/plain")
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.status(),
+            reqwest::StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(response.headers()["content-type"], "application/json");
+
+        let response = client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .header("Origin", "https://evil.example")
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+        // DNS rebinding: a browser sends the attacker's host name
+        let response = client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .header("Host", format!("evil.example:{}", addr.port()))
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_check_origin() {
+        let loopback: std::net::SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let headers = |pairs: &[(header::HeaderName, &'static str)]| {
+            let mut headers = header::HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(name.clone(), header::HeaderValue::from_static(value));
+            }
+            headers
+        };
+        assert!(check_origin(&headers(&[(header::HOST, "127.0.0.1:8080")]), &loopback).is_ok());
+        assert!(check_origin(&headers(&[(header::HOST, "localhost:8080")]), &loopback).is_ok());
+        assert!(check_origin(&headers(&[(header::HOST, "localhost:9090")]), &loopback).is_err());
+        assert!(check_origin(&headers(&[]), &loopback).is_err());
+        assert!(check_origin(
+            &headers(&[
+                (header::HOST, "127.0.0.1:8080"),
+                (header::ORIGIN, "http://127.0.0.1:8080")
+            ]),
+            &loopback
+        )
+        .is_err());
+
+        // Elsewhere the token guards the service
+        let any: std::net::SocketAddr = "0.0.0.0:8080".parse().unwrap();
+        assert!(check_origin(&headers(&[(header::HOST, "build.corp:8080")]), &any).is_ok());
+    }
+
+    #[test]
+    fn test_is_json() {
+        let mut headers = header::HeaderMap::new();
+        assert!(!is_json(&headers));
+        headers.insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("text/plain"),
+        );
+        assert!(!is_json(&headers));
+        headers.insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/json; charset=utf-8"),
+        );
+        assert!(is_json(&headers));
+    }
+}
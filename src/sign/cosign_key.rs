@@ -0,0 +1,189 @@
+//! cosign's encrypted private keys
+//!
+//! `cosign generate-key-pair` writes a PEM block whose body is JSON: the
+//! PKCS#8 private key sealed with NaCl secretbox (XSalsa20-Poly1305) under a
+//! key derived from the password with scrypt. aws-lc offers neither, so
+//! they come from the RustCrypto crates.
+
+use anyhow::{Context, Result};
+use crypto_secretbox::aead::{Aead, KeyInit};
+use crypto_secretbox::XSalsa20Poly1305;
+use serde::Deserialize;
+
+/// PEM labels cosign has used for encrypted private keys
+pub const ENCRYPTED_KEY_LABELS: &[&str] = &[
+    "ENCRYPTED SIGSTORE PRIVATE KEY",
+    "ENCRYPTED COSIGN PRIVATE KEY",
+];
+
+#[derive(Debug, Deserialize)]
+struct EncryptedKey {
+    kdf: Kdf,
+    cipher: Cipher,
+    #[serde(with = "base64_bytes")]
+    ciphertext: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Kdf {
+    name: String,
+    params: ScryptParams,
+    #[serde(with = "base64_bytes")]
+    salt: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScryptParams {
+    #[serde(rename = "N")]
+    n: u64,
+    r: u32,
+    p: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct Cipher {
+    name: String,
+    #[serde(with = "base64_bytes")]
+    nonce: Vec<u8>,
+}
+
+mod base64_bytes {
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Decrypt the JSON body of a cosign private key, returning the PKCS#8 key
+pub fn decrypt(body: &[u8], password: &[u8]) -> Result<Vec<u8>> {
+    let key: EncryptedKey =
+        serde_json::from_slice(body).context("Encrypted key is not in cosign's format")?;
+    if key.kdf.name != "scrypt" {
+        anyhow::bail!("Unsupported key derivation function {}", key.kdf.name);
+    }
+    if key.cipher.name != "nacl/secretbox" {
+        anyhow::bail!("Unsupported key cipher {}", key.cipher.name);
+    }
+    let nonce: &[u8; 24] = key
+        .cipher
+        .nonce
+        .as_slice()
+        .try_into()
+        .context("Key nonce must be 24 bytes")?;
+
+    let params = &key.kdf.params;
+    let secret = scrypt(password, &key.kdf.salt, params.n, params.r, params.p)?;
+    secretbox_open(&key.ciphertext, nonce, &secret)
+        .context("Failed to decrypt the key; is the password correct?")
+}
+
+/// Encode a PKCS#8 key as cosign does, for tests and fixtures
+#[cfg(test)]
+pub(crate) fn encrypt(pkcs8: &[u8], password: &[u8], salt: &[u8; 32], nonce: &[u8; 24]) -> String {
+    use base64::Engine;
+    let (n, r, p) = (1024, 8, 1);
+    let secret = scrypt(password, salt, n, r, p).unwrap();
+    let base64 = &base64::engine::general_purpose::STANDARD;
+    serde_json::json!({
+        "kdf": {"name": "scrypt", "params": {"N": n, "r": r, "p": p}, "salt": base64.encode(salt)},
+        "cipher": {"name": "nacl/secretbox", "nonce": base64.encode(nonce)},
+        "ciphertext": base64.encode(secretbox_seal(pkcs8, nonce, &secret)),
+    })
+    .to_string()
+}
+
+/// scrypt deriving the 32 byte secretbox key
+fn scrypt(password: &[u8], salt: &[u8], n: u64, r: u32, p: u32) -> Result<[u8; 32]> {
+    if n < 2 || !n.is_power_of_two() || r == 0 || p == 0 {
+        anyhow::bail!("Invalid scrypt parameters N={} r={} p={}", n, r, p);
+    }
+    // The scrypt crate allocates what the parameters ask for
+    if n.saturating_mul(128 * r as u64) > 1 << 30 {
+        anyhow::bail!("scrypt parameters N={} r={} need too much memory", n, r);
+    }
+    let params = scrypt::Params::new(n.trailing_zeros() as u8, r, p, 32)
+        .map_err(|e| anyhow::anyhow!("Invalid scrypt parameters N={} r={} p={}: {}", n, r, p, e))?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(password, salt, &params, &mut key)
+        .map_err(|e| anyhow::anyhow!("scrypt failed: {}", e))?;
+    Ok(key)
+}
+
+/// Open a NaCl secretbox: a Poly1305 tag followed by XSalsa20 ciphertext
+fn secretbox_open(sealed: &[u8], nonce: &[u8; 24], key: &[u8; 32]) -> Result<Vec<u8>> {
+    XSalsa20Poly1305::new(key.into())
+        .decrypt(nonce.into(), sealed)
+        .map_err(|_| anyhow::anyhow!("Key failed authentication"))
+}
+
+#[cfg(test)]
+fn secretbox_seal(message: &[u8], nonce: &[u8; 24], key: &[u8; 32]) -> Vec<u8> {
+    XSalsa20Poly1305::new(key.into())
+        .encrypt(nonce.into(), message)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_scrypt() {
+        // RFC 7914 section 12, truncated to 32 bytes
+        assert_eq!(
+            hex(&scrypt(b"password", b"NaCl", 1024, 8, 16).unwrap()),
+            "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162"
+        );
+        assert!(scrypt(b"password", b"NaCl", 1000, 8, 1).is_err());
+    }
+
+    #[test]
+    fn test_secretbox_round_trip() {
+        let key = [7u8; 32];
+        let nonce = [9u8; 24];
+        let message: Vec<u8> = (0..200u8).collect();
+        let sealed = secretbox_seal(&message, &nonce, &key);
+        assert_eq!(sealed.len(), message.len() + 16);
+        assert_eq!(secretbox_open(&sealed, &nonce, &key).unwrap(), message);
+
+        let mut tampered = sealed.clone();
+        tampered[20] ^= 1;
+        assert!(secretbox_open(&tampered, &nonce, &key).is_err());
+        assert!(secretbox_open(&sealed, &nonce, &[8u8; 32]).is_err());
+    }
+
+    #[test]
+    fn test_decrypt_cosign_key() {
+        // Written by `cosign generate-key-pair` with an empty password
+        let text = include_str!("testdata/cosign.key");
+        let (label, body) = crate::sign::parse_pem(text).unwrap();
+        assert!(ENCRYPTED_KEY_LABELS.contains(&label.as_str()));
+        let pkcs8 = decrypt(&body, b"").unwrap();
+        assert!(aws_lc_rs::signature::EcdsaKeyPair::from_pkcs8(
+            &aws_lc_rs::signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+            &pkcs8
+        )
+        .is_ok());
+        assert!(decrypt(&body, b"wrong").is_err());
+    }
+
+    #[test]
+    fn test_decrypt() {
+        let body = encrypt(b"pkcs8 key", b"hunter2", &[1u8; 32], &[2u8; 24]);
+        assert_eq!(decrypt(body.as_bytes(), b"hunter2").unwrap(), b"pkcs8 key");
+        assert!(decrypt(body.as_bytes(), b"wrong")
+            .unwrap_err()
+            .to_string()
+            .contains("password"));
+        assert!(decrypt(b"{}", b"").is_err());
+    }
+}
@@ -0,0 +1,323 @@
+//! Signing with a long-lived key
+//!
+//! [`SigningKey`] is implemented by each place a key can live. Keys are
+//! named as cosign names them: a path to a `cosign.key` or PKCS#8 PEM file,
+//! or a KMS URI such as `awskms:///alias/release`. Supporting another KMS
+//! means implementing [`SigningKey`] for it and recognizing its scheme in
+//! [`open_key`].
+
+use super::rekor::Rekor;
+use super::{cosign_key, parse_pem, pem, Signature, Signer};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use aws_lc_rs::encoding::{AsDer, PublicKeyX509Der};
+use aws_lc_rs::rand::SystemRandom;
+use aws_lc_rs::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+use base64::Engine;
+use std::path::Path;
+use tracing::debug;
+
+/// Environment variable holding the password of an encrypted cosign key
+pub const PASSWORD_ENV: &str = "COSIGN_PASSWORD";
+
+/// KMS schemes cosign understands that krust doesn't implement yet
+const UNSUPPORTED_KMS_SCHEMES: &[&str] = &["gcpkms", "azurekms", "hashivault", "k8s"];
+
+/// An ECDSA P-256 key that can sign payloads
+#[async_trait]
+pub trait SigningKey: Send + Sync {
+    /// The PEM public key, as `cosign public-key` prints it
+    async fn public_key(&self) -> Result<String>;
+
+    /// ASN.1 DER ECDSA signature of the SHA-256 of `payload`
+    async fn sign(&self, payload: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Open the key named by `reference`, a KMS URI or a key file. Encrypted
+/// key files are decrypted with the password in `COSIGN_PASSWORD`.
+pub fn open_key(reference: &str) -> Result<Box<dyn SigningKey>> {
+    if let Some((scheme, _)) = reference.split_once("://") {
+        if scheme == "awskms" {
+            return Ok(Box::new(AwsKmsKey::parse(reference)?));
+        }
+        if UNSUPPORTED_KMS_SCHEMES.contains(&scheme) {
+            anyhow::bail!(
+                "{} keys aren't supported yet; use awskms:// or a key file",
+                scheme
+            );
+        }
+        anyhow::bail!("Unknown key reference scheme {}://", scheme);
+    }
+    let password = std::env::var(PASSWORD_ENV).unwrap_or_default();
+    Ok(Box::new(LocalKey::load(Path::new(reference), &password)?))
+}
+
+/// A private key read from a file
+pub struct LocalKey {
+    key: EcdsaKeyPair,
+}
+
+impl LocalKey {
+    /// Read a cosign encrypted key, or an unencrypted PKCS#8 or SEC1 EC key
+    pub fn load(path: &Path, password: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read signing key {}", path.display()))?;
+        Self::from_pem(&text, password)
+            .with_context(|| format!("Failed to load signing key {}", path.display()))
+    }
+
+    pub fn from_pem(text: &str, password: &str) -> Result<Self> {
+        let (label, der) = parse_pem(text)?;
+        let key = if cosign_key::ENCRYPTED_KEY_LABELS.contains(&label.as_str()) {
+            if password.is_empty() {
+                debug!("{} is not set, trying an empty password", PASSWORD_ENV);
+            }
+            let pkcs8 = cosign_key::decrypt(&der, password.as_bytes())
+                .with_context(|| format!("Set {} to the key's password", PASSWORD_ENV))?;
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &pkcs8)
+        } else if label == "PRIVATE KEY" {
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &der)
+        } else if label == "EC PRIVATE KEY" {
+            EcdsaKeyPair::from_private_key_der(&ECDSA_P256_SHA256_ASN1_SIGNING, &der)
+        } else {
+            anyhow::bail!("Unsupported key type {}", label);
+        };
+        let key = key.map_err(|e| anyhow::anyhow!("Not an ECDSA P-256 private key: {}", e))?;
+        Ok(Self { key })
+    }
+}
+
+#[async_trait]
+impl SigningKey for LocalKey {
+    async fn public_key(&self) -> Result<String> {
+        let der: PublicKeyX509Der = self
+            .key
+            .public_key()
+            .as_der()
+            .map_err(|_| anyhow::anyhow!("Failed to encode the public key"))?;
+        Ok(pem("PUBLIC KEY", der.as_ref()))
+    }
+
+    async fn sign(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        let signature = self
+            .key
+            .sign(&SystemRandom::new(), payload)
+            .map_err(|_| anyhow::anyhow!("Failed to sign the payload"))?;
+        Ok(signature.as_ref().to_vec())
+    }
+}
+
+/// A key in AWS KMS, used through the AWS CLI and whatever AWS credentials
+/// it's configured with. `awskms://[ENDPOINT]/KEY` names the key by ID,
+/// alias or ARN, as cosign does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AwsKmsKey {
+    pub endpoint: Option<String>,
+    pub key_id: String,
+}
+
+impl AwsKmsKey {
+    pub fn parse(reference: &str) -> Result<Self> {
+        let rest = reference
+            .strip_prefix("awskms://")
+            .context("AWS KMS keys start with awskms://")?;
+        let (endpoint, key_id) = rest
+            .split_once('/')
+            .context("AWS KMS key reference has no key, e.g. awskms:///alias/my-key")?;
+        if key_id.is_empty() {
+            anyhow::bail!("AWS KMS key reference {} has no key", reference);
+        }
+        Ok(Self {
+            endpoint: (!endpoint.is_empty()).then(|| endpoint.to_string()),
+            key_id: key_id.to_string(),
+        })
+    }
+
+    async fn aws(&self, args: &[&str]) -> Result<String> {
+        let mut command = tokio::process::Command::new("aws");
+        command.args(["kms"]).args(args);
+        command.args(["--key-id", &self.key_id, "--output", "text"]);
+        if let Some(endpoint) = &self.endpoint {
+            command.args(["--endpoint-url", &format!("https://{}", endpoint)]);
+        }
+        let output = command
+            .output()
+            .await
+            .context("Failed to execute aws - is the AWS CLI installed?")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "aws kms {} failed for {}: {}",
+                args[0],
+                self.key_id,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
+
+#[async_trait]
+impl SigningKey for AwsKmsKey {
+    async fn public_key(&self) -> Result<String> {
+        let der = self
+            .aws(&["get-public-key", "--query", "PublicKey"])
+            .await?;
+        let der = base64::engine::general_purpose::STANDARD
+            .decode(der)
+            .context("AWS KMS returned an invalid public key")?;
+        Ok(pem("PUBLIC KEY", &der))
+    }
+
+    async fn sign(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        // The CLI reads binary messages from files
+        let message = std::env::temp_dir().join(format!(
+            "krust-kms-{}-{}",
+            std::process::id(),
+            sha256::digest(payload)
+        ));
+        std::fs::write(&message, payload)?;
+        let signature = self
+            .aws(&[
+                "sign",
+                "--message",
+                &format!("fileb://{}", message.display()),
+                "--message-type",
+                "RAW",
+                "--signing-algorithm",
+                "ECDSA_SHA_256",
+                "--query",
+                "Signature",
+            ])
+            .await;
+        let _ = std::fs::remove_file(&message);
+        base64::engine::general_purpose::STANDARD
+            .decode(signature?)
+            .context("AWS KMS returned an invalid signature")
+    }
+}
+
+/// Signs with a [`SigningKey`], optionally recording signatures in Rekor
+pub struct KeySigner {
+    key: Box<dyn SigningKey>,
+    rekor: Option<Rekor>,
+}
+
+impl KeySigner {
+    pub fn new(key: Box<dyn SigningKey>) -> Self {
+        Self { key, rekor: None }
+    }
+
+    /// Record signatures in this transparency log, which `cosign verify`
+    /// expects unless given `--insecure-ignore-tlog`
+    pub fn with_rekor(mut self, rekor: Option<Rekor>) -> Self {
+        self.rekor = rekor;
+        self
+    }
+}
+
+#[async_trait]
+impl Signer for KeySigner {
+    fn name(&self) -> &'static str {
+        "key"
+    }
+
+    async fn sign(&self, payload: &[u8]) -> Result<Signature> {
+        let signature = self.key.sign(payload).await?;
+        let bundle = match &self.rekor {
+            Some(rekor) => {
+                let public_key = self.key.public_key().await?;
+                Some(rekor.log(payload, &signature, &public_key).await?)
+            }
+            None => None,
+        };
+        Ok(Signature {
+            signature,
+            certificate: None,
+            chain: None,
+            bundle,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_lc_rs::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1};
+
+    fn pkcs8() -> Vec<u8> {
+        EcdsaKeyPair::generate(&ECDSA_P256_SHA256_ASN1_SIGNING)
+            .unwrap()
+            .to_pkcs8v1()
+            .unwrap()
+            .as_ref()
+            .to_vec()
+    }
+
+    async fn assert_signs(key: &dyn SigningKey) {
+        let signature = key.sign(b"payload").await.unwrap();
+        let (label, spki) = parse_pem(&key.public_key().await.unwrap()).unwrap();
+        assert_eq!(label, "PUBLIC KEY");
+        // The raw point is the last 65 bytes of the SubjectPublicKeyInfo
+        let point = &spki[spki.len() - 65..];
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, point)
+            .verify(b"payload", &signature)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_local_key_pkcs8() {
+        let key = LocalKey::from_pem(&pem("PRIVATE KEY", &pkcs8()), "").unwrap();
+        assert_signs(&key).await;
+    }
+
+    #[tokio::test]
+    async fn test_local_key_encrypted() {
+        let body = cosign_key::encrypt(&pkcs8(), b"secret", &[3u8; 32], &[4u8; 24]);
+        let text = pem("ENCRYPTED SIGSTORE PRIVATE KEY", body.as_bytes());
+
+        let key = LocalKey::from_pem(&text, "secret").unwrap();
+        assert_signs(&key).await;
+
+        let err = LocalKey::from_pem(&text, "").err().unwrap();
+        assert!(format!("{:#}", err).contains(PASSWORD_ENV));
+    }
+
+    #[test]
+    fn test_local_key_unsupported() {
+        let err = LocalKey::from_pem(&pem("RSA PRIVATE KEY", &[0; 8]), "")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("Unsupported key type"));
+    }
+
+    #[test]
+    fn test_aws_kms_key() {
+        assert_eq!(
+            AwsKmsKey::parse("awskms:///alias/release").unwrap(),
+            AwsKmsKey {
+                endpoint: None,
+                key_id: "alias/release".to_string()
+            }
+        );
+        assert_eq!(
+            AwsKmsKey::parse("awskms://localhost:4566/arn:aws:kms:us-east-1:123456789012:key/abc")
+                .unwrap(),
+            AwsKmsKey {
+                endpoint: Some("localhost:4566".to_string()),
+                key_id: "arn:aws:kms:us-east-1:123456789012:key/abc".to_string()
+            }
+        );
+        assert!(AwsKmsKey::parse("awskms://").is_err());
+        assert!(AwsKmsKey::parse("awskms:///").is_err());
+    }
+
+    #[test]
+    fn test_open_key_schemes() {
+        let err = open_key("gcpkms://projects/p/locations/l/keyRings/r/cryptoKeys/k")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("gcpkms keys aren't supported"));
+        assert!(open_key("nope://key").is_err());
+        assert!(open_key("/nonexistent/cosign.key").is_err());
+    }
+}
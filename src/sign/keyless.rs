@@ -0,0 +1,296 @@
+//! Sigstore keyless signing
+//!
+//! Each signature uses a fresh ECDSA P-256 key. Fulcio issues a short-lived
+//! certificate binding that key to the identity in an OIDC token, and the
+//! signature is recorded in the Rekor transparency log, so verifiers trust
+//! the signer's identity rather than a long-lived key.
+//!
+//! The OIDC token comes from `SIGSTORE_ID_TOKEN` or, in GitHub Actions jobs
+//! with `id-token: write` permission, from the Actions token service.
+
+use super::rekor::{Rekor, DEFAULT_REKOR_URL};
+use super::{http_client, pem, Signature, Signer};
+use crate::config::RegistrySettings;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use aws_lc_rs::encoding::{AsDer, PublicKeyX509Der};
+use aws_lc_rs::rand::SystemRandom;
+use aws_lc_rs::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::debug;
+
+/// Public good Fulcio instance
+pub const DEFAULT_FULCIO_URL: &str = "https://fulcio.sigstore.dev";
+
+/// Environment variable holding an OIDC identity token to sign with
+pub const ID_TOKEN_ENV: &str = "SIGSTORE_ID_TOKEN";
+
+/// Audience Fulcio requires of identity tokens
+const AUDIENCE: &str = "sigstore";
+
+/// Signs with an ephemeral key certified by Fulcio and logged in Rekor
+pub struct KeylessSigner {
+    client: reqwest::Client,
+    fulcio_url: String,
+    rekor: Rekor,
+    identity_token: Option<String>,
+}
+
+impl KeylessSigner {
+    /// Create a signer using the public good Sigstore instances, with the
+    /// registry settings' timeouts and User-Agent
+    pub fn new(settings: &RegistrySettings) -> Result<Self> {
+        let client = http_client(settings)?;
+        Ok(Self {
+            rekor: Rekor::new(client.clone(), DEFAULT_REKOR_URL),
+            client,
+            fulcio_url: DEFAULT_FULCIO_URL.to_string(),
+            identity_token: None,
+        })
+    }
+
+    /// Request certificates from this Fulcio instance
+    pub fn with_fulcio_url(mut self, url: &str) -> Self {
+        self.fulcio_url = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Record signatures in this Rekor instance
+    pub fn with_rekor_url(mut self, url: &str) -> Self {
+        self.rekor = Rekor::new(self.client.clone(), url);
+        self
+    }
+
+    /// Use this OIDC token instead of discovering one from the environment
+    pub fn with_identity_token(mut self, token: Option<String>) -> Self {
+        self.identity_token = token;
+        self
+    }
+
+    /// The OIDC token to exchange for a certificate
+    async fn identity_token(&self) -> Result<String> {
+        if let Some(token) = &self.identity_token {
+            return Ok(token.clone());
+        }
+        if let Ok(token) = std::env::var(ID_TOKEN_ENV) {
+            if !token.trim().is_empty() {
+                debug!("Using identity token from {}", ID_TOKEN_ENV);
+                return Ok(token.trim().to_string());
+            }
+        }
+        if let (Ok(url), Ok(request_token)) = (
+            std::env::var("ACTIONS_ID_TOKEN_REQUEST_URL"),
+            std::env::var("ACTIONS_ID_TOKEN_REQUEST_TOKEN"),
+        ) {
+            debug!("Requesting identity token from GitHub Actions");
+            return self.github_actions_token(&url, &request_token).await;
+        }
+        anyhow::bail!(
+            "Keyless signing needs an OIDC identity token: set {} or run in GitHub Actions with `id-token: write` permission",
+            ID_TOKEN_ENV
+        )
+    }
+
+    async fn github_actions_token(&self, url: &str, request_token: &str) -> Result<String> {
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            value: String,
+        }
+
+        // The request URL already carries a query string
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let response = self
+            .client
+            .get(format!("{}{}audience={}", url, separator, AUDIENCE))
+            .bearer_auth(request_token)
+            .send()
+            .await
+            .context("Failed to request a GitHub Actions identity token")?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!(
+                "Failed to get a GitHub Actions identity token: {} - {}",
+                status,
+                body
+            );
+        }
+        Ok(response.json::<TokenResponse>().await?.value)
+    }
+
+    /// Exchange the identity token for a certificate of the key's public half.
+    /// Returns the PEM certificates, the signing certificate first.
+    async fn certificate(&self, key: &EcdsaKeyPair, token: &str) -> Result<Vec<String>> {
+        let rng = SystemRandom::new();
+        let subject = token_subject(token)?;
+        let proof = key
+            .sign(&rng, subject.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Failed to sign the proof of possession"))?;
+        let public_key: PublicKeyX509Der = key
+            .public_key()
+            .as_der()
+            .map_err(|_| anyhow::anyhow!("Failed to encode the public key"))?;
+
+        let request = json!({
+            "credentials": {"oidcIdentityToken": token},
+            "publicKeyRequest": {
+                "publicKey": {
+                    "algorithm": "ECDSA",
+                    "content": pem("PUBLIC KEY", public_key.as_ref()),
+                },
+                "proofOfPossession": base64::engine::general_purpose::STANDARD.encode(proof.as_ref()),
+            },
+        });
+        let url = format!("{}/api/v2/signingCert", self.fulcio_url);
+        debug!("Requesting signing certificate from {}", url);
+        let response = self
+            .client
+            .post(&url)
+            .json(&request)
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach Fulcio at {}", self.fulcio_url))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!(
+                "Fulcio refused to issue a certificate: {} - {}",
+                status,
+                body
+            );
+        }
+        parse_certificates(&response.json().await?)
+    }
+}
+
+#[async_trait]
+impl Signer for KeylessSigner {
+    fn name(&self) -> &'static str {
+        "keyless"
+    }
+
+    async fn sign(&self, payload: &[u8]) -> Result<Signature> {
+        let token = self.identity_token().await?;
+        let key = EcdsaKeyPair::generate(&ECDSA_P256_SHA256_ASN1_SIGNING)
+            .map_err(|_| anyhow::anyhow!("Failed to generate a signing key"))?;
+        let mut certificates = self.certificate(&key, &token).await?.into_iter();
+        let certificate = certificates
+            .next()
+            .context("Fulcio returned no certificates")?;
+        let chain: String = certificates.collect();
+
+        let signature = key
+            .sign(&SystemRandom::new(), payload)
+            .map_err(|_| anyhow::anyhow!("Failed to sign the payload"))?;
+        let bundle = self
+            .rekor
+            .log(payload, signature.as_ref(), &certificate)
+            .await?;
+
+        Ok(Signature {
+            signature: signature.as_ref().to_vec(),
+            certificate: Some(certificate),
+            chain: (!chain.is_empty()).then_some(chain),
+            bundle: Some(bundle),
+        })
+    }
+}
+
+/// The identity Fulcio will put in the certificate, which the proof of
+/// possession signs: the token's email if it has one, otherwise its subject
+fn token_subject(token: &str) -> Result<String> {
+    let claims = token
+        .split('.')
+        .nth(1)
+        .context("Identity token is not a JWT")?;
+    let claims = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(claims.trim_end_matches('='))
+        .context("Identity token claims are not base64")?;
+    let claims: serde_json::Value =
+        serde_json::from_slice(&claims).context("Identity token claims are not JSON")?;
+    claims["email"]
+        .as_str()
+        .or_else(|| claims["sub"].as_str())
+        .map(String::from)
+        .context("Identity token has neither an email nor a subject")
+}
+
+/// PEM certificates from a Fulcio v2 signing certificate response
+fn parse_certificates(response: &serde_json::Value) -> Result<Vec<String>> {
+    let certificates = [
+        "signedCertificateEmbeddedSct",
+        "signedCertificateDetachedSct",
+    ]
+    .iter()
+    .find_map(|key| response[key]["chain"]["certificates"].as_array())
+    .context("Unexpected response from Fulcio")?;
+    Ok(certificates
+        .iter()
+        .filter_map(|c| c.as_str())
+        .map(|c| {
+            if c.ends_with('\n') {
+                c.to_string()
+            } else {
+                format!("{}\n", c)
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jwt(claims: serde_json::Value) -> String {
+        let encode = |v: &[u8]| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(v);
+        format!(
+            "{}.{}.sig",
+            encode(br#"{"alg":"RS256"}"#),
+            encode(claims.to_string().as_bytes())
+        )
+    }
+
+    #[test]
+    fn test_token_subject() {
+        let token = jwt(json!({"sub": "repo:example/app:ref:refs/heads/main"}));
+        assert_eq!(
+            token_subject(&token).unwrap(),
+            "repo:example/app:ref:refs/heads/main"
+        );
+
+        let token = jwt(json!({"sub": "1234", "email": "dev@example.com"}));
+        assert_eq!(token_subject(&token).unwrap(), "dev@example.com");
+
+        assert!(token_subject("not-a-jwt").is_err());
+        assert!(token_subject(&jwt(json!({"aud": "sigstore"}))).is_err());
+    }
+
+    #[test]
+    fn test_parse_certificates() {
+        let response = json!({
+            "signedCertificateEmbeddedSct": {
+                "chain": {"certificates": ["LEAF", "INTERMEDIATE\n", "ROOT"]}
+            }
+        });
+        assert_eq!(
+            parse_certificates(&response).unwrap(),
+            vec!["LEAF\n", "INTERMEDIATE\n", "ROOT\n"]
+        );
+
+        let detached = json!({
+            "signedCertificateDetachedSct": {"chain": {"certificates": ["LEAF"]}}
+        });
+        assert_eq!(parse_certificates(&detached).unwrap(), vec!["LEAF\n"]);
+        assert!(parse_certificates(&json!({})).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_identity_token_explicit() {
+        let signer = KeylessSigner::new(&RegistrySettings::default())
+            .unwrap()
+            .with_identity_token(Some("token".to_string()));
+        assert_eq!(signer.identity_token().await.unwrap(), "token");
+    }
+}
@@ -0,0 +1,414 @@
+//! Signing pushed images
+//!
+//! Signatures are stored the way cosign stores them, so `cosign verify`
+//! accepts krust-signed images: a "simple signing" payload naming the image
+//! digest is signed, and pushed as a layer of an image tagged
+//! `sha256-<hex>.sig` in the image's repository. The signature, and the
+//! certificate and transparency log entry that vouch for it, are annotations
+//! on that layer. Signing an image again adds a layer rather than replacing
+//! the signatures already there.
+
+pub mod cosign_key;
+pub mod key;
+pub mod keyless;
+pub mod rekor;
+pub mod trust;
+pub mod verify;
+pub mod x509;
+
+pub use key::{open_key, KeySigner, SigningKey};
+pub use keyless::KeylessSigner;
+pub use rekor::Rekor;
+pub use verify::Verifier;
+
+use crate::config::RegistrySettings;
+use crate::events::{self, Event};
+use crate::registry::{
+    is_manifest_not_found, media_types, ImageReference, OciDescriptor, OciImageManifest,
+    RegistryAuth, RegistryClient,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use serde_json::json;
+use std::collections::BTreeMap;
+use tracing::debug;
+
+/// Media type of the signed payload layer
+pub const SIMPLE_SIGNING_MEDIA_TYPE: &str = "application/vnd.dev.cosign.simplesigning.v1+json";
+
+/// Layer annotation holding the base64 signature of the payload
+pub const SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+
+/// Layer annotation holding the PEM signing certificate
+pub const CERTIFICATE_ANNOTATION: &str = "dev.sigstore.cosign/certificate";
+
+/// Layer annotation holding the PEM certificates between the signing
+/// certificate and the root
+pub const CHAIN_ANNOTATION: &str = "dev.sigstore.cosign/chain";
+
+/// Layer annotation holding the Rekor entry, for offline verification
+pub const BUNDLE_ANNOTATION: &str = "dev.sigstore.cosign/bundle";
+
+/// Timestamp cosign gives its signature images, so they're reproducible
+const SIGNATURE_IMAGE_CREATED: &str = "0001-01-01T00:00:00Z";
+
+/// Produces signatures of image payloads
+#[async_trait]
+pub trait Signer: Send + Sync {
+    /// Short description for logs, e.g. "keyless"
+    fn name(&self) -> &'static str;
+
+    /// Sign `payload`
+    async fn sign(&self, payload: &[u8]) -> Result<Signature>;
+}
+
+/// A signature and whatever a verifier needs to trust it
+#[derive(Debug, Clone, Default)]
+pub struct Signature {
+    /// ASN.1 DER signature of the payload
+    pub signature: Vec<u8>,
+    /// PEM certificate binding the signing key to an identity
+    pub certificate: Option<String>,
+    /// PEM intermediate and root certificates for `certificate`
+    pub chain: Option<String>,
+    /// Transparency log entry for the signature, as cosign's bundle
+    pub bundle: Option<serde_json::Value>,
+}
+
+impl Signature {
+    /// Annotations of the signature layer
+    pub fn annotations(&self) -> BTreeMap<String, String> {
+        let mut annotations = BTreeMap::from([(
+            SIGNATURE_ANNOTATION.to_string(),
+            base64::engine::general_purpose::STANDARD.encode(&self.signature),
+        )]);
+        if let Some(certificate) = &self.certificate {
+            annotations.insert(CERTIFICATE_ANNOTATION.to_string(), certificate.clone());
+        }
+        if let Some(chain) = &self.chain {
+            annotations.insert(CHAIN_ANNOTATION.to_string(), chain.clone());
+        }
+        if let Some(bundle) = &self.bundle {
+            annotations.insert(BUNDLE_ANNOTATION.to_string(), bundle.to_string());
+        }
+        annotations
+    }
+}
+
+/// The simple signing payload cosign signs for `repository@digest`
+pub fn payload(repository: &str, digest: &str) -> Vec<u8> {
+    json!({
+        "critical": {
+            "identity": {"docker-reference": repository},
+            "image": {"docker-manifest-digest": digest},
+            "type": "cosign container image signature",
+        },
+        "optional": null,
+    })
+    .to_string()
+    .into_bytes()
+}
+
+/// Tag of the signature image for the manifest with `digest`
+pub fn signature_tag(digest: &str) -> String {
+    format!("{}.sig", digest.replacen(':', "-", 1))
+}
+
+/// PEM encoding of a DER document
+pub fn pem(label: &str, der: &[u8]) -> String {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
+
+/// Parse a PEM document, returning its label and DER contents
+pub fn parse_pem(text: &str) -> Result<(String, Vec<u8>)> {
+    parse_pem_blocks(text)?
+        .into_iter()
+        .next()
+        .context("Not a PEM document")
+}
+
+/// Parse every block of a PEM document, such as a certificate chain
+pub fn parse_pem_blocks(text: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut blocks = Vec::new();
+    let mut lines = text.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        let Some(label) = line
+            .strip_prefix("-----BEGIN ")
+            .and_then(|line| line.strip_suffix("-----"))
+        else {
+            continue;
+        };
+        let end = format!("-----END {}-----", label);
+        let mut body = String::new();
+        let mut ended = false;
+        for line in lines.by_ref() {
+            if line == end {
+                ended = true;
+                break;
+            }
+            body.push_str(line);
+        }
+        if !ended {
+            anyhow::bail!("PEM document has no END line for {}", label);
+        }
+        let der = base64::engine::general_purpose::STANDARD
+            .decode(body)
+            .with_context(|| format!("Invalid base64 in PEM {}", label))?;
+        blocks.push((label.to_string(), der));
+    }
+    Ok(blocks)
+}
+
+/// HTTP client for Sigstore services, with the registry settings' timeouts
+/// and User-Agent
+pub fn http_client(settings: &RegistrySettings) -> Result<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .user_agent(settings.user_agent())
+        .connect_timeout(settings.connect_timeout())
+        .timeout(settings.timeout())
+        .build()?)
+}
+
+/// Sign `repository@digest` and push the signature. Returns the signature
+/// image reference.
+pub async fn sign_image(
+    signer: &dyn Signer,
+    registry_client: &mut RegistryClient,
+    repository: &str,
+    digest: &str,
+    auth: &RegistryAuth,
+) -> Result<String> {
+    let payload = payload(repository, digest);
+    let signature = signer
+        .sign(&payload)
+        .await
+        .with_context(|| format!("Failed to sign {}@{}", repository, digest))?;
+    attach(
+        registry_client,
+        repository,
+        digest,
+        &payload,
+        &signature,
+        auth,
+    )
+    .await
+}
+
+/// Add a signature of `payload` to the signature image of
+/// `repository@digest`, keeping any signatures already there
+pub async fn attach(
+    registry_client: &mut RegistryClient,
+    repository: &str,
+    digest: &str,
+    payload: &[u8],
+    signature: &Signature,
+    auth: &RegistryAuth,
+) -> Result<String> {
+    let signature_ref = format!("{}:{}", repository, signature_tag(digest));
+    let existing = match registry_client.get_manifest_raw(&signature_ref, auth).await {
+        Ok((body, _, _)) => {
+            serde_json::from_slice::<OciImageManifest>(&body)
+                .with_context(|| format!("Failed to parse signature image {}", signature_ref))?
+                .layers
+        }
+        Err(e) if is_manifest_not_found(&e) => {
+            debug!("No existing signatures at {}", signature_ref);
+            Vec::new()
+        }
+        // Pushing without the existing layers would drop their signatures
+        Err(e) => {
+            return Err(e.context(format!(
+                "Failed to read existing signatures at {}",
+                signature_ref
+            )))
+        }
+    };
+
+    let payload_digest = format!("sha256:{}", sha256::digest(payload));
+    registry_client
+        .push_blob(repository, payload, &payload_digest, auth)
+        .await?;
+    let layer = OciDescriptor {
+        media_type: SIMPLE_SIGNING_MEDIA_TYPE.to_string(),
+        digest: payload_digest,
+        size: payload.len() as i64,
+        urls: None,
+        annotations: Some(signature.annotations()),
+    };
+    events::emit(Event::LayerPushed {
+        repository: ImageReference::parse(repository)?.repository_url(),
+        digest: layer.digest.clone(),
+        size: layer.size,
+    });
+
+    let (config, manifest) = signature_image(existing, layer)?;
+    let config_digest = manifest
+        .config
+        .as_ref()
+        .map(|c| c.digest.clone())
+        .unwrap_or_default();
+    registry_client
+        .push_blob(repository, &config, &config_digest, auth)
+        .await?;
+    registry_client
+        .push_manifest(&signature_ref, &manifest, auth)
+        .await?;
+    Ok(signature_ref)
+}
+
+/// The config and manifest of a signature image holding `layers` plus
+/// `layer`. Re-signing with an identical signature leaves the image unchanged.
+fn signature_image(
+    mut layers: Vec<OciDescriptor>,
+    layer: OciDescriptor,
+) -> Result<(Vec<u8>, OciImageManifest)> {
+    let duplicate = layers
+        .iter()
+        .any(|l| l.digest == layer.digest && l.annotations == layer.annotations);
+    if !duplicate {
+        layers.push(layer);
+    }
+
+    let diff_ids: Vec<&str> = layers.iter().map(|l| l.digest.as_str()).collect();
+    let config = serde_json::to_vec(&json!({
+        "architecture": "",
+        "os": "",
+        "created": SIGNATURE_IMAGE_CREATED,
+        "config": {},
+        "rootfs": {"type": "layers", "diff_ids": diff_ids},
+    }))?;
+    let manifest = OciImageManifest {
+        schema_version: 2,
+        media_type: media_types::OCI_MANIFEST.to_string(),
+        artifact_type: None,
+        config: Some(OciDescriptor {
+            media_type: media_types::OCI_CONFIG.to_string(),
+            digest: format!("sha256:{}", sha256::digest(&config)),
+            size: config.len() as i64,
+            urls: None,
+            annotations: None,
+        }),
+        layers,
+        subject: None,
+        annotations: None,
+    };
+    Ok((config, manifest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(digest: &str, signature: &[u8]) -> OciDescriptor {
+        OciDescriptor {
+            media_type: SIMPLE_SIGNING_MEDIA_TYPE.to_string(),
+            digest: digest.to_string(),
+            size: 10,
+            urls: None,
+            annotations: Some(
+                Signature {
+                    signature: signature.to_vec(),
+                    ..Default::default()
+                }
+                .annotations(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_payload() {
+        let payload = payload("ghcr.io/example/app", "sha256:abc");
+        let value: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(
+            value["critical"]["identity"]["docker-reference"],
+            "ghcr.io/example/app"
+        );
+        assert_eq!(
+            value["critical"]["image"]["docker-manifest-digest"],
+            "sha256:abc"
+        );
+        assert_eq!(
+            value["critical"]["type"],
+            "cosign container image signature"
+        );
+        assert!(value["optional"].is_null());
+    }
+
+    #[test]
+    fn test_signature_tag() {
+        assert_eq!(signature_tag("sha256:abc123"), "sha256-abc123.sig");
+    }
+
+    #[test]
+    fn test_annotations() {
+        let signature = Signature {
+            signature: vec![1, 2, 3],
+            certificate: Some("CERT".to_string()),
+            chain: None,
+            bundle: Some(json!({"SignedEntryTimestamp": "set"})),
+        };
+        let annotations = signature.annotations();
+        assert_eq!(annotations[SIGNATURE_ANNOTATION], "AQID");
+        assert_eq!(annotations[CERTIFICATE_ANNOTATION], "CERT");
+        assert!(!annotations.contains_key(CHAIN_ANNOTATION));
+        assert_eq!(
+            annotations[BUNDLE_ANNOTATION],
+            r#"{"SignedEntryTimestamp":"set"}"#
+        );
+    }
+
+    #[test]
+    fn test_pem() {
+        let pem = pem("PUBLIC KEY", &[0u8; 60]);
+        let lines: Vec<&str> = pem.lines().collect();
+        assert_eq!(lines[0], "-----BEGIN PUBLIC KEY-----");
+        assert_eq!(lines[1].len(), 64);
+        assert_eq!(lines[2].len(), 16);
+        assert_eq!(lines[3], "-----END PUBLIC KEY-----");
+    }
+
+    #[test]
+    fn test_parse_pem() {
+        let der: Vec<u8> = (0..100).collect();
+        let (label, parsed) = parse_pem(&pem("PUBLIC KEY", &der)).unwrap();
+        assert_eq!(label, "PUBLIC KEY");
+        assert_eq!(parsed, der);
+
+        let chain = format!("{}{}", pem("CERTIFICATE", &[1]), pem("CERTIFICATE", &[2]));
+        let blocks = parse_pem_blocks(&chain).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1], ("CERTIFICATE".to_string(), vec![2]));
+
+        assert!(parse_pem("not pem").is_err());
+        assert!(parse_pem("-----BEGIN PUBLIC KEY-----\nAAAA\n").is_err());
+    }
+
+    #[test]
+    fn test_signature_image() {
+        let (config, manifest) =
+            signature_image(vec![layer("sha256:a", b"one")], layer("sha256:a", b"two")).unwrap();
+        assert_eq!(manifest.media_type, media_types::OCI_MANIFEST);
+        assert_eq!(manifest.layers.len(), 2);
+        assert!(media_types::validate_artifact(&manifest).is_ok());
+
+        let config: serde_json::Value = serde_json::from_slice(&config).unwrap();
+        assert_eq!(
+            config["rootfs"]["diff_ids"],
+            json!(["sha256:a", "sha256:a"])
+        );
+        assert_eq!(config["created"], SIGNATURE_IMAGE_CREATED);
+
+        // The same signature again doesn't add a layer
+        let (_, again) =
+            signature_image(manifest.layers.clone(), layer("sha256:a", b"two")).unwrap();
+        assert_eq!(again.layers.len(), 2);
+    }
+}
@@ -0,0 +1,165 @@
+//! The Rekor transparency log
+//!
+//! Signatures are recorded as `hashedrekord` entries. Rekor's signed entry
+//! timestamp for the entry is kept in cosign's bundle format, so `cosign
+//! verify` can check the signature was logged without querying Rekor.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use serde_json::json;
+use tracing::{debug, info};
+
+/// Public good Rekor instance
+pub const DEFAULT_REKOR_URL: &str = "https://rekor.sigstore.dev";
+
+/// A Rekor instance signatures are recorded in
+#[derive(Debug, Clone)]
+pub struct Rekor {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl Rekor {
+    pub fn new(client: reqwest::Client, url: &str) -> Self {
+        Self {
+            client,
+            url: url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Record a signature of `payload` made by the key in `public_key`, a
+    /// PEM certificate or public key. Returns cosign's bundle for the entry.
+    pub async fn log(
+        &self,
+        payload: &[u8],
+        signature: &[u8],
+        public_key: &str,
+    ) -> Result<serde_json::Value> {
+        let url = format!("{}/api/v1/log/entries", self.url);
+        debug!("Recording signature in {}", url);
+        let response = self
+            .client
+            .post(&url)
+            .json(&hashed_rekord(payload, signature, public_key))
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach Rekor at {}", self.url))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Rekor refused the log entry: {} - {}", status, body);
+        }
+        let bundle = bundle(&response.json().await?)?;
+        info!(
+            "Recorded signature in Rekor (log index {})",
+            bundle["Payload"]["logIndex"]
+        );
+        Ok(bundle)
+    }
+}
+
+/// A Rekor `hashedrekord` entry for a signature of `payload`
+fn hashed_rekord(payload: &[u8], signature: &[u8], public_key: &str) -> serde_json::Value {
+    let base64 = &base64::engine::general_purpose::STANDARD;
+    json!({
+        "apiVersion": "0.0.1",
+        "kind": "hashedrekord",
+        "spec": {
+            "data": {"hash": {"algorithm": "sha256", "value": sha256::digest(payload)}},
+            "signature": {
+                "content": base64.encode(signature),
+                "publicKey": {"content": base64.encode(public_key)},
+            },
+        },
+    })
+}
+
+/// cosign's bundle annotation for the single entry in a Rekor response
+fn bundle(response: &serde_json::Value) -> Result<serde_json::Value> {
+    let entry = response
+        .as_object()
+        .and_then(|entries| entries.values().next())
+        .context("Rekor returned no log entry")?;
+    let set = entry["verification"]["signedEntryTimestamp"]
+        .as_str()
+        .context("Rekor log entry has no signed entry timestamp")?;
+    Ok(json!({
+        "SignedEntryTimestamp": set,
+        "Payload": {
+            "body": entry["body"],
+            "integratedTime": entry["integratedTime"],
+            "logIndex": entry["logIndex"],
+            "logID": entry["logID"],
+        },
+    }))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use aws_lc_rs::rand::SystemRandom;
+    use aws_lc_rs::signature::EcdsaKeyPair;
+
+    /// The bundle Rekor, with key `rekor`, would give for logging a
+    /// signature at `integrated_time`
+    pub fn logged_bundle(
+        rekor: &EcdsaKeyPair,
+        payload: &[u8],
+        signature: &[u8],
+        public_key: &str,
+        integrated_time: u64,
+    ) -> serde_json::Value {
+        let base64 = &base64::engine::general_purpose::STANDARD;
+        let body = base64.encode(hashed_rekord(payload, signature, public_key).to_string());
+        let signed = format!(
+            r#"{{"body":"{}","integratedTime":{},"logID":"c0d23d6a","logIndex":7}}"#,
+            body, integrated_time
+        );
+        let set = rekor.sign(&SystemRandom::new(), signed.as_bytes()).unwrap();
+        bundle(&json!({
+            "24296fb24b8ad77a": {
+                "body": body,
+                "integratedTime": integrated_time,
+                "logID": "c0d23d6a",
+                "logIndex": 7,
+                "verification": {"signedEntryTimestamp": base64.encode(set.as_ref())}
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_hashed_rekord() {
+        let entry = hashed_rekord(b"payload", &[1, 2, 3], "CERT");
+        assert_eq!(entry["kind"], "hashedrekord");
+        assert_eq!(
+            entry["spec"]["data"]["hash"]["value"],
+            sha256::digest(b"payload")
+        );
+        assert_eq!(entry["spec"]["signature"]["content"], "AQID");
+        assert_eq!(
+            entry["spec"]["signature"]["publicKey"]["content"],
+            "Q0VSVA=="
+        );
+    }
+
+    #[test]
+    fn test_bundle() {
+        let response = json!({
+            "24296fb24b8ad77a": {
+                "body": "Ym9keQ==",
+                "integratedTime": 1700000000,
+                "logID": "c0d23d6a",
+                "logIndex": 42,
+                "verification": {"signedEntryTimestamp": "U0VU"}
+            }
+        });
+        let bundle = bundle(&response).unwrap();
+        assert_eq!(bundle["SignedEntryTimestamp"], "U0VU");
+        assert_eq!(bundle["Payload"]["body"], "Ym9keQ==");
+        assert_eq!(bundle["Payload"]["logIndex"], 42);
+        assert_eq!(bundle["Payload"]["integratedTime"], 1700000000);
+
+        assert!(super::bundle(&json!({})).is_err());
+    }
+}
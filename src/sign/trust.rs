@@ -0,0 +1,334 @@
+//! The Sigstore trusted root
+//!
+//! Keyless signatures are only as trustworthy as the Fulcio certificate
+//! authorities and Rekor keys they're checked against. Those come from a
+//! `trusted_root.json`, the file cosign keeps up to date from Sigstore's TUF
+//! repository (`cosign initialize`) in `~/.sigstore/root/targets`.
+
+use super::parse_pem;
+use anyhow::{Context, Result};
+use aws_lc_rs::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1};
+use base64::Engine;
+use rustls_pki_types::{CertificateDer, UnixTime};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use webpki::{EndEntityCert, KeyUsage};
+
+/// 1.3.6.1.5.5.7.3.3, the code signing extended key usage
+const CODE_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x03];
+
+/// Signature algorithms accepted in certificate chains and signatures
+pub const SIGNATURE_ALGORITHMS: &[&dyn rustls_pki_types::SignatureVerificationAlgorithm] = &[
+    webpki::aws_lc_rs::ECDSA_P256_SHA256,
+    webpki::aws_lc_rs::ECDSA_P256_SHA384,
+    webpki::aws_lc_rs::ECDSA_P384_SHA256,
+    webpki::aws_lc_rs::ECDSA_P384_SHA384,
+    webpki::aws_lc_rs::ED25519,
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TrustedRootJson {
+    #[serde(default)]
+    tlogs: Vec<TransparencyLog>,
+    #[serde(default)]
+    certificate_authorities: Vec<CertificateAuthority>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransparencyLog {
+    public_key: RawBytes,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CertificateAuthority {
+    cert_chain: CertChain,
+}
+
+#[derive(Debug, Deserialize)]
+struct CertChain {
+    certificates: Vec<RawBytes>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawBytes {
+    raw_bytes: String,
+}
+
+impl RawBytes {
+    fn decode(&self) -> Result<Vec<u8>> {
+        base64::engine::general_purpose::STANDARD
+            .decode(&self.raw_bytes)
+            .context("Invalid base64 in trusted root")
+    }
+}
+
+/// Fulcio certificate authorities and Rekor keys
+#[derive(Debug, Clone)]
+pub struct TrustedRoot {
+    /// Root certificates of each certificate authority
+    roots: Vec<CertificateDer<'static>>,
+    /// Certificates between the roots and signing certificates
+    intermediates: Vec<CertificateDer<'static>>,
+    /// DER SubjectPublicKeyInfo of each Rekor instance
+    tlog_keys: Vec<Vec<u8>>,
+}
+
+impl TrustedRoot {
+    /// Where cosign keeps the trusted root it fetched from Sigstore's TUF
+    /// repository
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".sigstore/root/targets/trusted_root.json"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).with_context(|| {
+            format!(
+                "Failed to read Sigstore trusted root {} (run `cosign initialize` to fetch it)",
+                path.display()
+            )
+        })?;
+        Self::from_json(&data)
+            .with_context(|| format!("Failed to parse Sigstore trusted root {}", path.display()))
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self> {
+        let json: TrustedRootJson = serde_json::from_slice(data)?;
+        let mut roots = Vec::new();
+        let mut intermediates = Vec::new();
+        for authority in &json.certificate_authorities {
+            // Each chain runs from the intermediate to the root
+            let mut chain = authority
+                .cert_chain
+                .certificates
+                .iter()
+                .map(|c| c.decode().map(CertificateDer::from))
+                .collect::<Result<Vec<_>>>()?;
+            if let Some(root) = chain.pop() {
+                roots.push(root);
+            }
+            intermediates.extend(chain);
+        }
+        let tlog_keys = json
+            .tlogs
+            .iter()
+            .map(|t| t.public_key.decode())
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            roots,
+            intermediates,
+            tlog_keys,
+        })
+    }
+
+    /// Check that `certificate` chains to a trusted certificate authority,
+    /// through `chain` if needed, and was valid for code signing at `time`
+    pub fn verify_certificate(
+        &self,
+        certificate: &[u8],
+        chain: &[Vec<u8>],
+        time: u64,
+    ) -> Result<()> {
+        let anchors = self
+            .roots
+            .iter()
+            .map(|root| webpki::anchor_from_trusted_cert(root).map_err(anyhow::Error::from))
+            .collect::<Result<Vec<_>>>()?;
+        if anchors.is_empty() {
+            anyhow::bail!("The trusted root has no certificate authorities");
+        }
+        let mut intermediates = self.intermediates.clone();
+        intermediates.extend(chain.iter().map(|c| CertificateDer::from(c.clone())));
+
+        let der = CertificateDer::from(certificate);
+        let certificate = EndEntityCert::try_from(&der)
+            .map_err(|e| anyhow::anyhow!("Invalid signing certificate: {}", e))?;
+        certificate
+            .verify_for_usage(
+                SIGNATURE_ALGORITHMS,
+                &anchors,
+                &intermediates,
+                UnixTime::since_unix_epoch(Duration::from_secs(time)),
+                KeyUsage::required(CODE_SIGNING),
+                None,
+                None,
+            )
+            .map_err(|e| anyhow::anyhow!("Signing certificate is not trusted: {}", e))?;
+        Ok(())
+    }
+
+    /// Check cosign's bundle for a signature: Rekor's signed entry timestamp
+    /// must verify with a trusted Rekor key, and the logged entry must be for
+    /// this payload, signature and certificate. Returns when it was logged.
+    pub fn verify_bundle(
+        &self,
+        bundle: &serde_json::Value,
+        payload: &[u8],
+        signature: &[u8],
+        certificate: &[u8],
+    ) -> Result<u64> {
+        let base64 = &base64::engine::general_purpose::STANDARD;
+        let entry = &bundle["Payload"];
+        let integrated_time = entry["integratedTime"]
+            .as_u64()
+            .context("Bundle has no integrated time")?;
+        let set = base64
+            .decode(
+                bundle["SignedEntryTimestamp"]
+                    .as_str()
+                    .context("Bundle has no signed entry timestamp")?,
+            )
+            .context("Invalid signed entry timestamp")?;
+
+        // Rekor signs the canonical JSON of the entry: sorted keys, no spaces
+        let signed = format!(
+            r#"{{"body":{},"integratedTime":{},"logID":{},"logIndex":{}}}"#,
+            entry["body"], integrated_time, entry["logID"], entry["logIndex"]
+        );
+        let trusted = self.tlog_keys.iter().any(|key| {
+            UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, key)
+                .verify(signed.as_bytes(), &set)
+                .is_ok()
+        });
+        if !trusted {
+            anyhow::bail!("Transparency log entry is not signed by a trusted Rekor key");
+        }
+
+        let body = base64
+            .decode(entry["body"].as_str().context("Bundle has no entry body")?)
+            .context("Invalid entry body")?;
+        let body: serde_json::Value =
+            serde_json::from_slice(&body).context("Entry body is not JSON")?;
+        let spec = &body["spec"];
+        if body["kind"] != "hashedrekord" {
+            anyhow::bail!("Unsupported transparency log entry kind {}", body["kind"]);
+        }
+        if spec["data"]["hash"]["value"] != sha256::digest(payload).as_str() {
+            anyhow::bail!("Transparency log entry is for a different payload");
+        }
+        if spec["signature"]["content"] != base64.encode(signature).as_str() {
+            anyhow::bail!("Transparency log entry is for a different signature");
+        }
+        let logged_key = spec["signature"]["publicKey"]["content"]
+            .as_str()
+            .and_then(|k| base64.decode(k).ok())
+            .and_then(|k| String::from_utf8(k).ok())
+            .and_then(|k| parse_pem(&k).ok());
+        if logged_key.map(|(_, der)| der).as_deref() != Some(certificate) {
+            anyhow::bail!("Transparency log entry is for a different certificate");
+        }
+        Ok(integrated_time)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::sign::pem;
+    use crate::sign::rekor::tests::logged_bundle;
+    use aws_lc_rs::encoding::AsDer;
+    use aws_lc_rs::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+
+    /// When the test signing certificate was valid
+    pub const SIGNED_AT: u64 = 1735689900;
+
+    fn der(pem: &str) -> Vec<u8> {
+        parse_pem(pem).unwrap().1
+    }
+
+    pub fn leaf() -> Vec<u8> {
+        der(include_str!("testdata/leaf.pem"))
+    }
+
+    /// A trusted root with the test certificate authority and `rekor`
+    pub fn trusted_root(rekor: &EcdsaKeyPair) -> TrustedRoot {
+        let base64 = &base64::engine::general_purpose::STANDARD;
+        let rekor_key: aws_lc_rs::encoding::PublicKeyX509Der = rekor.public_key().as_der().unwrap();
+        let json = serde_json::json!({
+            "mediaType": "application/vnd.dev.sigstore.trustedroot+json;version=0.1",
+            "tlogs": [{"publicKey": {"rawBytes": base64.encode(rekor_key.as_ref())}}],
+            "certificateAuthorities": [{"certChain": {"certificates": [
+                {"rawBytes": base64.encode(der(include_str!("testdata/intermediate.pem")))},
+                {"rawBytes": base64.encode(der(include_str!("testdata/root.pem")))},
+            ]}}],
+        });
+        TrustedRoot::from_json(json.to_string().as_bytes()).unwrap()
+    }
+
+    /// cosign's bundle for a signature logged in `rekor` at `SIGNED_AT`
+    pub fn bundle(rekor: &EcdsaKeyPair, payload: &[u8], signature: &[u8]) -> serde_json::Value {
+        let certificate = pem("CERTIFICATE", &leaf());
+        logged_bundle(rekor, payload, signature, &certificate, SIGNED_AT)
+    }
+
+    fn rekor_key() -> EcdsaKeyPair {
+        EcdsaKeyPair::generate(&ECDSA_P256_SHA256_ASN1_SIGNING).unwrap()
+    }
+
+    #[test]
+    fn test_verify_certificate() {
+        let root = trusted_root(&rekor_key());
+        root.verify_certificate(&leaf(), &[], SIGNED_AT).unwrap();
+
+        // Fulcio certificates are only valid for ten minutes
+        let err = root
+            .verify_certificate(&leaf(), &[], SIGNED_AT + 3600)
+            .unwrap_err();
+        assert!(err.to_string().contains("not trusted"));
+
+        let other = TrustedRoot::from_json(br#"{"certificateAuthorities": []}"#).unwrap();
+        assert!(other.verify_certificate(&leaf(), &[], SIGNED_AT).is_err());
+    }
+
+    #[test]
+    fn test_verify_certificate_with_chain() {
+        // The intermediate can come from the signature's chain annotation
+        let base64 = &base64::engine::general_purpose::STANDARD;
+        let json = serde_json::json!({
+            "certificateAuthorities": [{"certChain": {"certificates": [
+                {"rawBytes": base64.encode(der(include_str!("testdata/root.pem")))},
+            ]}}],
+        });
+        let root = TrustedRoot::from_json(json.to_string().as_bytes()).unwrap();
+        assert!(root.verify_certificate(&leaf(), &[], SIGNED_AT).is_err());
+        let chain = vec![der(include_str!("testdata/intermediate.pem"))];
+        root.verify_certificate(&leaf(), &chain, SIGNED_AT).unwrap();
+    }
+
+    #[test]
+    fn test_verify_bundle() {
+        let rekor = rekor_key();
+        let root = trusted_root(&rekor);
+        let bundle = bundle(&rekor, b"payload", b"signature");
+        assert_eq!(
+            root.verify_bundle(&bundle, b"payload", b"signature", &leaf())
+                .unwrap(),
+            SIGNED_AT
+        );
+
+        let err = root
+            .verify_bundle(&bundle, b"other", b"signature", &leaf())
+            .unwrap_err();
+        assert!(err.to_string().contains("different payload"));
+        let err = root
+            .verify_bundle(&bundle, b"payload", b"other", &leaf())
+            .unwrap_err();
+        assert!(err.to_string().contains("different signature"));
+
+        let mut tampered = bundle.clone();
+        tampered["Payload"]["integratedTime"] = serde_json::json!(SIGNED_AT + 1);
+        let err = root
+            .verify_bundle(&tampered, b"payload", b"signature", &leaf())
+            .unwrap_err();
+        assert!(err.to_string().contains("trusted Rekor key"));
+
+        let untrusted = trusted_root(&rekor_key());
+        assert!(untrusted
+            .verify_bundle(&bundle, b"payload", b"signature", &leaf())
+            .is_err());
+    }
+}
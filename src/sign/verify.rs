@@ -0,0 +1,390 @@
+//! Verifying cosign signatures on images
+//!
+//! Used by `verify_base` to check a base image before building on it. Each
+//! simple signing layer of the image's `sha256-<hex>.sig` image is checked
+//! against the policy's keys and keyless identities; one good signature is
+//! enough. Keyless signatures must have a certificate from a trusted Fulcio
+//! that was valid when Rekor logged the signature.
+
+use super::trust::{TrustedRoot, SIGNATURE_ALGORITHMS};
+use super::x509::certificate_identity;
+use super::{
+    parse_pem, parse_pem_blocks, signature_tag, BUNDLE_ANNOTATION, CERTIFICATE_ANNOTATION,
+    CHAIN_ANNOTATION, SIGNATURE_ANNOTATION, SIMPLE_SIGNING_MEDIA_TYPE,
+};
+use crate::config::{BasePolicy, SignerIdentity};
+use crate::registry::{Annotations, OciImageManifest, RegistryAuth, RegistryClient};
+use anyhow::{Context, Result};
+use aws_lc_rs::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1, ECDSA_P384_SHA384_ASN1};
+use base64::Engine;
+use rustls_pki_types::CertificateDer;
+use std::path::PathBuf;
+use tracing::debug;
+use webpki::EndEntityCert;
+
+/// Checks signatures against a [`BasePolicy`]
+pub struct Verifier {
+    /// Each key's path, for messages, and DER SubjectPublicKeyInfo
+    keys: Vec<(PathBuf, Vec<u8>)>,
+    identities: Vec<SignerIdentity>,
+    trusted_root: Option<TrustedRoot>,
+}
+
+impl Verifier {
+    /// Load the policy's keys, and the trusted root if it names keyless
+    /// identities
+    pub fn new(policy: &BasePolicy) -> Result<Self> {
+        if policy.keys.is_empty() && policy.identities.is_empty() {
+            anyhow::bail!(
+                "verify_base needs at least one key or identity under [base_policy] to trust"
+            );
+        }
+        let keys = policy
+            .keys
+            .iter()
+            .map(|path| {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read public key {}", path.display()))?;
+                let (label, der) = parse_pem(&text)
+                    .with_context(|| format!("Failed to parse public key {}", path.display()))?;
+                if label != "PUBLIC KEY" {
+                    anyhow::bail!("{} is a {}, not a PUBLIC KEY", path.display(), label);
+                }
+                Ok((path.clone(), der))
+            })
+            .collect::<Result<_>>()?;
+
+        let trusted_root = if policy.identities.is_empty() {
+            None
+        } else {
+            let path = policy
+                .trusted_root
+                .clone()
+                .or_else(TrustedRoot::default_path)
+                .context("No Sigstore trusted root; set base_policy.trusted_root")?;
+            Some(TrustedRoot::load(&path)?)
+        };
+        Ok(Self {
+            keys,
+            identities: policy.identities.clone(),
+            trusted_root,
+        })
+    }
+
+    #[cfg(test)]
+    fn with_trusted_root(mut self, trusted_root: TrustedRoot) -> Self {
+        self.trusted_root = Some(trusted_root);
+        self
+    }
+
+    /// Verify that `repository@digest` has a signature the policy trusts.
+    /// Returns who signed it.
+    pub async fn verify(
+        &self,
+        registry_client: &mut RegistryClient,
+        repository: &str,
+        digest: &str,
+        auth: &RegistryAuth,
+    ) -> Result<String> {
+        let signature_ref = format!("{}:{}", repository, signature_tag(digest));
+        let (body, _, _) = registry_client
+            .get_manifest_raw(&signature_ref, auth)
+            .await
+            .with_context(|| format!("No cosign signatures found for {}@{}", repository, digest))?;
+        let manifest: OciImageManifest = serde_json::from_slice(&body)
+            .with_context(|| format!("Failed to parse signature image {}", signature_ref))?;
+
+        let mut problems = Vec::new();
+        for layer in &manifest.layers {
+            if layer.media_type != SIMPLE_SIGNING_MEDIA_TYPE {
+                continue;
+            }
+            let payload = registry_client
+                .pull_blob(&signature_ref, layer, auth)
+                .await?;
+            let annotations = layer.annotations.clone().unwrap_or_default();
+            match self.verify_signature(digest, &payload, &annotations) {
+                Ok(signer) => return Ok(signer),
+                Err(e) => {
+                    debug!("Signature {} rejected: {:#}", layer.digest, e);
+                    problems.push(format!("{:#}", e));
+                }
+            }
+        }
+        if problems.is_empty() {
+            anyhow::bail!("{} has no cosign signatures", signature_ref);
+        }
+        anyhow::bail!(
+            "No signature of {}@{} is trusted by base_policy:\n  {}",
+            repository,
+            digest,
+            problems.join("\n  ")
+        )
+    }
+
+    /// Verify one signature layer's payload and annotations
+    fn verify_signature(
+        &self,
+        digest: &str,
+        payload: &[u8],
+        annotations: &Annotations,
+    ) -> Result<String> {
+        let claims: serde_json::Value =
+            serde_json::from_slice(payload).context("Signature payload is not JSON")?;
+        let signed_digest = claims["critical"]["image"]["docker-manifest-digest"]
+            .as_str()
+            .context("Signature payload names no image digest")?;
+        if signed_digest != digest {
+            anyhow::bail!("Signature is for {}, not {}", signed_digest, digest);
+        }
+        let signature = base64::engine::general_purpose::STANDARD
+            .decode(
+                annotations
+                    .get(SIGNATURE_ANNOTATION)
+                    .context("Signature layer has no signature")?,
+            )
+            .context("Invalid base64 signature")?;
+
+        for (path, key) in &self.keys {
+            let verified = [&ECDSA_P256_SHA256_ASN1, &ECDSA_P384_SHA384_ASN1]
+                .iter()
+                .any(|alg| {
+                    UnparsedPublicKey::new(*alg, key)
+                        .verify(payload, &signature)
+                        .is_ok()
+                });
+            if verified {
+                return Ok(format!("key {}", path.display()));
+            }
+        }
+
+        let Some(certificate) = annotations.get(CERTIFICATE_ANNOTATION) else {
+            anyhow::bail!("Signature doesn't verify with any key in base_policy");
+        };
+        let Some(trusted_root) = &self.trusted_root else {
+            anyhow::bail!("Signature doesn't verify with any key in base_policy");
+        };
+        let (_, certificate) = parse_pem(certificate)?;
+        let chain = match annotations.get(CHAIN_ANNOTATION) {
+            Some(chain) => parse_pem_blocks(chain)?
+                .into_iter()
+                .map(|(_, der)| der)
+                .collect(),
+            None => Vec::new(),
+        };
+
+        // Fulcio certificates expire within minutes, so they're checked at
+        // the time Rekor logged the signature
+        let bundle: serde_json::Value = serde_json::from_str(
+            annotations
+                .get(BUNDLE_ANNOTATION)
+                .context("Keyless signature has no transparency log bundle")?,
+        )
+        .context("Invalid transparency log bundle")?;
+        let logged_at = trusted_root.verify_bundle(&bundle, payload, &signature, &certificate)?;
+        trusted_root.verify_certificate(&certificate, &chain, logged_at)?;
+
+        let der = CertificateDer::from(certificate.as_slice());
+        let end_entity = EndEntityCert::try_from(&der)
+            .map_err(|e| anyhow::anyhow!("Invalid signing certificate: {}", e))?;
+        let verified = SIGNATURE_ALGORITHMS.iter().any(|alg| {
+            end_entity
+                .verify_signature(*alg, payload, &signature)
+                .is_ok()
+        });
+        if !verified {
+            anyhow::bail!("Signature doesn't verify with its certificate");
+        }
+
+        let identity = certificate_identity(&certificate)?;
+        if !self
+            .identities
+            .iter()
+            .any(|i| i.matches(&identity.issuer, &identity.subject))
+        {
+            anyhow::bail!(
+                "Signed by {} from {}, which isn't an identity in base_policy",
+                identity.subject,
+                identity.issuer
+            );
+        }
+        Ok(format!("{} from {}", identity.subject, identity.issuer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sign::key::{LocalKey, SigningKey};
+    use crate::sign::trust::tests::{bundle, leaf, trusted_root};
+    use crate::sign::{payload, pem, Signature};
+    use aws_lc_rs::signature::{EcdsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+
+    const DIGEST: &str = "sha256:0123";
+
+    fn identity(subject: &str) -> SignerIdentity {
+        SignerIdentity {
+            issuer: "https://token.actions.githubusercontent.com".to_string(),
+            subject: subject.to_string(),
+        }
+    }
+
+    fn keyless_verifier(subject: &str, rekor: &EcdsaKeyPair) -> Verifier {
+        Verifier {
+            keys: Vec::new(),
+            identities: vec![identity(subject)],
+            trusted_root: None,
+        }
+        .with_trusted_root(trusted_root(rekor))
+    }
+
+    /// A keyless signature of `payload` made with the test certificate
+    async fn keyless_signature(rekor: &EcdsaKeyPair, payload: &[u8]) -> Annotations {
+        let key = LocalKey::from_pem(include_str!("testdata/leaf.key"), "").unwrap();
+        let signature = key.sign(payload).await.unwrap();
+        Signature {
+            bundle: Some(bundle(rekor, payload, &signature)),
+            certificate: Some(pem("CERTIFICATE", &leaf())),
+            chain: None,
+            signature,
+        }
+        .annotations()
+    }
+
+    #[tokio::test]
+    async fn test_verify_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let pkcs8 = EcdsaKeyPair::generate(&ECDSA_P256_SHA256_ASN1_SIGNING)
+            .unwrap()
+            .to_pkcs8v1()
+            .unwrap();
+        let key = LocalKey::from_pem(&pem("PRIVATE KEY", pkcs8.as_ref()), "").unwrap();
+        let public_key = dir.path().join("cosign.pub");
+        std::fs::write(&public_key, key.public_key().await.unwrap()).unwrap();
+
+        let verifier = Verifier::new(&BasePolicy {
+            keys: vec![public_key.clone()],
+            ..Default::default()
+        })
+        .unwrap();
+        let payload = payload("cgr.dev/chainguard/static", DIGEST);
+        let annotations = Signature {
+            signature: key.sign(&payload).await.unwrap(),
+            ..Default::default()
+        }
+        .annotations();
+        assert_eq!(
+            verifier
+                .verify_signature(DIGEST, &payload, &annotations)
+                .unwrap(),
+            format!("key {}", public_key.display())
+        );
+
+        // A signature of another image doesn't count
+        let err = verifier
+            .verify_signature("sha256:4567", &payload, &annotations)
+            .unwrap_err();
+        assert!(err.to_string().contains("not sha256:4567"));
+
+        // Nor does a signature by another key
+        let other = LocalKey::from_pem(
+            &pem(
+                "PRIVATE KEY",
+                EcdsaKeyPair::generate(&ECDSA_P256_SHA256_ASN1_SIGNING)
+                    .unwrap()
+                    .to_pkcs8v1()
+                    .unwrap()
+                    .as_ref(),
+            ),
+            "",
+        )
+        .unwrap();
+        let annotations = Signature {
+            signature: other.sign(&payload).await.unwrap(),
+            ..Default::default()
+        }
+        .annotations();
+        assert!(verifier
+            .verify_signature(DIGEST, &payload, &annotations)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_verify_keyless() {
+        let rekor = EcdsaKeyPair::generate(&ECDSA_P256_SHA256_ASN1_SIGNING).unwrap();
+        let payload = payload("cgr.dev/chainguard/static", DIGEST);
+        let annotations = keyless_signature(&rekor, &payload).await;
+
+        let subject =
+            "https://github.com/example/base/.github/workflows/release.yaml@refs/heads/main";
+        let verifier = keyless_verifier(subject, &rekor);
+        assert_eq!(
+            verifier
+                .verify_signature(DIGEST, &payload, &annotations)
+                .unwrap(),
+            format!(
+                "{} from https://token.actions.githubusercontent.com",
+                subject
+            )
+        );
+        let wildcard = keyless_verifier(
+            "https://github.com/example/base/.github/workflows/*",
+            &rekor,
+        );
+        assert!(wildcard
+            .verify_signature(DIGEST, &payload, &annotations)
+            .is_ok());
+
+        let stranger = keyless_verifier("https://github.com/someone/else/*", &rekor);
+        let err = stranger
+            .verify_signature(DIGEST, &payload, &annotations)
+            .unwrap_err();
+        assert!(err.to_string().contains("isn't an identity in base_policy"));
+    }
+
+    #[tokio::test]
+    async fn test_verify_keyless_rejects_tampering() {
+        let rekor = EcdsaKeyPair::generate(&ECDSA_P256_SHA256_ASN1_SIGNING).unwrap();
+        let payload = payload("cgr.dev/chainguard/static", DIGEST);
+        let annotations = keyless_signature(&rekor, &payload).await;
+        let verifier = keyless_verifier("https://github.com/example/*", &rekor);
+
+        let mut unlogged = annotations.clone();
+        unlogged.remove(BUNDLE_ANNOTATION);
+        assert!(verifier
+            .verify_signature(DIGEST, &payload, &unlogged)
+            .is_err());
+
+        let untrusted_log = keyless_verifier(
+            "https://github.com/example/*",
+            &EcdsaKeyPair::generate(&ECDSA_P256_SHA256_ASN1_SIGNING).unwrap(),
+        );
+        assert!(untrusted_log
+            .verify_signature(DIGEST, &payload, &annotations)
+            .is_err());
+
+        let mut forged = annotations.clone();
+        forged.insert(
+            SIGNATURE_ANNOTATION.to_string(),
+            base64::engine::general_purpose::STANDARD.encode([0u8; 70]),
+        );
+        assert!(verifier
+            .verify_signature(DIGEST, &payload, &forged)
+            .is_err());
+    }
+
+    #[test]
+    fn test_policy_needs_signers() {
+        let err = Verifier::new(&BasePolicy::default()).err().unwrap();
+        assert!(err.to_string().contains("at least one key or identity"));
+
+        let err = Verifier::new(&BasePolicy {
+            identities: vec![identity("x")],
+            trusted_root: Some(PathBuf::from("/nonexistent/trusted_root.json")),
+            ..Default::default()
+        })
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("cosign initialize"));
+    }
+}
@@ -0,0 +1,176 @@
+//! The signer identity in a Fulcio certificate
+//!
+//! Fulcio puts the OIDC token's subject in the certificate's subject
+//! alternative name (an email address or URI) and the token's issuer in a
+//! Sigstore extension. Only those fields are read here; the certificate
+//! itself is validated by webpki.
+
+use anyhow::{Context, Result};
+
+/// 2.5.29.17, subjectAltName
+const SUBJECT_ALT_NAME_OID: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// 1.3.6.1.4.1.57264.1.1, the OIDC issuer as raw bytes
+const ISSUER_V1_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xbf, 0x30, 0x01, 0x01];
+
+/// 1.3.6.1.4.1.57264.1.8, the OIDC issuer as a DER UTF8String
+const ISSUER_V2_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xbf, 0x30, 0x01, 0x08];
+
+const SEQUENCE: u8 = 0x30;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const OCTET_STRING: u8 = 0x04;
+const UTF8_STRING: u8 = 0x0c;
+const EXTENSIONS: u8 = 0xa3;
+const RFC822_NAME: u8 = 0x81;
+const URI: u8 = 0x86;
+
+/// Who a Fulcio certificate was issued to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateIdentity {
+    /// Email address or URI the certificate was issued for
+    pub subject: String,
+    /// OIDC issuer that vouched for the subject
+    pub issuer: String,
+}
+
+/// Read the signer identity from a DER certificate
+pub fn certificate_identity(der: &[u8]) -> Result<CertificateIdentity> {
+    let (certificate, _) = expect(der, SEQUENCE)?;
+    let (tbs, _) = expect(certificate, SEQUENCE)?;
+
+    let mut fields = tbs;
+    let mut extensions = None;
+    while !fields.is_empty() {
+        let (tag, content, rest) = tlv(fields)?;
+        if tag == EXTENSIONS {
+            extensions = Some(expect(content, SEQUENCE)?.0);
+        }
+        fields = rest;
+    }
+    let mut extensions = extensions.context("Certificate has no extensions")?;
+
+    let (mut subject, mut issuer_v1, mut issuer_v2) = (None, None, None);
+    while !extensions.is_empty() {
+        let (extension, rest) = expect(extensions, SEQUENCE)?;
+        extensions = rest;
+        let (oid, mut rest) = expect(extension, OBJECT_IDENTIFIER)?;
+        let mut value = None;
+        while !rest.is_empty() {
+            let (tag, content, next) = tlv(rest)?;
+            if tag == OCTET_STRING {
+                value = Some(content);
+            }
+            rest = next;
+        }
+        let value = value.context("Certificate extension has no value")?;
+
+        match oid {
+            SUBJECT_ALT_NAME_OID => subject = subject_alt_name(value)?,
+            ISSUER_V1_OID => issuer_v1 = Some(text(value)?),
+            ISSUER_V2_OID => issuer_v2 = Some(text(expect(value, UTF8_STRING)?.0)?),
+            _ => {}
+        }
+    }
+
+    Ok(CertificateIdentity {
+        subject: subject.context("Certificate has no email or URI subject alternative name")?,
+        issuer: issuer_v2
+            .or(issuer_v1)
+            .context("Certificate has no OIDC issuer extension")?,
+    })
+}
+
+/// The first email address or URI among the subject alternative names
+fn subject_alt_name(value: &[u8]) -> Result<Option<String>> {
+    let (mut names, _) = expect(value, SEQUENCE)?;
+    while !names.is_empty() {
+        let (tag, content, rest) = tlv(names)?;
+        if tag == RFC822_NAME || tag == URI {
+            return Ok(Some(text(content)?));
+        }
+        names = rest;
+    }
+    Ok(None)
+}
+
+fn text(bytes: &[u8]) -> Result<String> {
+    String::from_utf8(bytes.to_vec()).context("Certificate field is not UTF-8")
+}
+
+/// The contents of the DER element at the start of `input`, which must
+/// have tag `expected`, and the input after it
+fn expect(input: &[u8], expected: u8) -> Result<(&[u8], &[u8])> {
+    let (tag, content, rest) = tlv(input)?;
+    if tag != expected {
+        anyhow::bail!(
+            "Malformed certificate: expected tag {:#04x}, found {:#04x}",
+            expected,
+            tag
+        );
+    }
+    Ok((content, rest))
+}
+
+/// Split the DER element at the start of `input` into its tag, contents and
+/// the input after it
+fn tlv(input: &[u8]) -> Result<(u8, &[u8], &[u8])> {
+    let truncated = || anyhow::anyhow!("Malformed certificate: truncated element");
+    let (&tag, rest) = input.split_first().ok_or_else(truncated)?;
+    let (&first, mut rest) = rest.split_first().ok_or_else(truncated)?;
+    let len = if first < 0x80 {
+        first as usize
+    } else {
+        let octets = (first & 0x7f) as usize;
+        if octets == 0 || octets > 4 || rest.len() < octets {
+            return Err(truncated());
+        }
+        let len = rest[..octets]
+            .iter()
+            .fold(0usize, |len, b| (len << 8) | *b as usize);
+        rest = &rest[octets..];
+        len
+    };
+    if rest.len() < len {
+        return Err(truncated());
+    }
+    Ok((tag, &rest[..len], &rest[len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sign::parse_pem;
+
+    #[test]
+    fn test_certificate_identity() {
+        let (_, der) = parse_pem(include_str!("testdata/leaf.pem")).unwrap();
+        assert_eq!(
+            certificate_identity(&der).unwrap(),
+            CertificateIdentity {
+                subject:
+                    "https://github.com/example/base/.github/workflows/release.yaml@refs/heads/main"
+                        .to_string(),
+                issuer: "https://token.actions.githubusercontent.com".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_certificate_without_identity() {
+        let (_, der) = parse_pem(include_str!("testdata/root.pem")).unwrap();
+        assert!(certificate_identity(&der).is_err());
+        assert!(certificate_identity(&der[..20]).is_err());
+    }
+
+    #[test]
+    fn test_tlv_long_length() {
+        let mut input = vec![0x04, 0x82, 0x01, 0x00];
+        input.extend(vec![7u8; 256]);
+        input.push(0x05);
+        let (tag, content, rest) = tlv(&input).unwrap();
+        assert_eq!(tag, 0x04);
+        assert_eq!(content.len(), 256);
+        assert_eq!(rest, &[0x05]);
+        assert!(tlv(&[0x04, 0x05, 0x00]).is_err());
+    }
+}
@@ -54,7 +54,7 @@ fn test_full_build_and_run_workflow() -> Result<()> {
 
     // Try to run the image
     let docker_output = StdCommand::new("docker")
-        .args(&["run", "--rm", &image_ref])
+        .args(["run", "--rm", &image_ref])
         .output()?;
 
     if !docker_output.status.success() {
@@ -114,7 +114,7 @@ fn test_multi_arch_build_and_run() -> Result<()> {
 
     // Try to run the image - it should work on the current architecture
     let docker_output = StdCommand::new("docker")
-        .args(&["run", "--rm", &image_ref])
+        .args(["run", "--rm", &image_ref])
         .output()?;
 
     assert!(docker_output.status.success(), "Docker run failed");